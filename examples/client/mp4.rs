@@ -37,6 +37,9 @@ pub struct Opts {
     #[structopt(default_value, long)]
     initial_timestamp: retina::client::InitialTimestampPolicy,
 
+    #[structopt(default_value, long)]
+    sequence_policy: retina::client::SequencePolicy,
+
     #[structopt(long)]
     no_video: bool,
 
@@ -104,10 +107,14 @@ struct TrakTracker {
 
     /// The durations of samples in a run-length encoding form: (number of samples, duration).
     /// This lags one sample behind calls to `add_sample` because each sample's duration
-    /// is calculated using the PTS of the following sample.
+    /// is calculated using the DTS of the following sample.
     durations: Vec<(u32, u32)>,
-    last_pts: Option<i64>,
+    last_dts: Option<i64>,
     tot_duration: u64,
+
+    /// The composition offsets (PTS - DTS) of samples in a run-length encoding form:
+    /// (number of samples, offset). Only written if some offset is non-zero.
+    composition_offsets: Vec<(u32, u32)>,
 }
 
 impl TrakTracker {
@@ -115,7 +122,8 @@ impl TrakTracker {
         &mut self,
         pos: u32,
         size: u32,
-        timestamp: retina::Timestamp,
+        decode_timestamp: retina::Timestamp,
+        composition_offset: u32,
         loss: u16,
     ) -> Result<(), Error> {
         if self.samples > 0 && loss > 0 {
@@ -127,8 +135,8 @@ impl TrakTracker {
         }
         self.sizes.push(size);
         self.next_pos = Some(pos + size);
-        if let Some(last_pts) = self.last_pts.replace(timestamp.timestamp()) {
-            let duration = timestamp.timestamp().checked_sub(last_pts).unwrap();
+        if let Some(last_dts) = self.last_dts.replace(decode_timestamp.timestamp()) {
+            let duration = decode_timestamp.timestamp().checked_sub(last_dts).unwrap();
             self.tot_duration += u64::try_from(duration).unwrap();
             let duration = u32::try_from(duration)?;
             match self.durations.last_mut() {
@@ -136,11 +144,15 @@ impl TrakTracker {
                 _ => self.durations.push((1, duration)),
             }
         }
+        match self.composition_offsets.last_mut() {
+            Some((s, o)) if *o == composition_offset => *s += 1,
+            _ => self.composition_offsets.push((1, composition_offset)),
+        }
         Ok(())
     }
 
     fn finish(&mut self) {
-        if self.last_pts.is_some() {
+        if self.last_dts.is_some() {
             self.durations.push((1, 0));
         }
     }
@@ -148,6 +160,7 @@ impl TrakTracker {
     /// Estimates the sum of the variable-sized portions of the data.
     fn size_estimate(&self) -> usize {
         (self.durations.len() * 8) + // stts
+        (self.composition_offsets.len() * 8) + // ctts
         (self.chunks.len() * 12) +   // stsc
         (self.sizes.len() * 4) +     // stsz
         (self.chunks.len() * 4) // stco
@@ -163,6 +176,16 @@ impl TrakTracker {
                 buf.put_u32(*duration);
             }
         });
+        if self.composition_offsets.iter().any(|&(_, o)| o != 0) {
+            write_box!(buf, b"ctts", {
+                buf.put_u32(0); // version
+                buf.put_u32(u32::try_from(self.composition_offsets.len())?);
+                for (samples, offset) in &self.composition_offsets {
+                    buf.put_u32(*samples);
+                    buf.put_u32(*offset);
+                }
+            });
+        }
         write_box!(buf, b"stsc", {
             buf.put_u32(0); // version
             buf.put_u32(u32::try_from(self.chunks.len())?);
@@ -342,7 +365,7 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                         write_box!(buf, b"stsd", {
                             buf.put_u32(0); // version
                             buf.put_u32(1); // entry_count
-                            self.write_video_sample_entry(buf, parameters)?;
+                            buf.extend_from_slice(&parameters.sample_entry()[..]);
                         });
                         self.video_trak.write_common_stbl_parts(buf)?;
                         write_box!(buf, b"stss", {
@@ -454,40 +477,6 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
         Ok(())
     }
 
-    fn write_video_sample_entry(
-        &self,
-        buf: &mut BytesMut,
-        parameters: &VideoParameters,
-    ) -> Result<(), Error> {
-        // TODO: this should move to client::VideoParameters::sample_entry() or some such.
-        write_box!(buf, b"avc1", {
-            buf.put_u32(0);
-            buf.put_u32(1); // data_reference_index = 1
-            buf.extend_from_slice(&[0; 16]);
-            buf.put_u16(u16::try_from(parameters.pixel_dimensions().0)?);
-            buf.put_u16(u16::try_from(parameters.pixel_dimensions().1)?);
-            buf.extend_from_slice(&[
-                0x00, 0x48, 0x00, 0x00, // horizresolution
-                0x00, 0x48, 0x00, 0x00, // vertresolution
-                0x00, 0x00, 0x00, 0x00, // reserved
-                0x00, 0x01, // frame count
-                0x00, 0x00, 0x00, 0x00, // compressorname
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x18, 0xff, 0xff, // depth + pre_defined
-            ]);
-            write_box!(buf, b"avcC", {
-                buf.extend_from_slice(parameters.extra_data());
-            });
-        });
-        Ok(())
-    }
-
     async fn video(&mut self, mut frame: retina::codec::VideoFrame) -> Result<(), Error> {
         println!(
             "{}: {}-byte video frame",
@@ -501,8 +490,13 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
             self.video_params = Some(p);
         }
         let size = u32::try_from(frame.data().remaining())?;
-        self.video_trak
-            .add_sample(self.mdat_pos, size, frame.timestamp, frame.loss)?;
+        self.video_trak.add_sample(
+            self.mdat_pos,
+            size,
+            frame.decode_timestamp(),
+            frame.composition_offset(),
+            frame.loss,
+        )?;
         self.mdat_pos = self
             .mdat_pos
             .checked_add(size)
@@ -524,7 +518,7 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
         );
         let size = u32::try_from(frame.data.remaining())?;
         self.audio_trak
-            .add_sample(self.mdat_pos, size, frame.timestamp, frame.loss)?;
+            .add_sample(self.mdat_pos, size, frame.timestamp, 0, frame.loss)?;
         self.mdat_pos = self
             .mdat_pos
             .checked_add(size)
@@ -579,6 +573,7 @@ pub async fn run(opts: Opts) -> Result<(), Error> {
         .play(
             retina::client::PlayOptions::default()
                 .initial_timestamp(opts.initial_timestamp)
+                .sequence_policy(opts.sequence_policy)
                 .enforce_timestamps_with_max_jump_secs(NonZeroU32::new(30).unwrap()),
         )
         .await?
@@ -601,8 +596,12 @@ pub async fn run(opts: Opts) -> Result<(), Error> {
                 match pkt.ok_or_else(|| anyhow!("EOF"))?? {
                     CodecItem::VideoFrame(f) => mp4.video(f).await?,
                     CodecItem::AudioFrame(f) => mp4.audio(f).await?,
-                    CodecItem::SenderReport(sr) => {
-                        println!("{}: SR ts={}", sr.timestamp, sr.ntp_timestamp);
+                    CodecItem::Rtcp(rtcp) => {
+                        if let (Some(t), Some(retina::rtcp::Packet::SenderReport(sr))) =
+                            (rtcp.rtp_timestamp, rtcp.pkts().next())
+                        {
+                            println!("{}: SR ts={}", t, sr.ntp_timestamp());
+                        }
                     },
                     _ => continue,
                 };
@@ -35,9 +35,11 @@ fuzz_target!(|data: &[u8]| {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number,
             loss: u16::from(loss),
             mark,
+            extension: None,
             payload: data.split_off(usize::from(len)),
         };
         //println!("pkt: {:#?}", pkt);
@@ -76,6 +76,14 @@ impl ChannelMappings {
         Ok(())
     }
 
+    /// Returns the RTCP channel id assigned to the given stream, if any.
+    pub fn rtcp_channel(&self, stream_i: usize) -> Option<u8> {
+        self.0
+            .iter()
+            .position(|c| matches!(c, Some(c) if usize::from(c.get() - 1) == stream_i))
+            .map(|i| ((i as u8) << 1) | 1)
+    }
+
     /// Looks up a channel id's mapping.
     pub fn lookup(&self, channel_id: u8) -> Option<ChannelMapping> {
         let i = usize::from(channel_id >> 1);
@@ -146,5 +154,7 @@ mod tests {
             })
         );
         assert_eq!(mappings.next_unassigned().unwrap(), 2);
+        assert_eq!(mappings.rtcp_channel(26), Some(9));
+        assert_eq!(mappings.rtcp_channel(27), None);
     }
 }
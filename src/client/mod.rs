@@ -20,8 +20,11 @@ use crate::{Error, ErrorInt, RtspMessageContext};
 
 mod channel_mapping;
 mod parse;
+mod reorder;
+mod rtcp_sender;
 pub mod rtp;
 mod timeline;
+mod wall_clock;
 
 /// Duration between keepalive RTSP requests during [Playing] state.
 pub const KEEPALIVE_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
@@ -48,6 +51,12 @@ pub enum InitialTimestampPolicy {
     /// specified for all of them; otherwise assume the first received packet
     /// for each stream is at NPT 0.
     Permissive,
+
+    /// Ignore the `rtptime` parameter and instead align each stream's NPT via
+    /// the NTP and RTP timestamp pairs in RTCP sender reports. Until a
+    /// stream's first usable sender report, its first received packet is at
+    /// NPT 0. Each stream's NPT may jump when a sender report arrives.
+    SenderReport,
 }
 
 impl Default for InitialTimestampPolicy {
@@ -63,6 +72,7 @@ impl std::fmt::Display for InitialTimestampPolicy {
             InitialTimestampPolicy::Require => "require",
             InitialTimestampPolicy::Ignore => "ignore",
             InitialTimestampPolicy::Permissive => "permissive",
+            InitialTimestampPolicy::SenderReport => "sender-report",
         })
     }
 }
@@ -76,9 +86,72 @@ impl std::str::FromStr for InitialTimestampPolicy {
             "require" => InitialTimestampPolicy::Require,
             "ignore" => InitialTimestampPolicy::Ignore,
             "permissive" => InitialTimestampPolicy::Permissive,
+            "sender-report" => InitialTimestampPolicy::SenderReport,
             _ => bail!(ErrorInt::InvalidArgument(format!(
                 "bad InitialTimestampPolicy {}; \
-                 expected default, require, ignore or permissive",
+                 expected default, require, ignore, permissive or sender-report",
+                s
+            ))),
+        })
+    }
+}
+
+/// Policy for RTP packets which don't follow a stream's expected sequence number.
+///
+/// Decisions are counted in [`rtp::StreamStats::sequence`]. These policies
+/// apply to packets from the stream's current RTP source; see
+/// [`PlayOptions::resync_on_ssrc_change`] for packets from another. With
+/// [`PlayOptions::reorder_buffer`], out-of-order packets are reordered rather
+/// than handled by this policy, but packets which step backward by more than
+/// the buffer could hold (and at least 100) are still handled as jumps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequencePolicy {
+    /// Default policy: fail the session on any backward step in sequence
+    /// numbers, including duplicates. Forward jumps of less than `0x8000` are
+    /// reported as loss.
+    Strict,
+
+    /// Drop packets which step backward (duplicates or late packets), as well
+    /// as forward jumps of `0x8000` or more, which are indistinguishable.
+    Tolerant,
+
+    /// Follow sequence number restarts, as when the camera reconfigures its
+    /// encoder, using the probation rules of [RFC 3550 appendix
+    /// A.1](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.1). A
+    /// packet which jumps forward by 3000 or more or backward by more than 100
+    /// is dropped unless the next packet follows it in sequence, in which case
+    /// the stream restarts at the latter and reports a
+    /// [`PacketItem::Discontinuity`]. Duplicates and smaller backward steps
+    /// are dropped.
+    ResyncOnJump,
+}
+
+impl Default for SequencePolicy {
+    fn default() -> Self {
+        SequencePolicy::Strict
+    }
+}
+
+impl std::fmt::Display for SequencePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            SequencePolicy::Strict => "strict",
+            SequencePolicy::Tolerant => "tolerant",
+            SequencePolicy::ResyncOnJump => "resync-on-jump",
+        })
+    }
+}
+
+impl std::str::FromStr for SequencePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "strict" => SequencePolicy::Strict,
+            "tolerant" => SequencePolicy::Tolerant,
+            "resync-on-jump" => SequencePolicy::ResyncOnJump,
+            _ => bail!(ErrorInt::InvalidArgument(format!(
+                "bad SequencePolicy {}; expected strict, tolerant or resync-on-jump",
                 s
             ))),
         })
@@ -146,6 +219,13 @@ pub struct PlayOptions {
     initial_timestamp: InitialTimestampPolicy,
     ignore_zero_seq: bool,
     enforce_timestamps_with_max_jump_secs: Option<NonZeroU32>,
+    no_receiver_reports: bool,
+    resync_on_ssrc_change: Option<NonZeroU16>,
+    reorder_buffer: Option<(std::time::Duration, NonZeroU16)>,
+    set_parameter_keyframe_request: Option<Bytes>,
+    request_keyframe_on_loss: bool,
+    sequence_policy: SequencePolicy,
+    nal_output: crate::codec::NalOutput,
 }
 
 impl PlayOptions {
@@ -178,6 +258,100 @@ impl PlayOptions {
             ..self
         }
     }
+
+    /// Periodically sends a RTCP receiver report for each stream, as described
+    /// in [RFC 3550 section 6.4.2](https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.2).
+    ///
+    /// This is enabled by default. Some servers tear down sessions which don't
+    /// send RTCP; disabling it may help with servers that mishandle interleaved
+    /// data sent by the client.
+    pub fn send_receiver_reports(self, send_receiver_reports: bool) -> Self {
+        Self {
+            no_receiver_reports: !send_receiver_reports,
+            ..self
+        }
+    }
+
+    /// Follows changes of RTP source (SSRC) within a stream rather than
+    /// failing the session.
+    ///
+    /// Some cameras switch to a new SSRC, restarting sequence numbers and
+    /// timestamps, when their encoder restarts (eg on a settings change).
+    /// With this option, a stream switches to a new SSRC after a RTCP BYE for
+    /// the current one or after `packets` consecutive packets from the new
+    /// SSRC. Packets are buffered until the switch is made. The switch is
+    /// reported via [`PacketItem::Discontinuity`] (or
+    /// [`CodecItem::Discontinuity`] when demuxed, which also resets the
+    /// stream's depacketizer).
+    ///
+    /// By default, packets from an unexpected SSRC are an error unless
+    /// [`SessionOptions::ignore_spurious_data`] is set. Note this option
+    /// takes precedence, so it's unsuitable for cameras which send sustained
+    /// spurious data.
+    pub fn resync_on_ssrc_change(self, packets: NonZeroU16) -> Self {
+        Self {
+            resync_on_ssrc_change: Some(packets),
+            ..self
+        }
+    }
+
+    /// Reorders each stream's RTP packets by sequence number before
+    /// depacketization, rather than treating out-of-order packets as errors.
+    ///
+    /// This is intended for transports which may reorder packets (such as
+    /// UDP). A missing packet is reported as lost (via [`rtp::Packet::loss`])
+    /// once a later packet has waited `max_delay` or when more than
+    /// `max_packets` packets are buffered. Deadlines are checked as packets
+    /// arrive. Late and duplicate packets are dropped.
+    ///
+    /// This is also required to use retransmissions offered by the server
+    /// (see [`Stream::rtx_payload_type`]); without a reorder buffer, they're
+    /// ignored.
+    pub fn reorder_buffer(self, max_delay: std::time::Duration, max_packets: NonZeroU16) -> Self {
+        Self {
+            reorder_buffer: Some((max_delay, max_packets)),
+            ..self
+        }
+    }
+
+    /// Sets the policy for packets which don't follow the expected sequence number.
+    pub fn sequence_policy(self, sequence_policy: SequencePolicy) -> Self {
+        Self {
+            sequence_policy,
+            ..self
+        }
+    }
+
+    /// Requests keyframes via a `SET_PARAMETER` with the given `text/parameters`
+    /// body when the SDP doesn't advertise RTCP feedback for a stream.
+    ///
+    /// There's no standard body; it depends on the server.
+    /// See [`Session::request_keyframe`].
+    pub fn set_parameter_keyframe_request(self, body: String) -> Self {
+        Self {
+            set_parameter_keyframe_request: Some(Bytes::from(body)),
+            ..self
+        }
+    }
+
+    /// Automatically requests a keyframe (where possible) when the
+    /// depacketizer discards a frame because of packet loss.
+    ///
+    /// This only applies when depacketizing via [`Demuxed`].
+    pub fn request_keyframe_on_loss(self, request_keyframe_on_loss: bool) -> Self {
+        Self {
+            request_keyframe_on_loss,
+            ..self
+        }
+    }
+
+    /// Sets the layout of H.264 and H.265 [`crate::codec::VideoFrame`] data, such as Annex B
+    /// start codes rather than length prefixes.
+    ///
+    /// This only applies when depacketizing via [`Demuxed`].
+    pub fn nal_output(self, nal_output: crate::codec::NalOutput) -> Self {
+        Self { nal_output, ..self }
+    }
 }
 
 #[derive(Debug)]
@@ -190,7 +364,10 @@ pub struct Presentation {
 }
 
 /// Information about a stream offered within a presentation.
-/// Currently if multiple formats are offered, this only describes the first.
+///
+/// The format-specific fields describe the selected format: initially the
+/// first offered, or as chosen via [`Session::set_format`]. All offered
+/// formats are available via [`Stream::formats`].
 #[derive(Debug)]
 pub struct Stream {
     /// Media type, as specified in the [IANA SDP parameters media
@@ -221,7 +398,28 @@ pub struct Stream {
     /// Number of audio channels, if applicable (`media` is `audio`) and known.
     pub channels: Option<NonZeroU16>,
 
-    depacketizer: Result<crate::codec::Depacketizer, String>,
+    /// RTP header extension element ids, as specified by `a=extmap` attributes
+    /// at the session or media level.
+    pub extmap: rtp::ExtMap,
+
+    /// RTCP feedback messages the server accepts for this stream's payload
+    /// type, as specified by `a=rtcp-fb` attributes. Each is lowercase, such as
+    /// `nack pli` or `ccm fir`.
+    pub rtcp_feedback: Vec<String>,
+
+    /// The payload type of retransmitted packets, if the SDP offers a `rtx`
+    /// format for this stream's payload type, as described in
+    /// [RFC 4588 section 8](https://datatracker.ietf.org/doc/html/rfc4588#section-8).
+    ///
+    /// Retransmissions are only used with [`PlayOptions::reorder_buffer`].
+    pub rtx_payload_type: Option<u8>,
+
+    /// All formats offered on the SDP's `m=` line, excluding retransmission
+    /// formats, in order.
+    formats: Vec<Format>,
+
+    /// The index within `formats` of the selected format.
+    format: usize,
 
     /// The specified control URL.
     /// This is needed with multiple streams to send `SETUP` requests and
@@ -238,6 +436,61 @@ impl Stream {
     ///
     /// Returns `None` on unknown codecs, bad parameters, or if parameters aren't specified
     /// via SDP. Some codecs allow parameters to be specified in-band instead.
+    pub fn parameters(&self) -> Option<crate::codec::Parameters> {
+        self.formats[self.format].parameters()
+    }
+
+    /// Returns all formats offered for this stream.
+    pub fn formats(&self) -> &[Format] {
+        &self.formats
+    }
+
+    /// Returns the index of the format within `formats` to handle packets with
+    /// the given RTP payload type, if it was offered with the selected
+    /// format's clock rate.
+    fn format_for(&self, rtp_payload_type: u8) -> Option<usize> {
+        self.formats
+            .iter()
+            .position(|f| f.rtp_payload_type == rtp_payload_type)
+            .filter(|&i| self.formats[i].clock_rate == self.clock_rate)
+    }
+}
+
+/// A format offered for a stream: one of the RTP payload types listed on the
+/// SDP's `m=` line.
+///
+/// When depacketizing via [`Demuxed`], packets are routed to the format
+/// matching their payload type. This allows a sender to switch between
+/// formats with the selected format's clock rate (eg H.264 with different
+/// packetization modes). Packets with other payload types are dropped, as are
+/// packets for formats which can't be depacketized.
+#[derive(Debug)]
+pub struct Format {
+    /// RTP payload type.
+    pub rtp_payload_type: u8,
+
+    /// An encoding name, as in [`Stream::encoding_name`].
+    pub encoding_name: String,
+
+    /// RTP clock rate, in Hz.
+    pub clock_rate: u32,
+
+    /// Number of audio channels, if applicable and known.
+    pub channels: Option<NonZeroU16>,
+
+    /// RTCP feedback messages the server accepts for this format, as in
+    /// [`Stream::rtcp_feedback`].
+    pub rtcp_feedback: Vec<String>,
+
+    /// The payload type of this format's retransmissions, as in
+    /// [`Stream::rtx_payload_type`].
+    pub rtx_payload_type: Option<u8>,
+
+    depacketizer: Result<crate::codec::Depacketizer, String>,
+}
+
+impl Format {
+    /// Returns the parameters for this format, as in [`Stream::parameters`].
     pub fn parameters(&self) -> Option<crate::codec::Parameters> {
         self.depacketizer.as_ref().ok().and_then(|d| d.parameters())
     }
@@ -333,6 +586,70 @@ pub struct Playing {
 
     #[pin]
     keepalive_timer: tokio::time::Sleep,
+
+    /// Sender of RTCP receiver reports, or `None` if they're disabled.
+    rtcp_sender: Option<rtcp_sender::RtcpSender>,
+
+    /// True iff RTCP packets or keyframe requests have been written but not
+    /// yet flushed.
+    rtcp_flushing: bool,
+
+    #[pin]
+    rtcp_timer: tokio::time::Sleep,
+
+    /// The stream whose RTP handler has queued items, if any.
+    pulling: Option<usize>,
+
+    /// True iff streams' NPT should be aligned via RTCP sender reports, as in
+    /// [`InitialTimestampPolicy::SenderReport`].
+    sync_via_sender_reports: bool,
+
+    /// The NTP time at NPT 0 for all streams, once known.
+    sync_epoch: Option<crate::NtpTimestamp>,
+
+    /// Streams with keyframe requests which have yet to be sent.
+    keyframe_requests: Vec<usize>,
+
+    /// Streams with lost packets which have yet to be requested via RTCP NACK.
+    nack_requests: Vec<usize>,
+
+    /// The `CSeq` of an outstanding `SET_PARAMETER` keyframe request, if any.
+    keyframe_request_cseq: Option<u32>,
+
+    /// See [`PlayOptions::set_parameter_keyframe_request`].
+    set_parameter_keyframe_request: Option<Bytes>,
+
+    /// See [`PlayOptions::request_keyframe_on_loss`].
+    request_keyframe_on_loss: bool,
+}
+
+/// How to ask a stream's sender for a keyframe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum KeyframeRequestMethod {
+    /// RTCP Picture Loss Indication.
+    Pli,
+
+    /// RTCP Full Intra Request.
+    Fir,
+
+    /// RTSP `SET_PARAMETER` with the configured body.
+    SetParameter,
+}
+
+impl KeyframeRequestMethod {
+    /// Chooses the preferred method for `stream`, if any is available.
+    fn choose(stream: &Stream, rtcp: bool, set_parameter: bool) -> Option<Self> {
+        let fb = |v: &str| rtcp && stream.rtcp_feedback.iter().any(|f| f == v);
+        if fb("nack pli") {
+            Some(KeyframeRequestMethod::Pli)
+        } else if fb("ccm fir") {
+            Some(KeyframeRequestMethod::Fir)
+        } else if set_parameter {
+            Some(KeyframeRequestMethod::SetParameter)
+        } else {
+            None
+        }
+    }
 }
 impl State for Playing {}
 
@@ -603,6 +920,37 @@ impl Session<Described> {
         &self.state.presentation.streams
     }
 
+    /// Selects the format of a stream by RTP payload type.
+    ///
+    /// This determines the stream's [`Stream::encoding_name`] and other
+    /// format-specific fields, including the clock rate used for timestamps.
+    /// Fails if the stream doesn't offer the given payload type.
+    ///
+    /// Panics if `stream_i >= self.streams().len()`.
+    pub fn set_format(&mut self, stream_i: usize, rtp_payload_type: u8) -> Result<(), Error> {
+        let stream = &mut self.state.presentation.streams[stream_i];
+        let i = match stream
+            .formats
+            .iter()
+            .position(|f| f.rtp_payload_type == rtp_payload_type)
+        {
+            Some(i) => i,
+            None => bail!(ErrorInt::InvalidArgument(format!(
+                "stream {} doesn't offer RTP payload type {}",
+                stream_i, rtp_payload_type
+            ))),
+        };
+        let f = &stream.formats[i];
+        stream.encoding_name = f.encoding_name.clone();
+        stream.rtp_payload_type = f.rtp_payload_type;
+        stream.clock_rate = f.clock_rate;
+        stream.channels = f.channels;
+        stream.rtcp_feedback = f.rtcp_feedback.clone();
+        stream.rtx_payload_type = f.rtx_payload_type;
+        stream.format = i;
+        Ok(())
+    }
+
     /// Sends a `SETUP` request for a stream.
     ///
     /// Note these can't reasonably be pipelined because subsequent requests
@@ -796,7 +1144,30 @@ impl Session<Described> {
                         }
                         o => o,
                     };
+                    for f in &mut s.formats {
+                        if let Ok(d) = &mut f.depacketizer {
+                            d.set_nal_output(policy.nal_output);
+                        }
+                    }
                     let conn_ctx = self.conn.inner.ctx();
+                    let mut rtp_handler = rtp::StrictSequenceChecker::new(ssrc, initial_seq)
+                        .resync_on_ssrc_change(policy.resync_on_ssrc_change)
+                        .sequence_policy(policy.sequence_policy);
+                    if let Some((max_delay, max_packets)) = policy.reorder_buffer {
+                        let nack = !policy.no_receiver_reports
+                            && s.rtcp_feedback.iter().any(|f| f == "nack");
+                        rtp_handler = rtp_handler.reorder_buffer(max_delay, max_packets);
+                        if let Some(rtx) = s.rtx_payload_type {
+                            rtp_handler = rtp_handler.retransmission(rtx, s.rtp_payload_type, nack);
+                        }
+                    } else if let Some(rtx) = s.rtx_payload_type {
+                        log::warn!(
+                            "Stream {} offers retransmissions (payload type {}), but they will be \
+                             ignored without PlayOptions::reorder_buffer",
+                            i,
+                            rtx
+                        );
+                    }
                     s.state = StreamState::Playing {
                         timeline: Timeline::new(
                             initial_rtptime,
@@ -813,13 +1184,22 @@ impl Session<Described> {
                                 description,
                             })
                         })?,
-                        rtp_handler: rtp::StrictSequenceChecker::new(ssrc, initial_seq),
+                        rtp_handler,
                     };
                 }
                 StreamState::Uninit => {}
                 StreamState::Playing { .. } => unreachable!(),
             };
         }
+        let mut rtcp_sender = if policy.no_receiver_reports {
+            None
+        } else {
+            Some(rtcp_sender::RtcpSender::new(self.conn.inner.ctx()))
+        };
+        let rtcp_interval = rtcp_sender
+            .as_mut()
+            .map(|s| s.next_interval())
+            .unwrap_or(KEEPALIVE_DURATION);
         Ok(Session {
             conn: self.conn,
             state: Playing {
@@ -831,6 +1211,20 @@ impl Session<Described> {
                 describe_ctx: self.state.describe_ctx,
                 describe_cseq: self.state.describe_cseq,
                 describe_status: self.state.describe_status,
+                rtcp_sender,
+                rtcp_flushing: false,
+                rtcp_timer: tokio::time::sleep(rtcp_interval),
+                pulling: None,
+                sync_via_sender_reports: matches!(
+                    policy.initial_timestamp,
+                    InitialTimestampPolicy::SenderReport
+                ),
+                sync_epoch: None,
+                keyframe_requests: Vec::new(),
+                nack_requests: Vec::new(),
+                keyframe_request_cseq: None,
+                set_parameter_keyframe_request: policy.set_parameter_keyframe_request,
+                request_keyframe_on_loss: policy.request_keyframe_on_loss,
             },
         })
     }
@@ -839,7 +1233,8 @@ impl Session<Described> {
 #[derive(Debug)]
 pub enum PacketItem {
     RtpPacket(rtp::Packet),
-    SenderReport(rtp::SenderReport),
+    Rtcp(rtp::ReceivedCompoundPacket),
+    Discontinuity(rtp::Discontinuity),
 }
 
 impl Session<Playing> {
@@ -849,7 +1244,7 @@ impl Session<Playing> {
     pub fn demuxed(mut self) -> Result<Demuxed, Error> {
         for s in &mut self.state.presentation.streams {
             if matches!(s.state, StreamState::Playing { .. }) {
-                if let Err(ref description) = s.depacketizer {
+                if let Err(ref description) = s.formats[s.format].depacketizer {
                     bail!(ErrorInt::RtspResponseError {
                         conn_ctx: *self.conn.inner.ctx(),
                         msg_ctx: self.state.describe_ctx,
@@ -867,6 +1262,190 @@ impl Session<Playing> {
         })
     }
 
+    /// Asks the sender of the given stream for a keyframe.
+    ///
+    /// This sends a RTCP Picture Loss Indication or Full Intra Request
+    /// ([RFC 4585](https://datatracker.ietf.org/doc/html/rfc4585) /
+    /// [RFC 5104](https://datatracker.ietf.org/doc/html/rfc5104)) if the SDP
+    /// advertises support via `a=rtcp-fb` and RTCP receiver reports are
+    /// enabled, or otherwise a `SET_PARAMETER` request if configured via
+    /// [`PlayOptions::set_parameter_keyframe_request`]. Fails if the stream isn't
+    /// playing or neither method is available.
+    ///
+    /// The request is sent on the next call to `poll_next`. Repeated requests
+    /// for a stream before then are coalesced.
+    pub fn request_keyframe(self: Pin<&mut Self>, stream_id: usize) -> Result<(), Error> {
+        let state = self.project().state.project();
+        let stream = match state.presentation.streams.get(stream_id) {
+            Some(s) if matches!(s.state, StreamState::Playing { .. }) => s,
+            _ => bail!(ErrorInt::InvalidArgument(format!(
+                "can't request keyframe for non-playing stream {}",
+                stream_id
+            ))),
+        };
+        if KeyframeRequestMethod::choose(
+            stream,
+            state.rtcp_sender.is_some(),
+            state.set_parameter_keyframe_request.is_some(),
+        )
+        .is_none()
+        {
+            bail!(ErrorInt::InvalidArgument(format!(
+                "no keyframe request method available for stream {}",
+                stream_id
+            )));
+        }
+        if !state.keyframe_requests.contains(&stream_id) {
+            state.keyframe_requests.push(stream_id);
+        }
+        Ok(())
+    }
+
+    /// Sends queued keyframe requests.
+    fn handle_keyframe_requests(
+        conn: &mut RtspConnection,
+        state: &mut PlayingProj<'_>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Result<(), Error> {
+        let now = std::time::Instant::now();
+        while let Some(&stream_id) = state.keyframe_requests.first() {
+            let stream = &mut state.presentation.streams[stream_id];
+            let method = KeyframeRequestMethod::choose(
+                stream,
+                state.rtcp_sender.is_some(),
+                state.set_parameter_keyframe_request.is_some(),
+            );
+            let rtp_handler = match &mut stream.state {
+                StreamState::Playing { rtp_handler, .. } => rtp_handler,
+                _ => unreachable!("keyframe requests are only queued for playing streams"),
+            };
+            match conn.inner.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => bail!(e),
+                Poll::Pending => break,
+            }
+            state.keyframe_requests.remove(0);
+            match method {
+                Some(m @ KeyframeRequestMethod::Pli) | Some(m @ KeyframeRequestMethod::Fir) => {
+                    let media_ssrc = match rtp_handler.ssrc() {
+                        Some(s) => s,
+                        None => {
+                            debug!(
+                                "Skipping keyframe request for stream {} with unknown ssrc",
+                                stream_id
+                            );
+                            continue;
+                        }
+                    };
+                    let channel_id = state
+                        .channels
+                        .rtcp_channel(stream_id)
+                        .expect("playing streams have assigned channels");
+                    let pkt = state
+                        .rtcp_sender
+                        .as_mut()
+                        .expect("RTCP feedback requires RTCP sender")
+                        .keyframe_request(
+                            rtp_handler.report_block(now),
+                            media_ssrc,
+                            m == KeyframeRequestMethod::Fir,
+                        );
+                    conn.inner
+                        .start_send_unpin(rtsp_types::Message::Data(rtsp_types::Data::new(
+                            channel_id, pkt,
+                        )))
+                        .expect("encoding is infallible");
+                }
+                Some(KeyframeRequestMethod::SetParameter) => {
+                    if let Some(cseq) = *state.keyframe_request_cseq {
+                        debug!(
+                            "Skipping keyframe request for stream {}; SET_PARAMETER {} outstanding",
+                            stream_id, cseq
+                        );
+                        continue;
+                    }
+                    let body = state
+                        .set_parameter_keyframe_request
+                        .clone()
+                        .expect("SET_PARAMETER method requires body");
+                    let mut req = rtsp_types::Request::builder(
+                        rtsp_types::Method::SetParameter,
+                        rtsp_types::Version::V1_0,
+                    )
+                    .request_uri(
+                        stream
+                            .control
+                            .as_ref()
+                            .unwrap_or(&state.presentation.base_url)
+                            .clone(),
+                    )
+                    .header(rtsp_types::headers::SESSION, state.session_id.clone())
+                    .header(rtsp_types::headers::CONTENT_TYPE, "text/parameters")
+                    .build(body);
+                    *state.keyframe_request_cseq = Some(conn.fill_req(&mut req)?);
+                    conn.inner
+                        .start_send_unpin(rtsp_types::Message::Request(req))
+                        .expect("encoding is infallible");
+                }
+                None => unreachable!("keyframe requests are only queued with a method"),
+            }
+        }
+        match conn.inner.poll_flush_unpin(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => bail!(e),
+            Poll::Pending => *state.rtcp_flushing = true,
+        }
+        Ok(())
+    }
+
+    /// Sends RTCP NACKs for lost packets.
+    fn handle_nack_requests(
+        conn: &mut RtspConnection,
+        state: &mut PlayingProj<'_>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Result<(), Error> {
+        let sender = state
+            .rtcp_sender
+            .as_mut()
+            .expect("NACKs are only enabled with RTCP reports");
+        let now = std::time::Instant::now();
+        while let Some(&stream_id) = state.nack_requests.first() {
+            // Like receiver reports, NACKs are best-effort; they're sent when
+            // the connection is ready.
+            match conn.inner.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => bail!(e),
+                Poll::Pending => break,
+            }
+            state.nack_requests.remove(0);
+            let rtp_handler = match &mut state.presentation.streams[stream_id].state {
+                StreamState::Playing { rtp_handler, .. } => rtp_handler,
+                _ => unreachable!("NACKs are only queued for playing streams"),
+            };
+            let lost = rtp_handler.take_nacks();
+            let media_ssrc = match rtp_handler.ssrc() {
+                Some(s) if !lost.is_empty() => s,
+                _ => continue,
+            };
+            let channel_id = state
+                .channels
+                .rtcp_channel(stream_id)
+                .expect("playing streams have assigned channels");
+            let pkt = sender.nack(rtp_handler.report_block(now), media_ssrc, &lost);
+            conn.inner
+                .start_send_unpin(rtsp_types::Message::Data(rtsp_types::Data::new(
+                    channel_id, pkt,
+                )))
+                .expect("encoding is infallible");
+        }
+        match conn.inner.poll_flush_unpin(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => bail!(e),
+            Poll::Pending => *state.rtcp_flushing = true,
+        }
+        Ok(())
+    }
+
     fn handle_keepalive_timer(
         conn: &mut RtspConnection,
         state: &mut PlayingProj<'_>,
@@ -898,12 +1477,16 @@ impl Session<Playing> {
             KeepaliveState::Idle => {}
         }
 
-        // Currently the only outbound data should be keepalives, and the previous one
-        // has already been flushed, so there's no reason the Sink shouldn't be ready.
-        if matches!(conn.inner.poll_ready_unpin(cx), Poll::Pending) {
-            bail!(ErrorInt::Internal(
-                "Unexpectedly not ready to send keepalive".into()
-            ));
+        // The previous keepalive has already been flushed, but RTCP receiver
+        // reports may not have been. If so, defer the keepalive; the expired
+        // timer will fire again on a later poll, after the Sink is ready.
+        match conn.inner.poll_ready_unpin(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => bail!(e),
+            Poll::Pending => {
+                debug!("Connection not ready; deferring keepalive");
+                return Ok(());
+            }
         }
 
         // Send a new one and reset the timer.
@@ -933,6 +1516,60 @@ impl Session<Playing> {
         Ok(())
     }
 
+    /// Sends a RTCP receiver report on the RTCP channel of each playing stream.
+    fn handle_rtcp_timer(
+        conn: &mut RtspConnection,
+        state: &mut PlayingProj<'_>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Result<(), Error> {
+        let sender = state
+            .rtcp_sender
+            .as_mut()
+            .expect("RTCP timer is only polled when reports are enabled");
+        let now = std::time::Instant::now();
+        for (i, s) in state.presentation.streams.iter_mut().enumerate() {
+            let rtp_handler = match s.state {
+                StreamState::Playing {
+                    ref mut rtp_handler,
+                    ..
+                } => rtp_handler,
+                _ => continue,
+            };
+            let channel_id = state
+                .channels
+                .rtcp_channel(i)
+                .expect("playing streams have assigned channels");
+
+            // Reports are best-effort. Rather than buffer them, skip this round
+            // if the connection isn't ready to accept more data.
+            match conn.inner.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => bail!(e),
+                Poll::Pending => {
+                    debug!("Connection not ready; skipping RTCP receiver reports");
+                    break;
+                }
+            }
+            let pkt = sender.receiver_report(rtp_handler.report_block(now));
+            conn.inner
+                .start_send_unpin(rtsp_types::Message::Data(rtsp_types::Data::new(
+                    channel_id, pkt,
+                )))
+                .expect("encoding is infallible");
+        }
+        match conn.inner.poll_flush_unpin(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => bail!(e),
+            Poll::Pending => *state.rtcp_flushing = true,
+        }
+
+        state
+            .rtcp_timer
+            .as_mut()
+            .reset(tokio::time::Instant::now() + sender.next_interval());
+        Ok(())
+    }
+
     fn handle_response(
         state: &mut PlayingProj<'_>,
         conn: &RtspConnection,
@@ -946,8 +1583,18 @@ impl Session<Playing> {
             *state.keepalive_state = KeepaliveState::Idle;
             return Ok(());
         }
+        if state.keyframe_request_cseq.is_some()
+            && parse::get_cseq(&response) == *state.keyframe_request_cseq
+        {
+            *state.keyframe_request_cseq = None;
+            if !response.status().is_success() {
+                warn!("Keyframe request failed with status {}", response.status());
+            }
+            return Ok(());
+        }
 
-        // The only response we expect in this state is to our keepalive request.
+        // The only responses we expect in this state are to our keepalive and
+        // keyframe requests.
         bail!(ErrorInt::RtspFramingError {
             conn_ctx: *conn.inner.ctx(),
             msg_ctx: *msg_ctx,
@@ -989,38 +1636,71 @@ impl Session<Playing> {
             ),
         };
         match m.channel_type {
-            ChannelType::Rtp => Ok(rtp_handler.rtp(
-                &conn.options,
-                conn.inner.ctx(),
-                msg_ctx,
-                &mut timeline,
-                channel_id,
-                m.stream_i,
-                data.into_body(),
-            )?),
-            ChannelType::Rtcp => rtp_handler
-                .rtcp(
+            ChannelType::Rtp => {
+                let item = rtp_handler.rtp(
                     &conn.options,
+                    conn.inner.ctx(),
                     msg_ctx,
                     &mut timeline,
+                    channel_id,
                     m.stream_i,
                     data.into_body(),
-                )
-                .map_err(|description| {
-                    wrap!(ErrorInt::RtspDataMessageError {
-                        conn_ctx: *conn.inner.ctx(),
-                        msg_ctx: *msg_ctx,
-                        channel_id,
-                        stream_id: m.stream_i,
-                        description,
-                    })
-                }),
+                )?;
+                if rtp_handler.has_queued() {
+                    *state.pulling = Some(m.stream_i);
+                }
+                if rtp_handler.has_nacks() && !state.nack_requests.contains(&m.stream_i) {
+                    state.nack_requests.push(m.stream_i);
+                }
+                Ok(item)
+            }
+            ChannelType::Rtcp => {
+                let item = rtp_handler
+                    .rtcp(
+                        &conn.options,
+                        msg_ctx,
+                        &mut timeline,
+                        m.stream_i,
+                        data.into_body(),
+                    )
+                    .map_err(|description| {
+                        wrap!(ErrorInt::RtspDataMessageError {
+                            conn_ctx: *conn.inner.ctx(),
+                            msg_ctx: *msg_ctx,
+                            channel_id,
+                            stream_id: m.stream_i,
+                            description,
+                        })
+                    })?;
+                if *state.sync_via_sender_reports
+                    && matches!(&item, Some(PacketItem::Rtcp(p)) if p.rtp_timestamp.is_some())
+                {
+                    rtp_handler.align(m.stream_i, &mut timeline, state.sync_epoch);
+                }
+                Ok(item)
+            }
         }
     }
 
     pub fn streams(&self) -> &[Stream] {
         &self.state.presentation.streams
     }
+
+    /// Returns a snapshot of reception statistics for the given stream, or
+    /// `None` if it isn't playing.
+    pub fn stats(&self, stream_id: usize) -> Option<rtp::StreamStats> {
+        match &self.state.presentation.streams.get(stream_id)?.state {
+            StreamState::Playing {
+                timeline,
+                rtp_handler,
+            } => {
+                let mut stats = rtp_handler.stats(timeline.clock_rate(), std::time::Instant::now());
+                stats.estimated_clock_rate = timeline.estimated_clock_rate();
+                Some(stats)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl futures::Stream for Session<Playing> {
@@ -1033,7 +1713,31 @@ impl futures::Stream for Session<Playing> {
         let this = self.project();
         let mut state = this.state.project();
         loop {
-            // First try receiving data. Let this starve keepalive handling; if we can't keep up,
+            // First return any items queued by the last packet.
+            if let Some(stream_i) = *state.pulling {
+                if let StreamState::Playing { rtp_handler, .. } =
+                    &mut state.presentation.streams[stream_i].state
+                {
+                    if let Some(item) = rtp_handler.pull() {
+                        return Poll::Ready(Some(Ok(item)));
+                    }
+                }
+                *state.pulling = None;
+            }
+
+            // Then send any requested keyframe requests and NACKs.
+            if !state.keyframe_requests.is_empty() {
+                if let Err(e) = Session::handle_keyframe_requests(this.conn, &mut state, cx) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+            if !state.nack_requests.is_empty() {
+                if let Err(e) = Session::handle_nack_requests(this.conn, &mut state, cx) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+
+            // Then try receiving data. Let this starve keepalive handling; if we can't keep up,
             // the server should probably drop us.
             match Pin::new(&mut this.conn.inner).poll_next(cx) {
                 Poll::Ready(Some(Ok(msg))) => match msg.msg {
@@ -1067,10 +1771,23 @@ impl futures::Stream for Session<Playing> {
                 Session::handle_keepalive_timer(this.conn, &mut state, cx)?;
             }
 
-            // Then finish flushing the current keepalive if necessary.
-            if let KeepaliveState::Flushing(cseq) = state.keepalive_state {
+            // Then check if it's time for new RTCP receiver reports.
+            if state.rtcp_sender.is_some()
+                && matches!(state.rtcp_timer.as_mut().poll(cx), Poll::Ready(()))
+            {
+                Session::handle_rtcp_timer(this.conn, &mut state, cx)?;
+            }
+
+            // Then finish flushing the current keepalive and/or RTCP reports if necessary.
+            if *state.rtcp_flushing || matches!(state.keepalive_state, KeepaliveState::Flushing(_))
+            {
                 match this.conn.inner.poll_flush_unpin(cx) {
-                    Poll::Ready(Ok(())) => *state.keepalive_state = KeepaliveState::Waiting(*cseq),
+                    Poll::Ready(Ok(())) => {
+                        if let KeepaliveState::Flushing(cseq) = *state.keepalive_state {
+                            *state.keepalive_state = KeepaliveState::Waiting(cseq);
+                        }
+                        *state.rtcp_flushing = false;
+                    }
                     Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Error(Box::new(e))))),
                     Poll::Pending => {}
                 }
@@ -1084,7 +1801,9 @@ impl futures::Stream for Session<Playing> {
 
 enum DemuxedState {
     Waiting,
-    Pulling(usize),
+
+    /// Pulling from the depacketizer of the given stream and format index.
+    Pulling(usize, usize),
     Fused,
 }
 
@@ -1096,6 +1815,30 @@ pub struct Demuxed {
     session: Session<Playing>,
 }
 
+impl Demuxed {
+    /// Returns a snapshot of reception statistics for the given stream, or
+    /// `None` if it isn't playing.
+    ///
+    /// Unlike [`Session::stats`], this includes frames discarded by the depacketizer.
+    pub fn stats(&self, stream_id: usize) -> Option<rtp::StreamStats> {
+        let mut stats = self.session.stats(stream_id)?;
+        stats.frames_discarded = self.session.state.presentation.streams[stream_id]
+            .formats
+            .iter()
+            .filter_map(|f| f.depacketizer.as_ref().ok())
+            .map(|d| d.frames_discarded())
+            .sum();
+        Some(stats)
+    }
+
+    /// Asks the sender of the given stream for a keyframe.
+    ///
+    /// See [`Session::request_keyframe`].
+    pub fn request_keyframe(self: Pin<&mut Self>, stream_id: usize) -> Result<(), Error> {
+        self.project().session.request_keyframe(stream_id)
+    }
+}
+
 impl futures::Stream for Demuxed {
     type Item = Result<CodecItem, Error>;
 
@@ -1105,26 +1848,54 @@ impl futures::Stream for Demuxed {
     ) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
         loop {
-            let (stream_id, pkt) = match this.state {
+            let (stream_id, format_i, pkt) = match this.state {
                 DemuxedState::Waiting => match ready!(this.session.as_mut().poll_next(cx)) {
-                    Some(Ok(PacketItem::RtpPacket(p))) => (p.stream_id, Some(p)),
-                    Some(Ok(PacketItem::SenderReport(p))) => {
-                        return Poll::Ready(Some(Ok(CodecItem::SenderReport(p))))
+                    Some(Ok(PacketItem::RtpPacket(p))) => {
+                        let stream = &this.session.state.presentation.streams[p.stream_id];
+                        match stream
+                            .format_for(p.payload_type)
+                            .filter(|&i| stream.formats[i].depacketizer.is_ok())
+                        {
+                            Some(i) => (p.stream_id, i, Some(p)),
+                            None => {
+                                debug!(
+                                    "Dropping packet on stream {} with unsupported payload type {}",
+                                    p.stream_id, p.payload_type
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    Some(Ok(PacketItem::Rtcp(p))) => {
+                        return Poll::Ready(Some(Ok(CodecItem::Rtcp(p))))
+                    }
+                    Some(Ok(PacketItem::Discontinuity(d))) => {
+                        let session = this.session.as_mut().project();
+                        let playing = session.state.project();
+                        let stream = &mut playing.presentation.streams[d.stream_id];
+                        for f in &mut stream.formats {
+                            if let Ok(depacketizer) = &mut f.depacketizer {
+                                depacketizer.reset();
+                            }
+                        }
+                        return Poll::Ready(Some(Ok(CodecItem::Discontinuity(d))));
                     }
                     Some(Err(e)) => return Poll::Ready(Some(Err(e))),
                     None => return Poll::Ready(None),
                 },
-                DemuxedState::Pulling(stream_id) => (*stream_id, None),
+                DemuxedState::Pulling(stream_id, format_i) => (*stream_id, *format_i, None),
                 DemuxedState::Fused => return Poll::Ready(None),
             };
             let session = this.session.as_mut().project();
             let playing = session.state.project();
-            let depacketizer = match &mut playing.presentation.streams[stream_id].depacketizer {
+            let stream = &mut playing.presentation.streams[stream_id];
+            let depacketizer = match &mut stream.formats[format_i].depacketizer {
                 Ok(d) => d,
                 Err(_) => unreachable!("depacketizer was Ok"),
             };
             let conn_ctx = session.conn.inner.ctx();
             if let Some(p) = pkt {
+                let discarded = depacketizer.frames_discarded();
                 let msg_ctx = p.ctx;
                 let channel_id = p.channel_id;
                 let stream_id = p.stream_id;
@@ -1141,10 +1912,56 @@ impl futures::Stream for Demuxed {
                         description,
                     })
                 })?;
+                if *playing.request_keyframe_on_loss
+                    && depacketizer.frames_discarded() > discarded
+                    && !playing.keyframe_requests.contains(&stream_id)
+                {
+                    if KeyframeRequestMethod::choose(
+                        stream,
+                        playing.rtcp_sender.is_some(),
+                        playing.set_parameter_keyframe_request.is_some(),
+                    )
+                    .is_some()
+                    {
+                        playing.keyframe_requests.push(stream_id);
+                    } else {
+                        debug!(
+                            "Frame discarded on stream {} but no keyframe request method available",
+                            stream_id
+                        );
+                    }
+                }
             }
+            let depacketizer = match &mut stream.formats[format_i].depacketizer {
+                Ok(d) => d,
+                Err(_) => unreachable!("depacketizer was Ok"),
+            };
             match depacketizer.pull(conn_ctx) {
-                Ok(Some(item)) => {
-                    *this.state = DemuxedState::Pulling(stream_id);
+                Ok(Some(mut item)) => {
+                    let frame = match &mut item {
+                        CodecItem::VideoFrame(f) => {
+                            Some((f.timestamp, &mut f.capture_time, &mut f.corrected_timestamp))
+                        }
+                        CodecItem::AudioFrame(f) => {
+                            Some((f.timestamp, &mut f.capture_time, &mut f.corrected_timestamp))
+                        }
+                        CodecItem::MessageFrame(f) => {
+                            Some((f.timestamp, &mut f.capture_time, &mut f.corrected_timestamp))
+                        }
+                        _ => None,
+                    };
+                    if let (
+                        Some((timestamp, capture_time, corrected_timestamp)),
+                        StreamState::Playing {
+                            timeline,
+                            rtp_handler,
+                        },
+                    ) = (frame, &stream.state)
+                    {
+                        *capture_time = rtp_handler.capture_time(timestamp, *capture_time);
+                        *corrected_timestamp = timeline.corrected(timestamp);
+                    }
+                    *this.state = DemuxedState::Pulling(stream_id, format_i);
                     return Poll::Ready(Some(Ok(item)));
                 }
                 Ok(None) => {
@@ -1265,6 +2082,12 @@ mod tests {
                     }
                     o => panic!("unexpected item: {:#?}", o),
                 }
+                let stats = session.stats(0).unwrap();
+                assert_eq!(stats.ssrc, Some(0xdcc4a0d8));
+                assert_eq!(stats.packets_received, 1);
+                assert_eq!(stats.bytes_received, 11);
+                assert_eq!(stats.packets_lost, 0);
+                assert!(session.stats(1).is_none());
             },
             async {
                 let pkt = b"\x80\x60\x41\xd4\x00\x00\x00\x00\xdc\xc4\xa0\xd8hello world";
@@ -1375,6 +2198,103 @@ mod tests {
         );
     }
 
+    /// Tests falling back to `SET_PARAMETER` keyframe requests when the SDP
+    /// doesn't advertise RTCP feedback.
+    #[tokio::test]
+    async fn set_parameter_keyframe_request() {
+        let (conn, mut server) = connect_to_mock(SessionOptions::default()).await;
+        let url = Url::parse("rtsp://192.168.5.206:554/h264Preview_01_main").unwrap();
+
+        // DESCRIBE.
+        let (session, _) = tokio::join!(
+            Session::describe_with_conn(conn, url),
+            req_reply(
+                &mut server,
+                rtsp_types::Method::Describe,
+                response(include_bytes!("testdata/reolink_describe.txt"))
+            ),
+        );
+        let mut session = session.unwrap();
+        assert!(session.streams()[0].rtcp_feedback.is_empty());
+
+        // SETUP.
+        tokio::join!(
+            async {
+                session.setup(0).await.unwrap();
+            },
+            req_reply(
+                &mut server,
+                rtsp_types::Method::Setup,
+                response(include_bytes!("testdata/reolink_setup.txt"))
+            ),
+        );
+
+        // PLAY.
+        let (session, _) = tokio::join!(
+            session.play(
+                PlayOptions::default()
+                    .set_parameter_keyframe_request("request_iframe: 1\r\n".to_owned())
+            ),
+            req_reply(
+                &mut server,
+                rtsp_types::Method::Play,
+                response(include_bytes!("testdata/reolink_play.txt"))
+            ),
+        );
+        let session = session.unwrap();
+        tokio::pin!(session);
+        session.as_mut().request_keyframe(1).unwrap_err();
+        session.as_mut().request_keyframe(0).unwrap();
+        session.as_mut().request_keyframe(0).unwrap();
+
+        // The request is sent once on the next poll, and its response is consumed.
+        tokio::join!(
+            async {
+                match session.next().await {
+                    Some(Ok(PacketItem::RtpPacket(p))) => {
+                        assert_eq!(&p.payload[..], b"hello world");
+                    }
+                    o => panic!("unexpected item: {:#?}", o),
+                }
+            },
+            async {
+                let req = loop {
+                    match server.next().await.unwrap().unwrap().msg {
+                        rtsp_types::Message::Request(r) => break r,
+                        _ => continue,
+                    }
+                };
+                assert_eq!(req.method(), rtsp_types::Method::SetParameter);
+                assert_eq!(
+                    req.header(&rtsp_types::headers::CONTENT_TYPE)
+                        .unwrap()
+                        .as_str(),
+                    "text/parameters"
+                );
+                assert_eq!(&req.body()[..], b"request_iframe: 1\r\n");
+                let cseq = req.header(&rtsp_types::headers::CSEQ).unwrap();
+                let mut resp = rtsp_types::Response::builder(
+                    rtsp_types::Version::V1_0,
+                    rtsp_types::StatusCode::Ok,
+                )
+                .build(Bytes::new());
+                resp.insert_header(rtsp_types::headers::CSEQ, cseq.as_str());
+                server
+                    .send(rtsp_types::Message::Response(resp))
+                    .await
+                    .unwrap();
+                let pkt = b"\x80\x60\x41\xd4\x00\x00\x00\x00\xdc\xc4\xa0\xd8hello world";
+                server
+                    .send(rtsp_types::Message::Data(rtsp_types::Data::new(
+                        0,
+                        Bytes::from_static(pkt),
+                    )))
+                    .await
+                    .unwrap();
+            },
+        );
+    }
+
     // See with: cargo test -- --nocapture client::tests::print_sizes
     #[test]
     fn print_sizes() {
@@ -1390,8 +2310,8 @@ mod tests {
             ("PacketItem", std::mem::size_of::<PacketItem>()),
             ("rtp::Packet", std::mem::size_of::<rtp::Packet>()),
             (
-                "rtp::SenderReport",
-                std::mem::size_of::<rtp::SenderReport>(),
+                "rtp::ReceivedCompoundPacket",
+                std::mem::size_of::<rtp::ReceivedCompoundPacket>(),
             ),
         ] {
             println!("{:-40} {:4}", name, size);
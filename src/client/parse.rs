@@ -224,7 +224,11 @@ pub(crate) fn get_cseq(response: &rtsp_types::Response<Bytes>) -> Option<u32> {
 /// Parses a [MediaDescription] to a [Stream].
 /// On failure, returns an error which is expected to be supplemented with
 /// the [MediaDescription] debug string and packed into a `RtspResponseError`.
-fn parse_media(base_url: &Url, media_description: &MediaDescription) -> Result<Stream, String> {
+fn parse_media(
+    base_url: &Url,
+    session_extmap: &super::rtp::ExtMap,
+    media_description: &MediaDescription,
+) -> Result<Stream, String> {
     let media = media_description.media_name.media.clone();
 
     // https://tools.ietf.org/html/rfc8866#section-5.14 says "If the <proto>
@@ -242,31 +246,17 @@ fn parse_media(base_url: &Url, media_description: &MediaDescription) -> Result<S
         return Err("Expected RTP-based proto".into());
     }
 
-    // RFC 8866 continues: "When a list of payload type numbers is given,
-    // this implies that all of these payload formats MAY be used in the
-    // session, but the first of these formats SHOULD be used as the default
-    // format for the session." Just use the first until we find a stream
-    // where this isn't the right thing to do.
-    let rtp_payload_type_str = media_description
-        .media_name
-        .formats
-        .first()
-        .ok_or_else(|| "missing RTP payload type".to_string())?;
-    let rtp_payload_type = u8::from_str_radix(rtp_payload_type_str, 10)
-        .map_err(|_| format!("invalid RTP payload type {:?}", rtp_payload_type_str))?;
-    if (rtp_payload_type & 0x80) != 0 {
-        return Err(format!("invalid RTP payload type {}", rtp_payload_type));
-    }
-
     // Capture interesting attributes.
     // RFC 8866: "For dynamic payload type assignments, the "a=rtpmap:"
     // attribute (see Section 6.6) SHOULD be used to map from an RTP payload
     // type number to a media encoding name that identifies the payload
     // format. The "a=fmtp:" attribute MAY be used to specify format
     // parameters (see Section 6.15)."
-    let mut rtpmap = None;
-    let mut fmtp = None;
+    let mut rtpmaps = Vec::new();
+    let mut fmtps = Vec::new();
+    let mut rtcp_feedback = Vec::new();
     let mut control = None;
+    let mut extmap = super::rtp::ExtMap::default();
     for a in &media_description.attributes {
         if a.key == "rtpmap" {
             let v = a
@@ -281,33 +271,103 @@ fn parse_media(base_url: &Url, media_description: &MediaDescription) -> Result<S
             // clock-rate = integer
             // encoding-params = channels
             // channels = integer
-            let (rtpmap_payload_type, v) = v
-                .split_once(' ')
-                .ok_or_else(|| "invalid rtmap attribute".to_string())?;
-            if rtpmap_payload_type == rtp_payload_type_str {
-                rtpmap = Some(v);
-            }
+            rtpmaps.push(
+                v.split_once(' ')
+                    .ok_or_else(|| "invalid rtmap attribute".to_string())?,
+            );
         } else if a.key == "fmtp" {
             // Similarly starts with payload-type SP.
             let v = a
                 .value
                 .as_ref()
                 .ok_or_else(|| "fmtp attribute with no value".to_string())?;
-            let (fmtp_payload_type, v) = v
-                .split_once(' ')
-                .ok_or_else(|| "invalid fmtp attribute".to_string())?;
-            if fmtp_payload_type == rtp_payload_type_str {
-                fmtp = Some(v);
-            }
+            fmtps.push(
+                v.split_once(' ')
+                    .ok_or_else(|| "invalid fmtp attribute".to_string())?,
+            );
         } else if a.key == "control" {
             control = a
                 .value
                 .as_deref()
                 .map(|c| join_control(base_url, c))
                 .transpose()?;
+        } else if a.key == "extmap" {
+            add_extmap(&mut extmap, a.value.as_deref());
+        } else if a.key == "rtcp-fb" {
+            // https://datatracker.ietf.org/doc/html/rfc4585#section-4.2
+            // rtcp-fb-syntax = "a=rtcp-fb:" rtcp-fb-pt SP rtcp-fb-val CRLF
+            // rtcp-fb-pt = "*" / fmt
+            // Feedback is optional, so a malformed attribute is skipped.
+            match a.value.as_deref().and_then(|v| v.split_once(' ')) {
+                Some((fb_payload_type, v)) => {
+                    rtcp_feedback.push((fb_payload_type, v.trim().to_ascii_lowercase()))
+                }
+                None => log::warn!("Ignoring invalid rtcp-fb attribute {:?}", a.value),
+            }
+        }
+    }
+    extmap.inherit(session_extmap);
+
+    // RFC 8866 continues: "When a list of payload type numbers is given,
+    // this implies that all of these payload formats MAY be used in the
+    // session, but the first of these formats SHOULD be used as the default
+    // format for the session." Expose all of them, with the first selected.
+    // A format which can't be understood is an error only if it's the first.
+    let payload_types = &media_description.media_name.formats;
+    if payload_types.is_empty() {
+        return Err("missing RTP payload type".into());
+    }
+    let mut formats = Vec::with_capacity(payload_types.len());
+    for (i, payload_type) in payload_types.iter().enumerate() {
+        match parse_format(&media, payload_type, &rtpmaps, &fmtps, &rtcp_feedback) {
+            Ok(Some(f)) => formats.push(f),
+            Ok(None) if i > 0 => {}
+            Ok(None) => return Err("first RTP payload type is a retransmission format".into()),
+            Err(e) if i > 0 => debug!("Ignoring RTP payload type {}: {}", payload_type, e),
+            Err(e) => return Err(e),
         }
     }
 
+    let first = &formats[0];
+    Ok(Stream {
+        media,
+        encoding_name: first.encoding_name.clone(),
+        clock_rate: first.clock_rate,
+        rtp_payload_type: first.rtp_payload_type,
+        channels: first.channels,
+        rtcp_feedback: first.rtcp_feedback.clone(),
+        rtx_payload_type: first.rtx_payload_type,
+        formats,
+        format: 0,
+        control,
+        extmap,
+        state: super::StreamState::Uninit,
+    })
+}
+
+/// Parses the format with RTP payload type `payload_type` from the media
+/// description's attributes, returning `None` for retransmission formats.
+fn parse_format(
+    media: &str,
+    payload_type: &str,
+    rtpmaps: &[(&str, &str)],
+    fmtps: &[(&str, &str)],
+    rtcp_feedback: &[(&str, String)],
+) -> Result<Option<super::Format>, String> {
+    let rtp_payload_type = u8::from_str_radix(payload_type, 10)
+        .map_err(|_| format!("invalid RTP payload type {:?}", payload_type))?;
+    if (rtp_payload_type & 0x80) != 0 {
+        return Err(format!("invalid RTP payload type {}", rtp_payload_type));
+    }
+    let rtpmap = rtpmaps
+        .iter()
+        .find(|(pt, _)| *pt == payload_type)
+        .map(|(_, v)| *v);
+    let fmtp = fmtps
+        .iter()
+        .find(|(pt, _)| *pt == payload_type)
+        .map(|(_, v)| *v);
+
     let encoding_name;
     let clock_rate;
     let channels;
@@ -348,26 +408,66 @@ fn parse_media(base_url: &Url, media_description: &MediaDescription) -> Result<S
             if type_.media != media {
                 return Err(format!(
                     "SDP media type {} must match RTP payload type {:#?}",
-                    &media, type_
+                    media, type_
                 ));
             }
         }
     }
 
     let encoding_name = encoding_name.to_ascii_lowercase();
-    let depacketizer =
-        crate::codec::Depacketizer::new(&media, &encoding_name, clock_rate, channels, fmtp);
+    if encoding_name == "rtx" {
+        return Ok(None);
+    }
 
-    Ok(Stream {
-        media,
+    // https://datatracker.ietf.org/doc/html/rfc4588#section-8.1
+    // A retransmission format is a "rtx" format whose "apt" parameter names
+    // the original format.
+    let rtx_payload_type = fmtps
+        .iter()
+        .find(|(pt, v)| {
+            rtpmaps.iter().any(|(rtx_pt, rtpmap)| {
+                rtx_pt == pt
+                    && matches!(rtpmap.split_once('/'), Some((e, _)) if e.eq_ignore_ascii_case("rtx"))
+            }) && v.split(';').any(|p| {
+                matches!(p.trim().split_once('='),
+                         Some((k, apt)) if k == "apt" && apt == payload_type)
+            })
+        })
+        .and_then(|(pt, _)| u8::from_str_radix(pt, 10).ok())
+        .filter(|pt| pt & 0x80 == 0);
+
+    let rtcp_feedback = rtcp_feedback
+        .iter()
+        .filter(|(pt, _)| *pt == "*" || *pt == payload_type)
+        .map(|(_, v)| v.clone())
+        .collect();
+
+    let depacketizer =
+        crate::codec::Depacketizer::new(media, &encoding_name, clock_rate, channels, fmtp);
+    Ok(Some(super::Format {
+        rtp_payload_type,
         encoding_name,
         clock_rate,
-        rtp_payload_type,
-        depacketizer,
-        control,
         channels,
-        state: super::StreamState::Uninit,
-    })
+        rtcp_feedback,
+        rtx_payload_type,
+        depacketizer,
+    }))
+}
+
+/// Adds an `extmap` attribute's mapping, as in
+/// [RFC 8285 section 8](https://datatracker.ietf.org/doc/html/rfc8285#section-8).
+///
+/// A malformed or duplicate mapping only makes the corresponding header extension element
+/// uninterpretable, so it's logged and skipped rather than failing the whole DESCRIBE.
+fn add_extmap(extmap: &mut super::rtp::ExtMap, value: Option<&str>) {
+    let result = match value {
+        None => Err("extmap attribute with no value".to_owned()),
+        Some(v) => extmap.add(v),
+    };
+    if let Err(e) = result {
+        log::warn!("Ignoring {}", e);
+    }
 }
 
 /// Parses a successful RTSP `DESCRIBE` response into a [Presentation].
@@ -428,12 +528,20 @@ pub(crate) fn parse_describe(
     }
     let control = control.unwrap_or(request_url);
 
+    // Session-level extmap attributes apply to all media.
+    let mut session_extmap = super::rtp::ExtMap::default();
+    for a in &sdp.attributes {
+        if a.key == "extmap" {
+            add_extmap(&mut session_extmap, a.value.as_deref());
+        }
+    }
+
     let streams = sdp
         .media_descriptions
         .iter()
         .enumerate()
         .map(|(i, m)| {
-            parse_media(&base_url, &m)
+            parse_media(&base_url, &session_extmap, &m)
                 .map_err(|e| format!("Unable to parse stream {}: {}\n\n{:#?}", i, &e, &m))
         })
         .collect::<Result<Vec<Stream>, String>>()?;
@@ -703,7 +811,15 @@ mod tests {
         assert_eq!(p.streams[0].media, "video");
         assert_eq!(p.streams[0].encoding_name, "h265");
         assert_eq!(p.streams[0].rtp_payload_type, 98);
-        assert!(p.streams[0].parameters().is_none());
+        match p.streams[0].parameters().unwrap() {
+            Parameters::Video(v) => {
+                assert_eq!(v.rfc6381_codec(), "hvc1.1.6.L90.B0");
+                assert_eq!(v.pixel_dimensions(), (704, 480));
+                assert_eq!(v.pixel_aspect_ratio(), None);
+                assert_eq!(v.frame_rate(), Some((1, 12)));
+            }
+            _ => panic!(),
+        }
         assert_eq!(p.streams[1].media, "audio");
         assert_eq!(p.streams[1].encoding_name, "pcma");
         assert_eq!(p.streams[1].rtp_payload_type, 8);
@@ -713,6 +829,72 @@ mod tests {
         };
     }
 
+    #[test]
+    fn synthetic() {
+        // Synthetic example with session- and media-level extmap attributes
+        // (including malformed and duplicate ones, which are skipped), RTCP
+        // feedback (including malformed attributes, also skipped), a
+        // retransmission format, and multiple formats per media section.
+        let p = parse_describe(
+            "rtsp://192.168.5.2/extmap",
+            include_bytes!("testdata/synthetic_describe.txt"),
+        )
+        .unwrap();
+        assert_eq!(p.streams.len(), 3);
+        let video = &p.streams[0].extmap;
+        assert_eq!(video.uri(1), Some("urn:ietf:params:rtp-hdrext:ntp-64"));
+        assert_eq!(
+            video.uri(2),
+            Some("http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time")
+        );
+        assert_eq!(video.uri(3), Some("urn:3gpp:video-orientation"));
+        assert_eq!(video.iter().count(), 3);
+        assert_eq!(
+            p.streams[0].rtcp_feedback,
+            vec!["nack", "nack pli", "ccm fir"]
+        );
+        assert!(p.streams[1].rtcp_feedback.is_empty());
+        assert_eq!(p.streams[0].rtx_payload_type, Some(97));
+        assert_eq!(p.streams[1].rtx_payload_type, None);
+
+        // The video stream offers two H.264 packetization modes; the first is
+        // selected. Packets of either are routed to the matching format;
+        // packets of payload types not offered are dropped.
+        let formats = p.streams[0].formats();
+        assert_eq!(
+            formats
+                .iter()
+                .map(|f| f.rtp_payload_type)
+                .collect::<Vec<_>>(),
+            vec![96, 98]
+        );
+        assert_eq!(formats[1].encoding_name, "h264");
+        assert_eq!(formats[1].rtcp_feedback, vec!["ccm fir"]);
+        assert_eq!(formats[1].rtx_payload_type, None);
+        assert!(matches!(
+            formats[1].parameters(),
+            Some(Parameters::Video(_))
+        ));
+        assert_eq!(p.streams[0].format_for(96), Some(0));
+        assert_eq!(p.streams[0].format_for(98), Some(1));
+        assert_eq!(p.streams[0].format_for(99), None);
+
+        // The audio stream offers PCMU and telephone events with the same
+        // clock rate; the latter can't be depacketized.
+        let audio = &p.streams[2];
+        assert_eq!(audio.encoding_name, "pcmu");
+        assert_eq!(audio.formats()[1].encoding_name, "telephone-event");
+        assert_eq!(audio.formats()[1].clock_rate, 8_000);
+        assert!(audio.formats()[1].parameters().is_none());
+        assert_eq!(audio.format_for(101), Some(1));
+        let metadata = &p.streams[1].extmap;
+        assert_eq!(metadata.iter().count(), 1);
+        assert_eq!(
+            metadata.id("http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time"),
+            Some(2)
+        );
+    }
+
     #[test]
     fn hikvision() {
         // DESCRIBE.
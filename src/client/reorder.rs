@@ -0,0 +1,179 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Reordering of RTP packets by sequence number, as needed when the transport
+//! (eg UDP) may deliver them out of order.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Buffers packets until they can be released in sequence number order.
+///
+/// A missing packet is considered lost once either the first packet after it
+/// has been held for `max_delay` or more than `max_packets` packets are held.
+/// Deadlines are evaluated as packets arrive; there's no timer, so packets may
+/// be held longer than `max_delay` if the stream stalls.
+///
+/// Packets which arrive after their sequence number has already been released
+/// or skipped, and duplicates of packets already held, are dropped.
+#[derive(Debug)]
+pub(crate) struct ReorderBuffer<T> {
+    max_delay: Duration,
+    max_packets: usize,
+
+    /// The extended (cycle-counting) sequence number of the next packet to
+    /// release, or `None` before the first release.
+    next: Option<u64>,
+
+    /// Held packets by extended sequence number, with their arrival time.
+    pending: BTreeMap<u64, (Instant, T)>,
+}
+
+/// The extended sequence number assigned to the first packet. It's offset from
+/// zero so that earlier packets can still be placed before it.
+const INITIAL_EXT: u64 = 1 << 32;
+
+impl<T> ReorderBuffer<T> {
+    /// Creates a new buffer which will next release sequence number `next_seq`, if known.
+    pub(crate) fn new(max_delay: Duration, max_packets: usize, next_seq: Option<u16>) -> Self {
+        ReorderBuffer {
+            max_delay,
+            max_packets,
+            next: next_seq.map(|s| INITIAL_EXT + u64::from(s)),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the maximum number of packets held while waiting for a missing one.
+    pub(crate) fn max_packets(&self) -> usize {
+        self.max_packets
+    }
+
+    /// Adds a packet, returning false if it was dropped as late or a duplicate.
+    pub(crate) fn push(&mut self, seq: u16, received: Instant, item: T) -> bool {
+        let reference = match self.next.or_else(|| self.pending.keys().next().copied()) {
+            None => {
+                self.pending
+                    .insert(INITIAL_EXT + u64::from(seq), (received, item));
+                return true;
+            }
+            Some(r) => r,
+        };
+        let delta = i64::from(seq.wrapping_sub(reference as u16) as i16);
+        let ext = (reference as i64 + delta) as u64;
+        if matches!(self.next, Some(n) if ext < n) || self.pending.contains_key(&ext) {
+            return false;
+        }
+        self.pending.insert(ext, (received, item));
+        true
+    }
+
+    /// Returns the next packet which is ready as of `now`, along with the
+    /// number of packets lost immediately before it.
+    pub(crate) fn pull(&mut self, now: Instant) -> Option<(u16, T)> {
+        let &head = self.pending.keys().next()?;
+        let ready = self.next == Some(head)
+            || self.pending.len() > self.max_packets
+            || self
+                .pending
+                .values()
+                .any(|&(received, _)| now.saturating_duration_since(received) >= self.max_delay);
+        if !ready {
+            return None;
+        }
+        self.pop_head()
+    }
+
+    /// Returns the next held packet, regardless of deadline.
+    pub(crate) fn flush(&mut self) -> Option<(u16, T)> {
+        self.pop_head()
+    }
+
+    /// Discards held packets and forgets the expected sequence number, as when
+    /// the RTP source changes.
+    pub(crate) fn reset(&mut self) {
+        self.next = None;
+        self.pending.clear();
+    }
+
+    fn pop_head(&mut self) -> Option<(u16, T)> {
+        let &head = self.pending.keys().next()?;
+        let (_, item) = self.pending.remove(&head).expect("head exists");
+        let loss = self.next.map(|n| head - n).unwrap_or(0);
+        self.next = Some(head + 1);
+        Some((loss as u16, item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes each of `seqs` 10 ms apart, returning what was released after each.
+    fn run(buf: &mut ReorderBuffer<u16>, start: Instant, seqs: &[u16]) -> Vec<Vec<(u16, u16)>> {
+        seqs.iter()
+            .enumerate()
+            .map(|(i, &seq)| {
+                let now = start + Duration::from_millis(10 * i as u64);
+                buf.push(seq, now, seq);
+                std::iter::from_fn(|| buf.pull(now)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reorder() {
+        let start = Instant::now();
+        let mut buf = ReorderBuffer::new(Duration::from_millis(100), 8, Some(0xfffe));
+        assert_eq!(
+            run(&mut buf, start, &[0xfffe, 0x0000, 0xffff, 0x0001]),
+            vec![
+                vec![(0, 0xfffe)],
+                vec![],
+                vec![(0, 0xffff), (0, 0x0000)],
+                vec![(0, 0x0001)],
+            ]
+        );
+    }
+
+    #[test]
+    fn late_and_duplicate() {
+        let start = Instant::now();
+        let mut buf = ReorderBuffer::new(Duration::from_millis(100), 8, None);
+        assert!(buf.push(5, start, 5));
+        assert!(!buf.push(5, start, 5));
+        assert_eq!(buf.flush(), Some((0, 5)));
+        assert!(!buf.push(5, start, 5));
+        assert!(!buf.push(4, start, 4));
+        assert!(buf.push(6, start, 6));
+    }
+
+    #[test]
+    fn loss_after_deadline() {
+        let start = Instant::now();
+        let mut buf = ReorderBuffer::new(Duration::from_millis(25), 8, Some(1));
+
+        // 2 is never received. 3 waits for it until 25 ms after its arrival.
+        let released = run(&mut buf, start, &[1, 3, 4, 5, 6]);
+        assert_eq!(
+            released,
+            vec![
+                vec![(0, 1)],
+                vec![],
+                vec![],
+                vec![],
+                vec![(1, 3), (0, 4), (0, 5), (0, 6)],
+            ]
+        );
+    }
+
+    #[test]
+    fn loss_after_max_packets() {
+        let start = Instant::now();
+        let mut buf = ReorderBuffer::new(Duration::from_secs(10), 2, Some(1));
+
+        // 1 and 2 are never received. 3 waits for them until a third packet is held.
+        let released = run(&mut buf, start, &[3, 4, 5]);
+        assert_eq!(released, vec![vec![], vec![], vec![(2, 3), (0, 4), (0, 5)]]);
+    }
+}
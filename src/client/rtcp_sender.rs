@@ -0,0 +1,125 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Sends RTCP packets during `PLAY`; see
+//! [RFC 3550 section 6](https://datatracker.ietf.org/doc/html/rfc3550#section-6).
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::rtcp::ReportBlock;
+
+/// The minimum interval between RTCP reports, as recommended in
+/// [RFC 3550 section 6.2](https://datatracker.ietf.org/doc/html/rfc3550#section-6.2).
+const MIN_INTERVAL_SECS: f64 = 5.;
+
+/// Builds and schedules Retina's RTCP packets for a session.
+pub(super) struct RtcpSender {
+    /// Retina's own SSRC, used as the sender of its RTCP packets.
+    ssrc: u32,
+
+    /// The canonical name sent in each source description.
+    cname: String,
+
+    /// State of a xorshift64* generator used to randomize report intervals.
+    rng: u64,
+
+    /// True until the first interval has been computed.
+    initial: bool,
+
+    /// The command sequence number of the next Full Intra Request.
+    fir_seq: u8,
+}
+
+impl RtcpSender {
+    pub(super) fn new(conn_ctx: &crate::ConnectionContext) -> Self {
+        // RandomState is seeded randomly; it's good enough for picking an SSRC
+        // and jittering intervals without another dependency. xorshift's state
+        // must be non-zero.
+        let rng = RandomState::new().build_hasher().finish() | 1;
+        let mut s = RtcpSender {
+            ssrc: 0,
+            cname: format!("retina@{}", conn_ctx.local_addr.ip()),
+            rng,
+            initial: true,
+            fir_seq: 0,
+        };
+        s.ssrc = (s.next_random() >> 32) as u32;
+        s
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns the time to wait before sending the next reports.
+    ///
+    /// This follows the computation in
+    /// [RFC 3550 section 6.3.1](https://datatracker.ietf.org/doc/html/rfc3550#section-6.3.1)
+    /// for a session with two members: the server, which is the only sender,
+    /// and Retina. Retina doesn't know the session bandwidth, so the
+    /// deterministic interval is always the minimum, halved before the first
+    /// report. It's then randomized to between 0.5 and 1.5 times that and
+    /// divided by `e - 3/2`.
+    pub(super) fn next_interval(&mut self) -> Duration {
+        let mut t = MIN_INTERVAL_SECS;
+        if std::mem::replace(&mut self.initial, false) {
+            t /= 2.;
+        }
+        let r = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        Duration::from_secs_f64(t * (0.5 + r) / (std::f64::consts::E - 1.5))
+    }
+
+    /// Returns a compound RTCP packet holding a receiver report (with the
+    /// given report block, if any) and a source description.
+    pub(super) fn receiver_report(&self, block: Option<ReportBlock>) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.write_report(block, &mut buf);
+        buf.freeze()
+    }
+
+    /// Returns a compound RTCP packet asking `media_ssrc` for a keyframe: a
+    /// receiver report and source description, followed by a Full Intra
+    /// Request if `fir` or a Picture Loss Indication otherwise.
+    pub(super) fn keyframe_request(
+        &mut self,
+        block: Option<ReportBlock>,
+        media_ssrc: u32,
+        fir: bool,
+    ) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.write_report(block, &mut buf);
+        if fir {
+            crate::rtcp::write_fir(self.ssrc, media_ssrc, self.fir_seq, &mut buf);
+            self.fir_seq = self.fir_seq.wrapping_add(1);
+        } else {
+            crate::rtcp::write_pli(self.ssrc, media_ssrc, &mut buf);
+        }
+        buf.freeze()
+    }
+
+    /// Returns a compound RTCP packet asking `media_ssrc` to retransmit the
+    /// `lost` sequence numbers: a receiver report and source description,
+    /// followed by a Generic NACK.
+    pub(super) fn nack(&self, block: Option<ReportBlock>, media_ssrc: u32, lost: &[u16]) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.write_report(block, &mut buf);
+        crate::rtcp::write_nack(self.ssrc, media_ssrc, lost, &mut buf);
+        buf.freeze()
+    }
+
+    fn write_report(&self, block: Option<ReportBlock>, buf: &mut BytesMut) {
+        let blocks = match block {
+            Some(ref b) => std::slice::from_ref(b),
+            None => &[],
+        };
+        crate::rtcp::write_receiver_report(self.ssrc, blocks, buf);
+        crate::rtcp::write_sdes_cname(self.ssrc, &self.cname, buf);
+    }
+}
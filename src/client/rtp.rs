@@ -6,7 +6,14 @@
 use bytes::{Buf, Bytes};
 use log::debug;
 use pretty_hex::PrettyHex;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::num::{NonZeroU16, NonZeroU32};
+use std::time::{Duration, Instant, SystemTime};
 
+use super::reorder::ReorderBuffer;
+use super::wall_clock::WallClock;
+use super::SequencePolicy;
 use crate::client::PacketItem;
 use crate::{Error, ErrorInt};
 
@@ -17,6 +24,10 @@ pub struct Packet {
     pub stream_id: usize,
     pub timestamp: crate::Timestamp,
     pub ssrc: u32,
+
+    /// The RTP payload type. For retransmitted packets, this is the payload
+    /// type of the original format.
+    pub payload_type: u8,
     pub sequence_number: u16,
 
     /// Number of skipped sequence numbers since the last packet.
@@ -29,6 +40,9 @@ pub struct Packet {
 
     pub mark: bool,
 
+    /// The header extension, if present.
+    pub extension: Option<HeaderExtension>,
+
     /// Guaranteed to be less than u16::MAX bytes.
     pub payload: Bytes,
 }
@@ -41,21 +55,645 @@ impl std::fmt::Debug for Packet {
             .field("stream_id", &self.stream_id)
             .field("timestamp", &self.timestamp)
             .field("ssrc", &self.ssrc)
+            .field("payload_type", &self.payload_type)
             .field("sequence_number", &self.sequence_number)
             .field("loss", &self.loss)
             .field("mark", &self.mark)
+            .field("extension", &self.extension)
             .field("payload", &self.payload.hex_dump())
             .finish()
     }
 }
 
-/// An RTCP sender report.
-#[derive(Debug)]
-pub struct SenderReport {
+/// A RTP header extension, as described in
+/// [RFC 3550 section 5.3.1](https://datatracker.ietf.org/doc/html/rfc3550#section-5.3.1).
+#[derive(Clone, PartialEq, Eq)]
+pub struct HeaderExtension {
+    profile: u16,
+    data: Bytes,
+}
+
+impl HeaderExtension {
+    pub fn new(profile: u16, data: Bytes) -> Self {
+        HeaderExtension { profile, data }
+    }
+
+    /// Returns the profile-defined 16-bit value which precedes the data.
+    ///
+    /// This is `0xBEDE` for RFC 8285 one-byte elements and `0x100X` for
+    /// two-byte elements.
+    pub fn profile(&self) -> u16 {
+        self.profile
+    }
+
+    /// Returns the extension data, excluding the four-byte header.
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Returns the elements of an extension in the
+    /// [RFC 8285](https://datatracker.ietf.org/doc/html/rfc8285) general
+    /// mechanism, or `None` if this extension uses another profile.
+    ///
+    /// The element ids can be mapped to URIs via the stream's [`ExtMap`].
+    pub fn elements(&self) -> Option<ExtensionElements<'_>> {
+        let two_byte = match self.profile {
+            0xBEDE => false,
+            p if (p & 0xFFF0) == 0x1000 => true,
+            _ => return None,
+        };
+        Some(ExtensionElements {
+            two_byte,
+            data: &self.data[..],
+        })
+    }
+
+    /// Returns the data of the first element with the given id, if any.
+    pub fn element(&self, id: u8) -> Option<&[u8]> {
+        self.elements()?
+            .take_while(Result::is_ok)
+            .filter_map(Result::ok)
+            .find(|e| e.id == id)
+            .map(|e| e.data)
+    }
+}
+
+impl std::fmt::Debug for HeaderExtension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeaderExtension")
+            .field("profile", &format_args!("{:04x}", self.profile))
+            .field("data", &self.data.hex_dump())
+            .finish()
+    }
+}
+
+/// An element of a RFC 8285 [`HeaderExtension`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExtensionElement<'a> {
+    /// The local identifier, as mapped to a URI via `a=extmap`.
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+/// Iterator returned by [`HeaderExtension::elements`].
+///
+/// Yields an error and stops if an element overflows the extension.
+pub struct ExtensionElements<'a> {
+    two_byte: bool,
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for ExtensionElements<'a> {
+    type Item = Result<ExtensionElement<'a>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&first, rest) = self.data.split_first()?;
+
+            // In both forms, id 0 is a single byte of padding.
+            if first == 0 {
+                self.data = rest;
+                continue;
+            }
+            let (id, len, rest) = if self.two_byte {
+                let (&len, rest) = match rest.split_first() {
+                    Some(r) => r,
+                    None => {
+                        self.data = &[];
+                        return Some(Err(format!("two-byte element {} has no length", first)));
+                    }
+                };
+                (first, usize::from(len), rest)
+            } else {
+                let id = first >> 4;
+                if id == 15 {
+                    // RFC 8285 section 4.2: "The local identifier value 15 is
+                    // reserved for a future extension and MUST NOT be used as
+                    // an identifier. If the ID value 15 is encountered, its
+                    // length field MUST be ignored, processing of the entire
+                    // extension MUST terminate at that point..."
+                    self.data = &[];
+                    return None;
+                }
+                (id, usize::from(first & 0xF) + 1, rest)
+            };
+            if rest.len() < len {
+                self.data = &[];
+                return Some(Err(format!(
+                    "element {} of length {} overflows extension with {} bytes remaining",
+                    id,
+                    len,
+                    rest.len()
+                )));
+            }
+            let (data, rest) = rest.split_at(len);
+            self.data = rest;
+            return Some(Ok(ExtensionElement { id, data }));
+        }
+    }
+}
+
+/// Mapping of RFC 8285 header extension element ids to URIs, as specified via
+/// SDP `a=extmap` attributes.
+///
+/// See [RFC 8285 section 8](https://datatracker.ietf.org/doc/html/rfc8285#section-8).
+/// Well-known URIs include:
+/// *   `urn:ietf:params:rtp-hdrext:ntp-64` (absolute capture time, as used by ONVIF)
+/// *   `http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time`
+/// *   `urn:3gpp:video-orientation`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtMap(Vec<(u8, String)>);
+
+impl ExtMap {
+    /// Adds a mapping from the value of an `a=extmap` attribute.
+    ///
+    /// ```text
+    /// extmap = mapentry SP extensionname [SP extensionattributes]
+    /// mapentry = "extmap:" 1*5DIGIT ["/" direction]
+    /// ```
+    pub(crate) fn add(&mut self, value: &str) -> Result<(), String> {
+        let (entry, rest) = value
+            .split_once(' ')
+            .ok_or_else(|| format!("invalid extmap attribute {:?}", value))?;
+        let id = entry.split_once('/').map(|(i, _)| i).unwrap_or(entry);
+        let id = match u8::from_str_radix(id, 10) {
+            Ok(i) if i != 0 => i,
+            _ => return Err(format!("invalid extmap id {:?}", id)),
+        };
+        let uri = rest
+            .split(' ')
+            .next()
+            .expect("split yields at least one item");
+        if self.uri(id).is_some() {
+            return Err(format!("duplicate extmap id {}", id));
+        }
+        self.0.push((id, uri.to_owned()));
+        Ok(())
+    }
+
+    /// Adds the mappings from `session` (session-level attributes) whose ids
+    /// aren't already mapped at the media level.
+    pub(crate) fn inherit(&mut self, session: &ExtMap) {
+        for (id, uri) in &session.0 {
+            if self.uri(*id).is_none() {
+                self.0.push((*id, uri.clone()));
+            }
+        }
+    }
+
+    /// Returns the URI for the given element id, if mapped.
+    pub fn uri(&self, id: u8) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, u)| u.as_str())
+    }
+
+    /// Returns the element id for the given URI, if mapped.
+    pub fn id(&self, uri: &str) -> Option<u8> {
+        self.0.iter().find(|(_, u)| u == uri).map(|(i, _)| *i)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.0.iter().map(|(i, u)| (*i, u.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A received RTCP compound packet.
+///
+/// The individual packets have been validated; iterate over them via
+/// [`ReceivedCompoundPacket::pkts`].
+pub struct ReceivedCompoundPacket {
+    pub ctx: crate::RtspMessageContext,
     pub stream_id: usize,
+
+    /// The RTP timestamp of the leading sender report, if any, placed on the
+    /// stream's timeline.
+    pub rtp_timestamp: Option<crate::Timestamp>,
+
+    raw: Bytes,
+}
+
+impl ReceivedCompoundPacket {
+    /// Returns the packets within this compound packet, in order.
+    pub fn pkts(&self) -> impl Iterator<Item = crate::rtcp::Packet<'_>> + '_ {
+        let mut data = &self.raw[..];
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let (pkt, rest) =
+                crate::rtcp::Packet::parse(data).expect("RTCP packets were validated");
+            data = rest;
+            Some(pkt)
+        })
+    }
+
+    /// Returns the raw bytes of the compound packet.
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+}
+
+impl std::fmt::Debug for ReceivedCompoundPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReceivedCompoundPacket")
+            .field("ctx", &self.ctx)
+            .field("stream_id", &self.stream_id)
+            .field("rtp_timestamp", &self.rtp_timestamp)
+            .field("pkts", &self.pkts().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A change of RTP source (SSRC) within a stream, or a restart of sequence
+/// numbers within the same source.
+///
+/// A change of source is only produced when enabled via
+/// [`crate::client::PlayOptions::resync_on_ssrc_change`]. Following packets
+/// come from the new source; their sequence numbers and timestamps restart
+/// from its first packet, so the stream's [`crate::Timestamp`]s may jump
+/// backward here.
+///
+/// A restart of sequence numbers (with `prev_ssrc == ssrc`) is only produced
+/// with [`crate::client::SequencePolicy::ResyncOnJump`].
+#[derive(Debug)]
+pub struct Discontinuity {
+    /// The context of the first packet from the new source.
     pub ctx: crate::RtspMessageContext,
-    pub timestamp: crate::Timestamp,
+    pub stream_id: usize,
+    pub prev_ssrc: u32,
+    pub ssrc: u32,
+
+    /// True iff the previous source announced its departure via RTCP BYE.
+    pub goodbye: bool,
+}
+
+/// Packets received from a SSRC other than the expected one, which may become
+/// the stream's new source if the run continues.
+#[derive(Debug)]
+struct SsrcCandidate {
+    ssrc: u32,
+    next_seq: u16,
+    pkts: Vec<(crate::RtspMessageContext, u8, Bytes)>,
+}
+
+/// A RTP packet which has been validated but not yet placed on the timeline.
+#[derive(Debug)]
+struct ReceivedPacket {
+    ctx: crate::RtspMessageContext,
+    channel_id: u8,
+    payload_type: u8,
+    sequence_number: u16,
+    rtp_timestamp: u32,
+    mark: bool,
+    extension: Option<HeaderExtension>,
+    payload: Bytes,
+}
+
+impl ReceivedPacket {
+    fn into_packet(
+        self,
+        conn_ctx: &crate::ConnectionContext,
+        timeline: &mut super::Timeline,
+        stream_id: usize,
+        ssrc: u32,
+        loss: u16,
+    ) -> Result<Packet, Error> {
+        let timestamp = match timeline.advance_to(self.rtp_timestamp) {
+            Ok(ts) => ts,
+            Err(description) => bail!(ErrorInt::RtpPacketError {
+                conn_ctx: *conn_ctx,
+                msg_ctx: self.ctx,
+                channel_id: self.channel_id,
+                stream_id,
+                ssrc,
+                sequence_number: self.sequence_number,
+                description,
+            }),
+        };
+        timeline.observe(timestamp, self.ctx.received);
+        Ok(Packet {
+            ctx: self.ctx,
+            channel_id: self.channel_id,
+            stream_id,
+            timestamp,
+            ssrc,
+            payload_type: self.payload_type,
+            sequence_number: self.sequence_number,
+            loss,
+            mark: self.mark,
+            extension: self.extension,
+            payload: self.payload,
+        })
+    }
+}
+
+/// A snapshot of a stream's reception statistics, as returned by
+/// [`super::Session::stats`] and [`super::Demuxed::stats`].
+///
+/// Except for `frames_discarded`, statistics cover the stream's current RTP
+/// source (SSRC); they restart on a [`Discontinuity`].
+#[derive(Clone, Debug)]
+pub struct StreamStats {
+    /// The RTP source, if known.
+    pub ssrc: Option<u32>,
+
+    /// Number of RTP packets received, including duplicates.
+    pub packets_received: u64,
+
+    /// Number of RTP payload bytes received.
+    pub bytes_received: u64,
+
+    /// Cumulative number of packets lost, as in RTCP receiver reports: the
+    /// number expected minus the number received. This may be negative if
+    /// duplicates were received.
+    pub packets_lost: i64,
+
+    /// Number of packets dropped by the reorder buffer as duplicates or as
+    /// arriving too late. Without a reorder buffer, these are errors instead.
+    pub duplicates: u64,
+
+    /// The interarrival jitter estimate, as described in
+    /// [RFC 3550 appendix A.8](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8).
+    pub jitter: Duration,
+
+    /// Frames (distinct RTP timestamps) per second over the last full
+    /// measurement interval of about a second, if one has completed.
+    pub frame_rate: Option<f64>,
+
+    /// Payload bits per second over the same interval.
+    pub bitrate: Option<u64>,
+
+    /// Time since the last RTP packet was received, if any.
+    pub since_last_packet: Option<Duration>,
+
+    /// The most recent RTCP sender report, if any.
+    pub last_sender_report: Option<SenderReportStats>,
+
+    /// Number of frames the depacketizer discarded because of packet loss.
+    ///
+    /// This is always 0 via [`super::Session::stats`], which doesn't depacketize.
+    pub frames_discarded: u64,
+
+    /// Decisions made by the [`super::SequencePolicy`].
+    pub sequence: SequenceDecisions,
+
+    /// Number of lost packets recovered via retransmission
+    /// ([RFC 4588](https://datatracker.ietf.org/doc/html/rfc4588)).
+    /// These aren't included in `packets_received`, so `packets_lost` reflects
+    /// loss before repair.
+    pub retransmitted: u64,
+
+    /// The sender's estimated actual RTP clock rate in Hz, if known.
+    ///
+    /// This comes from RTCP sender reports when available, or otherwise from
+    /// packet arrival times once enough have been observed. See
+    /// [`crate::client::Timeline::estimated_clock_rate`].
+    pub estimated_clock_rate: Option<f64>,
+}
+
+/// Counts of decisions made by the [`super::SequencePolicy`] about packets
+/// which don't follow the stream's sequence numbers.
+///
+/// Unlike the rest of [`StreamStats`], these aren't reset when the RTP source
+/// changes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceDecisions {
+    /// Packets dropped as duplicates or as arriving after later packets.
+    pub dropped_late: u64,
+
+    /// Packets dropped as a jump in sequence numbers, pending confirmation by
+    /// the following packet.
+    pub dropped_jump: u64,
+
+    /// Confirmed jumps, each of which restarted the stream's sequence numbers.
+    pub resyncs: u64,
+}
+
+/// Information about a received RTCP sender report.
+#[derive(Copy, Clone, Debug)]
+pub struct SenderReportStats {
     pub ntp_timestamp: crate::NtpTimestamp,
+    pub rtp_timestamp: crate::Timestamp,
+    pub received: Instant,
+}
+
+/// The tolerance, in milliseconds, before [`StrictSequenceChecker::align`]
+/// shifts a stream's NPT.
+const MAX_SYNC_ERROR_MS: i64 = 5;
+
+/// The largest forward jump in sequence numbers considered to be loss rather
+/// than a restart, from
+/// [RFC 3550 appendix A.1](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.1).
+const MAX_DROPOUT: u16 = 3000;
+
+/// The largest backward step in sequence numbers considered to be misordering
+/// rather than a restart, from RFC 3550 appendix A.1.
+const MAX_MISORDER: u16 = 100;
+
+/// The interval over which [`StreamStats::frame_rate`] and [`StreamStats::bitrate`] are measured.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reception statistics for a single source, used to fill in RTCP receiver
+/// report blocks as described in
+/// [RFC 3550 appendix A.3](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.3)
+/// and [appendix A.8](https://datatracker.ietf.org/doc/html/rfc3550#appendix-A.8).
+#[derive(Debug, Default)]
+struct ReceptionStats {
+    /// Number of RTP packets received.
+    received: u64,
+
+    /// Number of RTP payload bytes received.
+    bytes: u64,
+
+    /// Number of late or duplicate packets dropped.
+    duplicates: u64,
+
+    /// Number of lost packets recovered via retransmission.
+    retransmitted: u64,
+
+    /// The extended (cycle-counting) sequence number of the first packet.
+    base_seq: u64,
+
+    /// The highest extended sequence number received.
+    max_seq: u64,
+
+    /// The values of `received` and the expected packet count as of the previous report.
+    received_prior: u64,
+    expected_prior: u64,
+
+    /// The arrival time of the first packet, used as the epoch for arrival timestamps.
+    epoch: Option<Instant>,
+
+    /// The relative transit time of the previous packet, in RTP timestamp units.
+    transit: Option<u32>,
+
+    /// The interarrival jitter estimate, in RTP timestamp units.
+    jitter: f64,
+
+    /// The last SR received.
+    last_sr: Option<SenderReportStats>,
+
+    /// The RTP timestamp and arrival time of the most recent packet.
+    last: Option<(u32, Instant)>,
+
+    /// The start, frame count, and byte count of the current rate measurement interval.
+    window: Option<(Instant, u64, u64)>,
+
+    /// The frame rate and bitrate from the last complete interval.
+    rates: Option<(f64, u64)>,
+}
+
+impl ReceptionStats {
+    fn rtp(
+        &mut self,
+        sequence_number: u16,
+        rtp_timestamp: u32,
+        payload_len: usize,
+        received: Instant,
+        clock_rate: NonZeroU32,
+    ) {
+        let new_frame = !matches!(self.last, Some((ts, _)) if ts == rtp_timestamp);
+        self.last = Some((rtp_timestamp, received));
+        let window = self.window.get_or_insert((received, 0, 0));
+        let elapsed = received.saturating_duration_since(window.0);
+        if elapsed >= RATE_INTERVAL {
+            let secs = elapsed.as_secs_f64();
+            self.rates = Some((window.1 as f64 / secs, (window.2 as f64 * 8. / secs) as u64));
+            *window = (received, 0, 0);
+        }
+        window.1 += u64::from(new_frame);
+        window.2 += payload_len as u64;
+        self.bytes += payload_len as u64;
+
+        if self.received == 0 {
+            self.base_seq = u64::from(sequence_number);
+            self.max_seq = self.base_seq;
+        } else {
+            let delta = sequence_number.wrapping_sub(self.max_seq as u16);
+            if delta < 0x8000 {
+                self.max_seq += u64::from(delta);
+            }
+        }
+        self.received += 1;
+
+        // The arrival time only has to be in the same units as the RTP timestamp;
+        // its epoch is arbitrary.
+        let epoch = *self.epoch.get_or_insert(received);
+        let arrival = received.saturating_duration_since(epoch).as_nanos()
+            * u128::from(clock_rate.get())
+            / 1_000_000_000;
+        let transit = (arrival as u32).wrapping_sub(rtp_timestamp);
+        if let Some(prev) = self.transit {
+            let d = f64::from((transit.wrapping_sub(prev) as i32).unsigned_abs());
+            self.jitter += (d - self.jitter) / 16.;
+        }
+        self.transit = Some(transit);
+    }
+
+    fn sender_report(
+        &mut self,
+        ntp_timestamp: crate::NtpTimestamp,
+        rtp_timestamp: crate::Timestamp,
+        received: Instant,
+    ) {
+        self.last_sr = Some(SenderReportStats {
+            ntp_timestamp,
+            rtp_timestamp,
+            received,
+        });
+    }
+
+    fn expected(&self) -> u64 {
+        if self.received == 0 {
+            return 0;
+        }
+        self.max_seq - self.base_seq + 1
+    }
+
+    fn snapshot(&self, ssrc: Option<u32>, clock_rate: NonZeroU32, now: Instant) -> StreamStats {
+        StreamStats {
+            ssrc,
+            packets_received: self.received,
+            bytes_received: self.bytes,
+            packets_lost: self.expected() as i64 - self.received as i64,
+            duplicates: self.duplicates,
+            jitter: Duration::from_secs_f64(self.jitter / f64::from(clock_rate.get())),
+            frame_rate: self.rates.map(|(f, _)| f),
+            bitrate: self.rates.map(|(_, b)| b),
+            since_last_packet: self
+                .last
+                .map(|(_, received)| now.saturating_duration_since(received)),
+            last_sender_report: self.last_sr,
+            frames_discarded: 0,
+            sequence: SequenceDecisions::default(),
+            retransmitted: self.retransmitted,
+            estimated_clock_rate: None,
+        }
+    }
+
+    /// Continues the extended sequence numbers at `seq` after a restart, as if
+    /// it immediately followed the highest sequence number so far.
+    fn restart_sequence(&mut self, seq: u16) {
+        let next = (((self.max_seq >> 16) + 1) << 16) | u64::from(seq);
+        self.base_seq += next - 1 - self.max_seq;
+        self.max_seq = next - 1;
+    }
+
+    /// Returns a report block describing reception since the previous call,
+    /// or `None` if no packets have been received.
+    fn report_block(&mut self, ssrc: u32, now: Instant) -> Option<crate::rtcp::ReportBlock> {
+        if self.received == 0 {
+            return None;
+        }
+        let expected = self.expected();
+        let cumulative_lost = (expected as i64 - self.received as i64).clamp(-0x80_0000, 0x7F_FFFF);
+        let expected_interval = expected - self.expected_prior;
+        self.expected_prior = expected;
+        let received_interval = self.received - self.received_prior;
+        self.received_prior = self.received;
+        let lost_interval = expected_interval as i64 - received_interval as i64;
+        let fraction_lost = if expected_interval == 0 || lost_interval <= 0 {
+            0
+        } else {
+            std::cmp::min((lost_interval << 8) / expected_interval as i64, 255) as u8
+        };
+        let (last_sr, delay_since_last_sr) = match self.last_sr {
+            None => (0, 0),
+            Some(sr) => {
+                let d = now.saturating_duration_since(sr.received);
+                let d = (d.as_secs() << 16) + ((u64::from(d.subsec_nanos()) << 16) / 1_000_000_000);
+                (
+                    (sr.ntp_timestamp.0 >> 16) as u32,
+                    u32::try_from(d).unwrap_or(u32::MAX),
+                )
+            }
+        };
+        Some(crate::rtcp::ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: cumulative_lost as i32,
+            extended_highest_seq: self.max_seq as u32,
+            jitter: self.jitter as u32,
+            last_sr,
+            delay_since_last_sr,
+        })
+    }
+}
+
+/// Returns the header extension of the packet in `data`, if any.
+fn extension(reader: &rtp_rs::RtpReader<'_>, data: &Bytes) -> Option<HeaderExtension> {
+    reader.extension().map(|(profile, ext)| {
+        let ext = crate::as_range(data, ext)
+            .map(|r| data.slice(r))
+            .unwrap_or_else(Bytes::new);
+        HeaderExtension::new(profile, ext)
+    })
 }
 
 /// RTP/RTCP demarshaller which ensures packets have the correct SSRC and
@@ -71,21 +709,255 @@ pub struct SenderReport {
 /// although it'd be possible to discard the incorrect SSRC instead.
 ///
 /// [RFC 3550 section 8.2](https://tools.ietf.org/html/rfc3550#section-8.2) says that SSRC
-/// can change mid-session with a RTCP BYE message. This currently isn't handled. I'm
-/// not sure it will ever come up with IP cameras.
+/// can change mid-session with a RTCP BYE message. In practice, some cameras also
+/// switch SSRC without a BYE when their encoder restarts (eg on a settings change).
+/// When enabled via [`StrictSequenceChecker::resync_on_ssrc_change`], either
+/// a BYE followed by a new SSRC or a sustained run of packets on a new SSRC
+/// re-anchors the checker and timeline on the new source, reporting a
+/// [`Discontinuity`]. Packets from the new source are buffered until the switch is
+/// made, so [`StrictSequenceChecker::pull`] should be called after each packet.
+///
+/// It also keeps the reception statistics needed to send RTCP receiver reports.
 #[doc(hidden)]
 #[derive(Debug)]
 pub struct StrictSequenceChecker {
     ssrc: Option<u32>,
     next_seq: Option<u16>,
+    stats: ReceptionStats,
+
+    /// The number of consecutive packets on a new SSRC required to switch to
+    /// it, or `None` to treat other SSRCs as errors (or spurious data).
+    resync: Option<NonZeroU16>,
+
+    /// True iff a RTCP BYE has been received for `ssrc`.
+    goodbye: bool,
+
+    candidate: Option<SsrcCandidate>,
+
+    /// The buffer for reordering packets by sequence number, if enabled.
+    reorder: Option<ReorderBuffer<ReceivedPacket>>,
+
+    /// Items to be returned by `pull`.
+    queued: VecDeque<PacketItem>,
+
+    /// The mapping of RTP timestamps to wall-clock time, from RTCP sender reports.
+    wall_clock: WallClock,
+
+    /// The timeline timestamp and NTP time of the latest RTCP sender report, for
+    /// aligning streams in `align`. Unlike `wall_clock`, this accepts reports
+    /// from senders whose clocks disagree with local time: streams from the
+    /// same sender can be synchronized even when its clock is wrong.
+    sync_anchor: Option<(i64, crate::NtpTimestamp)>,
+
+    /// The payload type of retransmitted packets, as in
+    /// [RFC 4588](https://datatracker.ietf.org/doc/html/rfc4588), and the
+    /// payload type of the original packets.
+    rtx: Option<(u8, u8)>,
+
+    /// True iff sequence number gaps should be reported via `nacks`.
+    nack: bool,
+
+    /// Lost sequence numbers which have yet to be sent in a RTCP NACK.
+    nacks: Vec<u16>,
+
+    policy: SequencePolicy,
+
+    /// With [`SequencePolicy::ResyncOnJump`], the sequence number which would
+    /// confirm the last jump, as `bad_seq` in RFC 3550 appendix A.1.
+    bad_seq: Option<u16>,
+
+    decisions: SequenceDecisions,
 }
 
 impl StrictSequenceChecker {
     pub fn new(ssrc: Option<u32>, next_seq: Option<u16>) -> Self {
-        Self { ssrc, next_seq }
+        Self {
+            ssrc,
+            next_seq,
+            stats: ReceptionStats::default(),
+            resync: None,
+            goodbye: false,
+            candidate: None,
+            reorder: None,
+            queued: VecDeque::new(),
+            wall_clock: WallClock::default(),
+            sync_anchor: None,
+            rtx: None,
+            nack: false,
+            nacks: Vec::new(),
+            policy: SequencePolicy::default(),
+            bad_seq: None,
+            decisions: SequenceDecisions::default(),
+        }
+    }
+
+    /// Sets the policy for packets which don't follow the expected sequence number.
+    pub fn sequence_policy(self, policy: SequencePolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Reorders packets by sequence number rather than treating out-of-order
+    /// packets as errors.
+    ///
+    /// A missing packet is reported as lost (via [`Packet::loss`]) once a later
+    /// packet has waited `max_delay` or more than `max_packets` packets are
+    /// buffered. Late and duplicate packets are dropped.
+    pub fn reorder_buffer(self, max_delay: Duration, max_packets: NonZeroU16) -> Self {
+        Self {
+            reorder: Some(ReorderBuffer::new(
+                max_delay,
+                usize::from(max_packets.get()),
+                self.next_seq,
+            )),
+            ..self
+        }
     }
 
+    /// Accepts retransmitted packets with payload type `rtx_payload_type` for
+    /// those with payload type `apt` and, if `nack`, records sequence number
+    /// gaps to be requested via RTCP NACK (see
+    /// [`StrictSequenceChecker::take_nacks`]).
+    ///
+    /// Retransmissions may use a different SSRC than the original stream, as in
+    /// [RFC 4588 section 5](https://datatracker.ietf.org/doc/html/rfc4588#section-5).
+    /// They're only useful with a reorder buffer, which holds later packets
+    /// until the retransmission arrives; without one, they're dropped.
+    pub fn retransmission(self, rtx_payload_type: u8, apt: u8, nack: bool) -> Self {
+        Self {
+            rtx: Some((rtx_payload_type, apt)),
+            nack: nack && self.reorder.is_some(),
+            ..self
+        }
+    }
+
+    /// Switches to a new SSRC after a RTCP BYE or after the given number of
+    /// consecutive packets with that SSRC.
+    pub fn resync_on_ssrc_change(self, resync: Option<NonZeroU16>) -> Self {
+        Self { resync, ..self }
+    }
+
+    /// Returns a queued item, if any.
+    ///
+    /// Items are queued when switching SSRC or when the reorder buffer releases
+    /// several packets at once.
+    pub fn pull(&mut self) -> Option<PacketItem> {
+        self.queued.pop_front()
+    }
+
+    pub(crate) fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    pub(crate) fn has_nacks(&self) -> bool {
+        !self.nacks.is_empty()
+    }
+
+    /// Returns lost sequence numbers to request via RTCP NACK, in order.
+    pub(crate) fn take_nacks(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.nacks)
+    }
+
+    /// Returns the current RTP source, if known.
+    pub(crate) fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+
+    /// Returns a RTCP report block describing reception since the previous
+    /// call, or `None` if no packets have been received.
+    pub(crate) fn report_block(&mut self, now: Instant) -> Option<crate::rtcp::ReportBlock> {
+        let ssrc = self.ssrc?;
+        self.stats.report_block(ssrc, now)
+    }
+
+    /// Estimates the wall-clock capture time of `timestamp` from RTCP sender
+    /// reports, falling back to `received`.
+    pub(crate) fn capture_time(
+        &self,
+        timestamp: crate::Timestamp,
+        received: SystemTime,
+    ) -> SystemTime {
+        self.wall_clock.map(timestamp, received)
+    }
+
+    /// Aligns the NPT of `timeline` with the session-wide `epoch`, the NTP time
+    /// at NPT 0, as specified by the latest RTCP sender report.
+    ///
+    /// If `epoch` is unset, it's set from this stream's sender report, leaving
+    /// its NPT unchanged.
+    pub(crate) fn align(
+        &mut self,
+        stream_id: usize,
+        timeline: &mut super::Timeline,
+        epoch: &mut Option<crate::NtpTimestamp>,
+    ) {
+        let (anchor_ts, anchor_ntp, start) = match (self.sync_anchor, timeline.start()) {
+            (Some((ts, ntp)), Some(start)) => (ts, ntp, start),
+            _ => return,
+        };
+        let clock_rate = i64::from(timeline.clock_rate().get());
+        let elapsed = anchor_ts - i64::from(start);
+        let epoch = *epoch.get_or_insert_with(|| {
+            let elapsed_ntp = ((i128::from(elapsed) << 32) / i128::from(clock_rate)) as i64;
+            crate::NtpTimestamp(anchor_ntp.0.wrapping_sub(elapsed_ntp as u64))
+        });
+        let since_epoch = anchor_ntp.0.wrapping_sub(epoch.0) as i64;
+        let desired = ((i128::from(since_epoch) * i128::from(clock_rate)) >> 32) as i64;
+        let offset = desired - elapsed;
+        if offset.abs() * 1000 <= clock_rate * MAX_SYNC_ERROR_MS {
+            return;
+        }
+        log::debug!(
+            "stream {}: shifting NPT by {} ({:.03} sec) to match RTCP SR",
+            stream_id,
+            offset,
+            offset as f64 / clock_rate as f64
+        );
+        let adjustment = timeline.shift_epoch(offset);
+        self.wall_clock.shift(adjustment);
+        if let Some((ts, _)) = &mut self.sync_anchor {
+            *ts += adjustment;
+        }
+    }
+
+    /// Returns a snapshot of reception statistics for the current source.
+    pub(crate) fn stats(&self, clock_rate: NonZeroU32, now: Instant) -> StreamStats {
+        let mut stats = self.stats.snapshot(self.ssrc, clock_rate, now);
+        stats.sequence = self.decisions;
+        stats
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn rtp(
+        &mut self,
+        session_options: &super::SessionOptions,
+        conn_ctx: &crate::ConnectionContext,
+        msg_ctx: &crate::RtspMessageContext,
+        timeline: &mut super::Timeline,
+        channel_id: u8,
+        stream_id: usize,
+        data: Bytes,
+    ) -> Result<Option<PacketItem>, Error> {
+        let item = self.rtp_inner(
+            session_options,
+            conn_ctx,
+            msg_ctx,
+            timeline,
+            channel_id,
+            stream_id,
+            data,
+        )?;
+        if let Some(item) = item {
+            if self.queued.is_empty() {
+                return Ok(Some(item));
+            }
+            self.queued.push_back(item);
+        }
+        Ok(self.queued.pop_front())
+    }
+
+    /// Handles a RTP packet, either returning an item or adding any number to `queued`.
+    #[allow(clippy::too_many_arguments)]
+    fn rtp_inner(
         &mut self,
         session_options: &super::SessionOptions,
         conn_ctx: &crate::ConnectionContext,
@@ -122,11 +994,42 @@ impl StrictSequenceChecker {
                 ),
             })
         })?;
+        if let Some((_, apt)) = self.rtx.filter(|&(pt, _)| pt == reader.payload_type()) {
+            let rtp_timestamp = reader.timestamp();
+            let mark = reader.mark();
+            let extension = extension(&reader, &data);
+            let payload_range = crate::as_range(&data, reader.payload());
+            return self.retransmitted(
+                conn_ctx,
+                msg_ctx,
+                timeline,
+                channel_id,
+                stream_id,
+                apt,
+                rtp_timestamp,
+                mark,
+                extension,
+                payload_range.map(|r| data.slice(r)),
+            );
+        }
         let sequence_number = u16::from_be_bytes([data[2], data[3]]); // I don't like rtsp_rs::Seq.
         let ssrc = reader.ssrc();
-        let loss = sequence_number.wrapping_sub(self.next_seq.unwrap_or(sequence_number));
+        let mut loss = sequence_number.wrapping_sub(self.next_seq.unwrap_or(sequence_number));
         if matches!(self.ssrc, Some(s) if s != ssrc) {
-            if session_options.ignore_spurious_data {
+            if let Some(resync) = self.resync {
+                return self.ssrc_candidate(
+                    resync,
+                    session_options,
+                    conn_ctx,
+                    msg_ctx,
+                    timeline,
+                    channel_id,
+                    stream_id,
+                    ssrc,
+                    sequence_number,
+                    data,
+                );
+            } else if session_options.ignore_spurious_data {
                 log::debug!(
                     "Ignoring spurious RTP data with ssrc={:08x} seq={:04x} while expecting \
                              ssrc={:08x?} seq={:04x?}",
@@ -151,7 +1054,36 @@ impl StrictSequenceChecker {
                 });
             }
         }
-        if loss > 0x80_00 {
+        if self.policy == SequencePolicy::ResyncOnJump && self.stats.received > 0 {
+            // See RFC 3550 appendix A.1. Compare to the highest sequence number
+            // so far, which is the last one released when not reordering.
+            let delta = sequence_number.wrapping_sub(self.stats.max_seq as u16);
+            if delta == 0 || delta > u16::MAX - MAX_MISORDER {
+                if self.reorder.is_none() {
+                    return Ok(self.drop_late(stream_id, ssrc, sequence_number));
+                }
+            } else if delta < MAX_DROPOUT {
+                self.bad_seq = None;
+            } else if self.bad_seq == Some(sequence_number) {
+                self.restart_sequence(conn_ctx, msg_ctx, timeline, stream_id, sequence_number)?;
+                loss = 0;
+            } else {
+                log::debug!(
+                    "stream {}: dropping RTP packet ssrc={:08x} seq={:04x} after jump from \
+                     seq={:04x}; awaiting confirmation",
+                    stream_id,
+                    ssrc,
+                    sequence_number,
+                    self.stats.max_seq as u16
+                );
+                self.bad_seq = Some(sequence_number.wrapping_add(1));
+                self.decisions.dropped_jump += 1;
+                return Ok(None);
+            }
+        } else if self.backward_jump(loss) && self.policy == SequencePolicy::Tolerant {
+            return Ok(self.drop_late(stream_id, ssrc, sequence_number));
+        }
+        if self.backward_jump(loss) {
             bail!(ErrorInt::RtpPacketError {
                 conn_ctx: *conn_ctx,
                 msg_ctx: *msg_ctx,
@@ -165,20 +1097,12 @@ impl StrictSequenceChecker {
                 ),
             });
         }
-        let timestamp = match timeline.advance_to(reader.timestamp()) {
-            Ok(ts) => ts,
-            Err(description) => bail!(ErrorInt::RtpPacketError {
-                conn_ctx: *conn_ctx,
-                msg_ctx: *msg_ctx,
-                channel_id,
-                stream_id,
-                ssrc,
-                sequence_number,
-                description,
-            }),
-        };
+        self.candidate = None;
         self.ssrc = Some(ssrc);
+        let payload_type = reader.payload_type();
+        let rtp_timestamp = reader.timestamp();
         let mark = reader.mark();
+        let extension = extension(&reader, &data);
         let payload_range = crate::as_range(&data, reader.payload()).ok_or_else(|| {
             wrap!(ErrorInt::RtpPacketError {
                 conn_ctx: *conn_ctx,
@@ -192,18 +1116,293 @@ impl StrictSequenceChecker {
         })?;
         data.truncate(payload_range.end);
         data.advance(payload_range.start);
-        self.next_seq = Some(sequence_number.wrapping_add(1));
-        Ok(Some(PacketItem::RtpPacket(Packet {
+        if self.nack && self.stats.received > 0 {
+            // Request everything between the highest sequence number so far and
+            // this one, unless the gap is too large for the reorder buffer to wait.
+            let highest = self.stats.max_seq as u16;
+            let gap = sequence_number.wrapping_sub(highest).wrapping_sub(1);
+            let max = self.reorder.as_ref().map(|r| r.max_packets()).unwrap_or(0);
+            if gap > 0 && gap < 0x8000 && usize::from(gap) <= max {
+                self.nacks
+                    .extend((1..=gap).map(|i| highest.wrapping_add(i)));
+            }
+        }
+        self.stats.rtp(
+            sequence_number,
+            rtp_timestamp,
+            data.len(),
+            msg_ctx.received,
+            timeline.clock_rate(),
+        );
+        let pkt = ReceivedPacket {
             ctx: *msg_ctx,
             channel_id,
+            payload_type,
+            sequence_number,
+            rtp_timestamp,
+            mark,
+            extension,
+            payload: data,
+        };
+        if let Some(reorder) = &mut self.reorder {
+            if !reorder.push(sequence_number, msg_ctx.received, pkt) {
+                self.stats.duplicates += 1;
+                log::debug!(
+                    "Dropping late or duplicate RTP packet ssrc={:08x} seq={:04x}",
+                    ssrc,
+                    sequence_number
+                );
+                return Ok(None);
+            }
+            self.release(conn_ctx, msg_ctx, timeline, stream_id, ssrc)?;
+            return Ok(None);
+        }
+        self.next_seq = Some(sequence_number.wrapping_add(1));
+        Ok(Some(PacketItem::RtpPacket(
+            pkt.into_packet(conn_ctx, timeline, stream_id, ssrc, loss)?,
+        )))
+    }
+
+    /// Returns true if a packet `loss` after the next expected sequence number
+    /// is actually a step backward to be handled by the sequence policy.
+    ///
+    /// With a reorder buffer, a packet slightly behind is merely late and is
+    /// dropped by the buffer. One further behind than the buffer could have
+    /// held (or than RFC 3550's misordering limit) is still a jump.
+    fn backward_jump(&self, loss: u16) -> bool {
+        if loss <= 0x80_00 {
+            return false;
+        }
+        match &self.reorder {
+            None => true,
+            Some(r) => {
+                usize::from(loss.wrapping_neg()) > r.max_packets().max(usize::from(MAX_MISORDER))
+            }
+        }
+    }
+
+    /// Drops a duplicate or late packet as decided by the sequence policy.
+    fn drop_late(
+        &mut self,
+        stream_id: usize,
+        ssrc: u32,
+        sequence_number: u16,
+    ) -> Option<PacketItem> {
+        log::debug!(
+            "stream {}: dropping late or duplicate RTP packet ssrc={:08x} seq={:04x} while \
+             expecting seq={:04x?}",
             stream_id,
-            timestamp,
             ssrc,
             sequence_number,
-            loss,
+            self.next_seq
+        );
+        self.decisions.dropped_late += 1;
+        None
+    }
+
+    /// Restarts sequence numbers at `sequence_number` after a confirmed jump,
+    /// releasing any held packets and queueing a [`Discontinuity`].
+    fn restart_sequence(
+        &mut self,
+        conn_ctx: &crate::ConnectionContext,
+        msg_ctx: &crate::RtspMessageContext,
+        timeline: &mut super::Timeline,
+        stream_id: usize,
+        sequence_number: u16,
+    ) -> Result<(), Error> {
+        let ssrc = self.ssrc.expect("ssrc is set once packets are received");
+        log::info!(
+            "stream {}: ssrc={:08x} restarted sequence numbers from seq={:04x} to seq={:04x}",
+            stream_id,
+            ssrc,
+            self.stats.max_seq as u16,
+            sequence_number
+        );
+        if let Some(reorder) = &mut self.reorder {
+            while let Some((loss, pkt)) = reorder.flush() {
+                let pkt = pkt.into_packet(conn_ctx, timeline, stream_id, ssrc, loss)?;
+                self.queued.push_back(PacketItem::RtpPacket(pkt));
+            }
+            reorder.reset();
+        }
+        self.queued
+            .push_back(PacketItem::Discontinuity(Discontinuity {
+                ctx: *msg_ctx,
+                stream_id,
+                prev_ssrc: ssrc,
+                ssrc,
+                goodbye: false,
+            }));
+        self.decisions.resyncs += 1;
+        self.bad_seq = None;
+        self.next_seq = None;
+        self.nacks.clear();
+        self.stats.restart_sequence(sequence_number);
+        Ok(())
+    }
+
+    /// Queues packets which the reorder buffer has released.
+    fn release(
+        &mut self,
+        conn_ctx: &crate::ConnectionContext,
+        msg_ctx: &crate::RtspMessageContext,
+        timeline: &mut super::Timeline,
+        stream_id: usize,
+        ssrc: u32,
+    ) -> Result<(), Error> {
+        let reorder = match &mut self.reorder {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        while let Some((loss, pkt)) = reorder.pull(msg_ctx.received) {
+            self.next_seq = Some(pkt.sequence_number.wrapping_add(1));
+            let pkt = pkt.into_packet(conn_ctx, timeline, stream_id, ssrc, loss)?;
+            self.queued.push_back(PacketItem::RtpPacket(pkt));
+        }
+        Ok(())
+    }
+
+    /// Handles a retransmitted packet, as described in
+    /// [RFC 4588 section 4](https://datatracker.ietf.org/doc/html/rfc4588#section-4):
+    /// its payload is the original sequence number followed by the original payload.
+    #[allow(clippy::too_many_arguments)]
+    fn retransmitted(
+        &mut self,
+        conn_ctx: &crate::ConnectionContext,
+        msg_ctx: &crate::RtspMessageContext,
+        timeline: &mut super::Timeline,
+        channel_id: u8,
+        stream_id: usize,
+        payload_type: u8,
+        rtp_timestamp: u32,
+        mark: bool,
+        extension: Option<HeaderExtension>,
+        payload: Option<Bytes>,
+    ) -> Result<Option<PacketItem>, Error> {
+        let (ssrc, reorder) = match (self.ssrc, &mut self.reorder) {
+            (Some(s), Some(r)) => (s, r),
+            _ => {
+                log::debug!("Dropping retransmitted RTP packet without reorder buffer or source");
+                return Ok(None);
+            }
+        };
+        let mut payload = match payload {
+            Some(p) if p.len() >= 2 => p,
+            _ => {
+                log::debug!("Dropping retransmitted RTP packet without original sequence number");
+                return Ok(None);
+            }
+        };
+        let sequence_number = payload.get_u16();
+        let pkt = ReceivedPacket {
+            ctx: *msg_ctx,
+            channel_id,
+            payload_type,
+            sequence_number,
+            rtp_timestamp,
             mark,
-            payload: data,
-        })))
+            extension,
+            payload,
+        };
+        if !reorder.push(sequence_number, msg_ctx.received, pkt) {
+            self.stats.duplicates += 1;
+            log::debug!(
+                "Dropping late or duplicate retransmission of ssrc={:08x} seq={:04x}",
+                ssrc,
+                sequence_number
+            );
+            return Ok(None);
+        }
+        self.stats.retransmitted += 1;
+        self.release(conn_ctx, msg_ctx, timeline, stream_id, ssrc)?;
+        Ok(None)
+    }
+
+    /// Handles a RTP packet from `ssrc`, which doesn't match the current source.
+    #[allow(clippy::too_many_arguments)]
+    fn ssrc_candidate(
+        &mut self,
+        resync: NonZeroU16,
+        session_options: &super::SessionOptions,
+        conn_ctx: &crate::ConnectionContext,
+        msg_ctx: &crate::RtspMessageContext,
+        timeline: &mut super::Timeline,
+        channel_id: u8,
+        stream_id: usize,
+        ssrc: u32,
+        sequence_number: u16,
+        data: Bytes,
+    ) -> Result<Option<PacketItem>, Error> {
+        let mut candidate = match self.candidate.take() {
+            Some(c) if c.ssrc == ssrc && c.next_seq == sequence_number => c,
+            _ => SsrcCandidate {
+                ssrc,
+                next_seq: sequence_number,
+                pkts: Vec::new(),
+            },
+        };
+        candidate.next_seq = sequence_number.wrapping_add(1);
+        candidate.pkts.push((*msg_ctx, channel_id, data));
+        if !self.goodbye && candidate.pkts.len() < usize::from(resync.get()) {
+            log::debug!(
+                "Buffering RTP data with ssrc={:08x} seq={:04x} while expecting ssrc={:08x?} \
+                 seq={:04x?}; {}/{} packets needed to switch source",
+                ssrc,
+                sequence_number,
+                self.ssrc,
+                self.next_seq,
+                candidate.pkts.len(),
+                resync
+            );
+            self.candidate = Some(candidate);
+            return Ok(None);
+        }
+
+        let prev_ssrc = self.ssrc.expect("mismatched ssrc is set");
+        log::info!(
+            "stream {}: switching from ssrc={:08x} to ssrc={:08x}{}",
+            stream_id,
+            prev_ssrc,
+            ssrc,
+            if self.goodbye { " after RTCP BYE" } else { "" }
+        );
+        if let Some(reorder) = &mut self.reorder {
+            while let Some((loss, pkt)) = reorder.flush() {
+                let pkt = pkt.into_packet(conn_ctx, timeline, stream_id, prev_ssrc, loss)?;
+                self.queued.push_back(PacketItem::RtpPacket(pkt));
+            }
+            reorder.reset();
+        }
+        self.queued
+            .push_back(PacketItem::Discontinuity(Discontinuity {
+                ctx: candidate.pkts[0].0,
+                stream_id,
+                prev_ssrc,
+                ssrc,
+                goodbye: self.goodbye,
+            }));
+        self.ssrc = Some(ssrc);
+        self.next_seq = None;
+        self.stats = ReceptionStats::default();
+        self.wall_clock.reset();
+        self.sync_anchor = None;
+        self.nacks.clear();
+        self.goodbye = false;
+        timeline.reset();
+        for (msg_ctx, channel_id, data) in candidate.pkts {
+            if let Some(item) = self.rtp_inner(
+                session_options,
+                conn_ctx,
+                &msg_ctx,
+                timeline,
+                channel_id,
+                stream_id,
+                data,
+            )? {
+                self.queued.push_back(item);
+            }
+        }
+        Ok(None)
     }
 
     pub fn rtcp(
@@ -214,28 +1413,28 @@ impl StrictSequenceChecker {
         stream_id: usize,
         data: Bytes,
     ) -> Result<Option<PacketItem>, String> {
-        let mut sr = None;
+        let mut rtp_timestamp = None;
         let mut i = 0;
-        let mut data = &data[..];
-        while !data.is_empty() {
-            let (pkt, rest) = crate::rtcp::Packet::parse(data)?;
-            data = rest;
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let (pkt, r) = crate::rtcp::Packet::parse(rest)?;
+            rest = r;
             match pkt {
                 crate::rtcp::Packet::SenderReport(pkt) => {
                     if i > 0 {
                         return Err("RTCP SR must be first in packet".into());
                     }
-                    let timestamp =
-                        timeline
-                            .place(pkt.rtp_timestamp())
-                            .map_err(|mut description| {
-                                description.push_str(" in RTCP SR");
-                                description
-                            })?;
-
                     let ssrc = pkt.ssrc();
                     if matches!(self.ssrc, Some(s) if s != ssrc) {
-                        if session_options.ignore_spurious_data {
+                        if self.resync.is_some() {
+                            // The source may be about to change. Skip only this
+                            // report; a BYE may follow in the same packet.
+                            log::debug!(
+                                "Ignoring RTCP SR with ssrc={:08x} while expecting ssrc={:08x?}",
+                                ssrc,
+                                self.ssrc
+                            );
+                        } else if session_options.ignore_spurious_data {
                             log::debug!(
                                 "Ignoring spurious RTCP data with ssrc={:08x} while \
                                          expecting ssrc={:08x?}",
@@ -249,20 +1448,682 @@ impl StrictSequenceChecker {
                                 self.ssrc, ssrc
                             ));
                         }
+                    } else {
+                        let timestamp =
+                            timeline
+                                .place(pkt.rtp_timestamp())
+                                .map_err(|mut description| {
+                                    description.push_str(" in RTCP SR");
+                                    description
+                                })?;
+                        self.ssrc = Some(ssrc);
+                        self.stats
+                            .sender_report(pkt.ntp_timestamp(), timestamp, msg_ctx.received);
+                        self.wall_clock.sender_report(
+                            timestamp,
+                            pkt.ntp_timestamp(),
+                            msg_ctx.received_wall(),
+                        );
+                        timeline.set_sender_report_rate(self.wall_clock.clock_rate());
+                        self.sync_anchor = Some((timestamp.timestamp(), pkt.ntp_timestamp()));
+                        rtp_timestamp = Some(timestamp);
+                    }
+                }
+                crate::rtcp::Packet::Goodbye(pkt) => {
+                    if let Some(ssrc) = self.ssrc {
+                        if self.resync.is_some() && pkt.ssrcs().any(|s| s == ssrc) {
+                            log::info!(
+                                "stream {}: RTCP BYE for ssrc={:08x}, reason {:?}",
+                                stream_id,
+                                ssrc,
+                                pkt.reason().map(String::from_utf8_lossy)
+                            );
+                            self.goodbye = true;
+                        }
                     }
-                    self.ssrc = Some(ssrc);
-
-                    sr = Some(SenderReport {
-                        stream_id,
-                        ctx: *msg_ctx,
-                        timestamp,
-                        ntp_timestamp: pkt.ntp_timestamp(),
-                    });
                 }
                 crate::rtcp::Packet::Unknown(pkt) => debug!("rtcp: {:?}", pkt.payload_type()),
+                _ => {}
             }
             i += 1;
         }
-        Ok(sr.map(PacketItem::SenderReport))
+        Ok(Some(PacketItem::Rtcp(ReceivedCompoundPacket {
+            ctx: *msg_ctx,
+            stream_id,
+            rtp_timestamp,
+            raw: data,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use std::num::{NonZeroU16, NonZeroU32};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        ExtMap, ExtensionElement, HeaderExtension, ReceptionStats, SequenceDecisions,
+        StrictSequenceChecker,
+    };
+    use crate::client::{PacketItem, SequencePolicy, SessionOptions, Timeline};
+    use crate::rtcp::ReportBlock;
+    use crate::{ConnectionContext, RtspMessageContext};
+
+    fn rtp(ssrc: u32, seq: u16, timestamp: u32) -> Bytes {
+        let mut pkt = vec![0x80, 0x60];
+        pkt.extend_from_slice(&seq.to_be_bytes());
+        pkt.extend_from_slice(&timestamp.to_be_bytes());
+        pkt.extend_from_slice(&ssrc.to_be_bytes());
+        pkt.extend_from_slice(b"hello");
+        Bytes::from(pkt)
+    }
+
+    #[test]
+    fn ssrc_change() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+        let mut checker =
+            StrictSequenceChecker::new(None, None).resync_on_ssrc_change(NonZeroU16::new(2));
+        let push = |checker: &mut StrictSequenceChecker, timeline: &mut Timeline, data| {
+            checker
+                .rtp(&opts, &conn_ctx, &msg_ctx, timeline, 0, 0, data)
+                .unwrap()
+        };
+        for seq in 1..=2 {
+            match push(
+                &mut checker,
+                &mut timeline,
+                rtp(0x1111, seq, 9000 * u32::from(seq)),
+            ) {
+                Some(PacketItem::RtpPacket(p)) => assert_eq!(p.sequence_number, seq),
+                o => panic!("unexpected item {:#?}", o),
+            }
+        }
+
+        // A single packet from another source is buffered...
+        assert!(push(&mut checker, &mut timeline, rtp(0x2222, 100, 500)).is_none());
+        assert!(checker.pull().is_none());
+
+        // ...but a packet from the original source means it's not sustained.
+        assert!(push(&mut checker, &mut timeline, rtp(0x1111, 3, 27000)).is_some());
+        assert!(push(&mut checker, &mut timeline, rtp(0x2222, 101, 1400)).is_none());
+
+        // The second consecutive packet on the new source causes a switch.
+        match push(&mut checker, &mut timeline, rtp(0x2222, 102, 2300)) {
+            Some(PacketItem::Discontinuity(d)) => {
+                assert_eq!(d.prev_ssrc, 0x1111);
+                assert_eq!(d.ssrc, 0x2222);
+                assert!(!d.goodbye);
+            }
+            o => panic!("unexpected item {:#?}", o),
+        }
+        match checker.pull() {
+            Some(PacketItem::RtpPacket(p)) => {
+                assert_eq!(p.sequence_number, 101);
+                assert_eq!(p.timestamp.elapsed(), 0);
+                assert_eq!(p.loss, 0);
+            }
+            o => panic!("unexpected item {:#?}", o),
+        }
+        match checker.pull() {
+            Some(PacketItem::RtpPacket(p)) => {
+                assert_eq!(p.sequence_number, 102);
+                assert_eq!(p.timestamp.elapsed(), 900);
+            }
+            o => panic!("unexpected item {:#?}", o),
+        }
+        assert!(checker.pull().is_none());
+
+        // After a BYE, the next source is switched to immediately.
+        let bye = Bytes::from_static(b"\x81\xcb\x00\x01\x00\x00\x22\x22");
+        assert!(checker
+            .rtcp(&opts, &msg_ctx, &mut timeline, 0, bye)
+            .unwrap()
+            .is_some());
+        match push(&mut checker, &mut timeline, rtp(0x3333, 7, 0)) {
+            Some(PacketItem::Discontinuity(d)) => {
+                assert_eq!(d.prev_ssrc, 0x2222);
+                assert_eq!(d.ssrc, 0x3333);
+                assert!(d.goodbye);
+            }
+            o => panic!("unexpected item {:#?}", o),
+        }
+        match checker.pull() {
+            Some(PacketItem::RtpPacket(p)) => assert_eq!(p.ssrc, 0x3333),
+            o => panic!("unexpected item {:#?}", o),
+        }
+    }
+
+    #[test]
+    fn sender_report_from_new_source() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let mut timeline = Timeline::new(None, 90_000, NonZeroU32::new(10)).unwrap();
+        let mut checker =
+            StrictSequenceChecker::new(None, None).resync_on_ssrc_change(NonZeroU16::new(2));
+        assert!(checker
+            .rtp(
+                &opts,
+                &conn_ctx,
+                &msg_ctx,
+                &mut timeline,
+                0,
+                0,
+                rtp(0x1111, 1, 0)
+            )
+            .unwrap()
+            .is_some());
+
+        // A SR from the new source, far from the old source's timeline, followed
+        // by a BYE for the old source. The SR is skipped; the BYE still counts.
+        let mut compound = vec![0x80, 0xc8, 0x00, 0x06, 0x00, 0x00, 0x22, 0x22];
+        compound.extend_from_slice(&[0; 8]); // NTP timestamp
+        compound.extend_from_slice(&0x8000_0000u32.to_be_bytes());
+        compound.extend_from_slice(&[0; 8]); // packet and octet counts
+        compound.extend_from_slice(b"\x81\xcb\x00\x01\x00\x00\x11\x11");
+        match checker
+            .rtcp(&opts, &msg_ctx, &mut timeline, 0, Bytes::from(compound))
+            .unwrap()
+        {
+            Some(PacketItem::Rtcp(p)) => assert!(p.rtp_timestamp.is_none()),
+            o => panic!("unexpected item {:#?}", o),
+        }
+        assert!(checker.goodbye);
+        assert_eq!(checker.ssrc, Some(0x1111));
+    }
+
+    #[test]
+    fn reorder() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let mut msg_ctx = RtspMessageContext::dummy();
+        let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+        let mut checker = StrictSequenceChecker::new(None, Some(1))
+            .reorder_buffer(Duration::from_millis(50), NonZeroU16::new(8).unwrap());
+        let start = msg_ctx.received;
+        let mut released = Vec::new();
+
+        // 1 and 2 are swapped, 3 is never received, and 4 is duplicated.
+        for (i, &seq) in [2u16, 1, 5, 4, 4, 6].iter().enumerate() {
+            msg_ctx.received = start + Duration::from_millis(20 * i as u64);
+            let data = rtp(0x1111, seq, 900 * u32::from(seq));
+            let mut item = checker
+                .rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data)
+                .unwrap();
+            while let Some(PacketItem::RtpPacket(p)) = item {
+                released.push((p.sequence_number, p.loss, p.timestamp.elapsed()));
+                item = checker.pull();
+            }
+        }
+        assert_eq!(
+            released,
+            vec![
+                (1, 0, 0),
+                (2, 0, 900),
+                (4, 1, 2700),
+                (5, 0, 3600),
+                (6, 0, 4500)
+            ]
+        );
+
+        // 1 is now late.
+        let data = rtp(0x1111, 1, 900);
+        assert!(checker
+            .rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn reorder_backward_jump() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        for &policy in &[
+            SequencePolicy::Strict,
+            SequencePolicy::Tolerant,
+            SequencePolicy::ResyncOnJump,
+        ] {
+            let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+            let mut checker = StrictSequenceChecker::new(None, Some(1000))
+                .sequence_policy(policy)
+                .reorder_buffer(Duration::from_millis(50), NonZeroU16::new(8).unwrap());
+            let mut push = |seq: u16| {
+                let data = rtp(0x1111, seq, 900 * u32::from(seq));
+                checker.rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data)
+            };
+            for seq in 1000..=1002 {
+                assert!(matches!(push(seq), Ok(Some(PacketItem::RtpPacket(_)))));
+            }
+
+            // A packet slightly behind is merely late and dropped by the buffer...
+            assert!(matches!(push(995), Ok(None)));
+
+            // ...but one far behind is a jump, handled by the policy.
+            match policy {
+                SequencePolicy::Strict => {
+                    push(10).unwrap_err();
+                }
+                SequencePolicy::Tolerant => {
+                    assert!(matches!(push(10), Ok(None)));
+                    assert!(matches!(push(11), Ok(None)));
+                    assert_eq!(checker.decisions.dropped_late, 2);
+                }
+                SequencePolicy::ResyncOnJump => {
+                    assert!(matches!(push(10), Ok(None)));
+                    match push(11) {
+                        Ok(Some(PacketItem::Discontinuity(d))) => assert_eq!(d.ssrc, 0x1111),
+                        o => panic!("unexpected result {:#?}", o),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn retransmission() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+        let mut checker = StrictSequenceChecker::new(None, Some(1))
+            .reorder_buffer(Duration::from_secs(1), NonZeroU16::new(8).unwrap())
+            .retransmission(97, 96, true);
+        let mut released = Vec::new();
+        let mut push = |checker: &mut StrictSequenceChecker, data| {
+            let mut item = checker
+                .rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data)
+                .unwrap();
+            while let Some(PacketItem::RtpPacket(p)) = item {
+                released.push((p.sequence_number, p.loss, p.payload.clone()));
+                item = checker.pull();
+            }
+        };
+
+        // 3 and 4 are lost and requested.
+        for &seq in &[1u16, 2, 5] {
+            push(&mut checker, rtp(0x1111, seq, 900 * u32::from(seq)));
+        }
+        assert!(checker.has_nacks());
+        assert_eq!(checker.take_nacks(), vec![3, 4]);
+        assert!(!checker.has_nacks());
+
+        // They're retransmitted on a separate SSRC, out of order, and 3 twice.
+        let rtx = |seq: u16, osn: u16| {
+            let mut pkt = vec![0x80, 0x61];
+            pkt.extend_from_slice(&seq.to_be_bytes());
+            pkt.extend_from_slice(&(900 * u32::from(osn)).to_be_bytes());
+            pkt.extend_from_slice(&0x2222u32.to_be_bytes());
+            pkt.extend_from_slice(&osn.to_be_bytes());
+            pkt.extend_from_slice(b"again");
+            Bytes::from(pkt)
+        };
+        push(&mut checker, rtx(100, 4));
+        push(&mut checker, rtx(101, 3));
+        push(&mut checker, rtx(102, 3));
+        assert_eq!(
+            released,
+            vec![
+                (1, 0, Bytes::from_static(b"hello")),
+                (2, 0, Bytes::from_static(b"hello")),
+                (3, 0, Bytes::from_static(b"again")),
+                (4, 0, Bytes::from_static(b"again")),
+                (5, 0, Bytes::from_static(b"hello")),
+            ]
+        );
+        let stats = checker.stats(NonZeroU32::new(90_000).unwrap(), msg_ctx.received);
+        assert_eq!(stats.ssrc, Some(0x1111));
+        assert_eq!(stats.retransmitted, 2);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.packets_lost, 2);
+    }
+
+    #[test]
+    fn sequence_policy() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let clock_rate = NonZeroU32::new(90_000).unwrap();
+        let run = |checker: &mut StrictSequenceChecker, seqs: &[u16]| {
+            let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+            let mut items = Vec::new();
+            for &seq in seqs {
+                let data = rtp(0x1111, seq, 0);
+                let mut item = checker
+                    .rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data)
+                    .unwrap();
+                while let Some(i) = item {
+                    items.push(match i {
+                        PacketItem::RtpPacket(p) => (p.sequence_number, p.loss),
+                        PacketItem::Discontinuity(d) => {
+                            assert_eq!((d.prev_ssrc, d.ssrc), (0x1111, 0x1111));
+                            (0, u16::MAX)
+                        }
+                        o => panic!("unexpected item: {:#?}", o),
+                    });
+                    item = checker.pull();
+                }
+            }
+            items
+        };
+
+        // Strict fails on a duplicate.
+        let mut checker = StrictSequenceChecker::new(None, Some(1));
+        let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+        for &(seq, ok) in &[(1, true), (1, false)] {
+            let data = rtp(0x1111, seq, 0);
+            let r = checker.rtp(&opts, &conn_ctx, &msg_ctx, &mut timeline, 0, 0, data);
+            assert_eq!(r.is_ok(), ok);
+        }
+
+        // Tolerant drops duplicates and late packets.
+        let mut checker =
+            StrictSequenceChecker::new(None, Some(1)).sequence_policy(SequencePolicy::Tolerant);
+        assert_eq!(
+            run(&mut checker, &[1, 2, 2, 1, 4]),
+            vec![(1, 0), (2, 0), (4, 1)]
+        );
+        let stats = checker.stats(clock_rate, msg_ctx.received);
+        assert_eq!(
+            stats.sequence,
+            SequenceDecisions {
+                dropped_late: 2,
+                dropped_jump: 0,
+                resyncs: 0
+            }
+        );
+
+        // ResyncOnJump drops unconfirmed jumps and restarts on confirmed ones.
+        let mut checker =
+            StrictSequenceChecker::new(None, Some(1)).sequence_policy(SequencePolicy::ResyncOnJump);
+        assert_eq!(
+            run(
+                &mut checker,
+                &[1, 2, 3, 0x9000, 0x5000, 0x5001, 0x5002, 0x5001]
+            ),
+            vec![
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (0, u16::MAX),
+                (0x5001, 0),
+                (0x5002, 0)
+            ]
+        );
+        let stats = checker.stats(clock_rate, msg_ctx.received);
+        assert_eq!(
+            stats.sequence,
+            SequenceDecisions {
+                dropped_late: 1,
+                dropped_jump: 2,
+                resyncs: 1
+            }
+        );
+        assert_eq!(stats.packets_received, 5);
+        assert_eq!(stats.packets_lost, 0);
+    }
+
+    #[test]
+    fn align() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let now = msg_ctx
+            .received_wall()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let local_base = crate::UNIX_EPOCH.0 + (now.as_secs() << 32);
+        let sr = |ssrc: u32, rtp_timestamp: u32, ntp: u64| {
+            let mut pkt = vec![0x80, 0xc8, 0x00, 0x06];
+            pkt.extend_from_slice(&ssrc.to_be_bytes());
+            pkt.extend_from_slice(&ntp.to_be_bytes());
+            pkt.extend_from_slice(&rtp_timestamp.to_be_bytes());
+            pkt.extend_from_slice(&[0; 8]);
+            Bytes::from(pkt)
+        };
+        // Also try a sender whose clock is a day behind local time. Its sender
+        // reports are useless for wall-clock time but still synchronize streams.
+        for &base in &[local_base, local_base - (86_400 << 32)] {
+            let mut epoch = None;
+
+            // Video's NPT 0 is at `base`. Its sender report sets the epoch.
+            let mut video_timeline = Timeline::new(None, 90_000, None).unwrap();
+            let mut video = StrictSequenceChecker::new(None, None);
+            video
+                .rtp(
+                    &opts,
+                    &conn_ctx,
+                    &msg_ctx,
+                    &mut video_timeline,
+                    0,
+                    0,
+                    rtp(0x1111, 1, 1_000),
+                )
+                .unwrap();
+            video
+                .rtcp(
+                    &opts,
+                    &msg_ctx,
+                    &mut video_timeline,
+                    0,
+                    sr(0x1111, 91_000, base + (1 << 32)),
+                )
+                .unwrap();
+            video.align(0, &mut video_timeline, &mut epoch);
+            assert_eq!(epoch, Some(crate::NtpTimestamp(base)));
+            assert_eq!(
+                video_timeline.advance_to(181_000).unwrap().elapsed(),
+                180_000
+            );
+
+            // Audio's first packet is half a second later, so its NPT shifts forward.
+            let mut audio_timeline = Timeline::new(None, 8_000, None).unwrap();
+            let mut audio = StrictSequenceChecker::new(None, None);
+            audio
+                .rtp(
+                    &opts,
+                    &conn_ctx,
+                    &msg_ctx,
+                    &mut audio_timeline,
+                    2,
+                    1,
+                    rtp(0x2222, 1, 50),
+                )
+                .unwrap();
+            audio
+                .rtcp(
+                    &opts,
+                    &msg_ctx,
+                    &mut audio_timeline,
+                    1,
+                    sr(0x2222, 8_050, base + (3 << 31)),
+                )
+                .unwrap();
+            audio.align(1, &mut audio_timeline, &mut epoch);
+            assert_eq!(audio_timeline.advance_to(8_050).unwrap().elapsed(), 12_000);
+
+            // A consistent following report doesn't move it further.
+            audio
+                .rtcp(
+                    &opts,
+                    &msg_ctx,
+                    &mut audio_timeline,
+                    1,
+                    sr(0x2222, 16_050, base + (5 << 31)),
+                )
+                .unwrap();
+            audio.align(1, &mut audio_timeline, &mut epoch);
+            assert_eq!(audio_timeline.advance_to(16_050).unwrap().elapsed(), 20_000);
+        }
+    }
+
+    #[test]
+    fn header_extension() {
+        let opts = SessionOptions::default();
+        let conn_ctx = ConnectionContext::dummy();
+        let msg_ctx = RtspMessageContext::dummy();
+        let mut timeline = Timeline::new(None, 90_000, None).unwrap();
+        let mut checker = StrictSequenceChecker::new(None, None);
+
+        // One-byte elements: id 1 with 2 bytes, padding, id 2 with 1 byte.
+        let mut pkt = vec![0x90, 0x60, 0x00, 0x01];
+        pkt.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0x11, 0x11]);
+        pkt.extend_from_slice(&[0xbe, 0xde, 0x00, 0x02]);
+        pkt.extend_from_slice(&[0x11, 0xaa, 0xbb, 0x00, 0x20, 0xcc, 0x00, 0x00]);
+        pkt.extend_from_slice(b"hello");
+        let p = match checker
+            .rtp(
+                &opts,
+                &conn_ctx,
+                &msg_ctx,
+                &mut timeline,
+                0,
+                0,
+                Bytes::from(pkt),
+            )
+            .unwrap()
+        {
+            Some(PacketItem::RtpPacket(p)) => p,
+            o => panic!("unexpected item {:#?}", o),
+        };
+        assert_eq!(&p.payload[..], b"hello");
+        let ext = p.extension.unwrap();
+        assert_eq!(ext.profile(), 0xbede);
+        let elements: Vec<_> = ext.elements().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            elements,
+            vec![
+                ExtensionElement {
+                    id: 1,
+                    data: b"\xaa\xbb",
+                },
+                ExtensionElement {
+                    id: 2,
+                    data: b"\xcc",
+                },
+            ]
+        );
+        assert_eq!(ext.element(2).unwrap(), b"\xcc");
+
+        // Two-byte elements, including an empty one.
+        let ext = HeaderExtension::new(
+            0x1000,
+            Bytes::from_static(b"\x03\x00\x04\x02\xdd\xee\x00\x00"),
+        );
+        assert_eq!(ext.element(3).unwrap().len(), 0);
+        assert_eq!(ext.element(4).unwrap(), b"\xdd\xee");
+
+        // An element which overflows the extension is an error.
+        let ext = HeaderExtension::new(0xbede, Bytes::from_static(b"\x13\xaa\xbb\xcc"));
+        assert!(ext.elements().unwrap().next().unwrap().is_err());
+
+        // Other profiles have no defined element format.
+        assert!(HeaderExtension::new(0x1234, Bytes::new())
+            .elements()
+            .is_none());
+    }
+
+    #[test]
+    fn extmap() {
+        let mut m = ExtMap::default();
+        m.add("1 urn:ietf:params:rtp-hdrext:ntp-64").unwrap();
+        m.add("3/recvonly urn:3gpp:video-orientation attr").unwrap();
+        assert!(m.add("1 urn:ietf:params:rtp-hdrext:ntp-64").is_err());
+        assert!(m.add("0 urn:ietf:params:rtp-hdrext:ntp-64").is_err());
+        assert!(m.add("1").is_err());
+        assert_eq!(m.uri(3), Some("urn:3gpp:video-orientation"));
+        assert_eq!(m.id("urn:ietf:params:rtp-hdrext:ntp-64"), Some(1));
+        assert_eq!(m.uri(2), None);
+    }
+
+    #[test]
+    fn reception_stats() {
+        let clock_rate = NonZeroU32::new(90_000).unwrap();
+        let start = Instant::now();
+        let mut stats = ReceptionStats::default();
+
+        // Sequence numbers 0xfffe through 0x0003 except 0x0001, evenly spaced
+        // 10 ms apart in both arrival and RTP time.
+        for &i in &[0u16, 1, 2, 4, 5] {
+            stats.rtp(
+                0xfffe_u16.wrapping_add(i),
+                900 * u32::from(i),
+                100,
+                start + Duration::from_millis(10 * u64::from(i)),
+                clock_rate,
+            );
+        }
+        stats.sender_report(
+            crate::NtpTimestamp(0xe436_2f99_cccc_cccc),
+            crate::Timestamp::new(4500, clock_rate, 0).unwrap(),
+            start + Duration::from_millis(50),
+        );
+        assert_eq!(
+            stats.report_block(0x1234, start + Duration::from_millis(1050)),
+            Some(ReportBlock {
+                ssrc: 0x1234,
+                fraction_lost: 42, // 1/6 * 256, rounded down.
+                cumulative_lost: 1,
+                extended_highest_seq: 0x1_0003,
+                jitter: 0,
+                last_sr: 0x2f99_cccc,
+                delay_since_last_sr: 0x1_0000,
+            })
+        );
+
+        // The fraction lost covers only the interval since the previous report.
+        stats.rtp(
+            0x0004,
+            5400,
+            100,
+            start + Duration::from_millis(60),
+            clock_rate,
+        );
+        let block = stats
+            .report_block(0x1234, start + Duration::from_millis(2050))
+            .unwrap();
+        assert_eq!(block.fraction_lost, 0);
+        assert_eq!(block.cumulative_lost, 1);
+        assert_eq!(block.extended_highest_seq, 0x1_0004);
+
+        // Jitter increases with variation in transit time.
+        stats.rtp(
+            0x0005,
+            6300,
+            100,
+            start + Duration::from_millis(80),
+            clock_rate,
+        );
+        let block = stats
+            .report_block(0x1234, start + Duration::from_millis(3050))
+            .unwrap();
+        assert_eq!(block.jitter, 900 / 16);
+
+        // Rates are measured once a second has elapsed.
+        let snapshot = stats.snapshot(Some(0x1234), clock_rate, start + Duration::from_millis(90));
+        assert_eq!(snapshot.packets_received, 7);
+        assert_eq!(snapshot.bytes_received, 700);
+        assert_eq!(snapshot.packets_lost, 1);
+        assert_eq!(snapshot.jitter, Duration::from_micros(625));
+        assert_eq!(snapshot.since_last_packet, Some(Duration::from_millis(10)));
+        assert!(snapshot.frame_rate.is_none());
+        stats.rtp(
+            0x0006,
+            90_000,
+            100,
+            start + Duration::from_secs(1),
+            clock_rate,
+        );
+        let snapshot = stats.snapshot(Some(0x1234), clock_rate, start + Duration::from_secs(1));
+        assert_eq!(snapshot.frame_rate, Some(7.));
+        assert_eq!(snapshot.bitrate, Some(5600));
+        assert_eq!(
+            snapshot.last_sender_report.unwrap().ntp_timestamp,
+            crate::NtpTimestamp(0xe436_2f99_cccc_cccc)
+        );
     }
 }
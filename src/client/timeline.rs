@@ -3,11 +3,99 @@
 
 use std::convert::TryFrom;
 use std::num::{NonZeroI32, NonZeroU32};
+use std::time::Instant;
 
 use crate::Timestamp;
 
 const MAX_FORWARD_TIME_JUMP_SECS: u32 = 10;
 
+/// The minimum span of RTP time over which to estimate the clock rate from
+/// arrival times. Shorter spans are dominated by network jitter.
+const MIN_DRIFT_SPAN_SECS: f64 = 10.;
+
+/// The maximum deviation from the nominal clock rate to believe, as a fraction.
+const MAX_DRIFT: f64 = 0.05;
+
+/// Estimates a stream's actual RTP clock rate and integrates a drift-corrected
+/// timeline.
+///
+/// The local estimate is a least-squares fit of packet arrival time (per the
+/// local monotonic clock) against RTP time, computed incrementally with
+/// Welford's method to avoid loss of precision over long sessions. A rate
+/// measured from RTCP sender reports takes precedence when available.
+#[derive(Debug, Default)]
+struct Drift {
+    /// The first observed timestamp and its arrival time.
+    origin: Option<(i64, Instant)>,
+
+    /// The number of observations.
+    n: f64,
+
+    /// The running means of RTP ticks and arrival seconds since `origin`.
+    mean_ticks: f64,
+    mean_secs: f64,
+
+    /// The running sum of squared deviations of ticks, and of the product of
+    /// deviations of ticks and seconds.
+    m2_ticks: f64,
+    c_ticks_secs: f64,
+
+    /// The largest number of ticks since `origin` observed.
+    span: i64,
+
+    /// The clock rate measured from RTCP sender reports, if any.
+    sender_report_rate: Option<f64>,
+
+    /// The last observed timestamp and its corrected value.
+    last: Option<(i64, f64)>,
+}
+
+impl Drift {
+    fn observe(&mut self, timestamp: i64, received: Instant, nominal: f64) {
+        let (origin_ts, origin_received) = *self.origin.get_or_insert((timestamp, received));
+        let ticks = (timestamp - origin_ts) as f64;
+        let secs = received
+            .saturating_duration_since(origin_received)
+            .as_secs_f64();
+        self.n += 1.;
+        let d_ticks = ticks - self.mean_ticks;
+        self.mean_ticks += d_ticks / self.n;
+        self.mean_secs += (secs - self.mean_secs) / self.n;
+        self.m2_ticks += d_ticks * (ticks - self.mean_ticks);
+        self.c_ticks_secs += d_ticks * (secs - self.mean_secs);
+        self.span = self.span.max(timestamp - origin_ts);
+
+        // Integrate piecewise so that corrected timestamps stay continuous and
+        // monotonic as the estimate changes.
+        let corrected = match self.last {
+            None => timestamp as f64,
+            Some((last_ts, last_corrected)) => {
+                last_corrected + (timestamp - last_ts) as f64 * nominal / self.rate(nominal)
+            }
+        };
+        self.last = Some((timestamp, corrected));
+    }
+
+    /// Returns the estimated rate as measured against the local clock.
+    fn local_rate(&self, nominal: f64) -> Option<f64> {
+        if (self.span as f64) < MIN_DRIFT_SPAN_SECS * nominal || self.c_ticks_secs <= 0. {
+            return None;
+        }
+        Some(self.m2_ticks / self.c_ticks_secs).filter(|r| plausible(*r, nominal))
+    }
+
+    /// Returns the best available rate estimate, or `nominal`.
+    fn rate(&self, nominal: f64) -> f64 {
+        self.sender_report_rate
+            .or_else(|| self.local_rate(nominal))
+            .unwrap_or(nominal)
+    }
+}
+
+fn plausible(rate: f64, nominal: f64) -> bool {
+    (rate / nominal - 1.).abs() <= MAX_DRIFT
+}
+
 /// Creates [Timestamp]s (which don't wrap and can be converted to NPT aka normal play time)
 /// from 32-bit (wrapping) RTP timestamps. Unstable, exposed for benchmark.
 #[doc(hidden)]
@@ -23,6 +111,8 @@ pub struct Timeline {
 
     /// The same in seconds, for logging.
     max_forward_jump_secs: u32,
+
+    drift: Drift,
 }
 
 impl Timeline {
@@ -52,9 +142,99 @@ impl Timeline {
             max_forward_jump_secs: enforce_with_max_forward_jump_secs
                 .map(NonZeroU32::get)
                 .unwrap_or(0),
+            drift: Drift::default(),
         })
     }
 
+    pub fn clock_rate(&self) -> NonZeroU32 {
+        self.clock_rate
+    }
+
+    /// Forgets the starting timestamp, so that the next RTP timestamp starts a
+    /// fresh timeline. This is used when the stream's RTP source changes.
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.drift = Drift::default();
+    }
+
+    /// Records that the packet with the given timestamp (as returned by
+    /// [`Timeline::advance_to`]) arrived at `received`, for clock rate estimation.
+    pub fn observe(&mut self, timestamp: Timestamp, received: Instant) {
+        self.drift.observe(
+            timestamp.timestamp,
+            received,
+            f64::from(self.clock_rate.get()),
+        );
+    }
+
+    /// Sets the clock rate measured from RTCP sender reports, which is
+    /// preferred over the estimate from arrival times.
+    pub(crate) fn set_sender_report_rate(&mut self, rate: Option<f64>) {
+        let nominal = f64::from(self.clock_rate.get());
+        self.drift.sender_report_rate = rate.filter(|r| plausible(*r, nominal));
+    }
+
+    /// Returns the estimated actual clock rate in Hz, if known.
+    ///
+    /// This is measured from RTCP sender reports if available, or otherwise
+    /// from packet arrival times once enough have been observed.
+    pub fn estimated_clock_rate(&self) -> Option<f64> {
+        let nominal = f64::from(self.clock_rate.get());
+        self.drift
+            .sender_report_rate
+            .or_else(|| self.drift.local_rate(nominal))
+    }
+
+    /// Returns `timestamp` corrected for the difference between the estimated
+    /// and nominal clock rates, or `None` if there's no estimate yet.
+    ///
+    /// The corrected timeline advances at the nominal rate in real time. It's
+    /// integrated from observed packets, so this is most accurate for
+    /// timestamps near the last observed one.
+    ///
+    /// [`crate::client::Demuxed`] uses this to fill in each frame's
+    /// `corrected_timestamp`. Muxers can use that to write sample durations
+    /// which match the sender's actual clock.
+    pub fn corrected(&self, timestamp: Timestamp) -> Option<Timestamp> {
+        let rate = self.estimated_clock_rate()?;
+        let (last_ts, last_corrected) = self.drift.last?;
+        let corrected = last_corrected
+            + (timestamp.timestamp - last_ts) as f64 * f64::from(self.clock_rate.get()) / rate;
+        Timestamp::new(
+            corrected.round() as i64,
+            timestamp.clock_rate,
+            timestamp.start,
+        )
+    }
+
+    /// Returns the RTP timestamp at NPT 0, if it has been set.
+    pub(crate) fn start(&self) -> Option<u32> {
+        self.start
+    }
+
+    /// Moves the NPT epoch so that following timestamps have `offset` more
+    /// clock rate units elapsed.
+    ///
+    /// The epoch must have been set. Returns the amount by which
+    /// [Timestamp::timestamp] values changed, a multiple of 2^32 which is
+    /// normally 0. It's non-zero when the epoch's RTP timestamp wraps around.
+    pub(crate) fn shift_epoch(&mut self, offset: i64) -> i64 {
+        let start = i64::from(self.start.expect("epoch is set"));
+        let target = start - offset;
+        let new_start = target.rem_euclid(1 << 32);
+        let adjustment = new_start - target;
+        self.start = Some(new_start as u32);
+        self.timestamp += adjustment;
+        if let Some((ts, _)) = &mut self.drift.origin {
+            *ts += adjustment;
+        }
+        if let Some((ts, corrected)) = &mut self.drift.last {
+            *ts += adjustment;
+            *corrected += adjustment as f64;
+        }
+        adjustment
+    }
+
     /// Advances to the given (wrapping) RTP timestamp.
     ///
     /// If enforcement was enabled, this produces a monotonically increasing
@@ -130,6 +310,7 @@ impl Timeline {
 #[cfg(test)]
 mod tests {
     use std::num::NonZeroU32;
+    use std::time::{Duration, Instant};
 
     use super::Timeline;
 
@@ -172,6 +353,47 @@ mod tests {
         assert_eq!(t.advance_to(218250000).unwrap().elapsed(), 0);
     }
 
+    #[test]
+    fn drift() {
+        // A camera whose clock actually runs at 90,090 Hz, sending a frame
+        // every 1/30th of a second of real time.
+        let mut t = Timeline::new(Some(0), 90_000, None).unwrap();
+        let start = Instant::now();
+        let mut last = None;
+        for i in 0..=900u32 {
+            let ts = t.advance_to(i * 3003).unwrap();
+            t.observe(ts, start + Duration::from_secs(i.into()) / 30);
+            if i < 30 * 10 {
+                // Not enough history yet.
+                assert_eq!(t.estimated_clock_rate(), None);
+                assert_eq!(t.corrected(ts), None);
+            }
+            last = Some(ts);
+        }
+        let rate = t.estimated_clock_rate().unwrap();
+        assert!((rate - 90_090.).abs() < 1., "rate={}", rate);
+
+        // After 30 seconds, the corrected timestamp is close to 30 seconds at
+        // the nominal rate, rather than the 30.03 seconds the raw timestamp says.
+        let corrected = t.corrected(last.unwrap()).unwrap();
+        assert_eq!(last.unwrap().elapsed(), 2_702_700);
+        assert!(
+            (corrected.elapsed() - 2_700_000).abs() < 2_700_000 / 1000,
+            "corrected={}",
+            corrected
+        );
+
+        // A rate from sender reports takes precedence, if plausible.
+        t.set_sender_report_rate(Some(90_045.));
+        assert_eq!(t.estimated_clock_rate(), Some(90_045.));
+        t.set_sender_report_rate(Some(180_000.));
+        assert!((t.estimated_clock_rate().unwrap() - 90_090.).abs() < 1.);
+
+        // Resetting discards the estimate.
+        t.reset();
+        assert_eq!(t.estimated_clock_rate(), None);
+    }
+
     #[test]
     fn cast() {
         let a = 0x1FFFF_FFFFi64;
@@ -0,0 +1,282 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Mapping of RTP timestamps to wall-clock time via RTCP sender reports.
+
+use std::time::{Duration, SystemTime};
+
+use crate::NtpTimestamp;
+
+/// The maximum difference between a wall-clock time and the local time the
+/// corresponding data was received. Sender reports or mapped times beyond
+/// this are considered bogus (eg from a camera with a bad NTP configuration).
+const MAX_OFFSET: Duration = Duration::from_secs(60);
+
+/// The maximum difference between a sender report's NTP time and the time
+/// predicted by the current mapping before the report is considered an outlier.
+const MAX_ERROR_SECS: f64 = 0.05;
+
+/// The number of consecutive outliers which cause the mapping to restart from
+/// the latest sender report, as when the sender's clock has been stepped.
+const MAX_OUTLIERS: u32 = 3;
+
+/// The maximum drift between the sender's RTP and NTP clocks to apply when
+/// mapping timestamps to wall-clock time, as a fraction of the nominal clock rate.
+const MAX_DRIFT: f64 = 0.001;
+
+/// The weight given to each new drift measurement.
+const DRIFT_SMOOTHING: f64 = 0.25;
+
+/// Estimates the wall-clock time of RTP timestamps from a stream's RTCP sender
+/// reports.
+///
+/// Each accepted sender report anchors the mapping; the rate between anchors
+/// is the nominal clock rate adjusted by a smoothed measurement of the
+/// sender's drift.
+#[derive(Debug, Default)]
+pub(crate) struct WallClock {
+    /// The timeline timestamp and NTP time of the latest accepted sender report.
+    anchor: Option<(i64, NtpTimestamp)>,
+
+    /// The estimated duration of a RTP clock tick, in seconds, limited to
+    /// `MAX_DRIFT` from nominal.
+    secs_per_tick: f64,
+
+    /// As `secs_per_tick`, but without the `MAX_DRIFT` limit. This is what
+    /// [`WallClock::clock_rate`] reports, leaving plausibility checks to the caller.
+    measured_secs_per_tick: f64,
+
+    /// The number of consecutive sender reports rejected as outliers.
+    outliers: u32,
+
+    /// True iff `secs_per_tick` has been measured from at least two sender reports.
+    measured: bool,
+}
+
+impl WallClock {
+    /// Forgets the mapping, as when the RTP source changes.
+    pub(crate) fn reset(&mut self) {
+        *self = WallClock::default();
+    }
+
+    fn start(&mut self, timestamp: crate::Timestamp, ntp_timestamp: NtpTimestamp) {
+        let nominal = 1. / f64::from(timestamp.clock_rate().get());
+        *self = WallClock {
+            anchor: Some((timestamp.timestamp(), ntp_timestamp)),
+            secs_per_tick: nominal,
+            measured_secs_per_tick: nominal,
+            outliers: 0,
+            measured: false,
+        };
+    }
+
+    /// Updates the mapping from a sender report received at `received`.
+    pub(crate) fn sender_report(
+        &mut self,
+        timestamp: crate::Timestamp,
+        ntp_timestamp: NtpTimestamp,
+        received: SystemTime,
+    ) {
+        match ntp_to_system_time(ntp_timestamp) {
+            Some(t) if within(t, received, MAX_OFFSET) => {}
+            _ => {
+                log::debug!(
+                    "Ignoring RTCP SR with NTP time {} received at {:?}",
+                    ntp_timestamp,
+                    received
+                );
+                return;
+            }
+        }
+        let (anchor_ts, anchor_ntp) = match self.anchor {
+            None => return self.start(timestamp, ntp_timestamp),
+            Some(a) => a,
+        };
+        let ticks = timestamp.timestamp() - anchor_ts;
+        let actual = ntp_timestamp.0.wrapping_sub(anchor_ntp.0) as i64 as f64 / (1u64 << 32) as f64;
+        let predicted = ticks as f64 * self.secs_per_tick;
+        if (actual - predicted).abs() > MAX_ERROR_SECS {
+            self.outliers += 1;
+            if self.outliers < MAX_OUTLIERS {
+                log::debug!(
+                    "Ignoring RTCP SR which is {:.3} sec off from prediction",
+                    actual - predicted
+                );
+                return;
+            }
+            log::debug!(
+                "Restarting wall clock mapping after {} outliers",
+                self.outliers
+            );
+            return self.start(timestamp, ntp_timestamp);
+        }
+        self.outliers = 0;
+        if ticks > 0 {
+            let nominal = 1. / f64::from(timestamp.clock_rate().get());
+            let measured = actual / ticks as f64;
+            self.measured_secs_per_tick +=
+                (measured - self.measured_secs_per_tick) * DRIFT_SMOOTHING;
+            let limited = measured
+                .max(nominal * (1. - MAX_DRIFT))
+                .min(nominal * (1. + MAX_DRIFT));
+            self.secs_per_tick += (limited - self.secs_per_tick) * DRIFT_SMOOTHING;
+            self.measured = true;
+        }
+        self.anchor = Some((timestamp.timestamp(), ntp_timestamp));
+    }
+
+    /// Returns the sender's RTP clock rate in Hz, as measured against its NTP
+    /// clock, once at least two consistent sender reports have been received.
+    ///
+    /// Unlike the mapping, this isn't limited to `MAX_DRIFT`; the caller should
+    /// check that it's plausible.
+    pub(crate) fn clock_rate(&self) -> Option<f64> {
+        if self.measured {
+            Some(1. / self.measured_secs_per_tick)
+        } else {
+            None
+        }
+    }
+
+    /// Adjusts for a change of `adjustment` to all timeline timestamps, as
+    /// returned by [`super::Timeline::shift_epoch`].
+    pub(crate) fn shift(&mut self, adjustment: i64) {
+        if let Some((ts, _)) = &mut self.anchor {
+            *ts += adjustment;
+        }
+    }
+
+    /// Estimates the wall-clock time of `timestamp`, given that it was received at `received`.
+    ///
+    /// Falls back to `received` if there's no mapping or the estimate is out of bounds.
+    pub(crate) fn map(&self, timestamp: crate::Timestamp, received: SystemTime) -> SystemTime {
+        let (anchor_ts, anchor_ntp) = match self.anchor {
+            None => return received,
+            Some(a) => a,
+        };
+        let secs = (timestamp.timestamp() - anchor_ts) as f64 * self.secs_per_tick;
+        let offset = Duration::from_secs_f64(secs.abs());
+        let t = ntp_to_system_time(anchor_ntp).and_then(|a| {
+            if secs >= 0. {
+                a.checked_add(offset)
+            } else {
+                a.checked_sub(offset)
+            }
+        });
+        match t {
+            Some(t) if within(t, received, MAX_OFFSET) => t,
+            _ => received,
+        }
+    }
+}
+
+fn ntp_to_system_time(ntp: NtpTimestamp) -> Option<SystemTime> {
+    let since_epoch = ntp.0.checked_sub(crate::UNIX_EPOCH.0)?;
+    let nanos = ((since_epoch & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(since_epoch >> 32, nanos as u32))
+}
+
+/// Returns true iff `a` and `b` are no more than `max` apart.
+fn within(a: SystemTime, b: SystemTime, max: Duration) -> bool {
+    match a.duration_since(b) {
+        Ok(d) => d <= max,
+        Err(e) => e.duration() <= max,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::time::{Duration, SystemTime};
+
+    use super::WallClock;
+    use crate::{NtpTimestamp, Timestamp};
+
+    const CLOCK_RATE: u32 = 90_000;
+
+    fn ts(timestamp: i64) -> Timestamp {
+        Timestamp::new(timestamp, NonZeroU32::new(CLOCK_RATE).unwrap(), 0).unwrap()
+    }
+
+    /// Returns the NTP timestamp `secs` after `base`.
+    fn ntp(base: SystemTime, secs: f64) -> NtpTimestamp {
+        let d =
+            base.duration_since(SystemTime::UNIX_EPOCH).unwrap() + Duration::from_secs_f64(secs);
+        NtpTimestamp(
+            crate::UNIX_EPOCH.0
+                + (d.as_secs() << 32)
+                + ((u64::from(d.subsec_nanos()) << 32) / 1_000_000_000),
+        )
+    }
+
+    fn assert_near(a: SystemTime, b: SystemTime) {
+        let diff = match a.duration_since(b) {
+            Ok(d) => d,
+            Err(e) => e.duration(),
+        };
+        assert!(diff < Duration::from_millis(1), "{:?} vs {:?}", a, b);
+    }
+
+    #[test]
+    fn mapping() {
+        let base = SystemTime::now();
+        let late = base + Duration::from_secs(30);
+        let mut c = WallClock::default();
+
+        // Before any sender report, it falls back to the receive time.
+        assert_eq!(c.map(ts(0), late), late);
+
+        // The first sender report anchors the mapping at the nominal rate.
+        c.sender_report(ts(0), ntp(base, 0.), base);
+        assert_near(c.map(ts(90_000), late), base + Duration::from_secs(1));
+        assert_near(c.map(ts(-90_000), late), base - Duration::from_secs(1));
+
+        // A sender clock running 500 ppm fast pulls the rate toward it.
+        c.sender_report(ts(900_000), ntp(base, 10.005), base);
+        let one_sec_later = c.map(ts(990_000), late);
+        let drift = one_sec_later
+            .duration_since(base + Duration::from_secs_f64(10.005 + 1.))
+            .unwrap();
+        assert!(drift > Duration::from_micros(100) && drift < Duration::from_micros(200));
+
+        // A single report with a wild NTP time is ignored.
+        c.sender_report(ts(1_800_000), ntp(base, 25.), base);
+        assert_near(
+            c.map(ts(900_000), late),
+            base + Duration::from_secs_f64(10.005),
+        );
+
+        // Reports too far from the local receive time are ignored, and mapped
+        // times too far from it fall back.
+        c.sender_report(ts(1_800_000), ntp(base, 3600.), base);
+        assert_near(
+            c.map(ts(900_000), late),
+            base + Duration::from_secs_f64(10.005),
+        );
+        let very_late = base + Duration::from_secs(3600);
+        assert_eq!(c.map(ts(900_000), very_late), very_late);
+
+        // A sustained step is accepted.
+        for i in 0..3 {
+            c.sender_report(ts(1_800_000 + 90_000 * i), ntp(base, 40. + i as f64), base);
+        }
+        assert_near(
+            c.map(ts(1_800_000 + 180_000), late),
+            base + Duration::from_secs(42),
+        );
+    }
+
+    #[test]
+    fn clock_rate_beyond_mapping_limit() {
+        // A sender whose RTP clock runs 1% fast. The mapping limits its drift,
+        // but the reported clock rate converges on the actual rate.
+        let base = SystemTime::now();
+        let mut c = WallClock::default();
+        assert_eq!(c.clock_rate(), None);
+        for i in 0..12 {
+            c.sender_report(ts(181_800 * i), ntp(base, 2. * i as f64), base);
+        }
+        let rate = c.clock_rate().unwrap();
+        assert!(rate > 90_800. && rate < 90_900., "rate={}", rate);
+    }
+}
@@ -180,83 +180,6 @@ impl AudioSpecificConfig {
     }
 }
 
-/// Overwrites a buffer with a varint length, returning the length of the length.
-/// See ISO/IEC 14496-1 section 8.3.3.
-fn set_length(len: usize, data: &mut [u8]) -> Result<usize, String> {
-    if len < 1 << 7 {
-        data[0] = len as u8;
-        Ok(1)
-    } else if len < 1 << 14 {
-        data[0] = ((len & 0x7F) | 0x80) as u8;
-        data[1] = (len >> 7) as u8;
-        Ok(2)
-    } else if len < 1 << 21 {
-        data[0] = ((len & 0x7F) | 0x80) as u8;
-        data[1] = (((len >> 7) & 0x7F) | 0x80) as u8;
-        data[2] = (len >> 14) as u8;
-        Ok(3)
-    } else if len < 1 << 28 {
-        data[0] = ((len & 0x7F) | 0x80) as u8;
-        data[1] = (((len >> 7) & 0x7F) | 0x80) as u8;
-        data[2] = (((len >> 14) & 0x7F) | 0x80) as u8;
-        data[3] = (len >> 21) as u8;
-        Ok(4)
-    } else {
-        // BaseDescriptor sets a maximum length of 2**28 - 1.
-        return Err(format!("length {} too long", len));
-    }
-}
-
-/// Writes a box length and type (four-character code) for everything appended
-/// in the supplied scope.
-macro_rules! write_box {
-    ($buf:expr, $fourcc:expr, $b:block) => {{
-        let _: &mut BytesMut = $buf; // type-check.
-        let pos_start = $buf.len();
-        let fourcc: &[u8; 4] = $fourcc;
-        $buf.extend_from_slice(&[0, 0, 0, 0, fourcc[0], fourcc[1], fourcc[2], fourcc[3]]);
-        let r = {
-            $b;
-        };
-        let pos_end = $buf.len();
-        let len = pos_end.checked_sub(pos_start).unwrap();
-        $buf[pos_start..pos_start + 4].copy_from_slice(
-            &u32::try_from(len)
-                .map_err(|_| format!("box length {} exceeds u32::MAX", len))?
-                .to_be_bytes()[..],
-        );
-        r
-    }};
-}
-
-/// Writes a descriptor tag and length for everything appended in the supplied
-/// scope. See ISO/IEC 14496-1 Table 1 for the `tag`.
-macro_rules! write_descriptor {
-    ($buf:expr, $tag:expr, $b:block) => {{
-        let _: &mut BytesMut = $buf; // type-check.
-        let _: u8 = $tag;
-        let pos_start = $buf.len();
-
-        // Overallocate room for the varint length and append the body.
-        $buf.extend_from_slice(&[$tag, 0, 0, 0, 0]);
-        let r = {
-            $b;
-        };
-        let pos_end = $buf.len();
-
-        // Then fix it afterward: write the correct varint length and move
-        // the body backward. This approach seems better than requiring the
-        // caller to first prepare the body in a separate allocation (and
-        // awkward code ordering), or (as ffmpeg does) writing a "varint"
-        // which is padded with leading 0x80 bytes.
-        let len = pos_end.checked_sub(pos_start + 5).unwrap();
-        let len_len = set_length(len, &mut $buf[pos_start + 1..pos_start + 4])?;
-        $buf.copy_within(pos_start + 5..pos_end, pos_start + 1 + len_len);
-        $buf.truncate(pos_end + len_len - 4);
-        r
-    }};
-}
-
 /// Returns an MP4AudioSampleEntry (`mp4a`) box as in ISO/IEC 14496-14 section 5.6.1.
 /// `config` should be a raw AudioSpecificConfig.
 fn make_sample_entry(
@@ -422,6 +345,9 @@ fn parse_format_specific_params(
 pub(crate) struct Depacketizer {
     config: AudioSpecificConfig,
     state: DepacketizerState,
+
+    /// The number of fragmented frames discarded because of packet loss.
+    discarded: u64,
 }
 
 #[derive(Debug)]
@@ -450,6 +376,8 @@ struct Aggregate {
     /// The buffer, positioned at frame 0's header.
     buf: Bytes,
 
+    extension: Option<crate::client::rtp::HeaderExtension>,
+
     /// The index in range `[0, frame_count)` of the next frame to output.
     frame_i: u16,
 
@@ -479,6 +407,7 @@ struct Fragment {
 
     size: u16,
     buf: BytesMut,
+    extension: Option<crate::client::rtp::HeaderExtension>,
 }
 
 #[derive(Debug)]
@@ -508,6 +437,7 @@ impl Depacketizer {
         Ok(Self {
             config,
             state: DepacketizerState::Idle { prev_loss: 0 },
+            discarded: 0,
         })
     }
 
@@ -515,12 +445,21 @@ impl Depacketizer {
         Some(super::Parameters::Audio(self.config.to_parameters()))
     }
 
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = DepacketizerState::Idle { prev_loss: 0 };
+    }
+
     pub(super) fn push(&mut self, mut pkt: Packet) -> Result<(), String> {
         if pkt.loss > 0 && matches!(self.state, DepacketizerState::Fragmented(_)) {
             log::debug!(
                 "Discarding fragmented AAC frame due to loss of {} RTP packets.",
                 pkt.loss
             );
+            self.discarded += 1;
             self.state = DepacketizerState::Idle { prev_loss: 0 };
         }
 
@@ -563,6 +502,7 @@ impl Depacketizer {
                     std::cmp::Ordering::Less => {
                         if pkt.mark {
                             if frag.loss > 0 {
+                                self.discarded += 1;
                                 self.state = DepacketizerState::Idle {
                                     prev_loss: frag.loss,
                                 };
@@ -590,6 +530,9 @@ impl Depacketizer {
                             frame_length: NonZeroU32::from(self.config.frame_length),
                             stream_id: pkt.stream_id,
                             timestamp: pkt.timestamp,
+                            capture_time: pkt.ctx.received_wall(),
+                            corrected_timestamp: None,
+                            extension: frag.extension.take(),
                             data: std::mem::take(&mut frag.buf).freeze(),
                         });
                     }
@@ -611,6 +554,7 @@ impl Depacketizer {
                     sequence_number: pkt.sequence_number,
                     timestamp: pkt.timestamp,
                     buf: pkt.payload,
+                    extension: pkt.extension,
                     frame_i: 0,
                     frame_count: au_headers_count,
                     data_off,
@@ -675,6 +619,7 @@ impl Depacketizer {
                         loss_since_mark: agg.loss_since_mark,
                         size: size as u16,
                         buf,
+                        extension: agg.extension,
                     });
                     return Ok(None);
                 }
@@ -708,6 +653,9 @@ impl Depacketizer {
                             ))
                         }
                     },
+                    capture_time: agg.ctx.received_wall(),
+                    corrected_timestamp: None,
+                    extension: agg.extension.clone(),
                     data: agg.buf.slice(agg.data_off..agg.data_off + size),
                 };
                 agg.loss = 0;
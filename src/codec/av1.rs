@@ -0,0 +1,709 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! [AV1](https://aomediacodec.github.io/av1-spec/)-encoded video, as specified
+//! in the [RTP Payload Format For AV1](https://aomediacodec.github.io/av1-rtp-spec/).
+//!
+//! Each frame is a temporal unit in the low overhead bitstream format (as used
+//! in ISO BMFF samples): every OBU has a size field, and temporal delimiters
+//! are omitted.
+
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+
+use super::{CodecItem, VideoFrame};
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_FRAME_HEADER: u8 = 3;
+const OBU_FRAME: u8 = 6;
+const OBU_TILE_LIST: u8 = 8;
+const OBU_PADDING: u8 = 15;
+
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    state: State,
+
+    /// The current sequence header OBU payload and parameters, if known.
+    sequence_header: Option<(Bytes, SequenceHeader, super::VideoParameters)>,
+
+    high_water_size: usize,
+
+    /// The number of temporal units discarded because of packet loss.
+    discarded: u64,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Idle,
+
+    /// Ignoring the remainder of a temporal unit because of packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+    },
+
+    InProgress(InProgress),
+    Ready(VideoFrame),
+}
+
+#[derive(Debug)]
+struct InProgress {
+    start_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
+
+    /// True iff a packet had the `N` bit set, starting a coded video sequence.
+    new_coded_video_sequence: bool,
+
+    /// The OBU element continued in the next packet, if any.
+    fragment: Option<BytesMut>,
+
+    /// Complete OBUs, each with a size field.
+    data: BytesMut,
+
+    new_parameters: Option<Box<super::VideoParameters>>,
+
+    /// Whether the first frame (header) OBU is a key frame, once seen.
+    key_frame: Option<bool>,
+}
+
+impl Depacketizer {
+    pub(super) fn new(clock_rate: u32) -> Result<Self, String> {
+        if clock_rate != 90_000 {
+            return Err(format!(
+                "invalid AV1 clock rate {}; must always be 90000",
+                clock_rate
+            ));
+        }
+        Ok(Depacketizer {
+            state: State::Idle,
+            sequence_header: None,
+            high_water_size: 0,
+            discarded: 0,
+        })
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.sequence_header
+            .as_ref()
+            .map(|(_, _, p)| super::Parameters::Video(p.clone()))
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
+        let mut data = pkt.payload;
+        if data.is_empty() {
+            return Err("empty AV1 packet".into());
+        }
+
+        // Aggregation header: https://aomediacodec.github.io/av1-rtp-spec/#44-av1-aggregation-header
+        let aggregation_header = data.get_u8();
+        let z = (aggregation_header & 0b1000_0000) != 0;
+        let y = (aggregation_header & 0b0100_0000) != 0;
+        let w = (aggregation_header & 0b0011_0000) >> 4;
+        let n = (aggregation_header & 0b0000_1000) != 0;
+        if n && z {
+            return Err("AV1 packet starting a coded video sequence continues an OBU".into());
+        }
+
+        let mut discarded = false;
+        if pkt.loss > 0 {
+            if let State::InProgress(in_progress) = &self.state {
+                debug!(
+                    "Discarding {}-byte AV1 temporal unit prefix due to loss of {} RTP packets",
+                    in_progress.data.len(),
+                    pkt.loss
+                );
+                self.discarded += 1;
+                discarded = true;
+                self.state = State::Idle;
+            }
+        }
+        let mut in_progress = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(..) => panic!("push while in state ready"),
+            State::InProgress(in_progress) => {
+                if in_progress.timestamp.timestamp != pkt.timestamp.timestamp {
+                    return Err(format!(
+                        "Timestamp changed from {} to {} with temporal unit in progress",
+                        &in_progress.timestamp, &pkt.timestamp,
+                    ));
+                }
+                in_progress
+            }
+            State::Loss { timestamp } if timestamp.timestamp == pkt.timestamp.timestamp => {
+                if !pkt.mark {
+                    self.state = State::Loss { timestamp };
+                }
+                return Ok(());
+            }
+            State::Idle | State::Loss { .. } => {
+                if z {
+                    // The start of this temporal unit was lost.
+                    if !discarded {
+                        self.discarded += 1;
+                    }
+                    if !pkt.mark {
+                        self.state = State::Loss {
+                            timestamp: pkt.timestamp,
+                        };
+                    }
+                    return Ok(());
+                }
+                InProgress {
+                    start_ctx: pkt.ctx,
+                    timestamp: pkt.timestamp,
+                    loss: pkt.loss,
+                    extension: pkt.extension,
+                    new_coded_video_sequence: false,
+                    fragment: None,
+                    data: BytesMut::with_capacity(self.high_water_size),
+                    new_parameters: None,
+                    key_frame: None,
+                }
+            }
+        };
+        in_progress.new_coded_video_sequence |= n;
+        if z != in_progress.fragment.is_some() {
+            return Err(format!(
+                "AV1 packet has Z={} but {} OBU fragment in progress",
+                z,
+                if z { "no" } else { "an" }
+            ));
+        }
+
+        // OBU elements. When W is non-zero, it's the number of elements, and
+        // the last has no length field.
+        let mut i = 0usize;
+        while data.has_remaining() {
+            i += 1;
+            let len = if w == 0 || i < usize::from(w) {
+                let len = read_leb128(&mut data)?;
+                if len > data.remaining() {
+                    return Err(format!(
+                        "AV1 OBU element of length {} with only {} bytes remaining",
+                        len,
+                        data.remaining()
+                    ));
+                }
+                len
+            } else {
+                data.remaining()
+            };
+            let element = data.split_to(len);
+            let is_last = !data.has_remaining();
+            let obu = match in_progress.fragment.take() {
+                Some(mut f) => {
+                    f.extend_from_slice(&element[..]);
+                    f
+                }
+                None => BytesMut::from(&element[..]),
+            };
+            if is_last && y {
+                in_progress.fragment = Some(obu);
+            } else {
+                self.append_obu(&mut in_progress, obu.freeze())?;
+            }
+        }
+        if w != 0 && i != usize::from(w) {
+            return Err(format!("AV1 packet has W={} but {} OBU elements", w, i));
+        }
+
+        if !pkt.mark {
+            self.state = State::InProgress(in_progress);
+            return Ok(());
+        }
+        if in_progress.fragment.is_some() {
+            return Err("AV1 packet has mark bit set with OBU fragment in progress".into());
+        }
+        if in_progress.data.is_empty() {
+            return Err(format!(
+                "AV1 temporal unit at {} has no OBUs",
+                in_progress.timestamp
+            ));
+        }
+        self.high_water_size = std::cmp::max(self.high_water_size, in_progress.data.len());
+        self.state = State::Ready(VideoFrame {
+            new_parameters: in_progress.new_parameters,
+            loss: in_progress.loss,
+            start_ctx: in_progress.start_ctx,
+            end_ctx: pkt.ctx,
+            timestamp: in_progress.timestamp,
+            stream_id: pkt.stream_id,
+            is_random_access_point: in_progress.new_coded_video_sequence
+                || in_progress.key_frame == Some(true),
+            is_disposable: false,
+            capture_time: in_progress.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: in_progress.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: None,
+            data: in_progress.data.freeze(),
+        });
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<CodecItem> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(frame) => Some(CodecItem::VideoFrame(frame)),
+            s => {
+                self.state = s;
+                None
+            }
+        }
+    }
+
+    /// Appends a complete OBU to the temporal unit, with a size field.
+    fn append_obu(&mut self, in_progress: &mut InProgress, mut obu: Bytes) -> Result<(), String> {
+        if obu.is_empty() {
+            return Err("empty AV1 OBU".into());
+        }
+
+        // OBU header: https://aomediacodec.github.io/av1-spec/#obu-header-syntax
+        let header = obu.get_u8();
+        if (header & 0b1000_0000) != 0 {
+            return Err(format!(
+                "AV1 OBU header {:02x} has forbidden bit set",
+                header
+            ));
+        }
+        let obu_type = (header >> 3) & 0b1111;
+        let has_extension = (header & 0b0000_0100) != 0;
+        let has_size_field = (header & 0b0000_0010) != 0;
+        let extension = if has_extension {
+            if obu.is_empty() {
+                return Err("AV1 OBU truncated before extension header".into());
+            }
+            Some(obu.get_u8())
+        } else {
+            None
+        };
+        if has_size_field {
+            let size = read_leb128(&mut obu)?;
+            if size > obu.len() {
+                return Err(format!(
+                    "AV1 OBU has size {} with only {} bytes remaining",
+                    size,
+                    obu.len()
+                ));
+            }
+            obu.truncate(size);
+        }
+        match obu_type {
+            // Not allowed in ISO BMFF samples.
+            OBU_TEMPORAL_DELIMITER | OBU_TILE_LIST | OBU_PADDING => return Ok(()),
+            OBU_SEQUENCE_HEADER => {
+                let changed = match &self.sequence_header {
+                    Some((old, _, _)) => old != &obu,
+                    None => true,
+                };
+                if changed {
+                    let header = SequenceHeader::parse(&obu)?;
+                    let parameters = header.to_parameters(&obu)?;
+                    in_progress.new_parameters = Some(Box::new(parameters.clone()));
+                    self.sequence_header = Some((obu.clone(), header, parameters));
+                }
+            }
+            OBU_FRAME_HEADER | OBU_FRAME if in_progress.key_frame.is_none() => {
+                in_progress.key_frame = Some(match &self.sequence_header {
+                    Some((_, h, _)) => is_key_frame(h, &obu),
+                    None => false,
+                });
+            }
+            _ => {}
+        }
+        in_progress.data.put_u8(header | 0b0000_0010);
+        if let Some(e) = extension {
+            in_progress.data.put_u8(e);
+        }
+        write_leb128(&mut in_progress.data, obu.len());
+        in_progress.data.extend_from_slice(&obu[..]);
+        Ok(())
+    }
+}
+
+/// Reads an unsigned LEB128 value, as in AV1 section 4.10.5.
+fn read_leb128(data: &mut Bytes) -> Result<usize, String> {
+    let mut value: u64 = 0;
+    for i in 0..8 {
+        if !data.has_remaining() {
+            return Err("truncated leb128".into());
+        }
+        let b = data.get_u8();
+        value |= u64::from(b & 0x7f) << (i * 7);
+        if (b & 0x80) == 0 {
+            return usize::try_from(value).map_err(|_| format!("leb128 value {} too large", value));
+        }
+    }
+    Err("leb128 longer than 8 bytes".into())
+}
+
+/// Writes an unsigned LEB128 value.
+fn write_leb128(buf: &mut BytesMut, mut value: usize) {
+    loop {
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.put_u8(b);
+            return;
+        }
+        buf.put_u8(b | 0x80);
+    }
+}
+
+/// Returns true iff the given frame header or frame OBU payload is a key frame,
+/// as in AV1 section 5.9.2.
+fn is_key_frame(sequence_header: &SequenceHeader, payload: &[u8]) -> bool {
+    if sequence_header.reduced_still_picture_header {
+        return true;
+    }
+    match payload.first() {
+        // show_existing_frame = 0, frame_type = KEY_FRAME (0).
+        Some(b) => (b & 0b1110_0000) == 0,
+        None => false,
+    }
+}
+
+/// The fields of a sequence header OBU needed for [super::VideoParameters],
+/// as in AV1 section 5.5.
+#[derive(Debug)]
+struct SequenceHeader {
+    seq_profile: u8,
+    reduced_still_picture_header: bool,
+    seq_level_idx_0: u8,
+    seq_tier_0: u8,
+    max_frame_width: u32,
+    max_frame_height: u32,
+    frame_rate: Option<(u32, u32)>,
+    bit_depth: u8,
+    mono_chrome: bool,
+    subsampling_x: bool,
+    subsampling_y: bool,
+    chroma_sample_position: u8,
+}
+
+impl SequenceHeader {
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let mut r = bitreader::BitReader::new(raw);
+        let mut read = |bits: u8, name: &str| {
+            r.read_u32(bits)
+                .map_err(|e| format!("unable to read {}: {}", name, e))
+        };
+        let seq_profile = read(3, "seq_profile")? as u8;
+        if seq_profile > 2 {
+            return Err(format!("unsupported AV1 seq_profile {}", seq_profile));
+        }
+        read(1, "still_picture")?;
+        let reduced_still_picture_header = read(1, "reduced_still_picture_header")? == 1;
+        let seq_level_idx_0;
+        let mut seq_tier_0 = 0;
+        let mut frame_rate = None;
+        if reduced_still_picture_header {
+            seq_level_idx_0 = read(5, "seq_level_idx")? as u8;
+        } else {
+            let mut buffer_delay_length = 0;
+            let mut decoder_model_info_present = false;
+            if read(1, "timing_info_present_flag")? == 1 {
+                let num_units_in_display_tick = read(32, "num_units_in_display_tick")?;
+                let time_scale = read(32, "time_scale")?;
+                if read(1, "equal_picture_interval")? == 1 {
+                    // uvlc(), AV1 section 4.10.3.
+                    let mut leading_zeros = 0;
+                    while read(1, "num_ticks_per_picture_minus_1")? == 0 {
+                        leading_zeros += 1;
+                        if leading_zeros == 32 {
+                            return Err("bad num_ticks_per_picture_minus_1".into());
+                        }
+                    }
+                    let num_ticks_per_picture = if leading_zeros == 0 {
+                        1
+                    } else {
+                        read(leading_zeros, "num_ticks_per_picture_minus_1")? + (1 << leading_zeros)
+                    };
+                    frame_rate = num_units_in_display_tick
+                        .checked_mul(num_ticks_per_picture)
+                        .filter(|_| time_scale > 0)
+                        .map(|n| (n, time_scale));
+                }
+                decoder_model_info_present = read(1, "decoder_model_info_present_flag")? == 1;
+                if decoder_model_info_present {
+                    buffer_delay_length = read(5, "buffer_delay_length_minus_1")? as u8 + 1;
+                    read(32, "num_units_in_decoding_tick")?;
+                    read(5, "buffer_removal_time_length_minus_1")?;
+                    read(5, "frame_presentation_time_length_minus_1")?;
+                }
+            }
+            let initial_display_delay_present = read(1, "initial_display_delay_present_flag")? == 1;
+            let operating_points_cnt = read(5, "operating_points_cnt_minus_1")? + 1;
+            let mut first_level = None;
+            for _ in 0..operating_points_cnt {
+                read(12, "operating_point_idc")?;
+                let seq_level_idx = read(5, "seq_level_idx")? as u8;
+                let seq_tier = if seq_level_idx > 7 {
+                    read(1, "seq_tier")? as u8
+                } else {
+                    0
+                };
+                if first_level.is_none() {
+                    first_level = Some((seq_level_idx, seq_tier));
+                }
+                if decoder_model_info_present && read(1, "decoder_model_present_for_this_op")? == 1
+                {
+                    read(buffer_delay_length, "decoder_buffer_delay")?;
+                    read(buffer_delay_length, "encoder_buffer_delay")?;
+                    read(1, "low_delay_mode_flag")?;
+                }
+                if initial_display_delay_present
+                    && read(1, "initial_display_delay_present_for_this_op")? == 1
+                {
+                    read(4, "initial_display_delay_minus_1")?;
+                }
+            }
+            let (l, t) = first_level.expect("at least one operating point");
+            seq_level_idx_0 = l;
+            seq_tier_0 = t;
+        }
+        let frame_width_bits = read(4, "frame_width_bits_minus_1")? as u8 + 1;
+        let frame_height_bits = read(4, "frame_height_bits_minus_1")? as u8 + 1;
+        let max_frame_width = read(frame_width_bits, "max_frame_width_minus_1")? + 1;
+        let max_frame_height = read(frame_height_bits, "max_frame_height_minus_1")? + 1;
+        if !reduced_still_picture_header && read(1, "frame_id_numbers_present_flag")? == 1 {
+            read(4, "delta_frame_id_length_minus_2")?;
+            read(3, "additional_frame_id_length_minus_1")?;
+        }
+        read(1, "use_128x128_superblock")?;
+        read(1, "enable_filter_intra")?;
+        read(1, "enable_intra_edge_filter")?;
+        if !reduced_still_picture_header {
+            read(1, "enable_interintra_compound")?;
+            read(1, "enable_masked_compound")?;
+            read(1, "enable_warped_motion")?;
+            read(1, "enable_dual_filter")?;
+            let enable_order_hint = read(1, "enable_order_hint")? == 1;
+            if enable_order_hint {
+                read(1, "enable_jnt_comp")?;
+                read(1, "enable_ref_frame_mvs")?;
+            }
+            let seq_force_screen_content_tools = if read(1, "seq_choose_screen_content_tools")? == 1
+            {
+                2 // SELECT_SCREEN_CONTENT_TOOLS
+            } else {
+                read(1, "seq_force_screen_content_tools")?
+            };
+            if seq_force_screen_content_tools > 0 && read(1, "seq_choose_integer_mv")? == 0 {
+                read(1, "seq_force_integer_mv")?;
+            }
+            if enable_order_hint {
+                read(3, "order_hint_bits_minus_1")?;
+            }
+        }
+        read(1, "enable_superres")?;
+        read(1, "enable_cdef")?;
+        read(1, "enable_restoration")?;
+
+        // color_config, AV1 section 5.5.2.
+        let high_bitdepth = read(1, "high_bitdepth")? == 1;
+        let bit_depth = if seq_profile == 2 && high_bitdepth {
+            if read(1, "twelve_bit")? == 1 {
+                12
+            } else {
+                10
+            }
+        } else if high_bitdepth {
+            10
+        } else {
+            8
+        };
+        let mono_chrome = seq_profile != 1 && read(1, "mono_chrome")? == 1;
+        let (mut color_primaries, mut transfer_characteristics, mut matrix_coefficients) =
+            (2, 2, 2); // CP_UNSPECIFIED, TC_UNSPECIFIED, MC_UNSPECIFIED
+        if read(1, "color_description_present_flag")? == 1 {
+            color_primaries = read(8, "color_primaries")?;
+            transfer_characteristics = read(8, "transfer_characteristics")?;
+            matrix_coefficients = read(8, "matrix_coefficients")?;
+        }
+        let (subsampling_x, subsampling_y);
+        let mut chroma_sample_position = 0;
+        if mono_chrome {
+            subsampling_x = true;
+            subsampling_y = true;
+        } else if color_primaries == 1 && transfer_characteristics == 13 && matrix_coefficients == 0
+        {
+            // sRGB.
+            subsampling_x = false;
+            subsampling_y = false;
+        } else {
+            read(1, "color_range")?;
+            match seq_profile {
+                0 => {
+                    subsampling_x = true;
+                    subsampling_y = true;
+                }
+                1 => {
+                    subsampling_x = false;
+                    subsampling_y = false;
+                }
+                _ if bit_depth == 12 => {
+                    subsampling_x = read(1, "subsampling_x")? == 1;
+                    subsampling_y = subsampling_x && read(1, "subsampling_y")? == 1;
+                }
+                _ => {
+                    subsampling_x = true;
+                    subsampling_y = false;
+                }
+            }
+            if subsampling_x && subsampling_y {
+                chroma_sample_position = read(2, "chroma_sample_position")? as u8;
+            }
+        }
+        Ok(SequenceHeader {
+            seq_profile,
+            reduced_still_picture_header,
+            seq_level_idx_0,
+            seq_tier_0,
+            max_frame_width,
+            max_frame_height,
+            frame_rate,
+            bit_depth,
+            mono_chrome,
+            subsampling_x,
+            subsampling_y,
+            chroma_sample_position,
+        })
+    }
+
+    /// Returns parameters, given the raw sequence header OBU payload.
+    fn to_parameters(&self, raw: &[u8]) -> Result<super::VideoParameters, String> {
+        // AV1CodecConfigurationRecord, as in the AV1 Codec ISO Media File
+        // Format Binding section 2.3.3.
+        let mut av1c = BytesMut::with_capacity(4 + 2 + 2 + raw.len());
+        av1c.put_u8(0x81); // marker = 1, version = 1
+        av1c.put_u8((self.seq_profile << 5) | self.seq_level_idx_0);
+        av1c.put_u8(
+            (self.seq_tier_0 << 7)
+                | (u8::from(self.bit_depth > 8) << 6)
+                | (u8::from(self.bit_depth == 12) << 5)
+                | (u8::from(self.mono_chrome) << 4)
+                | (u8::from(self.subsampling_x) << 3)
+                | (u8::from(self.subsampling_y) << 2)
+                | self.chroma_sample_position,
+        );
+        av1c.put_u8(0); // initial_presentation_delay_present = 0
+
+        // configOBUs: the sequence header OBU.
+        av1c.put_u8((OBU_SEQUENCE_HEADER << 3) | 0b0000_0010);
+        write_leb128(&mut av1c, raw.len());
+        av1c.extend_from_slice(raw);
+
+        let pixel_dimensions = (self.max_frame_width, self.max_frame_height);
+        Ok(super::VideoParameters {
+            pixel_dimensions,
+            rfc6381_codec: format!(
+                "av01.{}.{:02}{}.{:02}",
+                self.seq_profile,
+                self.seq_level_idx_0,
+                if self.seq_tier_0 == 1 { 'H' } else { 'M' },
+                self.bit_depth
+            ),
+            pixel_aspect_ratio: None,
+            frame_rate: self.frame_rate,
+            sample_entry: super::make_video_sample_entry(
+                b"av01",
+                (b"av1C", &av1c[..]),
+                pixel_dimensions,
+                None,
+                None,
+            )?,
+            details: Default::default(),
+            extra_data: av1c.freeze(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::codec::testutil::{packet, video_frame};
+
+    /// A sequence header OBU payload: profile 0, level 4.0, 1920x1080, 8-bit 4:2:0.
+    const SEQUENCE_HEADER: &[u8] = b"\x00\x00\x00\x42\xab\xbf\xc3\x70\x09\xe6\x01";
+
+    #[test]
+    fn parse_sequence_header() {
+        let h = super::SequenceHeader::parse(SEQUENCE_HEADER).unwrap();
+        let p = h.to_parameters(SEQUENCE_HEADER).unwrap();
+        assert_eq!(p.rfc6381_codec(), "av01.0.08M.08");
+        assert_eq!(p.pixel_dimensions(), (1920, 1080));
+        assert_eq!(p.frame_rate(), None);
+        let mut expected = b"\x81\x08\x0c\x00\x0a\x0b".to_vec();
+        expected.extend_from_slice(SEQUENCE_HEADER);
+        assert_eq!(&p.extra_data()[..], &expected[..]);
+    }
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(90_000).unwrap();
+        assert!(d.parameters().is_none());
+
+        // A key frame starting a coded video sequence: the sequence header OBU
+        // and the start of a frame OBU (Y=1, W=2, N=1), then the rest of the
+        // frame OBU (Z=1, W=1).
+        let mut payload = vec![0x68, 0x0c, 0x08];
+        payload.extend_from_slice(SEQUENCE_HEADER);
+        payload.extend_from_slice(b"\x30\x10abc");
+        let mut pkt = packet(0, 0, 0, false, b"");
+        pkt.payload = Bytes::from(payload);
+        d.push(pkt).unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(0, 0, 0, true, b"\x90def")).unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.is_random_access_point);
+        let p = frame.new_parameters.as_ref().unwrap();
+        assert_eq!(p.pixel_dimensions(), (1920, 1080));
+        let mut expected = b"\x0a\x0b".to_vec();
+        expected.extend_from_slice(SEQUENCE_HEADER);
+        expected.extend_from_slice(b"\x32\x07\x10abcdef");
+        assert_eq!(&frame.data()[..], &expected[..]);
+
+        // An inter frame with a temporal delimiter, which is dropped (W=2).
+        d.push(packet(0, 3000, 0, true, b"\x20\x01\x10\x30\x20inter"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(!frame.is_random_access_point);
+        assert!(frame.new_parameters.is_none());
+        assert_eq!(&frame.data()[..], b"\x32\x06\x20inter");
+
+        // A temporal unit whose start was lost is discarded.
+        d.push(packet(0, 6000, 1, true, b"\x90cont")).unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+        d.push(packet(0, 9000, 0, true, b"\x10\x30\x20inter"))
+            .unwrap();
+        assert_eq!(video_frame(d.pull()).timestamp.timestamp, 9000);
+
+        // More than 255 OBU elements (W=0): temporal delimiters, then a frame.
+        let mut payload = vec![0x00];
+        for _ in 0..300 {
+            payload.extend_from_slice(b"\x01\x10");
+        }
+        payload.extend_from_slice(b"\x07\x30\x20inter");
+        let mut pkt = packet(0, 12000, 0, true, b"");
+        pkt.payload = Bytes::from(payload);
+        d.push(pkt).unwrap();
+        assert_eq!(&video_frame(d.pull()).data()[..], b"\x32\x06\x20inter");
+    }
+}
@@ -62,6 +62,9 @@ impl Depacketizer {
             stream_id: pkt.stream_id,
             timestamp: pkt.timestamp,
             frame_length: NonZeroU32::new(240).unwrap(),
+            capture_time: pkt.ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: pkt.extension,
             data: pkt.payload,
         });
         Ok(())
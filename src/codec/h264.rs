@@ -3,6 +3,8 @@
 
 //! [H.264](https://www.itu.int/rec/T-REC-H.264-201906-I/en)-encoded video.
 
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
 use std::convert::TryFrom;
 use std::fmt::Write;
 
@@ -12,7 +14,9 @@ use log::{debug, log_enabled, trace};
 
 use crate::{client::rtp::Packet, Error, Timestamp};
 
-use super::VideoFrame;
+use super::{
+    ClockTimestamp, PicTiming, RbspReader, RecoveryPoint, SeiMessage, SeiPayload, VideoFrame,
+};
 
 /// A [super::Depacketizer] implementation which finds access unit boundaries
 /// and produces unfragmented NAL units as specified in [RFC
@@ -28,8 +32,9 @@ use super::VideoFrame;
 pub(crate) struct Depacketizer {
     input_state: DepacketizerInputState,
 
-    /// A complete video frame ready for pull.
-    pending: Option<VideoFrame>,
+    /// Complete video frames ready for pull. Usually there's at most one, but
+    /// in interleaved mode a single packet may complete several.
+    pending: VecDeque<VideoFrame>,
 
     parameters: InternalParameters,
 
@@ -40,6 +45,17 @@ pub(crate) struct Depacketizer {
     /// In state `PreMark`, an entry for each NAL.
     /// Kept around (empty) in other states to re-use the backing allocation.
     nals: Vec<Nal>,
+
+    /// The number of access units discarded because of packet loss.
+    discarded: u64,
+
+    /// In interleaved mode (`packetization-mode=2`), the deinterleaving state.
+    deinterleaver: Option<Deinterleaver>,
+
+    output: super::NalOutput,
+
+    /// State for computing picture order counts and decode timestamps.
+    poc: PocState,
 }
 
 #[derive(Debug)]
@@ -51,6 +67,8 @@ struct Nal {
 
     /// The total length of this NAL, including the header byte.
     len: u32,
+    /// The decoding order number, as sent in STAP-B, MTAP, and FU-B packets.
+    don: Option<u16>,
 }
 
 /// An access unit that is currently being accumulated during `PreMark` state.
@@ -68,6 +86,9 @@ struct AccessUnit {
     loss: u16,
 
     same_ts_as_prev: bool,
+
+    /// The RTP header extension of the first packet.
+    extension: Option<crate::client::rtp::HeaderExtension>,
 }
 
 #[derive(Debug)]
@@ -93,6 +114,191 @@ enum DepacketizerInputState {
     },
 }
 
+/// The maximum number of NALs held for deinterleaving, regardless of the
+/// `fmtp` parameters.
+const MAX_DEINTERLEAVE_NALS: usize = 1024;
+
+/// An access unit delimiter NAL with `primary_pic_type` 7 (any slice type), as in H.264
+/// section 7.3.2.4.
+const AUD: &[u8] = b"\x09\xf0";
+
+/// Deinterleaving state for interleaved mode (`packetization-mode=2`).
+///
+/// In this mode, NALs are sent out of decoding order, each with a decoding
+/// order number (DON). They're buffered and released in DON order as in
+/// [RFC 6184 section 13](https://tools.ietf.org/html/rfc6184#section-13).
+#[derive(Debug)]
+struct Deinterleaver {
+    /// `sprop-interleaving-depth`: the maximum number of VCL NALs which may
+    /// precede a VCL NAL in transmission order and follow it in decoding order.
+    interleaving_depth: u16,
+
+    /// `sprop-deint-buf-req`: the buffer size in bytes needed for deinterleaving.
+    deint_buf_req: Option<u32>,
+
+    /// `sprop-max-don-diff`: the maximum DON difference between a NAL and
+    /// one which precedes it in transmission order but follows it in decoding order.
+    max_don_diff: Option<u16>,
+
+    /// Buffered NALs, keyed by absolute DON and then arrival order.
+    buf: BTreeMap<(i64, u64), DeinterleavedNal>,
+    next_arrival: u64,
+
+    /// The total length of the buffered NALs, including header bytes.
+    buf_bytes: usize,
+
+    /// The number of buffered VCL NALs.
+    buf_vcl: usize,
+
+    /// The DON and absolute DON of the most recently received NAL, for
+    /// unwrapping the 16-bit DON.
+    last_don: Option<(u16, i64)>,
+
+    /// The highest absolute DON received.
+    max_abs_don: i64,
+
+    /// The absolute DON of the most recently released NAL.
+    last_released: Option<i64>,
+
+    /// A NAL being reassembled from a FU-B and following FU-As.
+    fragment: Option<(DeinterleavedNal, BytesMut)>,
+
+    /// True iff FU-As should be ignored because their FU-B may have been lost.
+    skip_fu_a: bool,
+
+    /// RTP packets lost since the current access unit was started.
+    loss: u16,
+}
+
+#[derive(Debug)]
+struct DeinterleavedNal {
+    abs_don: i64,
+    don: u16,
+    timestamp: crate::Timestamp,
+    ctx: crate::RtspMessageContext,
+    hdr: NalHeader,
+
+    /// The total length of this NAL, including the header byte.
+    len: u32,
+
+    /// The NAL excluding its header byte.
+    data: Bytes,
+}
+
+impl Deinterleaver {
+    /// Returns a deinterleaver iff the `fmtp` specifies interleaved mode.
+    fn parse_format_specific_params(format_specific_params: &str) -> Result<Option<Self>, String> {
+        let mut interleaved = false;
+        let mut interleaving_depth = 0;
+        let mut deint_buf_req = None;
+        let mut max_don_diff = None;
+        for p in format_specific_params.split(';') {
+            let (key, value) = match p.trim().split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let bad = || format!("bad H.264 {} {:?}", key, value);
+            match key {
+                "packetization-mode" => interleaved = value == "2",
+                "sprop-interleaving-depth" => {
+                    interleaving_depth = value.parse().map_err(|_| bad())?;
+                }
+                "sprop-deint-buf-req" => deint_buf_req = Some(value.parse().map_err(|_| bad())?),
+                "sprop-max-don-diff" => max_don_diff = Some(value.parse().map_err(|_| bad())?),
+                _ => {}
+            }
+        }
+        if !interleaved {
+            return Ok(None);
+        }
+        Ok(Some(Deinterleaver {
+            interleaving_depth,
+            deint_buf_req,
+            max_don_diff,
+            buf: BTreeMap::new(),
+            next_arrival: 0,
+            buf_bytes: 0,
+            buf_vcl: 0,
+            last_don: None,
+            max_abs_don: 0,
+            last_released: None,
+            fragment: None,
+            skip_fu_a: false,
+            loss: 0,
+        }))
+    }
+
+    fn reset(&mut self) {
+        self.buf.clear();
+        self.buf_bytes = 0;
+        self.buf_vcl = 0;
+        self.last_don = None;
+        self.max_abs_don = 0;
+        self.last_released = None;
+        self.fragment = None;
+        self.skip_fu_a = false;
+        self.loss = 0;
+    }
+
+    /// Adds a complete NAL to the buffer, returning false if it arrived too
+    /// late, after a NAL following it in decoding order was released.
+    fn insert(&mut self, mut nal: DeinterleavedNal) -> bool {
+        nal.abs_don = match self.last_don {
+            None => i64::from(nal.don),
+            Some((prev, prev_abs)) => prev_abs + i64::from(nal.don.wrapping_sub(prev) as i16),
+        };
+        self.last_don = Some((nal.don, nal.abs_don));
+        if matches!(self.last_released, Some(r) if nal.abs_don < r) {
+            return false;
+        }
+        self.max_abs_don = if self.buf.is_empty() && self.last_released.is_none() {
+            nal.abs_don
+        } else {
+            std::cmp::max(self.max_abs_don, nal.abs_don)
+        };
+        self.buf_bytes += usize::try_from(nal.len).expect("u32 fits in usize");
+        if is_vcl(nal.hdr) {
+            self.buf_vcl += 1;
+        }
+        self.buf.insert((nal.abs_don, self.next_arrival), nal);
+        self.next_arrival += 1;
+        true
+    }
+
+    /// Removes and returns the NAL with the lowest DON, if it should be released.
+    ///
+    /// A NAL is released when there are more buffered VCL NALs than the
+    /// interleaving depth, when the buffer exceeds its size requirement (or
+    /// `MAX_DEINTERLEAVE_NALS`), or when no NAL yet to arrive can precede it
+    /// given the maximum DON difference.
+    fn pop(&mut self) -> Option<DeinterleavedNal> {
+        let key = *self.buf.keys().next()?;
+        let over_size = match self.deint_buf_req {
+            Some(r) => self.buf_bytes > usize::try_from(r).expect("u32 fits in usize"),
+            None => false,
+        };
+        let release = self.buf_vcl > usize::from(self.interleaving_depth)
+            || over_size
+            || self.buf.len() > MAX_DEINTERLEAVE_NALS
+            || matches!(self.max_don_diff, Some(d) if key.0 + i64::from(d) <= self.max_abs_don);
+        if !release {
+            return None;
+        }
+        let nal = self.buf.remove(&key).expect("key is present");
+        self.buf_bytes -= usize::try_from(nal.len).expect("u32 fits in usize");
+        if is_vcl(nal.hdr) {
+            self.buf_vcl -= 1;
+        }
+        self.last_released = Some(key.0);
+        Some(nal)
+    }
+}
+
+/// Returns true iff the NAL is a VCL (coded slice) NAL.
+fn is_vcl(hdr: NalHeader) -> bool {
+    (1..=5).contains(&hdr.nal_unit_type().id())
+}
+
 impl Depacketizer {
     pub(super) fn new(
         clock_rate: u32,
@@ -108,26 +314,53 @@ impl Depacketizer {
         // TODO: the spec doesn't require out-of-band parameters, so we shouldn't either.
         let format_specific_params = format_specific_params
             .ok_or_else(|| "H.264 depacketizer expects out-of-band parameters".to_owned())?;
+        let parameters = InternalParameters::parse_format_specific_params(format_specific_params)?;
+        let poc = PocState::new(parameters.slice.reorder_depth);
         Ok(Depacketizer {
             input_state: DepacketizerInputState::New,
-            pending: None,
+            pending: VecDeque::new(),
             pieces: Vec::new(),
             nals: Vec::new(),
-            parameters: InternalParameters::parse_format_specific_params(format_specific_params)?,
+            parameters,
+            discarded: 0,
+            deinterleaver: Deinterleaver::parse_format_specific_params(format_specific_params)?,
+            output: super::NalOutput::default(),
+            poc,
         })
     }
 
+    pub(super) fn set_nal_output(&mut self, output: super::NalOutput) {
+        self.output = output;
+    }
+
     pub(super) fn parameters(&self) -> Option<super::Parameters> {
         Some(super::Parameters::Video(
             self.parameters.generic_parameters.clone(),
         ))
     }
 
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.input_state = DepacketizerInputState::New;
+        self.pieces.clear();
+        self.nals.clear();
+        if let Some(d) = self.deinterleaver.as_mut() {
+            d.reset();
+        }
+        self.poc = PocState::new(self.parameters.slice.reorder_depth);
+    }
+
     pub(super) fn push(&mut self, pkt: Packet) -> Result<(), String> {
         // Push shouldn't be called until pull is exhausted.
-        if let Some(p) = self.pending.as_ref() {
+        if let Some(p) = self.pending.front() {
             panic!("push with data already pending: {:?}", p);
         }
+        if self.deinterleaver.is_some() {
+            return self.push_interleaved(pkt);
+        }
 
         let mut access_unit =
             match std::mem::replace(&mut self.input_state, DepacketizerInputState::New) {
@@ -138,6 +371,7 @@ impl Depacketizer {
                 }
                 DepacketizerInputState::PreMark(mut access_unit) => {
                     if pkt.loss > 0 {
+                        self.discarded += 1;
                         self.nals.clear();
                         self.pieces.clear();
                         if access_unit.timestamp.timestamp == pkt.timestamp.timestamp {
@@ -170,8 +404,8 @@ impl Depacketizer {
                         let last_nal_hdr = self.nals.last().unwrap().hdr;
                         if can_end_au(last_nal_hdr.nal_unit_type()) {
                             access_unit.end_ctx = pkt.ctx;
-                            self.pending =
-                                Some(self.finalize_access_unit(access_unit, "ts change")?);
+                            let frame = self.finalize_access_unit(access_unit, "ts change")?;
+                            self.pending.push_back(frame);
                             AccessUnit::start(&pkt, 0, false)
                         } else {
                             log::debug!(
@@ -232,64 +466,39 @@ impl Depacketizer {
                     hdr: NalHeader::new(nal_header).expect("header w/o F bit set is valid"),
                     next_piece_idx,
                     len,
+                    don: None,
                 });
             }
-            24 => {
-                // STAP-A. https://tools.ietf.org/html/rfc6184#section-5.7.1
-                loop {
-                    if data.remaining() < 3 {
-                        return Err(format!(
-                            "STAP-A has {} remaining bytes; expecting 2-byte length, non-empty NAL",
-                            data.remaining()
-                        ));
-                    }
-                    let len = data.get_u16();
-                    if len == 0 {
-                        return Err("zero length in STAP-A".into());
-                    }
-                    let hdr = NalHeader::new(data[0])
-                        .map_err(|_| format!("bad header {:02x} in STAP-A", data[0]))?;
-                    match data.remaining().cmp(&usize::from(len)) {
-                        std::cmp::Ordering::Less => {
-                            return Err(format!(
-                                "STAP-A too short: {} bytes remaining, expecting {}-byte NAL",
-                                data.remaining(),
-                                len
-                            ))
-                        }
-                        std::cmp::Ordering::Equal => {
-                            data.advance(1);
-                            let next_piece_idx = self.add_piece(data)?;
-                            self.nals.push(Nal {
-                                hdr,
-                                next_piece_idx,
-                                len: u32::from(len),
-                            });
-                            break;
-                        }
-                        std::cmp::Ordering::Greater => {
-                            let mut piece = data.split_to(usize::from(len));
-                            piece.advance(1);
-                            let next_piece_idx = self.add_piece(piece)?;
-                            self.nals.push(Nal {
-                                hdr,
-                                next_piece_idx,
-                                len: u32::from(len),
-                            });
-                        }
-                    }
-                }
-            }
-            25..=27 | 29 => {
-                return Err(format!(
-                    "unimplemented/unexpected interleaved mode NAL ({:02x})",
-                    nal_header,
-                ))
+            24..=27 => {
+                let nals = &mut self.nals;
+                let pieces = &mut self.pieces;
+                parse_aggregation(nal_header, data, |unit| {
+                    // The TS offset of MTAP units is unused: outside
+                    // interleaved mode, NALs are assembled in transmission order.
+                    pieces.push(unit.data);
+                    let next_piece_idx = u32::try_from(pieces.len())
+                        .map_err(|_| "more than u32::MAX pieces!".to_string())?;
+                    nals.push(Nal {
+                        hdr: unit.hdr,
+                        next_piece_idx,
+                        len: unit.len,
+                        don: unit.don,
+                    });
+                    Ok(())
+                })?;
             }
-            28 => {
-                // FU-A. https://tools.ietf.org/html/rfc6184#section-5.8
-                if data.len() < 2 {
-                    return Err(format!("FU-A len {} too short", data.len()));
+            28 | 29 => {
+                // FU-A or FU-B. https://tools.ietf.org/html/rfc6184#section-5.8
+                // FU-B is identical except for a DON after the FU header. It
+                // is used only for the first fragment; the rest are FU-As.
+                let fu_b = (nal_header & 0b11111) == 29;
+                let min_len = if fu_b { 4 } else { 2 };
+                if data.len() < min_len {
+                    return Err(format!(
+                        "{} len {} too short",
+                        if fu_b { "FU-B" } else { "FU-A" },
+                        data.len()
+                    ));
                 }
                 let fu_header = data[0];
                 let start = (fu_header & 0b10000000) != 0;
@@ -302,6 +511,13 @@ impl Depacketizer {
                 if (start && end) || reserved {
                     return Err(format!("Invalid FU-A header {:02x}", fu_header));
                 }
+                let mut don = None;
+                if fu_b {
+                    if !start {
+                        return Err(format!("FU-B header {:02x} without start bit", fu_header));
+                    }
+                    don = Some(data.get_u16());
+                }
                 if !end && pkt.mark {
                     return Err("FU-A pkt with MARK && !END".into());
                 }
@@ -314,6 +530,7 @@ impl Depacketizer {
                             hdr: nal_header,
                             next_piece_idx: u32::MAX, // should be overwritten later.
                             len: 1 + u32_len,
+                            don,
                         });
                         access_unit.in_fu_a = true;
                     }
@@ -336,6 +553,7 @@ impl Depacketizer {
                     }
                     (false, false) => {
                         if pkt.loss > 0 {
+                            self.discarded += 1;
                             self.pieces.clear();
                             self.nals.clear();
                             self.input_state = DepacketizerInputState::Loss {
@@ -354,7 +572,8 @@ impl Depacketizer {
             let last_nal_hdr = self.nals.last().unwrap().hdr;
             if can_end_au(last_nal_hdr.nal_unit_type()) {
                 access_unit.end_ctx = pkt.ctx;
-                self.pending = Some(self.finalize_access_unit(access_unit, "mark")?);
+                let frame = self.finalize_access_unit(access_unit, "mark")?;
+                self.pending.push_back(frame);
                 DepacketizerInputState::PostMark {
                     timestamp: pkt.timestamp,
                     loss: 0,
@@ -373,8 +592,208 @@ impl Depacketizer {
         Ok(())
     }
 
+    /// Handles a packet in interleaved mode.
+    ///
+    /// Only STAP-B, MTAP, and FU packets are allowed in this mode, as in [RFC
+    /// 6184 section 6.4](https://tools.ietf.org/html/rfc6184#section-6.4).
+    /// Each complete NAL goes into the deinterleaving buffer; NALs released
+    /// from it in decoding order are assembled into access units, each of which
+    /// ends when a NAL with a different timestamp is released.
+    fn push_interleaved(&mut self, pkt: Packet) -> Result<(), String> {
+        let d = self
+            .deinterleaver
+            .as_mut()
+            .expect("push_interleaved requires deinterleaver");
+        let timestamp = pkt.timestamp;
+        let ctx = pkt.ctx;
+        d.loss = d.loss.saturating_add(pkt.loss);
+        if pkt.loss > 0 {
+            // The lost packets may have included the start of a fragmented NAL.
+            d.skip_fu_a = true;
+            if let Some((nal, _)) = d.fragment.take() {
+                debug!(
+                    "Discarding fragmented NAL with DON {} due to loss of {} RTP packets",
+                    nal.don, pkt.loss
+                );
+                self.discarded += 1;
+            }
+        }
+        let mut data = pkt.payload;
+        if data.is_empty() {
+            return Err("Empty NAL".into());
+        }
+        let nal_header = data[0];
+        if (nal_header >> 7) != 0 {
+            return Err(format!("NAL header {:02x} has F bit set", nal_header));
+        }
+        data.advance(1); // skip the header byte.
+        let mut late = 0;
+        match nal_header & 0b11111 {
+            25..=27 => {
+                if d.fragment.is_some() {
+                    return Err(format!(
+                        "Aggregation NAL {:02x} while fragment in progress",
+                        nal_header
+                    ));
+                }
+                d.skip_fu_a = false;
+                parse_aggregation(nal_header, data, |unit| {
+                    let nal = DeinterleavedNal {
+                        abs_don: 0, // filled in by insert.
+                        don: unit.don.expect("STAP-B and MTAP NALs have DONs"),
+                        timestamp: crate::Timestamp {
+                            timestamp: timestamp.timestamp + i64::from(unit.ts_offset),
+                            ..timestamp
+                        },
+                        ctx,
+                        hdr: unit.hdr,
+                        len: unit.len,
+                        data: unit.data,
+                    };
+                    if !d.insert(nal) {
+                        late += 1;
+                    }
+                    Ok(())
+                })?;
+            }
+            28 | 29 => {
+                // FU-B starts a fragmented NAL; FU-A continues it.
+                // https://tools.ietf.org/html/rfc6184#section-5.8
+                let fu_b = (nal_header & 0b11111) == 29;
+                if data.len() < if fu_b { 4 } else { 2 } {
+                    return Err(format!("FU len {} too short", data.len()));
+                }
+                let fu_header = data[0];
+                let start = (fu_header & 0b10000000) != 0;
+                let end = (fu_header & 0b01000000) != 0;
+                let reserved = (fu_header & 0b00100000) != 0;
+                let hdr = NalHeader::new((nal_header & 0b011100000) | (fu_header & 0b00011111))
+                    .expect("NalHeader is valid");
+                data.advance(1);
+                if (start && end) || reserved || start != fu_b {
+                    return Err(format!(
+                        "Invalid FU header {:02x} in {} in interleaved mode",
+                        fu_header,
+                        if fu_b { "FU-B" } else { "FU-A" }
+                    ));
+                }
+                match d.fragment.take() {
+                    None if fu_b => {
+                        d.skip_fu_a = false;
+                        let don = data.get_u16();
+                        let nal = DeinterleavedNal {
+                            abs_don: 0, // filled in by insert.
+                            don,
+                            timestamp,
+                            ctx,
+                            hdr,
+                            len: 0, // filled in when complete.
+                            data: Bytes::new(),
+                        };
+                        d.fragment = Some((nal, BytesMut::from(&data[..])));
+                    }
+                    None if d.skip_fu_a => {
+                        if end {
+                            d.skip_fu_a = false;
+                        }
+                    }
+                    None => return Err("FU-A with no fragment in progress".into()),
+                    Some(_) if fu_b => {
+                        return Err("FU-B while fragment in progress".into());
+                    }
+                    Some((mut nal, mut buf)) => {
+                        if u8::from(hdr) != u8::from(nal.hdr) {
+                            return Err(format!(
+                                "FU-A has inconsistent NAL type: {:?} then {:?}",
+                                nal.hdr, hdr,
+                            ));
+                        }
+                        buf.extend_from_slice(&data[..]);
+                        if end {
+                            nal.len = u32::try_from(buf.len() + 1)
+                                .map_err(|_| "fragmented NAL too long".to_string())?;
+                            nal.data = buf.freeze();
+                            if !d.insert(nal) {
+                                late += 1;
+                            }
+                        } else {
+                            d.fragment = Some((nal, buf));
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "NAL {:02x} not allowed in interleaved mode",
+                    nal_header
+                ))
+            }
+        }
+        if late > 0 {
+            // The access units these belong to have been (or are being) emitted without them.
+            debug!(
+                "Discarding {} NALs received after later NALs were released",
+                late
+            );
+            self.discarded += 1;
+        }
+        while let Some(nal) = self.deinterleaver.as_mut().and_then(Deinterleaver::pop) {
+            self.append_deinterleaved(nal, pkt.stream_id)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a NAL released in decoding order to the current access unit,
+    /// first finishing that access unit if the NAL's timestamp differs.
+    fn append_deinterleaved(
+        &mut self,
+        nal: DeinterleavedNal,
+        stream_id: usize,
+    ) -> Result<(), String> {
+        let same_au = matches!(
+            &self.input_state,
+            DepacketizerInputState::PreMark(au) if au.timestamp.timestamp == nal.timestamp.timestamp
+        );
+        if same_au {
+            if let DepacketizerInputState::PreMark(au) = &mut self.input_state {
+                au.end_ctx = nal.ctx;
+            }
+        } else {
+            if let DepacketizerInputState::PreMark(au) =
+                std::mem::replace(&mut self.input_state, DepacketizerInputState::New)
+            {
+                let frame = self.finalize_access_unit(au, "ts change")?;
+                self.pending.push_back(frame);
+            }
+            let loss = std::mem::replace(
+                &mut self.deinterleaver.as_mut().expect("interleaved mode").loss,
+                0,
+            );
+            self.input_state = DepacketizerInputState::PreMark(AccessUnit {
+                start_ctx: nal.ctx,
+                end_ctx: nal.ctx,
+                timestamp: nal.timestamp,
+                stream_id,
+                in_fu_a: false,
+                loss,
+                same_ts_as_prev: false,
+
+                // NALs of several packets are mixed; there's no single extension.
+                extension: None,
+            });
+        }
+        let next_piece_idx = self.add_piece(nal.data)?;
+        self.nals.push(Nal {
+            hdr: nal.hdr,
+            next_piece_idx,
+            len: nal.len,
+            don: Some(nal.don),
+        });
+        Ok(())
+    }
+
     pub(super) fn pull(&mut self) -> Option<super::CodecItem> {
-        self.pending.take().map(super::CodecItem::VideoFrame)
+        self.pending.pop_front().map(super::CodecItem::VideoFrame)
     }
 
     /// Adds a piece to `self.pieces`, erroring if it becomes absurdly large.
@@ -396,6 +815,9 @@ impl Depacketizer {
             let mut nals = String::new();
             for (i, nal) in self.nals.iter().enumerate() {
                 let _ = write!(&mut nals, "\n  {}: {:?}", i, nal.hdr);
+                if let Some(don) = nal.don {
+                    let _ = write!(&mut nals, " (DON {})", don);
+                }
             }
             debug!(
                 "bad access unit (ended by {}) at ts {}\nerrors are:{}\nNALs are:{}",
@@ -405,6 +827,9 @@ impl Depacketizer {
             let mut nals = String::new();
             for (i, nal) in self.nals.iter().enumerate() {
                 let _ = write!(&mut nals, "\n  {}: {:?}", i, nal.hdr);
+                if let Some(don) = nal.don {
+                    let _ = write!(&mut nals, " (DON {})", don);
+                }
             }
             trace!(
                 "access unit (ended by {}) at ts {}; NALS are:{}",
@@ -422,6 +847,10 @@ impl Depacketizer {
         let mut is_disposable = true;
         let mut new_sps = None;
         let mut new_pps = None;
+        let mut has_sps = false;
+        let mut has_pps = false;
+        let mut sei_nals = Vec::new();
+        let mut slices = Vec::new();
 
         if log_enabled!(log::Level::Debug) {
             self.log_access_unit(&au, reason);
@@ -431,16 +860,25 @@ impl Depacketizer {
             let nal_pieces = &self.pieces[piece_idx..next_piece_idx];
             match nal.hdr.nal_unit_type() {
                 UnitType::SeqParameterSet => {
+                    has_sps = true;
                     if !matches(&self.parameters.sps_nal[..], nal.hdr, nal_pieces) {
                         new_sps = Some(to_bytes(nal.hdr, nal.len, nal_pieces));
                     }
                 }
                 UnitType::PicParameterSet => {
+                    has_pps = true;
                     if !matches(&self.parameters.pps_nal[..], nal.hdr, nal_pieces) {
                         new_pps = Some(to_bytes(nal.hdr, nal.len, nal_pieces));
                     }
                 }
-                UnitType::SliceLayerWithoutPartitioningIdr => is_random_access_point = true,
+                UnitType::SEI => sei_nals.push(to_bytes(nal.hdr, nal.len, nal_pieces)),
+                UnitType::SliceLayerWithoutPartitioningIdr => {
+                    is_random_access_point = true;
+                    slices.push((nal.hdr, slice_header_prefix(nal_pieces)));
+                }
+                UnitType::SliceLayerWithoutPartitioningNonIdr => {
+                    slices.push((nal.hdr, slice_header_prefix(nal_pieces)))
+                }
                 _ => {}
             }
             if nal.hdr.nal_ref_idc() != 0 {
@@ -450,12 +888,45 @@ impl Depacketizer {
             retained_len += 4usize + usize::try_from(nal.len).expect("u32 fits in usize");
             piece_idx = next_piece_idx;
         }
+
+        // Parameter sets to insert before the first VCL NAL. The inserted ones are never
+        // changed by this access unit, so the current parameters are correct.
+        let mut inserted: Vec<&[u8]> = Vec::new();
+        if self.output.insert_parameters && is_random_access_point {
+            if !has_sps {
+                inserted.push(&self.parameters.sps_nal[..]);
+            }
+            if !has_pps {
+                inserted.push(&self.parameters.pps_nal[..]);
+            }
+        }
+        let insert_aud = self.output.insert_aud
+            && self.nals.first().map(|n| n.hdr.nal_unit_type())
+                != Some(UnitType::AccessUnitDelimiter);
+        if insert_aud {
+            retained_len += 4 + AUD.len();
+        }
+        retained_len += inserted.iter().map(|n| 4 + n.len()).sum::<usize>();
+
         let mut data = Vec::with_capacity(retained_len);
+        if insert_aud {
+            self.output.put_prefix(&mut data, AUD.len() as u32);
+            data.extend_from_slice(AUD);
+        }
         piece_idx = 0;
         for nal in &self.nals {
             let next_piece_idx = usize::try_from(nal.next_piece_idx).expect("u32 fits in usize");
             let nal_pieces = &self.pieces[piece_idx..next_piece_idx];
-            data.extend_from_slice(&nal.len.to_be_bytes()[..]);
+            if !inserted.is_empty() && is_vcl(nal.hdr) {
+                for set in inserted.drain(..) {
+                    self.output.put_prefix(
+                        &mut data,
+                        u32::try_from(set.len()).expect("parameter set fits in u16"),
+                    );
+                    data.extend_from_slice(set);
+                }
+            }
+            self.output.put_prefix(&mut data, nal.len);
             data.push(nal.hdr.into());
             let mut actual_len = 1;
             for piece in nal_pieces {
@@ -478,10 +949,47 @@ impl Depacketizer {
             let pps_nal = new_pps.as_deref().unwrap_or(&self.parameters.pps_nal);
             // TODO: could map this to a RtpPacketError more accurately.
             self.parameters = InternalParameters::parse_sps_and_pps(sps_nal, pps_nal)?;
+            self.poc.restart(self.parameters.slice.reorder_depth);
             Some(Box::new(self.parameters.generic_parameters.clone()))
         } else {
             None
         };
+
+        // Parse SEI messages after any parameter change, as picture timing depends on the SPS.
+        let mut sei = Vec::new();
+        for nal in &sei_nals {
+            let rbsp = Bytes::from(h264_reader::rbsp::decode_nal(&nal[1..]).into_owned());
+            if let Err(e) = parse_sei(rbsp, &self.parameters.pic_timing, &mut sei) {
+                debug!("ignoring rest of malformed SEI NAL: {}", e);
+            }
+        }
+
+        let (picture_type, pic_order_cnt) =
+            match parse_slice_headers(&slices, &self.parameters.slice) {
+                Ok(Some((picture_type, first))) => (
+                    Some(picture_type),
+                    self.poc.pic_order_cnt(&self.parameters.slice, &first),
+                ),
+                Ok(None) => (None, None),
+                Err(e) => {
+                    debug!("unable to parse slice header: {}", e);
+                    (None, None)
+                }
+            };
+        let frame_duration = self
+            .parameters
+            .generic_parameters
+            .frame_rate
+            .and_then(|(num, den)| {
+                let clock_rate = u64::from(au.timestamp.clock_rate().get());
+                (clock_rate * u64::from(num)).checked_div(u64::from(den))
+            })
+            .and_then(|d| i64::try_from(d).ok())
+            .filter(|&d| d > 0)
+            .unwrap_or(1);
+        let composition_offset =
+            self.poc
+                .composition_offset(pic_order_cnt, au.timestamp.timestamp(), frame_duration);
         Ok(VideoFrame {
             new_parameters,
             loss: au.loss,
@@ -491,11 +999,118 @@ impl Depacketizer {
             stream_id: au.stream_id,
             is_random_access_point,
             is_disposable,
+            capture_time: au.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: au.extension,
+            sei,
+            composition_offset,
+            pic_order_cnt,
+            picture_type,
+            nal_layout: Some(self.output.layout(false)),
             data,
         })
     }
 }
 
+/// A NAL unit within an aggregation packet.
+struct AggregatedNal {
+    hdr: NalHeader,
+
+    /// The decoding order number, for STAP-B and MTAP packets.
+    don: Option<u16>,
+
+    /// The timestamp offset, for MTAP packets.
+    ts_offset: u32,
+
+    /// The total length of this NAL, including the header byte.
+    len: u32,
+
+    /// The NAL excluding its header byte.
+    data: Bytes,
+}
+
+/// Parses a STAP-A, STAP-B, MTAP16, or MTAP24 packet, calling `f` for each NAL.
+///
+/// `data` is the payload following the aggregation packet's NAL header byte.
+/// See [RFC 6184 section 5.7](https://tools.ietf.org/html/rfc6184#section-5.7).
+fn parse_aggregation<F>(nal_header: u8, mut data: Bytes, mut f: F) -> Result<(), String>
+where
+    F: FnMut(AggregatedNal) -> Result<(), String>,
+{
+    let kind = nal_header & 0b11111;
+    let name = ["STAP-A", "STAP-B", "MTAP16", "MTAP24"][usize::from(kind - 24)];
+
+    // STAP-B: the DON of the next NAL. MTAP: the decoding order number base.
+    let mut don = None;
+    if kind != 24 {
+        if data.remaining() < 2 {
+            return Err(format!("{} too short for DON", name));
+        }
+        don = Some(data.get_u16());
+    }
+
+    // The bytes preceding each NAL within an MTAP unit: DOND and TS offset.
+    let unit_hdr_len = match kind {
+        26 => 3,
+        27 => 4,
+        _ => 0,
+    };
+    loop {
+        if data.remaining() < 3 + unit_hdr_len {
+            return Err(format!(
+                "{} has {} remaining bytes; expecting 2-byte length, non-empty NAL",
+                name,
+                data.remaining()
+            ));
+        }
+        let len = usize::from(data.get_u16());
+        if len <= unit_hdr_len {
+            return Err(format!("{} has unit of length {}", name, len));
+        }
+        if data.remaining() < len {
+            return Err(format!(
+                "{} too short: {} bytes remaining, expecting {}-byte unit",
+                name,
+                data.remaining(),
+                len
+            ));
+        }
+        let mut unit = data.split_to(len);
+        let mut ts_offset = 0;
+        let nal_don = match (kind, don) {
+            (25, Some(d)) => {
+                // Subsequent NALs in a STAP-B have consecutive DONs.
+                don = Some(d.wrapping_add(1));
+                Some(d)
+            }
+            (26..=27, Some(donb)) => {
+                let dond = unit.get_u8();
+                ts_offset = if kind == 26 {
+                    u32::from(unit.get_u16())
+                } else {
+                    (u32::from(unit.get_u8()) << 16) | u32::from(unit.get_u16())
+                };
+                Some(donb.wrapping_add(u16::from(dond)))
+            }
+            _ => None,
+        };
+        let hdr = NalHeader::new(unit[0])
+            .map_err(|_| format!("bad header {:02x} in {}", unit[0], name))?;
+        let len = u32::try_from(unit.len()).expect("unit len < u16::MAX");
+        unit.advance(1);
+        f(AggregatedNal {
+            hdr,
+            don: nal_don,
+            ts_offset,
+            len,
+            data: unit,
+        })?;
+        if !data.has_remaining() {
+            return Ok(());
+        }
+    }
+}
+
 /// Returns true if we allow the given NAL unit type to end an access unit.
 ///
 /// We specifically prohibit this for the SPS and PPS. Reolink cameras sometimes
@@ -526,6 +1141,7 @@ impl AccessUnit {
             // TODO: overflow?
             loss: pkt.loss + additional_loss,
             same_ts_as_prev,
+            extension: pkt.extension.clone(),
         }
     }
 }
@@ -536,7 +1152,14 @@ impl AccessUnit {
 /// problems.
 fn validate_order(nals: &[Nal], errs: &mut String) {
     let mut seen_vcl = false;
+    let mut prev_don = None;
     for (i, nal) in nals.iter().enumerate() {
+        if let (Some(prev), Some(don)) = (prev_don, nal.don) {
+            if (don.wrapping_sub(prev) as i16) < 0 {
+                let _ = write!(errs, "\n* NAL with DON {} after DON {}", don, prev);
+            }
+        }
+        prev_don = nal.don.or(prev_don);
         match nal.hdr.nal_unit_type() {
             /* 1 */ UnitType::SliceLayerWithoutPartitioningNonIdr |
             /* 2 */ UnitType::SliceDataPartitionALayer |
@@ -583,40 +1206,505 @@ struct InternalParameters {
 
     /// The (single) PPS NAL.
     pps_nal: Bytes,
+
+    /// The SPS's parameters needed to decode picture timing SEI messages.
+    pic_timing: PicTimingParams,
+
+    /// The SPS's and PPS's parameters needed to parse slice headers.
+    slice: SliceParams,
 }
 
-impl InternalParameters {
-    /// Parses metadata from the `format-specific-params` of a SDP `fmtp` media attribute.
-    fn parse_format_specific_params(format_specific_params: &str) -> Result<Self, String> {
-        let mut sprop_parameter_sets = None;
-        for p in format_specific_params.split(';') {
-            let (key, value) = p.trim().split_once('=').unwrap();
-            if key == "sprop-parameter-sets" {
-                sprop_parameter_sets = Some(value);
+/// SPS- and PPS-derived parameters for parsing slice headers and computing picture order counts,
+/// as in ISO/IEC 14496-10 sections 7.3.3 and 8.2.1.
+#[derive(Clone, Debug)]
+struct SliceParams {
+    separate_colour_plane_flag: bool,
+    log2_max_frame_num: u8,
+    frame_mbs_only_flag: bool,
+    pic_order_cnt: h264_reader::nal::sps::PicOrderCntType,
+    bottom_field_pic_order_in_frame_present_flag: bool,
+
+    /// The maximum number of frames which may precede any frame in decoding order and follow it
+    /// in output order.
+    reorder_depth: u32,
+}
+
+impl SliceParams {
+    fn new(
+        sps: &h264_reader::nal::sps::SeqParameterSet,
+        sps_rbsp: &[u8],
+        pps_nal: &[u8],
+    ) -> Result<Self, String> {
+        use h264_reader::nal::sps::{FrameMbsFlags, PicOrderCntType};
+        if sps.log2_max_frame_num_minus4 > 12 {
+            return Err(format!(
+                "SPS has invalid log2_max_frame_num_minus4 {}",
+                sps.log2_max_frame_num_minus4
+            ));
+        }
+        if let PicOrderCntType::TypeZero {
+            log2_max_pic_order_cnt_lsb_minus4,
+        } = sps.pic_order_cnt
+        {
+            if log2_max_pic_order_cnt_lsb_minus4 > 12 {
+                return Err(format!(
+                    "SPS has invalid log2_max_pic_order_cnt_lsb_minus4 {}",
+                    log2_max_pic_order_cnt_lsb_minus4
+                ));
             }
         }
-        let sprop_parameter_sets = sprop_parameter_sets
-            .ok_or_else(|| "no sprop-parameter-sets in H.264 format-specific-params".to_string())?;
 
-        let mut sps_nal = None;
-        let mut pps_nal = None;
-        for nal in sprop_parameter_sets.split(',') {
-            let nal =
-                base64::decode(nal).map_err(|_| "NAL has invalid base64 encoding".to_string())?;
-            if nal.is_empty() {
-                return Err("empty NAL".into());
+        // The start of the PPS, as in ISO/IEC 14496-10 section 7.3.2.2.
+        let pps_rbsp = h264_reader::rbsp::decode_nal(pps_nal.get(1..).unwrap_or_default());
+        let mut r = RbspReader(bitreader::BitReader::new(&pps_rbsp));
+        r.ue("pic_parameter_set_id")?;
+        r.ue("seq_parameter_set_id")?;
+        r.flag("entropy_coding_mode_flag")?;
+        let bottom_field_pic_order_in_frame_present_flag =
+            r.flag("bottom_field_pic_order_in_frame_present_flag")?;
+
+        Ok(SliceParams {
+            separate_colour_plane_flag: sps.chroma_info.separate_colour_plane_flag,
+            log2_max_frame_num: sps.log2_max_frame_num_minus4 + 4,
+            frame_mbs_only_flag: matches!(sps.frame_mbs_flags, FrameMbsFlags::Frames),
+            pic_order_cnt: sps.pic_order_cnt.clone(),
+            bottom_field_pic_order_in_frame_present_flag,
+            reorder_depth: reorder_depth(sps, sps_rbsp),
+        })
+    }
+}
+
+/// Returns the maximum number of frames which may precede any frame in decoding order and follow
+/// it in output order.
+///
+/// This is the VUI's `max_num_reorder_frames` if present, or otherwise the value inferred as in
+/// ISO/IEC 14496-10 section E.2.1. It's capped at 16, the maximum DPB size.
+fn reorder_depth(sps: &h264_reader::nal::sps::SeqParameterSet, sps_rbsp: &[u8]) -> u32 {
+    use h264_reader::nal::sps::{FrameMbsFlags, PicOrderCntType};
+
+    // With picture order count type 2, output order is the same as decoding order.
+    if matches!(sps.pic_order_cnt, PicOrderCntType::TypeTwo) {
+        return 0;
+    }
+    if let Some(r) = sps
+        .vui_parameters
+        .as_ref()
+        .and_then(|v| v.bitstream_restrictions.as_ref())
+    {
+        return r.max_num_reorder_frames.min(16);
+    }
+    let (profile_idc, constraint_flags, level_idc) = (sps_rbsp[0], sps_rbsp[1], sps_rbsp[2]);
+    let constraint_set3_flag = constraint_flags & 0x10 != 0;
+    if constraint_set3_flag && matches!(profile_idc, 44 | 86 | 100 | 110 | 122 | 244) {
+        return 0;
+    }
+
+    // MaxDpbFrames, from MaxDpbMbs in Table A-1.
+    let max_dpb_mbs: u32 = match level_idc {
+        9 | 10 => 396,
+        11 => 900,
+        12 | 13 | 20 => 2_376,
+        21 => 4_752,
+        22 | 30 => 8_100,
+        31 => 18_000,
+        32 => 20_480,
+        40 | 41 => 32_768,
+        42 => 34_816,
+        50 => 110_400,
+        51 | 52 => 184_320,
+        60..=62 => 696_320,
+        _ => return 16,
+    };
+    let frame_height_in_mbs = (sps.pic_height_in_map_units_minus1 + 1)
+        * match sps.frame_mbs_flags {
+            FrameMbsFlags::Frames => 1,
+            FrameMbsFlags::Fields { .. } => 2,
+        };
+    let frame_size_in_mbs = (sps.pic_width_in_mbs_minus1 + 1).saturating_mul(frame_height_in_mbs);
+    (max_dpb_mbs / frame_size_in_mbs).min(16)
+}
+
+/// The parts of a slice header needed to compute its picture order count, as in ISO/IEC 14496-10
+/// section 7.3.3.
+#[derive(Debug)]
+struct SliceHeader {
+    nal_ref_idc: u8,
+    idr: bool,
+    slice_type: u32,
+    frame_num: u32,
+    field_pic_flag: bool,
+    bottom_field_flag: bool,
+    pic_order_cnt_lsb: u32,
+    delta_pic_order_cnt_bottom: i32,
+    delta_pic_order_cnt: [i32; 2],
+}
+
+impl SliceHeader {
+    fn parse(hdr: NalHeader, rbsp: &[u8], params: &SliceParams) -> Result<Self, String> {
+        use h264_reader::nal::sps::PicOrderCntType;
+        let mut r = RbspReader(bitreader::BitReader::new(rbsp));
+        r.ue("first_mb_in_slice")?;
+        let slice_type = r.ue("slice_type")?;
+        if slice_type > 9 {
+            return Err(format!("invalid slice_type {}", slice_type));
+        }
+        r.ue("pic_parameter_set_id")?;
+        if params.separate_colour_plane_flag {
+            r.skip(2, "colour_plane_id")?;
+        }
+        let frame_num = r.bits(params.log2_max_frame_num, "frame_num")?;
+        let mut field_pic_flag = false;
+        let mut bottom_field_flag = false;
+        if !params.frame_mbs_only_flag {
+            field_pic_flag = r.flag("field_pic_flag")?;
+            if field_pic_flag {
+                bottom_field_flag = r.flag("bottom_field_flag")?;
             }
-            let header = h264_reader::nal::NalHeader::new(nal[0])
-                .map_err(|_| format!("bad NAL header {:0x}", nal[0]))?;
-            match header.nal_unit_type() {
-                UnitType::SeqParameterSet => {
-                    if sps_nal.is_some() {
-                        return Err("multiple SPSs".into());
-                    }
-                    sps_nal = Some(nal);
+        }
+        let idr = hdr.nal_unit_type() == UnitType::SliceLayerWithoutPartitioningIdr;
+        if idr {
+            r.ue("idr_pic_id")?;
+        }
+        let mut pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_cnt_bottom = 0;
+        let mut delta_pic_order_cnt = [0; 2];
+        let bottom_present = params.bottom_field_pic_order_in_frame_present_flag && !field_pic_flag;
+        match params.pic_order_cnt {
+            PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4,
+            } => {
+                pic_order_cnt_lsb =
+                    r.bits(log2_max_pic_order_cnt_lsb_minus4 + 4, "pic_order_cnt_lsb")?;
+                if bottom_present {
+                    delta_pic_order_cnt_bottom = r.se("delta_pic_order_cnt_bottom")?;
                 }
-                UnitType::PicParameterSet => {
-                    if pps_nal.is_some() {
+            }
+            PicOrderCntType::TypeOne {
+                delta_pic_order_always_zero_flag: false,
+                ..
+            } => {
+                delta_pic_order_cnt[0] = r.se("delta_pic_order_cnt[0]")?;
+                if bottom_present {
+                    delta_pic_order_cnt[1] = r.se("delta_pic_order_cnt[1]")?;
+                }
+            }
+            _ => {}
+        }
+        Ok(SliceHeader {
+            nal_ref_idc: hdr.nal_ref_idc(),
+            idr,
+            slice_type,
+            frame_num,
+            field_pic_flag,
+            bottom_field_flag,
+            pic_order_cnt_lsb,
+            delta_pic_order_cnt_bottom,
+            delta_pic_order_cnt,
+        })
+    }
+}
+
+/// Parses the slice headers of an access unit's slices, as collected by `slice_header_prefix`.
+///
+/// Returns the picture type implied by all the slice types and the first slice's header, or
+/// `None` if there are no slices.
+fn parse_slice_headers(
+    slices: &[(NalHeader, Vec<u8>)],
+    params: &SliceParams,
+) -> Result<Option<(super::PictureType, SliceHeader)>, String> {
+    use super::PictureType;
+    let mut first = None;
+    let mut picture_type = PictureType::I;
+    for (hdr, prefix) in slices {
+        let rbsp = h264_reader::rbsp::decode_nal(prefix);
+        let header = SliceHeader::parse(*hdr, &rbsp, params)?;
+        match header.slice_type % 5 {
+            1 => picture_type = PictureType::B,
+            0 | 3 if picture_type == PictureType::I => picture_type = PictureType::P,
+            _ => {}
+        }
+        if first.is_none() {
+            first = Some(header);
+        }
+    }
+    Ok(first.map(|f| (picture_type, f)))
+}
+
+/// Picture order count and decode timestamp state, carried from one access unit to the next.
+#[derive(Debug)]
+struct PocState {
+    /// `prevPicOrderCntMsb` and `prevPicOrderCntLsb`, as in ISO/IEC 14496-10 section 8.2.1.1.
+    prev_pic_order_cnt_msb: i64,
+    prev_pic_order_cnt_lsb: i64,
+
+    /// `prevFrameNumOffset` and `prevFrameNum`, as in ISO/IEC 14496-10 sections 8.2.1.2 and
+    /// 8.2.1.3.
+    prev_frame_num_offset: i64,
+    prev_frame_num: i64,
+
+    /// The number of IDR pictures seen, which orders pictures across picture order count resets.
+    idr_count: u64,
+
+    /// Presentation timestamps not yet used as decode timestamps, keyed by output order.
+    reorder: BinaryHeap<Reverse<(u64, i32, i64)>>,
+
+    /// The number of decode timestamps which still need to be extrapolated backward from
+    /// `first_timestamp` because `reorder` doesn't yet hold enough pictures.
+    extrapolate: u32,
+    first_timestamp: Option<i64>,
+
+    prev_decode_timestamp: Option<i64>,
+}
+
+impl PocState {
+    fn new(reorder_depth: u32) -> Self {
+        PocState {
+            prev_pic_order_cnt_msb: 0,
+            prev_pic_order_cnt_lsb: 0,
+            prev_frame_num_offset: 0,
+            prev_frame_num: 0,
+            idr_count: 0,
+            reorder: BinaryHeap::new(),
+            extrapolate: reorder_depth,
+            first_timestamp: None,
+            prev_decode_timestamp: None,
+        }
+    }
+
+    /// Restarts decode timestamp calculation after a parameter change, keeping them monotonic.
+    fn restart(&mut self, reorder_depth: u32) {
+        self.reorder.clear();
+        self.extrapolate = reorder_depth;
+        self.first_timestamp = None;
+    }
+
+    /// Computes a picture's order count as in ISO/IEC 14496-10 section 8.2.1.
+    ///
+    /// Doesn't account for `memory_management_control_operation` 5, which would require parsing
+    /// the entire slice header.
+    fn pic_order_cnt(&mut self, params: &SliceParams, h: &SliceHeader) -> Option<i32> {
+        use h264_reader::nal::sps::PicOrderCntType;
+        if h.idr {
+            self.idr_count += 1;
+        }
+        let frame_num = i64::from(h.frame_num);
+        let frame_num_offset = if h.idr {
+            0
+        } else if self.prev_frame_num > frame_num {
+            self.prev_frame_num_offset + (1 << params.log2_max_frame_num)
+        } else {
+            self.prev_frame_num_offset
+        };
+        self.prev_frame_num_offset = frame_num_offset;
+        self.prev_frame_num = frame_num;
+        let (top, bottom) = match params.pic_order_cnt {
+            PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4,
+            } => {
+                // Section 8.2.1.1.
+                if h.idr {
+                    self.prev_pic_order_cnt_msb = 0;
+                    self.prev_pic_order_cnt_lsb = 0;
+                }
+                let max_lsb = 1i64 << (log2_max_pic_order_cnt_lsb_minus4 + 4);
+                let lsb = i64::from(h.pic_order_cnt_lsb);
+                let prev_lsb = self.prev_pic_order_cnt_lsb;
+                let msb = if lsb < prev_lsb && prev_lsb - lsb >= max_lsb / 2 {
+                    self.prev_pic_order_cnt_msb + max_lsb
+                } else if lsb > prev_lsb && lsb - prev_lsb > max_lsb / 2 {
+                    self.prev_pic_order_cnt_msb - max_lsb
+                } else {
+                    self.prev_pic_order_cnt_msb
+                };
+                if h.nal_ref_idc != 0 {
+                    self.prev_pic_order_cnt_msb = msb;
+                    self.prev_pic_order_cnt_lsb = lsb;
+                }
+                let top = msb + lsb;
+                if h.field_pic_flag {
+                    (top, top)
+                } else {
+                    (top, top + i64::from(h.delta_pic_order_cnt_bottom))
+                }
+            }
+            PicOrderCntType::TypeOne {
+                offset_for_non_ref_pic,
+                offset_for_top_to_bottom_field,
+                ref offsets_for_ref_frame,
+                ..
+            } => {
+                // Section 8.2.1.2.
+                let cycle_len = offsets_for_ref_frame.len() as i64;
+                let mut abs_frame_num = if cycle_len != 0 {
+                    frame_num_offset + frame_num
+                } else {
+                    0
+                };
+                if h.nal_ref_idc == 0 && abs_frame_num > 0 {
+                    abs_frame_num -= 1;
+                }
+                let mut expected = 0;
+                if abs_frame_num > 0 {
+                    let cycle_cnt = (abs_frame_num - 1) / cycle_len;
+                    let frame_num_in_cycle = (abs_frame_num - 1) % cycle_len;
+                    let delta_per_cycle: i64 =
+                        offsets_for_ref_frame.iter().map(|&o| i64::from(o)).sum();
+                    expected = cycle_cnt * delta_per_cycle
+                        + offsets_for_ref_frame[..=frame_num_in_cycle as usize]
+                            .iter()
+                            .map(|&o| i64::from(o))
+                            .sum::<i64>();
+                }
+                if h.nal_ref_idc == 0 {
+                    expected += i64::from(offset_for_non_ref_pic);
+                }
+                let top = expected + i64::from(h.delta_pic_order_cnt[0]);
+                if !h.field_pic_flag {
+                    (
+                        top,
+                        top + i64::from(offset_for_top_to_bottom_field)
+                            + i64::from(h.delta_pic_order_cnt[1]),
+                    )
+                } else if h.bottom_field_flag {
+                    let bottom = expected
+                        + i64::from(offset_for_top_to_bottom_field)
+                        + i64::from(h.delta_pic_order_cnt[0]);
+                    (bottom, bottom)
+                } else {
+                    (top, top)
+                }
+            }
+            PicOrderCntType::TypeTwo => {
+                // Section 8.2.1.3.
+                let temp = if h.idr {
+                    0
+                } else if h.nal_ref_idc == 0 {
+                    2 * (frame_num_offset + frame_num) - 1
+                } else {
+                    2 * (frame_num_offset + frame_num)
+                };
+                (temp, temp)
+            }
+        };
+        i32::try_from(top.min(bottom)).ok()
+    }
+
+    /// Returns the difference between a picture's presentation and decode timestamps.
+    ///
+    /// Pictures arrive in decoding order. Each decode timestamp is the lowest presentation
+    /// timestamp (in output order) not yet used, once `reorder_depth` pictures have been
+    /// buffered; before then, it's extrapolated backward from the first picture via
+    /// `frame_duration`. The result is clamped to be monotonic and not after the presentation
+    /// timestamp.
+    fn composition_offset(
+        &mut self,
+        pic_order_cnt: Option<i32>,
+        timestamp: i64,
+        frame_duration: i64,
+    ) -> u32 {
+        let candidate = match pic_order_cnt {
+            Some(poc) => {
+                let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+                self.reorder.push(Reverse((self.idr_count, poc, timestamp)));
+                if self.extrapolate > 0 {
+                    let c = first_timestamp - i64::from(self.extrapolate) * frame_duration;
+                    self.extrapolate -= 1;
+                    c
+                } else {
+                    let Reverse((_, _, t)) = self.reorder.pop().expect("just pushed");
+                    t
+                }
+            }
+            None => timestamp,
+        };
+        let decode_timestamp = match self.prev_decode_timestamp {
+            Some(p) => candidate.max(p),
+            None => candidate,
+        }
+        .min(timestamp)
+        .max(timestamp - i64::from(u32::MAX));
+        self.prev_decode_timestamp = Some(decode_timestamp);
+        u32::try_from(timestamp - decode_timestamp).expect("offset clamped to u32")
+    }
+}
+
+/// SPS-derived parameters for decoding picture timing SEI messages, as in ISO/IEC 14496-10
+/// section D.2.3.
+#[derive(Clone, Debug)]
+struct PicTimingParams {
+    /// The lengths in bits of `cpb_removal_delay` and `dpb_output_delay`, iff
+    /// `CpbDpbDelaysPresentFlag` is set.
+    delay_lengths: Option<(u8, u8)>,
+
+    /// The length in bits of `time_offset`.
+    time_offset_length: u8,
+
+    pic_struct_present_flag: bool,
+}
+
+impl PicTimingParams {
+    fn new(sps: &h264_reader::nal::sps::SeqParameterSet) -> Self {
+        let vui = match sps.vui_parameters {
+            Some(ref vui) => vui,
+            None => {
+                return PicTimingParams {
+                    delay_lengths: None,
+                    time_offset_length: 24,
+                    pic_struct_present_flag: false,
+                }
+            }
+        };
+        let hrd = vui
+            .nal_hrd_parameters
+            .as_ref()
+            .or(vui.vcl_hrd_parameters.as_ref());
+        PicTimingParams {
+            delay_lengths: hrd.map(|h| {
+                (
+                    h.cpb_removal_delay_length_minus1 + 1,
+                    h.dpb_output_delay_length_minus1 + 1,
+                )
+            }),
+
+            // ISO/IEC 14496-10 section E.2.2: inferred to be 24 when there are no HRD parameters.
+            time_offset_length: hrd.map(|h| h.time_offset_length).unwrap_or(24),
+            pic_struct_present_flag: vui.pic_struct_present_flag,
+        }
+    }
+}
+
+impl InternalParameters {
+    /// Parses metadata from the `format-specific-params` of a SDP `fmtp` media attribute.
+    fn parse_format_specific_params(format_specific_params: &str) -> Result<Self, String> {
+        let mut sprop_parameter_sets = None;
+        for p in format_specific_params.split(';') {
+            let (key, value) = p.trim().split_once('=').unwrap();
+            if key == "sprop-parameter-sets" {
+                sprop_parameter_sets = Some(value);
+            }
+        }
+        let sprop_parameter_sets = sprop_parameter_sets
+            .ok_or_else(|| "no sprop-parameter-sets in H.264 format-specific-params".to_string())?;
+
+        let mut sps_nal = None;
+        let mut pps_nal = None;
+        for nal in sprop_parameter_sets.split(',') {
+            let nal =
+                base64::decode(nal).map_err(|_| "NAL has invalid base64 encoding".to_string())?;
+            if nal.is_empty() {
+                return Err("empty NAL".into());
+            }
+            let header = h264_reader::nal::NalHeader::new(nal[0])
+                .map_err(|_| format!("bad NAL header {:0x}", nal[0]))?;
+            match header.nal_unit_type() {
+                UnitType::SeqParameterSet => {
+                    if sps_nal.is_some() {
+                        return Err("multiple SPSs".into());
+                    }
+                    sps_nal = Some(nal);
+                }
+                UnitType::PicParameterSet => {
+                    if pps_nal.is_some() {
                         return Err("multiple PPSs".into());
                     }
                     pps_nal = Some(nal);
@@ -693,6 +1781,8 @@ impl InternalParameters {
         let pps_nal_end = avc_decoder_config.len();
         assert_eq!(avc_decoder_config.len(), 11 + sps_nal.len() + pps_nal.len());
 
+        let pic_timing = PicTimingParams::new(&sps);
+        let slice = SliceParams::new(&sps, &sps_rbsp, pps_nal)?;
         let (pixel_aspect_ratio, frame_rate);
         match sps.vui_parameters {
             Some(ref vui) => {
@@ -702,7 +1792,8 @@ impl InternalParameters {
                     .and_then(|a| a.clone().get())
                     .map(|(h, v)| (u32::from(h), (u32::from(v))));
 
-                // TODO: study H.264, (E-34). This quick'n'dirty calculation isn't always right.
+                // Each frame lasts two ticks (DeltaTfiDivisor = 2 in H.264 Table E-6) unless
+                // picture timing SEIs say otherwise, eg for field pictures or frame doubling.
                 frame_rate = vui.timing_info.as_ref().and_then(|t| {
                     t.num_units_in_tick
                         .checked_mul(2)
@@ -715,6 +1806,13 @@ impl InternalParameters {
             }
         }
         let avc_decoder_config = avc_decoder_config.freeze();
+        let sample_entry = super::make_video_sample_entry(
+            b"avc1",
+            (b"avcC", &avc_decoder_config[..]),
+            pixel_dimensions,
+            pixel_aspect_ratio,
+            hrd_bit_rate(&sps),
+        )?;
         let sps_nal = avc_decoder_config.slice(sps_nal_start..sps_nal_end);
         let pps_nal = avc_decoder_config.slice(pps_nal_start..pps_nal_end);
         Ok(InternalParameters {
@@ -724,13 +1822,304 @@ impl InternalParameters {
                 pixel_aspect_ratio,
                 frame_rate,
                 extra_data: avc_decoder_config,
+                sample_entry,
+                details: sequence_details(&sps, sps_rbsp[0], sps_rbsp[2]),
             },
             sps_nal,
             pps_nal,
+            pic_timing,
+            slice,
         })
     }
 }
 
+/// Returns the details of the SPS exposed via [`super::VideoParameters`].
+fn sequence_details(
+    sps: &h264_reader::nal::sps::SeqParameterSet,
+    profile_idc: u8,
+    level_idc: u8,
+) -> super::SequenceDetails {
+    use h264_reader::nal::sps::{ChromaFormat, FrameMbsFlags};
+    let chroma = &sps.chroma_info;
+    let chroma_format = match chroma.chroma_format {
+        ChromaFormat::Monochrome => Some(super::ChromaFormat::Monochrome),
+        ChromaFormat::YUV420 => Some(super::ChromaFormat::Yuv420),
+        ChromaFormat::YUV422 => Some(super::ChromaFormat::Yuv422),
+        ChromaFormat::YUV444 => Some(super::ChromaFormat::Yuv444),
+        ChromaFormat::Invalid(_) => None,
+    };
+    let frame_mbs_only = matches!(sps.frame_mbs_flags, FrameMbsFlags::Frames);
+
+    // CropUnitX and CropUnitY, as in H.264 equations 7-19 through 7-22.
+    let (sub_width_c, sub_height_c) = match chroma_format {
+        Some(super::ChromaFormat::Yuv420) => (2, 2),
+        Some(super::ChromaFormat::Yuv422) => (2, 1),
+        _ => (1, 1),
+    };
+    let crop_unit_y = sub_height_c * if frame_mbs_only { 1 } else { 2 };
+    let cropping = sps.frame_cropping.as_ref().map(|c| super::Cropping {
+        left: c.left_offset.saturating_mul(sub_width_c),
+        right: c.right_offset.saturating_mul(sub_width_c),
+        top: c.top_offset.saturating_mul(crop_unit_y),
+        bottom: c.bottom_offset.saturating_mul(crop_unit_y),
+    });
+
+    let vui = sps.vui_parameters.as_ref();
+    let video_signal_type = vui.and_then(|v| v.video_signal_type.as_ref());
+    super::SequenceDetails {
+        profile_idc: Some(profile_idc),
+        level_idc: Some(level_idc),
+        chroma_format,
+        bit_depth: Some((
+            chroma.bit_depth_luma_minus8.saturating_add(8),
+            chroma.bit_depth_chroma_minus8.saturating_add(8),
+        )),
+        frame_mbs_only: Some(frame_mbs_only),
+        cropping,
+        full_range: video_signal_type.map(|t| t.video_full_range_flag),
+        color_description: video_signal_type
+            .and_then(|t| t.colour_description.as_ref())
+            .map(|c| super::ColorDescription {
+                primaries: c.colour_primaries,
+                transfer_characteristics: c.transfer_characteristics,
+                matrix_coefficients: c.matrix_coefficients,
+            }),
+        fixed_frame_rate: vui
+            .and_then(|v| v.timing_info.as_ref())
+            .map(|t| t.fixed_frame_rate_flag),
+        max_num_reorder_frames: vui
+            .and_then(|v| v.bitstream_restrictions.as_ref())
+            .map(|b| b.max_num_reorder_frames),
+    }
+}
+
+/// Returns the bitrate and buffer size of the SPS's highest-rate HRD schedule, if any.
+///
+/// See ISO/IEC 14496-10 section E.2.2 for the `BitRate` and `CpbSize` formulas.
+fn hrd_bit_rate(sps: &h264_reader::nal::sps::SeqParameterSet) -> Option<super::BitRate> {
+    let vui = sps.vui_parameters.as_ref()?;
+    let hrd = vui
+        .nal_hrd_parameters
+        .as_ref()
+        .or(vui.vcl_hrd_parameters.as_ref())?;
+    let spec = hrd.cpb_specs.last()?;
+    let bit_rate = (u64::from(spec.bit_rate_value_minus1) + 1) << (6 + hrd.bit_rate_scale);
+    let cpb_size = (u64::from(spec.cpb_size_value_minus1) + 1) << (4 + hrd.cpb_size_scale);
+    let bit_rate = u32::try_from(bit_rate).unwrap_or(u32::MAX);
+    Some(super::BitRate {
+        buffer_size_db: u32::try_from(cpb_size / 8).unwrap_or(u32::MAX),
+        max_bitrate: bit_rate,
+
+        // A constant bitrate schedule's rate is also the average.
+        avg_bitrate: if spec.cbr_flag { bit_rate } else { 0 },
+    })
+}
+
+/// Parses the SEI messages within a SEI NAL's RBSP, as in ISO/IEC 14496-10 section 7.3.2.3.1.
+///
+/// A payload which can't be decoded is returned as [`SeiPayload::Other`] rather than failing;
+/// only errors in the message framing are returned.
+///
+/// This doesn't use `h264_reader::nal::sei`: in h264-reader 0.5, SEI messages are only available
+/// through push-style `NalHandler` callbacks which report a `HeaderType` without its numeric
+/// `payloadType`. Its `pic_timing::Delays` fields are private, its delay parsing ignores VCL HRD
+/// parameters, and `PicTiming::read` assumes SPS id 0. Its ITU-T T.35 parser is private.
+fn parse_sei(
+    rbsp: Bytes,
+    timing: &PicTimingParams,
+    out: &mut Vec<SeiMessage>,
+) -> Result<(), String> {
+    // Stop at the rbsp_trailing_bits. Messages are byte-aligned, so the rbsp_stop_one_bit should
+    // be a whole 0x80 byte, possibly followed by zero bytes.
+    let end = match rbsp.iter().rposition(|&b| b != 0) {
+        Some(i) if rbsp[i] == 0x80 => i,
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let mut pos = 0;
+    while pos < end {
+        let payload_type = read_sei_value(&rbsp[..end], &mut pos, "payloadType")?;
+        let payload_size = read_sei_value(&rbsp[..end], &mut pos, "payloadSize")?;
+        let payload_end = pos + usize::try_from(payload_size).expect("u32 fits in usize");
+        if payload_end > end {
+            return Err(format!(
+                "SEI payload of type {} has size {}; only {} bytes remain",
+                payload_type,
+                payload_size,
+                end - pos
+            ));
+        }
+        let payload = rbsp.slice(pos..payload_end);
+        pos = payload_end;
+        let decoded = decode_sei_payload(payload_type, &payload, timing).unwrap_or_else(|e| {
+            debug!(
+                "unable to decode SEI payload of type {}: {}",
+                payload_type, e
+            );
+            SeiPayload::Other
+        });
+        out.push(SeiMessage {
+            payload_type,
+            payload,
+            decoded,
+        });
+    }
+    Ok(())
+}
+
+/// Reads a `payloadType` or `payloadSize`, which are coded as a run of 0xFF bytes plus a final byte.
+fn read_sei_value(data: &[u8], pos: &mut usize, name: &str) -> Result<u32, String> {
+    let mut value = 0u32;
+    loop {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| format!("SEI message truncated within {}", name))?;
+        *pos += 1;
+        value = value
+            .checked_add(u32::from(b))
+            .ok_or_else(|| format!("SEI {} overflows", name))?;
+        if b != 0xff {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_sei_payload(
+    payload_type: u32,
+    payload: &Bytes,
+    timing: &PicTimingParams,
+) -> Result<SeiPayload, String> {
+    match payload_type {
+        1 => decode_pic_timing(payload, timing).map(SeiPayload::PicTiming),
+        4 => {
+            let country_code = *payload.first().ok_or("empty payload")?;
+            let (country_code_extension, data_start) = if country_code == 0xff {
+                (
+                    Some(*payload.get(1).ok_or("missing country code extension")?),
+                    2,
+                )
+            } else {
+                (None, 1)
+            };
+            Ok(SeiPayload::UserDataRegisteredItuTT35 {
+                country_code,
+                country_code_extension,
+                data: payload.slice(data_start..),
+            })
+        }
+        5 => {
+            if payload.len() < 16 {
+                return Err(format!(
+                    "{}-byte payload is too short for uuid",
+                    payload.len()
+                ));
+            }
+            let mut uuid = [0u8; 16];
+            uuid.copy_from_slice(&payload[..16]);
+            Ok(SeiPayload::UserDataUnregistered {
+                uuid,
+                data: payload.slice(16..),
+            })
+        }
+        6 => {
+            let mut r = RbspReader(bitreader::BitReader::new(payload));
+            Ok(SeiPayload::RecoveryPoint(RecoveryPoint {
+                recovery_frame_cnt: r.ue("recovery_frame_cnt")?,
+                exact_match_flag: r.flag("exact_match_flag")?,
+                broken_link_flag: r.flag("broken_link_flag")?,
+                changing_slice_group_idc: r.bits(2, "changing_slice_group_idc")? as u8,
+            }))
+        }
+        _ => Ok(SeiPayload::Other),
+    }
+}
+
+/// Decodes a picture timing SEI payload, as in ISO/IEC 14496-10 section D.1.3.
+fn decode_pic_timing(payload: &[u8], timing: &PicTimingParams) -> Result<PicTiming, String> {
+    let mut r = RbspReader(bitreader::BitReader::new(payload));
+    let (cpb_removal_delay, dpb_output_delay) = match timing.delay_lengths {
+        Some((cpb_len, dpb_len)) => (
+            Some(r.bits(cpb_len, "cpb_removal_delay")?),
+            Some(r.bits(dpb_len, "dpb_output_delay")?),
+        ),
+        None => (None, None),
+    };
+    let mut pic_struct = None;
+    let mut clock_timestamps = Vec::new();
+    if timing.pic_struct_present_flag {
+        let s = r.bits(4, "pic_struct")? as u8;
+
+        // NumClockTS, from Table D-1.
+        let num_clock_ts = match s {
+            0..=2 => 1,
+            3 | 4 | 7 => 2,
+            5 | 6 | 8 => 3,
+            _ => return Err(format!("reserved pic_struct {}", s)),
+        };
+        pic_struct = Some(s);
+        for _ in 0..num_clock_ts {
+            clock_timestamps.push(if r.flag("clock_timestamp_flag")? {
+                Some(decode_clock_timestamp(&mut r, timing.time_offset_length)?)
+            } else {
+                None
+            });
+        }
+    }
+    Ok(PicTiming {
+        cpb_removal_delay,
+        dpb_output_delay,
+        pic_struct,
+        clock_timestamps,
+    })
+}
+
+fn decode_clock_timestamp(
+    r: &mut RbspReader,
+    time_offset_length: u8,
+) -> Result<ClockTimestamp, String> {
+    let ct_type = r.bits(2, "ct_type")? as u8;
+    let nuit_field_based_flag = r.flag("nuit_field_based_flag")?;
+    let counting_type = r.bits(5, "counting_type")? as u8;
+    let full_timestamp_flag = r.flag("full_timestamp_flag")?;
+    let discontinuity_flag = r.flag("discontinuity_flag")?;
+    let cnt_dropped_flag = r.flag("cnt_dropped_flag")?;
+    let n_frames = r.bits(8, "n_frames")? as u8;
+    let (mut seconds, mut minutes, mut hours) = (None, None, None);
+    if full_timestamp_flag {
+        seconds = Some(r.bits(6, "seconds_value")? as u8);
+        minutes = Some(r.bits(6, "minutes_value")? as u8);
+        hours = Some(r.bits(5, "hours_value")? as u8);
+    } else if r.flag("seconds_flag")? {
+        seconds = Some(r.bits(6, "seconds_value")? as u8);
+        if r.flag("minutes_flag")? {
+            minutes = Some(r.bits(6, "minutes_value")? as u8);
+            if r.flag("hours_flag")? {
+                hours = Some(r.bits(5, "hours_value")? as u8);
+            }
+        }
+    }
+    let time_offset = match time_offset_length {
+        0 => 0,
+        len => {
+            // Sign-extend the len-bit two's complement value.
+            let shift = 32 - u32::from(len);
+            ((r.bits(len, "time_offset")? << shift) as i32) >> shift
+        }
+    };
+    Ok(ClockTimestamp {
+        ct_type,
+        nuit_field_based_flag,
+        counting_type,
+        discontinuity_flag,
+        cnt_dropped_flag,
+        n_frames,
+        seconds,
+        minutes,
+        hours,
+        time_offset,
+    })
+}
+
 /// Returns true iff the bytes of `nal` equal the bytes of `[hdr, ..data]`.
 fn matches(nal: &[u8], hdr: NalHeader, pieces: &[Bytes]) -> bool {
     if nal.is_empty() || nal[0] != u8::from(hdr) {
@@ -750,6 +2139,21 @@ fn matches(nal: &[u8], hdr: NalHeader, pieces: &[Bytes]) -> bool {
     nal_pos == nal.len()
 }
 
+/// Returns enough of a slice NAL (excluding its header byte) to parse the slice header fields
+/// used by `SliceHeader`, including room for emulation prevention bytes.
+fn slice_header_prefix(pieces: &[Bytes]) -> Vec<u8> {
+    const MAX_LEN: usize = 64;
+    let mut out = Vec::with_capacity(MAX_LEN);
+    for piece in pieces {
+        let len = std::cmp::min(piece.len(), MAX_LEN - out.len());
+        out.extend_from_slice(&piece[..len]);
+        if out.len() == MAX_LEN {
+            break;
+        }
+    }
+    out
+}
+
 /// Saves the given NAL to a contiguous Bytes.
 fn to_bytes(hdr: NalHeader, len: u32, pieces: &[Bytes]) -> Bytes {
     let len = usize::try_from(len).expect("u32 fits in usize");
@@ -844,16 +2248,18 @@ impl Packetizer {
                         left: len + 1 - u32::from(self.max_payload_size),
                         data,
                     };
-                    // TODO: ctx, channel_id, and ssrc are placeholders.
+                    // TODO: ctx, channel_id, ssrc, and payload_type are placeholders.
                     return Ok(Some(Packet {
                         ctx: crate::RtspMessageContext::dummy(),
                         channel_id: 0,
                         stream_id: self.stream_id,
                         timestamp,
                         ssrc: 0,
+                        payload_type: 0,
                         sequence_number,
                         loss: 0,
                         mark: false,
+                        extension: None,
                         payload: Bytes::from(payload),
                     }));
                 }
@@ -875,9 +2281,11 @@ impl Packetizer {
                     stream_id: self.stream_id,
                     timestamp,
                     ssrc: 0,
+                    payload_type: 0,
                     sequence_number,
                     loss: 0,
                     mark,
+                    extension: None,
                     payload: data,
                 }))
             }
@@ -928,9 +2336,11 @@ impl Packetizer {
                     stream_id: self.stream_id,
                     timestamp,
                     ssrc: 0,
+                    payload_type: 0,
                     sequence_number,
                     loss: 0,
                     mark,
+                    extension: None,
                     payload: Bytes::from(payload),
                 }))
             }
@@ -965,7 +2375,10 @@ mod tests {
     use bytes::Bytes;
     use std::num::NonZeroU32;
 
-    use crate::{client::rtp::Packet, codec::CodecItem};
+    use crate::{
+        client::rtp::Packet,
+        codec::{ClockTimestamp, CodecItem, PicTiming, PictureType, RecoveryPoint, SeiPayload},
+    };
 
     /*
      * This test requires
@@ -1035,9 +2448,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 0,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x06plain"),
         })
         .unwrap();
@@ -1049,9 +2464,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 1,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x18\x00\x09\x06stap-a 1\x00\x09\x06stap-a 2"),
         })
         .unwrap();
@@ -1063,9 +2480,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 2,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x7c\x86fu-a start, "),
         })
         .unwrap();
@@ -1077,9 +2496,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 3,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x7c\x06fu-a middle, "),
         })
         .unwrap();
@@ -1091,9 +2512,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 4,
             loss: 0,
             mark: true,
+            extension: None,
             payload: Bytes::from_static(b"\x7c\x46fu-a end"),
         })
         .unwrap();
@@ -1110,140 +2533,567 @@ mod tests {
         );
     }
 
-    /// Test bad framing at the start of stream from a Reolink RLC-822A
-    /// Reolink RLC-822A (IPC_523128M8MP) running firmware v3.0.0.177_21012101:
-    /// suppress incorrect access unit changes after the SPS and PPS.
     #[test]
-    fn depacketize_reolink_bad_framing_at_start() {
-        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=640033;sprop-parameter-sets=Z2QAM6wVFKCgL/lQ,aO48sA==")).unwrap();
-        let ts1 = crate::Timestamp {
+    fn depacketize_stap_b() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let timestamp = crate::Timestamp {
             timestamp: 0,
             clock_rate: NonZeroU32::new(90_000).unwrap(),
             start: 0,
         };
-        let ts2 = crate::Timestamp {
-            timestamp: 1,
-            clock_rate: NonZeroU32::new(90_000).unwrap(),
-            start: 0,
-        };
         d.push(Packet {
-            // SPS with (incorrect) mark
+            // STAP-B packet with DON 5.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
-            timestamp: ts1,
+            timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 0,
             loss: 0,
-            mark: true,
-            payload: Bytes::from_static(b"\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50"),
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x05\x00\x09\x06stap-b 1\x00\x09\x06stap-b 2"),
         })
         .unwrap();
         assert!(d.pull().is_none());
+        let dons: Vec<_> = d.nals.iter().map(|n| n.don).collect();
+        assert_eq!(dons, [Some(5), Some(6)]);
         d.push(Packet {
-            // PPS
+            // plain slice packet.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
-            timestamp: ts1,
+            timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 1,
             loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x65slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x09\x06stap-b 1\
+                     \x00\x00\x00\x09\x06stap-b 2\
+                     \x00\x00\x00\x06\x65slice"
+        );
+    }
+
+    #[test]
+    fn depacketize_mtap16() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let timestamp = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // MTAP16 packet with DONB 0xfffe and DONDs 0 and 3, wrapping around.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
             mark: false,
-            payload: Bytes::from_static(b"\x68\xee\x3c\xb0"),
+            extension: None,
+            payload: Bytes::from_static(
+                b"\x1a\xff\xfe\x00\x0a\x00\x00\x00\x06mtap16\x00\x09\x03\x00\x00\x65slice",
+            ),
         })
         .unwrap();
         assert!(d.pull().is_none());
+        let dons: Vec<_> = d.nals.iter().map(|n| n.don).collect();
+        assert_eq!(dons, [Some(0xfffe), Some(1)]);
         d.push(Packet {
-            // Slice layer without partitioning IDR.
-            // This has a different timestamp than the SPS and PPS, even though
-            // RFC 6184 section 5.1 says that "the timestamp must match that of
-            // the primary coded picture of the access unit and that the marker
-            // bit can only be set on the final packet of the access unit.""
+            // plain slice packet.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
-            timestamp: ts2,
+            timestamp,
             ssrc: 0,
-            sequence_number: 2,
+            payload_type: 0,
+            sequence_number: 1,
             loss: 0,
             mark: true,
-            payload: Bytes::from_static(b"\x65slice"),
+            extension: None,
+            payload: Bytes::from_static(b"\x61slice"),
         })
         .unwrap();
         let frame = match d.pull() {
             Some(CodecItem::VideoFrame(frame)) => frame,
-            o => panic!("unexpected pull result {:#?}", o),
+            _ => panic!(),
         };
         assert_eq!(
             &frame.data()[..],
-            b"\x00\x00\x00\x0C\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50\
-              \x00\x00\x00\x04\x68\xee\x3c\xb0\
-              \x00\x00\x00\x06\x65slice"
+            b"\x00\x00\x00\x07\x06mtap16\
+                     \x00\x00\x00\x06\x65slice\
+                     \x00\x00\x00\x06\x61slice"
         );
-        assert_eq!(frame.timestamp, ts2); // use the timestamp from the video frame.
     }
 
-    /// Test bad framing at a GOP boundary in a stream from a Reolink RLC-822A
-    /// Reolink RLC-822A (IPC_523128M8MP) running firmware v3.0.0.177_21012101:
-    /// suppress incorrect access unit changes after the SPS and PPS.
     #[test]
-    fn depacketize_reolink_gop_boundary() {
-        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=640033;sprop-parameter-sets=Z2QAM6wVFKCgL/lQ,aO48sA==")).unwrap();
-        let ts1 = crate::Timestamp {
+    fn depacketize_mtap24() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let timestamp = crate::Timestamp {
             timestamp: 0,
             clock_rate: NonZeroU32::new(90_000).unwrap(),
             start: 0,
         };
-        let ts2 = crate::Timestamp {
-            timestamp: 1,
-            clock_rate: NonZeroU32::new(90_000).unwrap(),
-            start: 0,
-        };
         d.push(Packet {
-            // Slice layer without partitioning non-IDR, representing the
-            // last frame of the previous GOP.
+            // MTAP24 packet with DONB 0x10 and DONDs 2 and 4.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
-            timestamp: ts1,
+            timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 0,
             loss: 0,
             mark: true,
-            payload: Bytes::from_static(b"\x01slice"),
+            extension: None,
+            payload: Bytes::from_static(
+                b"\x1b\x00\x10\x00\x0b\x02\x00\x00\x00\x06mtap24\x00\x0a\x04\x00\x00\x00\x65slice",
+            ),
         })
         .unwrap();
         let frame = match d.pull() {
             Some(CodecItem::VideoFrame(frame)) => frame,
-            o => panic!("unexpected pull result {:#?}", o),
+            _ => panic!(),
         };
-        assert_eq!(&frame.data()[..], b"\x00\x00\x00\x06\x01slice");
-        assert_eq!(frame.timestamp, ts1);
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x07\x06mtap24\
+                     \x00\x00\x00\x06\x65slice"
+        );
+
+        // A unit too short to hold its DOND and TS offset is an error.
         d.push(Packet {
-            // SPS with (incorrect) timestamp matching last frame.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
-            timestamp: ts1,
+            timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 1,
             loss: 0,
-            mark: false, // correctly has no mark, unlike first SPS in stream.
-            payload: Bytes::from_static(b"\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50"),
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x1b\x00\x10\x00\x04\x02\x00\x00\x00\x65"),
         })
-        .unwrap();
-        assert!(d.pull().is_none());
-        d.push(Packet {
+        .unwrap_err();
+    }
+
+    #[test]
+    fn depacketize_fu_b() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let timestamp = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // FU-B packet, start, with DON 7.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x7d\x85\x00\x07fu-b start, "),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        let dons: Vec<_> = d.nals.iter().map(|n| n.don).collect();
+        assert_eq!(dons, [Some(7)]);
+        d.push(Packet {
+            // FU-A packet, end.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x7c\x45fu-a end"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x15\x65fu-b start, fu-a end"
+        );
+
+        // FU-B is only valid for the first fragment.
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 2,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x7d\x45\x00\x08fu-b end"),
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn depacketize_interleaved() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=2;sprop-interleaving-depth=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let ts = |timestamp| crate::Timestamp {
+            timestamp,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // STAP-B packet with the second access unit's slice, DON 2.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(3000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x02\x00\x08\x41p-slice"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // MTAP16 packet with the first access unit, DONs 0 and 1.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(0),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(
+                b"\x1a\x00\x00\x00\x07\x00\x00\x00\x06sei\x00\x07\x01\x00\x00\x65idr",
+            ),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // FU-B packet with the third access unit's slice, DON 3.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(6000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 2,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x7d\x81\x00\x03p-sl"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // FU-A packet, end. Releasing DON 2 completes the first access unit.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(6000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 3,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x7c\x41ice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        assert!(d.pull().is_none());
+        assert_eq!(frame.timestamp.timestamp, 0);
+        assert!(frame.is_random_access_point);
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x04\x06sei\
+                     \x00\x00\x00\x04\x65idr"
+        );
+
+        // A NAL arriving after a later one in decoding order was released
+        // can't be used.
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(0),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 4,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x01\x00\x05\x41late"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+
+        // Single NAL packets aren't allowed in interleaved mode.
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(9000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 5,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x65slice"),
+        })
+        .unwrap_err();
+    }
+
+    /// Tests releasing NALs because of `sprop-max-don-diff` and `sprop-deint-buf-req`.
+    #[test]
+    fn depacketize_interleaved_release() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=2;sprop-interleaving-depth=100;sprop-max-don-diff=1;sprop-deint-buf-req=16;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let ts = |timestamp| crate::Timestamp {
+            timestamp,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // STAP-B packet, DON 0.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(0),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x00\x00\x04\x65idr"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // STAP-B packet, DON 1. This releases DON 0.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(3000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x01\x00\x02\x41p"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // STAP-B packet, DON 3. This releases DON 1 by DON difference and
+            // DON 3 by size, completing two access units.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts(9000),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 2,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x19\x00\x03\x00\x11\x41big p-slice data"),
+        })
+        .unwrap();
+        let timestamps: Vec<_> = std::iter::from_fn(|| match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => Some(frame.timestamp.timestamp),
+            None => None,
+            _ => panic!(),
+        })
+        .collect();
+        assert_eq!(timestamps, [0, 3000]);
+    }
+
+    /// Test bad framing at the start of stream from a Reolink RLC-822A
+    /// Reolink RLC-822A (IPC_523128M8MP) running firmware v3.0.0.177_21012101:
+    /// suppress incorrect access unit changes after the SPS and PPS.
+    #[test]
+    fn depacketize_reolink_bad_framing_at_start() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=640033;sprop-parameter-sets=Z2QAM6wVFKCgL/lQ,aO48sA==")).unwrap();
+        let ts1 = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        let ts2 = crate::Timestamp {
+            timestamp: 1,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // SPS with (incorrect) mark
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts1,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // PPS
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts1,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x68\xee\x3c\xb0"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
+            // Slice layer without partitioning IDR.
+            // This has a different timestamp than the SPS and PPS, even though
+            // RFC 6184 section 5.1 says that "the timestamp must match that of
+            // the primary coded picture of the access unit and that the marker
+            // bit can only be set on the final packet of the access unit.""
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts2,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 2,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x65slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            o => panic!("unexpected pull result {:#?}", o),
+        };
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x0C\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50\
+              \x00\x00\x00\x04\x68\xee\x3c\xb0\
+              \x00\x00\x00\x06\x65slice"
+        );
+        assert_eq!(frame.timestamp, ts2); // use the timestamp from the video frame.
+    }
+
+    /// Test bad framing at a GOP boundary in a stream from a Reolink RLC-822A
+    /// Reolink RLC-822A (IPC_523128M8MP) running firmware v3.0.0.177_21012101:
+    /// suppress incorrect access unit changes after the SPS and PPS.
+    #[test]
+    fn depacketize_reolink_gop_boundary() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=640033;sprop-parameter-sets=Z2QAM6wVFKCgL/lQ,aO48sA==")).unwrap();
+        let ts1 = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        let ts2 = crate::Timestamp {
+            timestamp: 1,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // Slice layer without partitioning non-IDR, representing the
+            // last frame of the previous GOP.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts1,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x01slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            o => panic!("unexpected pull result {:#?}", o),
+        };
+        assert_eq!(&frame.data()[..], b"\x00\x00\x00\x06\x01slice");
+        assert_eq!(frame.timestamp, ts1);
+        d.push(Packet {
+            // SPS with (incorrect) timestamp matching last frame.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: ts1,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: false, // correctly has no mark, unlike first SPS in stream.
+            extension: None,
+            payload: Bytes::from_static(b"\x67\x64\x00\x33\xac\x15\x14\xa0\xa0\x2f\xf9\x50"),
+        })
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(Packet {
             // PPS, again with timestamp matching last frame.
             ctx: crate::RtspMessageContext::dummy(),
             channel_id: 0,
             stream_id: 0,
             timestamp: ts1,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 2,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x68\xee\x3c\xb0"),
         })
         .unwrap();
@@ -1255,9 +3105,11 @@ mod tests {
             stream_id: 0,
             timestamp: ts2,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 3,
             loss: 0,
             mark: true,
+            extension: None,
             payload: Bytes::from_static(b"\x65slice"),
         })
         .unwrap();
@@ -1294,9 +3146,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 0,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x67\x4d\x40\x1e\x9a\x64\x05\x01\xef\xf3\x50\x10\x10\x14\x00\x00\x0f\xa0\x00\x01\x38\x80\x10"),
         }).unwrap();
         assert!(d.pull().is_none());
@@ -1307,9 +3161,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 1,
             loss: 0,
             mark: false,
+            extension: None,
             payload: Bytes::from_static(b"\x68\xee\x3c\x80"),
         })
         .unwrap();
@@ -1321,9 +3177,11 @@ mod tests {
             stream_id: 0,
             timestamp,
             ssrc: 0,
+            payload_type: 0,
             sequence_number: 2,
             loss: 0,
             mark: true,
+            extension: None,
             payload: Bytes::from_static(b"\x65slice"),
         })
         .unwrap();
@@ -1352,4 +3210,423 @@ mod tests {
         );
         assert_eq!(&params.pps_nal[..], b"\x68\xee\x3c\x80");
     }
+
+    #[test]
+    fn depacketize_sei() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        let timestamp = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // SEI with several messages, then an unknown type using an emulation prevention byte.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(
+                b"\x06\
+                  \x06\x01\xc4\
+                  \x05\x150123456789abcdefhello\
+                  \x04\x08\xb5\x00\x31GA94\x03\
+                  \xff\x02\x03\x00\x00\x03\x01\
+                  \x80",
+            ),
+        })
+        .unwrap();
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x65idr slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        let sei = frame.sei();
+        assert_eq!(sei.len(), 4);
+        assert_eq!(sei[0].payload_type, 6);
+        assert_eq!(
+            sei[0].decoded,
+            SeiPayload::RecoveryPoint(RecoveryPoint {
+                recovery_frame_cnt: 0,
+                exact_match_flag: true,
+                broken_link_flag: false,
+                changing_slice_group_idc: 0,
+            })
+        );
+        assert_eq!(sei[1].payload_type, 5);
+        assert_eq!(sei[1].payload.len(), 21);
+        assert_eq!(
+            sei[1].decoded,
+            SeiPayload::UserDataUnregistered {
+                uuid: *b"0123456789abcdef",
+                data: Bytes::from_static(b"hello"),
+            }
+        );
+        assert_eq!(sei[2].payload_type, 4);
+        assert_eq!(
+            sei[2].decoded,
+            SeiPayload::UserDataRegisteredItuTT35 {
+                country_code: 0xb5,
+                country_code_extension: None,
+                data: Bytes::from_static(b"\x00\x31GA94\x03"),
+            }
+        );
+        assert_eq!(sei[3].payload_type, 257);
+        assert_eq!(&sei[3].payload[..], b"\x00\x00\x01");
+        assert_eq!(sei[3].decoded, SeiPayload::Other);
+
+        // A truncated SEI NAL keeps the messages before the damage and doesn't fail the frame.
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: timestamp.try_add(3000).unwrap(),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 2,
+            loss: 0,
+            mark: false,
+            extension: None,
+            payload: Bytes::from_static(b"\x06\x06\x01\xc4\x05\x15short\x80"),
+        })
+        .unwrap();
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: timestamp.try_add(3000).unwrap(),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 3,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x01non-idr slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        let types: Vec<_> = frame.sei().iter().map(|m| m.payload_type).collect();
+        assert_eq!(types, [6]);
+    }
+
+    #[test]
+    fn decode_pic_timing() {
+        let timing = super::PicTimingParams {
+            delay_lengths: Some((8, 8)),
+            time_offset_length: 5,
+            pic_struct_present_flag: true,
+        };
+        let pic_timing =
+            super::decode_pic_timing(b"\x01\x02\x08\x04\x05\x78\xf6\x76", &timing).unwrap();
+        assert_eq!(
+            pic_timing,
+            PicTiming {
+                cpb_removal_delay: Some(1),
+                dpb_output_delay: Some(2),
+                pic_struct: Some(0),
+                clock_timestamps: vec![Some(ClockTimestamp {
+                    ct_type: 0,
+                    nuit_field_based_flag: false,
+                    counting_type: 0,
+                    discontinuity_flag: false,
+                    cnt_dropped_flag: false,
+                    n_frames: 5,
+                    seconds: Some(30),
+                    minutes: Some(15),
+                    hours: Some(12),
+                    time_offset: -3,
+                })],
+            }
+        );
+    }
+
+    #[test]
+    fn depacketize_b_frames() {
+        // Main profile SPS with picture order count type 0, 30 fps timing (60 ticks/s, two
+        // ticks per frame, so 3000 clock units per frame), and max_num_reorder_frames=2,
+        // followed by a hierarchical GOP in decoding order: an IDR picture, a P picture, a
+        // reference B picture, and two non-reference B pictures.
+        let mut d = super::Depacketizer::new(
+            90_000,
+            Some("packetization-mode=1;profile-level-id=4D401E;sprop-parameter-sets=Z01AHvYKD9CAAAADAIAAAB5G0EQiyQ==,aO48gA=="),
+        )
+        .unwrap();
+        use PictureType::{B, I, P};
+        let frames = [
+            (&b"\x65\x88\x84\x2a\xa0"[..], 0, 0, I, false, 6_000),
+            (&b"\x41\x9a\x31\x55"[..], 12_000, 8, P, false, 15_000),
+            (&b"\x21\x9e\x49\x55"[..], 6_000, 4, B, false, 6_000),
+            (&b"\x01\x9e\x65\x55"[..], 3_000, 2, B, true, 0),
+            (&b"\x01\x9e\x6d\x55"[..], 9_000, 6, B, true, 3_000),
+        ];
+        let mut prev_decode_timestamp = None;
+        for (i, &(payload, ts, poc, picture_type, is_disposable, composition_offset)) in
+            frames.iter().enumerate()
+        {
+            let timestamp = crate::Timestamp {
+                timestamp: ts,
+                clock_rate: NonZeroU32::new(90_000).unwrap(),
+                start: 0,
+            };
+            d.push(Packet {
+                ctx: crate::RtspMessageContext::dummy(),
+                channel_id: 0,
+                stream_id: 0,
+                timestamp,
+                ssrc: 0,
+                payload_type: 0,
+                sequence_number: i as u16,
+                loss: 0,
+                mark: true,
+                extension: None,
+                payload: Bytes::from_static(payload),
+            })
+            .unwrap();
+            let frame = match d.pull() {
+                Some(CodecItem::VideoFrame(frame)) => frame,
+                _ => panic!(),
+            };
+            assert_eq!(frame.pic_order_cnt(), Some(poc), "frame {}", i);
+            assert_eq!(frame.picture_type(), Some(picture_type), "frame {}", i);
+            assert_eq!(frame.is_disposable, is_disposable, "frame {}", i);
+            assert_eq!(
+                frame.composition_offset(),
+                composition_offset,
+                "frame {}",
+                i
+            );
+            let decode_timestamp = frame.decode_timestamp().timestamp();
+            assert_eq!(decode_timestamp, ts - i64::from(composition_offset));
+            assert!(prev_decode_timestamp < Some(decode_timestamp));
+            prev_decode_timestamp = Some(decode_timestamp);
+        }
+    }
+
+    #[test]
+    fn pic_order_cnt_types_1_and_2() {
+        use h264_reader::nal::sps::PicOrderCntType;
+        let mut params = super::SliceParams {
+            separate_colour_plane_flag: false,
+            log2_max_frame_num: 4,
+            frame_mbs_only_flag: true,
+            pic_order_cnt: PicOrderCntType::TypeOne {
+                delta_pic_order_always_zero_flag: true,
+                offset_for_non_ref_pic: -1,
+                offset_for_top_to_bottom_field: 0,
+                offsets_for_ref_frame: vec![2],
+            },
+            bottom_field_pic_order_in_frame_present_flag: false,
+            reorder_depth: 1,
+        };
+
+        // An IDR picture, a P picture, and a non-reference picture to display between them.
+        let pictures = [(0x65, 0), (0x41, 1), (0x01, 2)];
+        let header = |hdr: u8, frame_num: u32| super::SliceHeader {
+            nal_ref_idc: hdr >> 5,
+            idr: hdr & 0x1f == 5,
+            slice_type: 0,
+            frame_num,
+            field_pic_flag: false,
+            bottom_field_flag: false,
+            pic_order_cnt_lsb: 0,
+            delta_pic_order_cnt_bottom: 0,
+            delta_pic_order_cnt: [0; 2],
+        };
+        let mut state = super::PocState::new(1);
+        let pocs: Vec<_> = pictures
+            .iter()
+            .map(|&(hdr, frame_num)| state.pic_order_cnt(&params, &header(hdr, frame_num)))
+            .collect();
+        assert_eq!(pocs, &[Some(0), Some(2), Some(1)]);
+
+        // Type 2 can't reorder; the non-reference picture follows the P picture.
+        params.pic_order_cnt = PicOrderCntType::TypeTwo;
+        let mut state = super::PocState::new(0);
+        let pocs: Vec<_> = pictures
+            .iter()
+            .map(|&(hdr, frame_num)| state.pic_order_cnt(&params, &header(hdr, frame_num)))
+            .collect();
+        assert_eq!(pocs, &[Some(0), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn depacketize_annex_b() {
+        let mut d = super::Depacketizer::new(90_000, Some("packetization-mode=1;profile-level-id=64001E;sprop-parameter-sets=Z2QAHqwsaoLA9puCgIKgAAADACAAAAMD0IAA,aO4xshsA")).unwrap();
+        d.set_nal_output(
+            crate::codec::NalOutput::default()
+                .annex_b(true)
+                .insert_parameters(true)
+                .insert_aud(true),
+        );
+        let timestamp = crate::Timestamp {
+            timestamp: 0,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            start: 0,
+        };
+        d.push(Packet {
+            // STAP-A with SEI and IDR slice.
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp,
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 0,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x18\x00\x04\x06sei\x00\x04\x65idr"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        assert_eq!(
+            &frame.data()[..],
+            &b"\x00\x00\x00\x01\x09\xf0\
+               \x00\x00\x00\x01\x06sei\
+               \x00\x00\x00\x01\x67\x64\x00\x1e\xac\x2c\x6a\x82\xc0\xf6\x9b\x82\x80\x82\xa0\
+               \x00\x00\x03\x00\x20\x00\x00\x03\x03\xd0\x80\x00\
+               \x00\x00\x00\x01\x68\xee\x31\xb2\x1b\x00\
+               \x00\x00\x00\x01\x65idr"[..]
+        );
+        let types: Vec<_> = frame.nal_units().map(|n| n.nal_unit_type()).collect();
+        assert_eq!(types, [9, 6, 7, 8, 5]);
+
+        // A non-IDR frame with its own delimiter is unchanged, other than framing.
+        d.set_nal_output(crate::codec::NalOutput::default().insert_aud(true));
+        d.push(Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: timestamp.try_add(3000).unwrap(),
+            ssrc: 0,
+            payload_type: 0,
+            sequence_number: 1,
+            loss: 0,
+            mark: true,
+            extension: None,
+            payload: Bytes::from_static(b"\x18\x00\x02\x09\x30\x00\x06\x01slice"),
+        })
+        .unwrap();
+        let frame = match d.pull() {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            _ => panic!(),
+        };
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x02\x09\x30\x00\x00\x00\x06\x01slice"
+        );
+        let nals: Vec<_> = frame
+            .nal_units()
+            .map(|n| (n.nal_unit_type(), n.data()))
+            .collect();
+        assert_eq!(nals, [(9, &b"\x09\x30"[..]), (1, &b"\x01slice"[..])]);
+    }
+
+    #[test]
+    fn sample_entry() {
+        // Baseline 320x240 SPS with a 4:3 pixel aspect ratio and a 1 Mbps CBR NAL HRD schedule.
+        let params = super::InternalParameters::parse_sps_and_pps(
+            b"\x67\x42\xc0\x1e\xda\x05\x07\xec\x38\x40\x00\x00\x03\x00\x40\x00\x00\x0f\x38\x00\
+              \x00\x3d\x09\x00\x00\xf4\x24\x6f\x7b\xe0\x20",
+            b"\x68\xce\x3c\x80",
+        )
+        .unwrap();
+        let p = &params.generic_parameters;
+        assert_eq!(p.pixel_dimensions(), (320, 240));
+        assert_eq!(p.pixel_aspect_ratio(), Some((4, 3)));
+        let entry = p.sample_entry();
+        let avcc_end = 86 + 8 + p.extra_data().len();
+        assert_eq!(entry.len(), avcc_end + 16 + 20);
+        assert_eq!(&entry[..4], &(entry.len() as u32).to_be_bytes()[..]);
+        assert_eq!(&entry[4..8], b"avc1");
+        assert_eq!(&entry[32..36], b"\x01\x40\x00\xf0"); // width, height
+        assert_eq!(&entry[90..94], b"avcC");
+        assert_eq!(&entry[94..avcc_end], &p.extra_data()[..]);
+        assert_eq!(
+            &entry[avcc_end..],
+            b"\x00\x00\x00\x10pasp\x00\x00\x00\x04\x00\x00\x00\x03\
+              \x00\x00\x00\x14btrt\x00\x03\xd0\x90\x00\x0f\x42\x40\x00\x0f\x42\x40"
+        );
+    }
+
+    #[test]
+    fn sequence_details() {
+        let params = super::InternalParameters::parse_sps_and_pps(
+            b"\x67\x42\xc0\x1e\xda\x05\x07\xec\x38\x40\x00\x00\x03\x00\x40\x00\x00\x0f\x38\x00\
+              \x00\x3d\x09\x00\x00\xf4\x24\x6f\x7b\xe0\x20",
+            b"\x68\xce\x3c\x80",
+        )
+        .unwrap();
+        let p = &params.generic_parameters;
+        assert_eq!(p.profile_idc(), Some(66));
+        assert_eq!(p.level_idc(), Some(30));
+        assert_eq!(p.chroma_format(), Some(crate::codec::ChromaFormat::Yuv420));
+        assert_eq!(p.bit_depth_luma(), Some(8));
+        assert_eq!(p.bit_depth_chroma(), Some(8));
+        assert_eq!(p.frame_mbs_only(), Some(true));
+        assert_eq!(p.cropping(), None);
+        assert_eq!(p.full_range(), None);
+        assert_eq!(p.color_description(), None);
+        assert_eq!(p.frame_rate(), Some((2, 60)));
+        assert_eq!(p.fixed_frame_rate(), Some(true));
+        assert_eq!(p.max_num_reorder_frames(), None);
+
+        // High profile 1920x1080 interlaced (MBAFF) SPS, cropped from 1088 lines, with full-range
+        // BT.709 colour and bitstream restrictions.
+        let params = super::InternalParameters::parse_sps_and_pps(
+            b"\x67\x64\x00\x28\xac\xe5\x01\xe0\x11\x3f\x73\x70\x10\x10\x14\x00\x00\x0f\xa4\x00\
+              \x03\xa9\x80\x36\x82\x21\x16\x58",
+            b"\x68\xce\x3c\x80",
+        )
+        .unwrap();
+        let p = &params.generic_parameters;
+        assert_eq!(p.pixel_dimensions(), (1920, 1080));
+        assert_eq!(p.profile_idc(), Some(100));
+        assert_eq!(p.level_idc(), Some(40));
+        assert_eq!(p.frame_mbs_only(), Some(false));
+        assert_eq!(
+            p.cropping(),
+            Some(crate::codec::Cropping {
+                left: 0,
+                right: 0,
+                top: 0,
+                bottom: 8,
+            })
+        );
+        assert_eq!(p.full_range(), Some(true));
+        assert_eq!(
+            p.color_description(),
+            Some(crate::codec::ColorDescription {
+                primaries: 1,
+                transfer_characteristics: 1,
+                matrix_coefficients: 1,
+            })
+        );
+        assert_eq!(p.fixed_frame_rate(), Some(false));
+        assert_eq!(p.max_num_reorder_frames(), Some(2));
+    }
 }
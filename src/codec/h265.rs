@@ -0,0 +1,1419 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! [H.265](https://www.itu.int/rec/T-REC-H.265)-encoded video.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{debug, log_enabled, trace};
+
+use crate::client::rtp::Packet;
+
+use super::{RbspReader, VideoFrame};
+
+// NAL unit types, from H.265 Table 7-1 and RFC 7798 section 4.4.
+const BLA_W_LP: u8 = 16;
+const RSV_IRAP_VCL23: u8 = 23;
+const MAX_VCL: u8 = 31;
+const VPS: u8 = 32;
+const SPS: u8 = 33;
+const PPS: u8 = 34;
+const AUD: u8 = 35;
+const EOS: u8 = 36;
+const EOB: u8 = 37;
+const SUFFIX_SEI: u8 = 40;
+const AP: u8 = 48;
+const FU: u8 = 49;
+const PACI: u8 = 50;
+
+/// A [super::Depacketizer] implementation which finds access unit boundaries
+/// and produces unfragmented NAL units as specified in [RFC
+/// 7798](https://tools.ietf.org/html/rfc7798).
+///
+/// Like [super::h264::Depacketizer], this doesn't inspect the contents of the
+/// VCL NAL units. It supports single NAL unit packets, aggregation packets
+/// (APs), and fragmentation units (FUs), but not PACI packets or the
+/// decoding order numbers used when `sprop-max-don-diff` is non-zero.
+///
+/// Currently expects that the stream starts at an access unit boundary unless
+/// packet loss is indicated.
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    input_state: DepacketizerInputState,
+
+    /// A complete video frame ready for pull.
+    pending: Option<VideoFrame>,
+
+    /// The current parameters, if known from the `fmtp` or in-band parameter sets.
+    parameters: Option<InternalParameters>,
+
+    /// In state `PreMark`, pieces of NALs, excluding their header bytes.
+    /// Kept around (empty) in other states to re-use the backing allocation.
+    pieces: Vec<Bytes>,
+
+    /// In state `PreMark`, an entry for each NAL.
+    /// Kept around (empty) in other states to re-use the backing allocation.
+    nals: Vec<Nal>,
+
+    /// The number of access units discarded because of packet loss.
+    discarded: u64,
+
+    output: super::NalOutput,
+}
+
+/// A NAL unit header, as in H.265 section 7.3.1.2.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct NalHeader([u8; 2]);
+
+impl NalHeader {
+    fn new(hdr: [u8; 2]) -> Result<Self, String> {
+        if (hdr[0] & 0b1000_0000) != 0 {
+            return Err(format!(
+                "NAL header {:02x}{:02x} has F bit set",
+                hdr[0], hdr[1]
+            ));
+        }
+        if (hdr[1] & 0b111) == 0 {
+            return Err(format!(
+                "NAL header {:02x}{:02x} has zero nuh_temporal_id_plus1",
+                hdr[0], hdr[1]
+            ));
+        }
+        Ok(NalHeader(hdr))
+    }
+
+    fn unit_type(self) -> u8 {
+        (self.0[0] >> 1) & 0b11_1111
+    }
+
+    /// Returns a copy of this header with the given unit type, as when
+    /// reconstructing a fragmented NAL's header.
+    fn with_unit_type(self, unit_type: u8) -> Self {
+        NalHeader([(self.0[0] & 0b1000_0001) | (unit_type << 1), self.0[1]])
+    }
+}
+
+impl std::fmt::Debug for NalHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NalHeader")
+            .field("unit_type", &UnitTypeName(self.unit_type()))
+            .field("layer_id", &(((self.0[0] & 1) << 5) | (self.0[1] >> 3)))
+            .field("temporal_id_plus1", &(self.0[1] & 0b111))
+            .finish()
+    }
+}
+
+/// Formats a NAL unit type with its name from H.265 Table 7-1.
+struct UnitTypeName(u8);
+
+impl std::fmt::Debug for UnitTypeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        #[rustfmt::skip]
+        const NAMES: [&str; 41] = [
+            /* 0 */ "TRAIL_N", "TRAIL_R", "TSA_N", "TSA_R", "STSA_N", "STSA_R", "RADL_N", "RADL_R",
+            /* 8 */ "RASL_N", "RASL_R", "RSV_VCL_N10", "RSV_VCL_R11", "RSV_VCL_N12",
+            /* 13 */ "RSV_VCL_R13", "RSV_VCL_N14", "RSV_VCL_R15", "BLA_W_LP", "BLA_W_RADL",
+            /* 18 */ "BLA_N_LP", "IDR_W_RADL", "IDR_N_LP", "CRA_NUT", "RSV_IRAP_VCL22",
+            /* 23 */ "RSV_IRAP_VCL23", "RSV_VCL24", "RSV_VCL25", "RSV_VCL26", "RSV_VCL27",
+            /* 28 */ "RSV_VCL28", "RSV_VCL29", "RSV_VCL30", "RSV_VCL31", "VPS_NUT", "SPS_NUT",
+            /* 34 */ "PPS_NUT", "AUD_NUT", "EOS_NUT", "EOB_NUT", "FD_NUT", "PREFIX_SEI_NUT",
+            /* 40 */ "SUFFIX_SEI_NUT",
+        ];
+        match NAMES.get(usize::from(self.0)) {
+            Some(name) => write!(f, "{} ({})", name, self.0),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Nal {
+    hdr: NalHeader,
+
+    /// The length of `Depacketizer::pieces` as this NAL finishes.
+    next_piece_idx: u32,
+
+    /// The total length of this NAL, including the two header bytes.
+    len: u32,
+}
+
+/// An access unit that is currently being accumulated during `PreMark` state.
+#[derive(Debug)]
+struct AccessUnit {
+    start_ctx: crate::RtspMessageContext,
+    end_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    stream_id: usize,
+
+    /// True iff currently processing a FU.
+    in_fu: bool,
+
+    /// RTP packets lost as this access unit was starting.
+    loss: u16,
+
+    same_ts_as_prev: bool,
+
+    /// The RTP header extension of the first packet.
+    extension: Option<crate::client::rtp::HeaderExtension>,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum DepacketizerInputState {
+    /// Not yet processing an access unit.
+    New,
+
+    /// Ignoring the remainder of an access unit because of interior packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+        pkts: u16,
+    },
+
+    /// Currently processing an access unit.
+    /// This will be flushed after a marked packet or when receiving a later timestamp.
+    PreMark(AccessUnit),
+
+    /// Finished processing the given packet. It's an error to receive the same timestamp again.
+    PostMark {
+        timestamp: crate::Timestamp,
+        loss: u16,
+    },
+}
+
+impl Depacketizer {
+    pub(super) fn new(
+        clock_rate: u32,
+        format_specific_params: Option<&str>,
+    ) -> Result<Self, String> {
+        if clock_rate != 90_000 {
+            return Err(format!(
+                "invalid H.265 clock rate {}; must always be 90000",
+                clock_rate
+            ));
+        }
+        let parameters = match format_specific_params {
+            None => None,
+            Some(p) => InternalParameters::parse_format_specific_params(p)?,
+        };
+        Ok(Depacketizer {
+            input_state: DepacketizerInputState::New,
+            pending: None,
+            pieces: Vec::new(),
+            nals: Vec::new(),
+            parameters,
+            discarded: 0,
+            output: super::NalOutput::default(),
+        })
+    }
+
+    pub(super) fn set_nal_output(&mut self, output: super::NalOutput) {
+        self.output = output;
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.parameters
+            .as_ref()
+            .map(|p| super::Parameters::Video(p.generic_parameters.clone()))
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.input_state = DepacketizerInputState::New;
+        self.pieces.clear();
+        self.nals.clear();
+    }
+
+    pub(super) fn push(&mut self, pkt: Packet) -> Result<(), String> {
+        // Push shouldn't be called until pull is exhausted.
+        if let Some(p) = self.pending.as_ref() {
+            panic!("push with data already pending: {:?}", p);
+        }
+
+        let mut access_unit =
+            match std::mem::replace(&mut self.input_state, DepacketizerInputState::New) {
+                DepacketizerInputState::New => {
+                    debug_assert!(self.nals.is_empty());
+                    debug_assert!(self.pieces.is_empty());
+                    AccessUnit::start(&pkt, 0, false)
+                }
+                DepacketizerInputState::PreMark(mut access_unit) => {
+                    if pkt.loss > 0 {
+                        self.discarded += 1;
+                        self.nals.clear();
+                        self.pieces.clear();
+                        if access_unit.timestamp.timestamp == pkt.timestamp.timestamp {
+                            // Loss within this access unit. Ignore until mark or new timestamp.
+                            self.input_state = if pkt.mark {
+                                DepacketizerInputState::PostMark {
+                                    timestamp: pkt.timestamp,
+                                    loss: pkt.loss,
+                                }
+                            } else {
+                                DepacketizerInputState::Loss {
+                                    timestamp: pkt.timestamp,
+                                    pkts: pkt.loss,
+                                }
+                            };
+                            return Ok(());
+                        }
+                        // A suffix of a previous access unit was lost; discard it.
+                        // A prefix of the new one may have been lost; try parsing.
+                        AccessUnit::start(&pkt, 0, false)
+                    } else if access_unit.timestamp.timestamp != pkt.timestamp.timestamp {
+                        if access_unit.in_fu {
+                            return Err(format!(
+                                "Timestamp changed from {} to {} in the middle of a fragmented NAL",
+                                access_unit.timestamp, pkt.timestamp
+                            ));
+                        }
+                        let last_nal_hdr = self.nals.last().unwrap().hdr;
+                        if can_end_au(last_nal_hdr.unit_type()) {
+                            access_unit.end_ctx = pkt.ctx;
+                            self.pending =
+                                Some(self.finalize_access_unit(access_unit, "ts change")?);
+                            AccessUnit::start(&pkt, 0, false)
+                        } else {
+                            log::debug!(
+                                "Bogus mid-access unit timestamp change after {:?}",
+                                last_nal_hdr
+                            );
+                            access_unit.timestamp.timestamp = pkt.timestamp.timestamp;
+                            access_unit
+                        }
+                    } else {
+                        access_unit
+                    }
+                }
+                DepacketizerInputState::PostMark {
+                    timestamp: state_ts,
+                    loss,
+                } => {
+                    debug_assert!(self.nals.is_empty());
+                    debug_assert!(self.pieces.is_empty());
+                    AccessUnit::start(&pkt, loss, state_ts.timestamp == pkt.timestamp.timestamp)
+                }
+                DepacketizerInputState::Loss {
+                    timestamp,
+                    mut pkts,
+                } => {
+                    debug_assert!(self.nals.is_empty());
+                    debug_assert!(self.pieces.is_empty());
+                    if pkt.timestamp.timestamp == timestamp.timestamp {
+                        pkts += pkt.loss;
+                        self.input_state = DepacketizerInputState::Loss { timestamp, pkts };
+                        return Ok(());
+                    }
+                    AccessUnit::start(&pkt, pkts, false)
+                }
+            };
+
+        let mut data = pkt.payload;
+        if data.len() < 2 {
+            return Err(format!(
+                "{}-byte payload too short for NAL header",
+                data.len()
+            ));
+        }
+        // https://tools.ietf.org/html/rfc7798#section-4.4
+        let payload_hdr = NalHeader::new([data[0], data[1]])?;
+        data.advance(2); // skip the header bytes.
+        match payload_hdr.unit_type() {
+            0..=47 => {
+                if access_unit.in_fu {
+                    return Err(format!(
+                        "Non-fragmented NAL {:?} while fragment in progress",
+                        payload_hdr
+                    ));
+                }
+                let len = u32::try_from(data.len()).expect("data len < u16::MAX") + 2;
+                let next_piece_idx = self.add_piece(data)?;
+                self.nals.push(Nal {
+                    hdr: payload_hdr,
+                    next_piece_idx,
+                    len,
+                });
+            }
+            AP => {
+                // https://tools.ietf.org/html/rfc7798#section-4.4.2
+                if access_unit.in_fu {
+                    return Err("AP while fragment in progress".into());
+                }
+                loop {
+                    if data.remaining() < 4 {
+                        return Err(format!(
+                            "AP has {} remaining bytes; expecting 2-byte length, NAL header",
+                            data.remaining()
+                        ));
+                    }
+                    let len = data.get_u16();
+                    if len < 2 {
+                        return Err(format!("{}-byte NAL in AP", len));
+                    }
+                    let hdr = NalHeader::new([data[0], data[1]])
+                        .map_err(|e| format!("bad header in AP: {}", e))?;
+                    match data.remaining().cmp(&usize::from(len)) {
+                        std::cmp::Ordering::Less => {
+                            return Err(format!(
+                                "AP too short: {} bytes remaining, expecting {}-byte NAL",
+                                data.remaining(),
+                                len
+                            ))
+                        }
+                        std::cmp::Ordering::Equal => {
+                            data.advance(2);
+                            let next_piece_idx = self.add_piece(data)?;
+                            self.nals.push(Nal {
+                                hdr,
+                                next_piece_idx,
+                                len: u32::from(len),
+                            });
+                            break;
+                        }
+                        std::cmp::Ordering::Greater => {
+                            let mut piece = data.split_to(usize::from(len));
+                            piece.advance(2);
+                            let next_piece_idx = self.add_piece(piece)?;
+                            self.nals.push(Nal {
+                                hdr,
+                                next_piece_idx,
+                                len: u32::from(len),
+                            });
+                        }
+                    }
+                }
+            }
+            FU => {
+                // https://tools.ietf.org/html/rfc7798#section-4.4.3
+                if data.len() < 2 {
+                    return Err(format!("FU len {} too short", data.len()));
+                }
+                let fu_header = data[0];
+                let start = (fu_header & 0b10000000) != 0;
+                let end = (fu_header & 0b01000000) != 0;
+                let fu_type = fu_header & 0b00111111;
+                data.advance(1);
+                if (start && end) || fu_type == AP || fu_type == FU || fu_type == PACI {
+                    return Err(format!("Invalid FU header {:02x}", fu_header));
+                }
+                let nal_header = payload_hdr.with_unit_type(fu_type);
+                if !end && pkt.mark {
+                    return Err("FU pkt with MARK && !END".into());
+                }
+                let u32_len = u32::try_from(data.len()).expect("RTP packet len must be < u16::MAX");
+                match (start, access_unit.in_fu) {
+                    (true, true) => return Err("FU with start bit while frag in progress".into()),
+                    (true, false) => {
+                        self.add_piece(data)?;
+                        self.nals.push(Nal {
+                            hdr: nal_header,
+                            next_piece_idx: u32::MAX, // should be overwritten later.
+                            len: 2 + u32_len,
+                        });
+                        access_unit.in_fu = true;
+                    }
+                    (false, true) => {
+                        let pieces = self.add_piece(data)?;
+                        let nal = self.nals.last_mut().expect("nals non-empty while in fu");
+                        if nal_header != nal.hdr {
+                            return Err(format!(
+                                "FU has inconsistent NAL header: {:?} then {:?}",
+                                nal.hdr, nal_header,
+                            ));
+                        }
+                        nal.len += u32_len;
+                        if end {
+                            nal.next_piece_idx = pieces;
+                            access_unit.in_fu = false;
+                        }
+                    }
+                    (false, false) => {
+                        if pkt.loss > 0 {
+                            self.discarded += 1;
+                            self.pieces.clear();
+                            self.nals.clear();
+                            self.input_state = DepacketizerInputState::Loss {
+                                timestamp: pkt.timestamp,
+                                pkts: pkt.loss,
+                            };
+                            return Ok(());
+                        }
+                        return Err("FU has start bit unset while no frag in progress".into());
+                    }
+                }
+            }
+            PACI => return Err("unimplemented PACI packet".into()),
+            _ => return Err(format!("bad payload header {:?}", payload_hdr)),
+        }
+        self.input_state = if pkt.mark {
+            let last_nal_hdr = self.nals.last().unwrap().hdr;
+            if can_end_au(last_nal_hdr.unit_type()) {
+                access_unit.end_ctx = pkt.ctx;
+                self.pending = Some(self.finalize_access_unit(access_unit, "mark")?);
+                DepacketizerInputState::PostMark {
+                    timestamp: pkt.timestamp,
+                    loss: 0,
+                }
+            } else {
+                log::debug!(
+                    "Bogus mid-access unit timestamp change after {:?}",
+                    last_nal_hdr
+                );
+                access_unit.timestamp.timestamp = pkt.timestamp.timestamp;
+                DepacketizerInputState::PreMark(access_unit)
+            }
+        } else {
+            DepacketizerInputState::PreMark(access_unit)
+        };
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<super::CodecItem> {
+        self.pending.take().map(super::CodecItem::VideoFrame)
+    }
+
+    /// Adds a piece to `self.pieces`, erroring if it becomes absurdly large.
+    fn add_piece(&mut self, piece: Bytes) -> Result<u32, String> {
+        self.pieces.push(piece);
+        u32::try_from(self.pieces.len()).map_err(|_| "more than u32::MAX pieces!".to_string())
+    }
+
+    /// Logs information about each access unit.
+    /// Currently, "bad" access units (violating certain specification rules)
+    /// are logged at debug priority, and others are logged at trace priority.
+    fn log_access_unit(&self, au: &AccessUnit, reason: &str) {
+        let mut errs = String::new();
+        if au.same_ts_as_prev {
+            errs.push_str("\n* same timestamp as previous access unit");
+        }
+        validate_order(&self.nals, &mut errs);
+        if !errs.is_empty() {
+            let mut nals = String::new();
+            for (i, nal) in self.nals.iter().enumerate() {
+                let _ = write!(&mut nals, "\n  {}: {:?}", i, nal.hdr);
+            }
+            debug!(
+                "bad access unit (ended by {}) at ts {}\nerrors are:{}\nNALs are:{}",
+                reason, au.timestamp, errs, nals
+            );
+        } else if log_enabled!(log::Level::Trace) {
+            let mut nals = String::new();
+            for (i, nal) in self.nals.iter().enumerate() {
+                let _ = write!(&mut nals, "\n  {}: {:?}", i, nal.hdr);
+            }
+            trace!(
+                "access unit (ended by {}) at ts {}; NALS are:{}",
+                reason,
+                au.timestamp,
+                nals
+            );
+        }
+    }
+
+    fn finalize_access_unit(&mut self, au: AccessUnit, reason: &str) -> Result<VideoFrame, String> {
+        let mut piece_idx = 0;
+        let mut retained_len = 0usize;
+        let mut is_random_access_point = false;
+        let mut is_disposable = true;
+        let mut new_sets: [Option<Bytes>; 3] = [None, None, None];
+        let mut has_sets = [false; 3];
+
+        if log_enabled!(log::Level::Debug) {
+            self.log_access_unit(&au, reason);
+        }
+        for nal in &self.nals {
+            let next_piece_idx = usize::try_from(nal.next_piece_idx).expect("u32 fits in usize");
+            let nal_pieces = &self.pieces[piece_idx..next_piece_idx];
+            let unit_type = nal.hdr.unit_type();
+            match unit_type {
+                VPS | SPS | PPS => {
+                    has_sets[usize::from(unit_type - VPS)] = true;
+                    let current = self.parameters.as_ref().map(|p| p.nal(unit_type));
+                    if !current.map_or(false, |c| matches(c, nal.hdr, nal_pieces)) {
+                        new_sets[usize::from(unit_type - VPS)] =
+                            Some(to_bytes(nal.hdr, nal.len, nal_pieces));
+                    }
+                }
+                BLA_W_LP..=RSV_IRAP_VCL23 => {
+                    is_random_access_point = true;
+                    is_disposable = false;
+                }
+                0..=MAX_VCL => {
+                    // Even types up to 14 are sub-layer non-reference pictures.
+                    if unit_type > 14 || unit_type % 2 == 1 {
+                        is_disposable = false;
+                    }
+                }
+                _ => {}
+            }
+            retained_len += 4usize + usize::try_from(nal.len).expect("u32 fits in usize");
+            piece_idx = next_piece_idx;
+        }
+
+        // Parameter sets to insert before the first VCL NAL. As in H.264, the inserted ones
+        // are never changed by this access unit.
+        let mut inserted: Vec<&[u8]> = Vec::new();
+        if let (true, true, Some(p)) = (
+            self.output.insert_parameters,
+            is_random_access_point,
+            self.parameters.as_ref(),
+        ) {
+            for (i, &has) in has_sets.iter().enumerate() {
+                if !has {
+                    inserted.push(&p.nal(VPS + i as u8)[..]);
+                }
+            }
+        }
+
+        // An access unit delimiter with pic_type 2 (any slice type), as in H.265 section 7.3.2.5.
+        // Its TemporalId must match the access unit's.
+        let aud = match self.nals.first() {
+            Some(first) if self.output.insert_aud && first.hdr.unit_type() != AUD => {
+                Some([AUD << 1, first.hdr.0[1] & 0b111, 0x50])
+            }
+            _ => None,
+        };
+        if let Some(ref aud) = aud {
+            retained_len += 4 + aud.len();
+        }
+        retained_len += inserted.iter().map(|n| 4 + n.len()).sum::<usize>();
+
+        let mut data = Vec::with_capacity(retained_len);
+        if let Some(ref aud) = aud {
+            self.output.put_prefix(&mut data, aud.len() as u32);
+            data.extend_from_slice(&aud[..]);
+        }
+        piece_idx = 0;
+        for nal in &self.nals {
+            let next_piece_idx = usize::try_from(nal.next_piece_idx).expect("u32 fits in usize");
+            let nal_pieces = &self.pieces[piece_idx..next_piece_idx];
+            if !inserted.is_empty() && nal.hdr.unit_type() <= MAX_VCL {
+                for set in inserted.drain(..) {
+                    self.output.put_prefix(
+                        &mut data,
+                        u32::try_from(set.len()).expect("parameter set fits in u16"),
+                    );
+                    data.extend_from_slice(set);
+                }
+            }
+            self.output.put_prefix(&mut data, nal.len);
+            data.extend_from_slice(&nal.hdr.0[..]);
+            let mut actual_len = 2;
+            for piece in nal_pieces {
+                data.extend_from_slice(&piece[..]);
+                actual_len += piece.len();
+            }
+            debug_assert_eq!(
+                usize::try_from(nal.len).expect("u32 fits in usize"),
+                actual_len
+            );
+            piece_idx = next_piece_idx;
+        }
+        debug_assert_eq!(retained_len, data.len());
+        let data = Bytes::from(data);
+        self.nals.clear();
+        self.pieces.clear();
+
+        let new_parameters = if new_sets.iter().any(Option::is_some) {
+            let current = self.parameters.as_ref();
+            let nal = |i: usize| {
+                new_sets[i]
+                    .as_deref()
+                    .or_else(|| current.map(|p| &p.nal(VPS + i as u8)[..]))
+            };
+            match (nal(0), nal(1), nal(2)) {
+                (Some(vps_nal), Some(sps_nal), Some(pps_nal)) => {
+                    // TODO: could map this to a RtpPacketError more accurately.
+                    let parameters =
+                        InternalParameters::parse_vps_sps_pps(vps_nal, sps_nal, pps_nal)?;
+                    let generic_parameters = parameters.generic_parameters.clone();
+                    self.parameters = Some(parameters);
+                    Some(Box::new(generic_parameters))
+                }
+                _ => {
+                    debug!(
+                        "ignoring incomplete in-band parameter sets at ts {}",
+                        au.timestamp
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Ok(VideoFrame {
+            new_parameters,
+            loss: au.loss,
+            start_ctx: au.start_ctx,
+            end_ctx: au.end_ctx,
+            timestamp: au.timestamp,
+            stream_id: au.stream_id,
+            is_random_access_point,
+            is_disposable,
+            capture_time: au.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: au.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: Some(self.output.layout(true)),
+            data,
+        })
+    }
+}
+
+/// Returns true if we allow the given NAL unit type to end an access unit.
+///
+/// As with H.264, parameter sets can't follow the last VCL NAL unit of an
+/// access unit (H.265 section 7.4.2.4.4), so a marker bit or timestamp change
+/// after one is bogus.
+fn can_end_au(unit_type: u8) -> bool {
+    unit_type != VPS && unit_type != SPS && unit_type != PPS
+}
+
+impl AccessUnit {
+    fn start(
+        pkt: &crate::client::rtp::Packet,
+        additional_loss: u16,
+        same_ts_as_prev: bool,
+    ) -> Self {
+        AccessUnit {
+            start_ctx: pkt.ctx,
+            end_ctx: pkt.ctx,
+            timestamp: pkt.timestamp,
+            stream_id: pkt.stream_id,
+            in_fu: false,
+
+            // TODO: overflow?
+            loss: pkt.loss + additional_loss,
+            same_ts_as_prev,
+            extension: pkt.extension.clone(),
+        }
+    }
+}
+
+/// Checks NAL unit type ordering against rules of H.265 section 7.4.2.4.4.
+///
+/// This doesn't precisely check every rule there but enough to diagnose some
+/// problems.
+fn validate_order(nals: &[Nal], errs: &mut String) {
+    let mut seen_vcl = false;
+    for (i, nal) in nals.iter().enumerate() {
+        match nal.hdr.unit_type() {
+            0..=MAX_VCL => seen_vcl = true,
+            AUD => {
+                if i != 0 {
+                    let _ = write!(
+                        errs,
+                        "\n* access unit delimiter must be first in AU; was preceded by {:?}",
+                        nals[i - 1].hdr
+                    );
+                }
+            }
+            SUFFIX_SEI => {
+                if !seen_vcl {
+                    errs.push_str("\n* suffix SEI before VCL");
+                }
+            }
+            EOS => {
+                if !seen_vcl {
+                    errs.push_str("\n* end of sequence without VCL");
+                }
+            }
+            EOB => {
+                if i != nals.len() - 1 {
+                    errs.push_str("\n* end of bitstream NAL isn't last");
+                }
+            }
+            _ => {}
+        }
+    }
+    if !seen_vcl {
+        errs.push_str("\n* missing VCL");
+    }
+}
+
+#[derive(Clone, Debug)]
+struct InternalParameters {
+    generic_parameters: super::VideoParameters,
+
+    /// The (single) VPS NAL.
+    vps_nal: Bytes,
+
+    /// The (single) SPS NAL.
+    sps_nal: Bytes,
+
+    /// The (single) PPS NAL.
+    pps_nal: Bytes,
+}
+
+impl InternalParameters {
+    /// Returns the parameter set NAL of the given unit type.
+    fn nal(&self, unit_type: u8) -> &Bytes {
+        match unit_type {
+            VPS => &self.vps_nal,
+            SPS => &self.sps_nal,
+            _ => &self.pps_nal,
+        }
+    }
+
+    /// Parses metadata from the `format-specific-params` of a SDP `fmtp` media attribute.
+    ///
+    /// Returns `None` if there are no out-of-band parameter sets.
+    fn parse_format_specific_params(format_specific_params: &str) -> Result<Option<Self>, String> {
+        let mut vps_nal = None;
+        let mut sps_nal = None;
+        let mut pps_nal = None;
+        for p in format_specific_params.split(';') {
+            let (key, value) = match p.trim().split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let (nal, unit_type) = match key {
+                "sprop-vps" => (&mut vps_nal, VPS),
+                "sprop-sps" => (&mut sps_nal, SPS),
+                "sprop-pps" => (&mut pps_nal, PPS),
+                "sprop-max-don-diff" => {
+                    let diff: u16 = value
+                        .parse()
+                        .map_err(|_| format!("bad sprop-max-don-diff {:?}", value))?;
+                    if diff > 0 {
+                        return Err(format!(
+                            "unimplemented sprop-max-don-diff={} (decoding order numbers)",
+                            diff
+                        ));
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if value.contains(',') {
+                return Err(format!("multiple NALs in {}", key));
+            }
+            let decoded =
+                base64::decode(value).map_err(|_| "NAL has invalid base64 encoding".to_string())?;
+            if decoded.len() < 2 {
+                return Err(format!("{}-byte NAL in {}", decoded.len(), key));
+            }
+            let hdr = NalHeader::new([decoded[0], decoded[1]])?;
+            if hdr.unit_type() != unit_type {
+                return Err(format!("unexpected {:?} in {}", hdr, key));
+            }
+
+            // Strip a trailing Annex B start code, as with H.264 parameters
+            // from GW Security cameras.
+            let len = decoded
+                .strip_suffix(b"\x00\x00\x00\x01")
+                .unwrap_or(&decoded)
+                .len();
+            *nal = Some(Bytes::from(decoded).split_to(len));
+        }
+        match (vps_nal, sps_nal, pps_nal) {
+            (None, None, None) => Ok(None),
+            (Some(vps_nal), Some(sps_nal), Some(pps_nal)) => {
+                Self::parse_vps_sps_pps(&vps_nal, &sps_nal, &pps_nal).map(Some)
+            }
+            _ => Err("expected all or none of sprop-vps, sprop-sps, and sprop-pps".into()),
+        }
+    }
+
+    fn parse_vps_sps_pps(
+        vps_nal: &[u8],
+        sps_nal: &[u8],
+        pps_nal: &[u8],
+    ) -> Result<InternalParameters, String> {
+        let sps_rbsp = h264_reader::rbsp::decode_nal(&sps_nal[2..]);
+        let sps = Sps::parse(&sps_rbsp)?;
+        debug!("sps: {:#?}", &sps);
+
+        // Create the HEVCDecoderConfigurationRecord, ISO/IEC 14496-15 section 8.3.3.1.
+        let mut hvcc = BytesMut::with_capacity(38 + vps_nal.len() + sps_nal.len() + pps_nal.len());
+        hvcc.put_u8(1); // configurationVersion
+
+        // general_profile_space through general_level_idc, as in the SPS.
+        hvcc.extend_from_slice(&sps.general_profile_tier_level[..]);
+
+        hvcc.put_u16(0xf000); // reserved + min_spatial_segmentation_idc (unknown)
+        hvcc.put_u8(0xfc); // reserved + parallelismType (unknown)
+        hvcc.put_u8(0xfc | sps.chroma_format_idc);
+        hvcc.put_u8(0xf8 | sps.bit_depth_luma_minus8);
+        hvcc.put_u8(0xf8 | sps.bit_depth_chroma_minus8);
+        hvcc.put_u16(0); // avgFrameRate (unknown)
+
+        // constantFrameRate (unknown), numTemporalLayers, temporalIdNested,
+        // and lengthSizeMinusOne, hardcoded to 3 to match the 4-byte lengths
+        // in the frame data.
+        hvcc.put_u8(
+            ((sps.max_sub_layers_minus1 + 1) << 3) | (u8::from(sps.temporal_id_nested) << 2) | 3,
+        );
+
+        // Only support one each of VPS, SPS, and PPS, as with H.264.
+        hvcc.put_u8(3); // numOfArrays
+        let mut ranges = [0..0, 0..0, 0..0];
+        for (range, &(unit_type, nal)) in
+            ranges
+                .iter_mut()
+                .zip(&[(VPS, vps_nal), (SPS, sps_nal), (PPS, pps_nal)])
+        {
+            hvcc.put_u8(0b1000_0000 | unit_type); // array_completeness + NAL_unit_type
+            hvcc.put_u16(1); // numNalus
+            hvcc.put_u16(u16::try_from(nal.len()).map_err(|_| {
+                format!(
+                    "{:?} NAL is {} bytes long; must fit in u16",
+                    UnitTypeName(unit_type),
+                    nal.len()
+                )
+            })?);
+            let start = hvcc.len();
+            hvcc.extend_from_slice(nal);
+            *range = start..hvcc.len();
+        }
+        let hvcc = hvcc.freeze();
+        let [vps_range, sps_range, pps_range] = ranges;
+        Ok(InternalParameters {
+            generic_parameters: super::VideoParameters {
+                rfc6381_codec: sps.rfc6381_codec(),
+                pixel_dimensions: sps.pixel_dimensions,
+                pixel_aspect_ratio: sps.pixel_aspect_ratio,
+                frame_rate: sps.frame_rate,
+                sample_entry: super::make_video_sample_entry(
+                    b"hvc1",
+                    (b"hvcC", &hvcc[..]),
+                    sps.pixel_dimensions,
+                    sps.pixel_aspect_ratio,
+                    None,
+                )?,
+                details: Default::default(),
+                extra_data: hvcc.clone(),
+            },
+            vps_nal: hvcc.slice(vps_range),
+            sps_nal: hvcc.slice(sps_range),
+            pps_nal: hvcc.slice(pps_range),
+        })
+    }
+}
+
+/// The fields of a sequence parameter set (H.265 section 7.3.2.2) needed for
+/// [super::VideoParameters].
+#[derive(Debug)]
+struct Sps {
+    /// The `general_profile_space` through `general_level_idc` fields of the
+    /// `profile_tier_level` (H.265 section 7.3.3), as raw bytes.
+    general_profile_tier_level: [u8; 12],
+
+    max_sub_layers_minus1: u8,
+    temporal_id_nested: bool,
+    chroma_format_idc: u8,
+    bit_depth_luma_minus8: u8,
+    bit_depth_chroma_minus8: u8,
+
+    /// The dimensions after applying the conformance cropping window.
+    pixel_dimensions: (u32, u32),
+
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    frame_rate: Option<(u32, u32)>,
+}
+
+/// The sample aspect ratios from H.265 Table E.1, indexed by `aspect_ratio_idc`.
+#[rustfmt::skip]
+const ASPECT_RATIOS: [Option<(u32, u32)>; 17] = [
+    None, Some((1, 1)), Some((12, 11)), Some((10, 11)), Some((16, 11)), Some((40, 33)),
+    Some((24, 11)), Some((20, 11)), Some((32, 11)), Some((80, 33)), Some((18, 11)),
+    Some((15, 11)), Some((64, 33)), Some((160, 99)), Some((4, 3)), Some((3, 2)), Some((2, 1)),
+];
+
+impl Sps {
+    /// Parses from the RBSP (without the NAL header or emulation prevention bytes).
+    fn parse(rbsp: &[u8]) -> Result<Self, String> {
+        if rbsp.len() < 13 {
+            return Err(format!("{}-byte SPS is too short", rbsp.len()));
+        }
+        let mut r = RbspReader(bitreader::BitReader::new(rbsp));
+        r.skip(4, "sps_video_parameter_set_id")?;
+        let max_sub_layers_minus1 = r.bits(3, "sps_max_sub_layers_minus1")? as u8;
+        let temporal_id_nested = r.flag("sps_temporal_id_nesting_flag")?;
+
+        // profile_tier_level(1, sps_max_sub_layers_minus1)
+        let mut general_profile_tier_level = [0u8; 12];
+        general_profile_tier_level.copy_from_slice(&rbsp[1..13]);
+        r.skip(96, "general profile_tier_level")?;
+        let mut sub_layer_present = [(false, false); 7];
+        for p in &mut sub_layer_present[..usize::from(max_sub_layers_minus1)] {
+            *p = (
+                r.flag("sub_layer_profile_present_flag")?,
+                r.flag("sub_layer_level_present_flag")?,
+            );
+        }
+        if max_sub_layers_minus1 > 0 {
+            r.skip(
+                2 * (8 - u64::from(max_sub_layers_minus1)),
+                "reserved_zero_2bits",
+            )?;
+        }
+        for &(profile_present, level_present) in
+            &sub_layer_present[..usize::from(max_sub_layers_minus1)]
+        {
+            if profile_present {
+                r.skip(88, "sub_layer profile")?;
+            }
+            if level_present {
+                r.skip(8, "sub_layer_level_idc")?;
+            }
+        }
+
+        r.ue("sps_seq_parameter_set_id")?;
+        let chroma_format_idc = r.ue("chroma_format_idc")?;
+        if chroma_format_idc > 3 {
+            return Err(format!("bad chroma_format_idc {}", chroma_format_idc));
+        }
+        if chroma_format_idc == 3 {
+            r.flag("separate_colour_plane_flag")?;
+        }
+        let mut width = r.ue("pic_width_in_luma_samples")?;
+        let mut height = r.ue("pic_height_in_luma_samples")?;
+        if r.flag("conformance_window_flag")? {
+            // The offsets are in units of chroma samples, per H.265 Table 6-1.
+            let (sub_width_c, sub_height_c) = match chroma_format_idc {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            };
+            let left = r.ue("conf_win_left_offset")?;
+            let right = r.ue("conf_win_right_offset")?;
+            let top = r.ue("conf_win_top_offset")?;
+            let bottom = r.ue("conf_win_bottom_offset")?;
+            width = u64::from(left)
+                .checked_add(u64::from(right))
+                .map(|o| o * sub_width_c)
+                .and_then(|o| u64::from(width).checked_sub(o))
+                .and_then(|w| u32::try_from(w).ok())
+                .ok_or_else(|| "conformance window is wider than the picture".to_string())?;
+            height = u64::from(top)
+                .checked_add(u64::from(bottom))
+                .map(|o| o * sub_height_c)
+                .and_then(|o| u64::from(height).checked_sub(o))
+                .and_then(|h| u32::try_from(h).ok())
+                .ok_or_else(|| "conformance window is taller than the picture".to_string())?;
+        }
+        let bit_depth_luma_minus8 = r.ue("bit_depth_luma_minus8")?;
+        let bit_depth_chroma_minus8 = r.ue("bit_depth_chroma_minus8")?;
+        if bit_depth_luma_minus8 > 7 || bit_depth_chroma_minus8 > 7 {
+            return Err(format!(
+                "unsupported bit depths {}/{}",
+                bit_depth_luma_minus8 + 8,
+                bit_depth_chroma_minus8 + 8
+            ));
+        }
+        let log2_max_pic_order_cnt_lsb_minus4 = r.ue("log2_max_pic_order_cnt_lsb_minus4")?;
+        if log2_max_pic_order_cnt_lsb_minus4 > 12 {
+            return Err(format!(
+                "bad log2_max_pic_order_cnt_lsb_minus4 {}",
+                log2_max_pic_order_cnt_lsb_minus4
+            ));
+        }
+        let first_sub_layer = if r.flag("sps_sub_layer_ordering_info_present_flag")? {
+            0
+        } else {
+            max_sub_layers_minus1
+        };
+        for _ in first_sub_layer..=max_sub_layers_minus1 {
+            r.ue("sps_max_dec_pic_buffering_minus1")?;
+            r.ue("sps_max_num_reorder_pics")?;
+            r.ue("sps_max_latency_increase_plus1")?;
+        }
+        r.ue("log2_min_luma_coding_block_size_minus3")?;
+        r.ue("log2_diff_max_min_luma_coding_block_size")?;
+        r.ue("log2_min_luma_transform_block_size_minus2")?;
+        r.ue("log2_diff_max_min_luma_transform_block_size")?;
+        r.ue("max_transform_hierarchy_depth_inter")?;
+        r.ue("max_transform_hierarchy_depth_intra")?;
+        if r.flag("scaling_list_enabled_flag")? && r.flag("sps_scaling_list_data_present_flag")? {
+            skip_scaling_list_data(&mut r)?;
+        }
+        r.flag("amp_enabled_flag")?;
+        r.flag("sample_adaptive_offset_enabled_flag")?;
+        if r.flag("pcm_enabled_flag")? {
+            r.skip(8, "pcm_sample_bit_depth")?;
+            r.ue("log2_min_pcm_luma_coding_block_size_minus3")?;
+            r.ue("log2_diff_max_min_pcm_luma_coding_block_size")?;
+            r.flag("pcm_loop_filter_disabled_flag")?;
+        }
+        let num_short_term_ref_pic_sets = r.ue("num_short_term_ref_pic_sets")?;
+        if num_short_term_ref_pic_sets > 64 {
+            return Err(format!(
+                "bad num_short_term_ref_pic_sets {}",
+                num_short_term_ref_pic_sets
+            ));
+        }
+        let mut num_delta_pocs = Vec::with_capacity(num_short_term_ref_pic_sets as usize);
+        for i in 0..num_short_term_ref_pic_sets as usize {
+            let n = skip_st_ref_pic_set(&mut r, i, &num_delta_pocs)?;
+            num_delta_pocs.push(n);
+        }
+        if r.flag("long_term_ref_pics_present_flag")? {
+            let num_long_term_ref_pics_sps = r.ue("num_long_term_ref_pics_sps")?;
+            if num_long_term_ref_pics_sps > 32 {
+                return Err(format!(
+                    "bad num_long_term_ref_pics_sps {}",
+                    num_long_term_ref_pics_sps
+                ));
+            }
+            for _ in 0..num_long_term_ref_pics_sps {
+                r.skip(
+                    u64::from(log2_max_pic_order_cnt_lsb_minus4) + 4,
+                    "lt_ref_pic_poc_lsb_sps",
+                )?;
+                r.flag("used_by_curr_pic_lt_sps_flag")?;
+            }
+        }
+        r.flag("sps_temporal_mvp_enabled_flag")?;
+        r.flag("strong_intra_smoothing_enabled_flag")?;
+
+        // vui_parameters, H.265 section E.2.1. Only the fields before the HRD
+        // parameters are of interest.
+        let mut pixel_aspect_ratio = None;
+        let mut frame_rate = None;
+        if r.flag("vui_parameters_present_flag")? {
+            if r.flag("aspect_ratio_info_present_flag")? {
+                pixel_aspect_ratio = match r.bits(8, "aspect_ratio_idc")? {
+                    255 => Some((r.bits(16, "sar_width")?, r.bits(16, "sar_height")?)),
+                    i => ASPECT_RATIOS.get(i as usize).copied().flatten(),
+                };
+            }
+            if r.flag("overscan_info_present_flag")? {
+                r.flag("overscan_appropriate_flag")?;
+            }
+            if r.flag("video_signal_type_present_flag")? {
+                r.skip(4, "video_format, video_full_range_flag")?;
+                if r.flag("colour_description_present_flag")? {
+                    r.skip(24, "colour_description")?;
+                }
+            }
+            if r.flag("chroma_loc_info_present_flag")? {
+                r.ue("chroma_sample_loc_type_top_field")?;
+                r.ue("chroma_sample_loc_type_bottom_field")?;
+            }
+            r.skip(
+                3,
+                "neutral_chroma_indication_flag, field_seq_flag, frame_field_info_present_flag",
+            )?;
+            if r.flag("default_display_window_flag")? {
+                for _ in 0..4 {
+                    r.ue("def_disp_win_offset")?;
+                }
+            }
+            if r.flag("vui_timing_info_present_flag")? {
+                let num_units_in_tick = r.bits(32, "vui_num_units_in_tick")?;
+                let time_scale = r.bits(32, "vui_time_scale")?;
+                if num_units_in_tick != 0 && time_scale != 0 {
+                    frame_rate = Some((num_units_in_tick, time_scale));
+                }
+            }
+        }
+        Ok(Sps {
+            general_profile_tier_level,
+            max_sub_layers_minus1,
+            temporal_id_nested,
+            chroma_format_idc: chroma_format_idc as u8,
+            bit_depth_luma_minus8: bit_depth_luma_minus8 as u8,
+            bit_depth_chroma_minus8: bit_depth_chroma_minus8 as u8,
+            pixel_dimensions: (width, height),
+            pixel_aspect_ratio,
+            frame_rate,
+        })
+    }
+
+    /// Returns a codec string as in ISO/IEC 14496-15 section E.3, eg `hvc1.1.6.L93.B0`.
+    fn rfc6381_codec(&self) -> String {
+        let ptl = &self.general_profile_tier_level;
+        let profile_space = ["", "A", "B", "C"][usize::from(ptl[0] >> 6)];
+        let tier = if (ptl[0] & 0b0010_0000) != 0 {
+            'H'
+        } else {
+            'L'
+        };
+        let profile_idc = ptl[0] & 0b1_1111;
+
+        // The compatibility flags are written in reverse bit order.
+        let compatibility = u32::from_be_bytes([ptl[1], ptl[2], ptl[3], ptl[4]]).reverse_bits();
+        let mut codec = format!(
+            "hvc1.{}{}.{:X}.{}{}",
+            profile_space, profile_idc, compatibility, tier, ptl[11]
+        );
+
+        // Trailing zero bytes of the constraint flags are omitted.
+        let constraints = &ptl[5..11];
+        let len = constraints
+            .iter()
+            .rposition(|&b| b != 0)
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        for b in &constraints[..len] {
+            let _ = write!(&mut codec, ".{:X}", b);
+        }
+        codec
+    }
+}
+
+/// Skips a `scaling_list_data`, as in H.265 section 7.3.4.
+fn skip_scaling_list_data(r: &mut RbspReader) -> Result<(), String> {
+    for size_id in 0..4 {
+        let mut matrix_id = 0;
+        while matrix_id < 6 {
+            if !r.flag("scaling_list_pred_mode_flag")? {
+                r.ue("scaling_list_pred_matrix_id_delta")?;
+            } else {
+                let coef_num = std::cmp::min(64, 1 << (4 + (size_id << 1)));
+                if size_id > 1 {
+                    r.se("scaling_list_dc_coef_minus8")?;
+                }
+                for _ in 0..coef_num {
+                    r.se("scaling_list_delta_coef")?;
+                }
+            }
+            matrix_id += if size_id == 3 { 3 } else { 1 };
+        }
+    }
+    Ok(())
+}
+
+/// Skips a `st_ref_pic_set(idx)` within a SPS, as in H.265 section 7.3.7,
+/// returning its `NumDeltaPocs`.
+fn skip_st_ref_pic_set(
+    r: &mut RbspReader,
+    idx: usize,
+    num_delta_pocs: &[u32],
+) -> Result<u32, String> {
+    if idx != 0 && r.flag("inter_ref_pic_set_prediction_flag")? {
+        // Within the SPS, delta_idx_minus1 is absent, so this is predicted
+        // from the previous set.
+        r.flag("delta_rps_sign")?;
+        r.ue("abs_delta_rps_minus1")?;
+        let mut n = 0;
+        for _ in 0..=num_delta_pocs[idx - 1] {
+            if r.flag("used_by_curr_pic_flag")? || r.flag("use_delta_flag")? {
+                n += 1;
+            }
+        }
+        return Ok(n);
+    }
+    let num_negative_pics = r.ue("num_negative_pics")?;
+    let num_positive_pics = r.ue("num_positive_pics")?;
+    if num_negative_pics > 16 || num_positive_pics > 16 {
+        return Err(format!(
+            "bad st_ref_pic_set with {} negative and {} positive pictures",
+            num_negative_pics, num_positive_pics
+        ));
+    }
+    for _ in 0..num_negative_pics + num_positive_pics {
+        r.ue("delta_poc_minus1")?;
+        r.flag("used_by_curr_pic_flag")?;
+    }
+    Ok(num_negative_pics + num_positive_pics)
+}
+
+/// Returns true iff the bytes of `nal` equal the bytes of `[hdr, ..data]`.
+fn matches(nal: &[u8], hdr: NalHeader, pieces: &[Bytes]) -> bool {
+    if nal.len() < 2 || nal[..2] != hdr.0[..] {
+        return false;
+    }
+    let mut nal_pos = 2;
+    for piece in pieces {
+        let new_pos = nal_pos + piece.len();
+        if nal.len() < new_pos {
+            return false;
+        }
+        if piece[..] != nal[nal_pos..new_pos] {
+            return false;
+        }
+        nal_pos = new_pos;
+    }
+    nal_pos == nal.len()
+}
+
+/// Saves the given NAL to a contiguous Bytes.
+fn to_bytes(hdr: NalHeader, len: u32, pieces: &[Bytes]) -> Bytes {
+    let len = usize::try_from(len).expect("u32 fits in usize");
+    let mut out = Vec::with_capacity(len);
+    out.extend_from_slice(&hdr.0[..]);
+    for piece in pieces {
+        out.extend_from_slice(&piece[..]);
+    }
+    debug_assert_eq!(len, out.len());
+    out.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::testutil::{packet, video_frame};
+
+    /// The `fmtp` from `src/client/testdata/dahua_describe_h265_pcma.txt`.
+    const DAHUA_FMTP: &str = "profile-id=1;\
+        sprop-sps=QgEBAWAAAAMAsAAAAwAAAwBaoAWCAeFja5JFL83BQYFBAAADAAEAAAMADKE=;\
+        sprop-pps=RAHA8saNA7NA;\
+        sprop-vps=QAEMAf//AWAAAAMAsAAAAwAAAwBarAwAAAMABAAAAwAyqA==";
+
+    #[test]
+    fn parse_dahua_params() {
+        let d = super::Depacketizer::new(90_000, Some(DAHUA_FMTP)).unwrap();
+        let p = match d.parameters() {
+            Some(crate::codec::Parameters::Video(p)) => p,
+            o => panic!("unexpected parameters {:#?}", o),
+        };
+        assert_eq!(p.rfc6381_codec(), "hvc1.1.6.L90.B0");
+        assert_eq!(p.pixel_dimensions(), (704, 480));
+        assert_eq!(p.pixel_aspect_ratio(), None);
+        assert_eq!(p.frame_rate(), Some((1, 12)));
+        let hvcc = p.extra_data();
+        assert_eq!(
+            &hvcc[..23],
+            b"\x01\x01\x60\x00\x00\x00\xb0\x00\x00\x00\x00\x00\x5a\xf0\x00\xfc\xfd\xf8\xf8\x00\x00\x0f\x03"
+        );
+        assert_eq!(hvcc.len(), 23 + 3 * 5 + 34 + 44 + 9);
+    }
+
+    #[test]
+    fn bad_params() {
+        // All three parameter sets must be present if any are.
+        super::Depacketizer::new(90_000, Some("sprop-pps=RAHA8saNA7NA")).unwrap_err();
+
+        // Decoding order numbers are unsupported.
+        super::Depacketizer::new(90_000, Some("sprop-max-don-diff=2")).unwrap_err();
+
+        // Out-of-band parameters are optional.
+        let d = super::Depacketizer::new(90_000, Some("profile-id=1")).unwrap();
+        assert!(d.parameters().is_none());
+    }
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(90_000, Some(DAHUA_FMTP)).unwrap();
+
+        // Aggregation packet: access unit delimiter and prefix SEI.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x60\x01\x00\x03\x46\x01\x50\x00\x07\x4e\x01sei 1",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+
+        // Plain prefix SEI.
+        d.push(packet(1, 0, 0, false, b"\x4e\x01sei 2")).unwrap();
+        assert!(d.pull().is_none());
+
+        // Fragmented IDR_W_RADL.
+        d.push(packet(2, 0, 0, false, b"\x62\x01\x93fu start, "))
+            .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(3, 0, 0, false, b"\x62\x01\x13fu middle, "))
+            .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(4, 0, 0, true, b"\x62\x01\x53fu end"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x03\x46\x01\x50\
+              \x00\x00\x00\x07\x4e\x01sei 1\
+              \x00\x00\x00\x07\x4e\x01sei 2\
+              \x00\x00\x00\x1d\x26\x01fu start, fu middle, fu end"
+        );
+        assert!(frame.is_random_access_point);
+        assert!(!frame.is_disposable);
+        assert!(frame.new_parameters.is_none());
+
+        // A TRAIL_N picture is disposable.
+        d.push(packet(5, 3750, 0, true, b"\x00\x01trail_n"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert_eq!(&frame.data()[..], b"\x00\x00\x00\x09\x00\x01trail_n");
+        assert!(!frame.is_random_access_point);
+        assert!(frame.is_disposable);
+
+        // A FU of an aggregation packet is invalid.
+        d.push(packet(6, 7500, 0, true, b"\x62\x01\xb0bad"))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn depacketize_in_band_params() {
+        let mut d = super::Depacketizer::new(90_000, None).unwrap();
+        assert!(d.parameters().is_none());
+
+        // Aggregation packet with the VPS, SPS, and PPS, then an IDR_N_LP.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x60\x01\
+              \x00\x22\x40\x01\x0c\x01\xff\xff\x01\x60\x00\x00\x03\x00\xb0\x00\x00\x03\x00\x00\
+              \x03\x00\x5a\xac\x0c\x00\x00\x03\x00\x04\x00\x00\x03\x00\x32\xa8\
+              \x00\x2c\x42\x01\x01\x01\x60\x00\x00\x03\x00\xb0\x00\x00\x03\x00\x00\x03\x00\x5a\
+              \xa0\x05\x82\x01\xe1\x63\x6b\x92\x45\x2f\xcd\xc1\x41\x81\x41\x00\x00\x03\x00\x01\
+              \x00\x00\x03\x00\x0c\xa1\
+              \x00\x09\x44\x01\xc0\xf2\xc6\x8d\x03\xb3\x40",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(1, 0, 0, true, b"\x28\x01idr")).unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.is_random_access_point);
+        let p = frame.new_parameters.unwrap();
+        assert_eq!(p.rfc6381_codec(), "hvc1.1.6.L90.B0");
+        assert_eq!(p.pixel_dimensions(), (704, 480));
+        assert!(d.parameters().is_some());
+
+        // The same parameters again aren't reported as new.
+        d.push(packet(
+            2,
+            3750,
+            0,
+            false,
+            b"\x44\x01\xc0\xf2\xc6\x8d\x03\xb3\x40",
+        ))
+        .unwrap();
+        d.push(packet(3, 3750, 0, true, b"\x02\x01trail_r"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.new_parameters.is_none());
+        assert!(!frame.is_disposable);
+    }
+
+    #[test]
+    fn depacketize_annex_b() {
+        let mut d = super::Depacketizer::new(90_000, Some(DAHUA_FMTP)).unwrap();
+        d.set_nal_output(
+            crate::codec::NalOutput::default()
+                .annex_b(true)
+                .insert_parameters(true)
+                .insert_aud(true),
+        );
+
+        // IDR_W_RADL without in-band parameters.
+        d.push(packet(0, 0, 0, true, b"\x26\x01idr")).unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame
+            .data()
+            .starts_with(b"\x00\x00\x00\x01\x46\x01\x50\x00\x00\x00\x01\x40\x01"));
+        assert!(frame.data().ends_with(b"\x00\x00\x00\x01\x26\x01idr"));
+        let types: Vec<_> = frame.nal_units().map(|n| n.nal_unit_type()).collect();
+        assert_eq!(types, [35, 32, 33, 34, 19]);
+
+        // Non-IRAP TRAIL_R gets just the access unit delimiter.
+        d.push(packet(1, 1, 0, true, b"\x02\x01trail")).unwrap();
+        let frame = video_frame(d.pull());
+        assert_eq!(
+            &frame.data()[..],
+            b"\x00\x00\x00\x01\x46\x01\x50\x00\x00\x00\x01\x02\x01trail"
+        );
+    }
+}
@@ -0,0 +1,635 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Motion JPEG video, as specified in [RFC 2435](https://datatracker.ietf.org/doc/html/rfc2435).
+//!
+//! RTP packets carry only the entropy-coded scan data and a compact header.
+//! The depacketizer reconstructs the JPEG headers from that compact header,
+//! so each [super::VideoFrame] is a complete, self-contained image.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+
+use super::{CodecItem, VideoFrame};
+
+const FIXED_CLOCK_RATE: u32 = 90_000;
+
+/// The example luma quantization table from RFC 2435 appendix A, in zigzag order.
+#[rustfmt::skip]
+const LUMA_QUANTIZER: [u8; 64] = [
+    16, 11, 12, 14, 12, 10, 16, 14,
+    13, 14, 18, 17, 16, 19, 24, 40,
+    26, 24, 22, 22, 24, 49, 35, 37,
+    29, 40, 58, 51, 61, 60, 57, 51,
+    56, 55, 64, 72, 92, 78, 64, 68,
+    87, 69, 55, 56, 80, 109, 81, 87,
+    95, 98, 103, 104, 103, 62, 77, 113,
+    121, 112, 100, 120, 92, 101, 103, 99,
+];
+
+/// The example chroma quantization table from RFC 2435 appendix A, in zigzag order.
+#[rustfmt::skip]
+const CHROMA_QUANTIZER: [u8; 64] = [
+    17, 18, 18, 24, 21, 24, 47, 26,
+    26, 47, 99, 66, 56, 66, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// The typical Huffman tables from JPEG (ITU T.81) section K.3, which RFC 2435
+// section 3.1.3 says are in use for types 0-127.
+const LUM_DC_CODELENS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const LUM_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const LUM_AC_CODELENS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+#[rustfmt::skip]
+const LUM_AC_SYMBOLS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12,
+    0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08,
+    0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16,
+    0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39,
+    0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59,
+    0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79,
+    0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98,
+    0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+    0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4,
+    0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea,
+    0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const CHM_DC_CODELENS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const CHM_DC_SYMBOLS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const CHM_AC_CODELENS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+#[rustfmt::skip]
+const CHM_AC_SYMBOLS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21,
+    0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91,
+    0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34,
+    0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38,
+    0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58,
+    0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78,
+    0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96,
+    0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+    0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2,
+    0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9,
+    0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    state: State,
+
+    /// The parameters of the most recent frame, if any.
+    parameters: Option<super::VideoParameters>,
+
+    /// The most recent in-band quantization tables, as `(q, tables)`.
+    /// Senders may omit tables for `q` values 128-254 after sending them once.
+    in_band_tables: Option<(u8, Bytes)>,
+
+    high_water_size: usize,
+
+    /// The number of frames discarded because of packet loss.
+    discarded: u64,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Idle,
+
+    /// Ignoring the remainder of a frame because of packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+    },
+
+    InProgress(InProgress),
+    Ready(VideoFrame),
+}
+
+#[derive(Debug)]
+struct InProgress {
+    start_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
+    pixel_dimensions: (u32, u32),
+
+    /// The fragment offset expected of the next packet.
+    next_offset: u32,
+
+    /// The reconstructed headers followed by the scan data so far.
+    data: BytesMut,
+}
+
+/// The main JPEG header, as in RFC 2435 section 3.1.
+#[derive(Debug)]
+struct MainHeader {
+    fragment_offset: u32,
+    type_: u8,
+    q: u8,
+    width: u16,
+    height: u16,
+}
+
+impl MainHeader {
+    /// Parses the main header and the restart marker header (if any) from
+    /// the start of `data`, advancing past them.
+    fn parse(data: &mut Bytes) -> Result<(Self, u16), String> {
+        if data.len() < 8 {
+            return Err(format!("{}-byte JPEG packet too short", data.len()));
+        }
+        let type_specific = data.get_u8();
+        if type_specific != 0 {
+            return Err(format!(
+                "unimplemented interlaced JPEG (type-specific {})",
+                type_specific
+            ));
+        }
+        let fragment_offset = data.get_uint(3) as u32;
+        let type_ = data.get_u8();
+        let q = data.get_u8();
+        let width = u16::from(data.get_u8()) * 8;
+        let height = u16::from(data.get_u8()) * 8;
+        if width == 0 || height == 0 {
+            return Err(format!("bad JPEG dimensions {}x{}", width, height));
+        }
+        let restart_interval = match type_ {
+            0 | 1 => 0,
+            64 | 65 => {
+                // Restart marker header, RFC 2435 section 3.1.7. The F, L, and
+                // restart count fields are only useful for decoding partial
+                // frames, which this doesn't attempt.
+                if data.len() < 4 {
+                    return Err("JPEG packet too short for restart marker header".into());
+                }
+                let restart_interval = data.get_u16();
+                data.advance(2);
+                restart_interval
+            }
+            _ => return Err(format!("unimplemented JPEG type {}", type_)),
+        };
+        if q == 0 || (100..128).contains(&q) {
+            return Err(format!("reserved JPEG Q value {}", q));
+        }
+        Ok((
+            MainHeader {
+                fragment_offset,
+                type_,
+                q,
+                width,
+                height,
+            },
+            restart_interval,
+        ))
+    }
+}
+
+impl Depacketizer {
+    pub(super) fn new(clock_rate: u32) -> Result<Self, String> {
+        if clock_rate != FIXED_CLOCK_RATE {
+            return Err(format!(
+                "Expected clock rate of {} for JPEG, got {}",
+                FIXED_CLOCK_RATE, clock_rate
+            ));
+        }
+        Ok(Depacketizer {
+            state: State::Idle,
+            parameters: None,
+            in_band_tables: None,
+            high_water_size: 0,
+            discarded: 0,
+        })
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.parameters.clone().map(super::Parameters::Video)
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
+        let mut discarded = false;
+        if pkt.loss > 0 {
+            if let State::InProgress(in_progress) = &self.state {
+                debug!(
+                    "Discarding {}-byte JPEG frame prefix due to loss of {} RTP packets",
+                    in_progress.data.len(),
+                    pkt.loss
+                );
+                self.discarded += 1;
+                discarded = true;
+                self.state = State::Idle;
+            }
+        }
+        let mut data = pkt.payload;
+        let (hdr, restart_interval) = MainHeader::parse(&mut data)?;
+        let mut in_progress = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(..) => panic!("push while in state ready"),
+            State::InProgress(in_progress) => {
+                if in_progress.timestamp.timestamp != pkt.timestamp.timestamp {
+                    return Err(format!(
+                        "Timestamp changed from {} to {} with frame in progress",
+                        &in_progress.timestamp, &pkt.timestamp,
+                    ));
+                }
+                if hdr.fragment_offset != in_progress.next_offset {
+                    return Err(format!(
+                        "Expected JPEG fragment offset {}, got {}",
+                        in_progress.next_offset, hdr.fragment_offset
+                    ));
+                }
+                in_progress
+            }
+            State::Loss { timestamp } if timestamp.timestamp == pkt.timestamp.timestamp => {
+                if !pkt.mark {
+                    self.state = State::Loss { timestamp };
+                }
+                return Ok(());
+            }
+            State::Idle | State::Loss { .. } => {
+                if hdr.fragment_offset != 0 {
+                    if pkt.loss > 0 {
+                        // The start of this frame was lost.
+                        if !discarded {
+                            self.discarded += 1;
+                        }
+                        if !pkt.mark {
+                            self.state = State::Loss {
+                                timestamp: pkt.timestamp,
+                            };
+                        }
+                        return Ok(());
+                    }
+                    return Err(format!(
+                        "JPEG fragment offset {} with no frame in progress",
+                        hdr.fragment_offset
+                    ));
+                }
+                let mut out = BytesMut::with_capacity(self.high_water_size);
+                let tables = self.quantization_tables(&hdr, &mut data)?;
+                write_headers(&mut out, &hdr, restart_interval, &tables)?;
+                InProgress {
+                    start_ctx: pkt.ctx,
+                    timestamp: pkt.timestamp,
+                    loss: pkt.loss,
+                    extension: pkt.extension,
+                    pixel_dimensions: (u32::from(hdr.width), u32::from(hdr.height)),
+                    next_offset: 0,
+                    data: out,
+                }
+            }
+        };
+        in_progress.next_offset = in_progress
+            .next_offset
+            .checked_add(data.len() as u32)
+            .filter(|&o| o < 1 << 24)
+            .ok_or_else(|| "JPEG frame too large".to_string())?;
+        in_progress.data.extend_from_slice(&data[..]);
+        if !pkt.mark {
+            self.state = State::InProgress(in_progress);
+            return Ok(());
+        }
+
+        // RFC 2435 section 3.1 doesn't say whether the scan data includes an
+        // end of image marker. Add one if it's missing.
+        if !in_progress.data.ends_with(b"\xff\xd9") {
+            in_progress.data.extend_from_slice(b"\xff\xd9");
+        }
+        self.high_water_size = std::cmp::max(self.high_water_size, in_progress.data.len());
+        let new_parameters = match &self.parameters {
+            Some(p) if p.pixel_dimensions == in_progress.pixel_dimensions => None,
+            _ => {
+                // ISO/IEC 14496-14 has no JPEG-specific sample entry; use `mp4v` as ffmpeg does.
+                let esds = super::mp4v::make_esds(0x6c /* Visual ISO/IEC 10918-1 */, None)?;
+                let p = super::VideoParameters {
+                    pixel_dimensions: in_progress.pixel_dimensions,
+                    rfc6381_codec: "jpeg".to_owned(),
+                    pixel_aspect_ratio: None,
+                    frame_rate: None,
+                    extra_data: Bytes::new(),
+                    sample_entry: super::make_video_sample_entry(
+                        b"mp4v",
+                        (b"esds", &esds[..]),
+                        in_progress.pixel_dimensions,
+                        None,
+                        None,
+                    )?,
+                    details: Default::default(),
+                };
+                self.parameters = Some(p.clone());
+                Some(Box::new(p))
+            }
+        };
+        self.state = State::Ready(VideoFrame {
+            new_parameters,
+            loss: in_progress.loss,
+            start_ctx: in_progress.start_ctx,
+            end_ctx: pkt.ctx,
+            timestamp: in_progress.timestamp,
+            stream_id: pkt.stream_id,
+
+            // Each frame is independently decodable, and no other frame depends on it.
+            is_random_access_point: true,
+            is_disposable: true,
+            capture_time: in_progress.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: in_progress.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: None,
+            data: in_progress.data.freeze(),
+        });
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<CodecItem> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(frame) => Some(CodecItem::VideoFrame(frame)),
+            s => {
+                self.state = s;
+                None
+            }
+        }
+    }
+
+    /// Returns the quantization tables for the first packet of a frame,
+    /// advancing `data` past the quantization table header (if any).
+    ///
+    /// Each table is returned as `(precision, table)`, where a precision of 0
+    /// means 8-bit values and 1 means 16-bit values.
+    fn quantization_tables(
+        &mut self,
+        hdr: &MainHeader,
+        data: &mut Bytes,
+    ) -> Result<Vec<(u8, Bytes)>, String> {
+        if hdr.q < 128 {
+            return Ok(make_tables(hdr.q));
+        }
+
+        // Quantization table header, RFC 2435 section 3.1.8.
+        if data.len() < 4 {
+            return Err("JPEG packet too short for quantization table header".into());
+        }
+        data.advance(1); // MBZ
+        let precision = data.get_u8();
+        let len = usize::from(data.get_u16());
+        let tables = if len == 0 {
+            match &self.in_band_tables {
+                Some((q, tables)) if *q == hdr.q && hdr.q != 255 => tables.clone(),
+                _ => return Err(format!("missing JPEG quantization tables for Q {}", hdr.q)),
+            }
+        } else {
+            if data.len() < len {
+                return Err(format!(
+                    "JPEG packet has {} bytes; too short for {}-byte quantization tables",
+                    data.len(),
+                    len
+                ));
+            }
+            let tables = data.split_to(len);
+            self.in_band_tables = Some((hdr.q, tables.clone()));
+            tables
+        };
+
+        // Split into tables of 64 8-bit or 16-bit values according to the
+        // precision bits, where bit i is for table i.
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < tables.len() {
+            if out.len() == 4 {
+                return Err("more than 4 JPEG quantization tables".into());
+            }
+            let table_precision = (precision >> out.len()) & 1;
+            let table_len = 64 << table_precision;
+            if tables.len() - pos < table_len {
+                return Err(format!(
+                    "{}-byte JPEG quantization tables don't match precision {:#x}",
+                    tables.len(),
+                    precision
+                ));
+            }
+            out.push((table_precision, tables.slice(pos..pos + table_len)));
+            pos += table_len;
+        }
+        Ok(out)
+    }
+}
+
+/// Makes the luma and chroma quantization tables for the given `q` in 1-99,
+/// as in RFC 2435 appendix A.
+fn make_tables(q: u8) -> Vec<(u8, Bytes)> {
+    let factor = u32::from(q);
+    let scale = if factor < 50 {
+        5000 / factor
+    } else {
+        200 - factor * 2
+    };
+    let scale_table = |base: &[u8; 64]| {
+        let table: Vec<u8> = base
+            .iter()
+            .map(|&v| ((u32::from(v) * scale + 50) / 100).clamp(1, 255) as u8)
+            .collect();
+        (0, Bytes::from(table))
+    };
+    vec![scale_table(&LUMA_QUANTIZER), scale_table(&CHROMA_QUANTIZER)]
+}
+
+/// Writes the JPEG headers from start of image through start of scan, as in
+/// RFC 2435 appendix B.
+fn write_headers(
+    out: &mut BytesMut,
+    hdr: &MainHeader,
+    restart_interval: u16,
+    tables: &[(u8, Bytes)],
+) -> Result<(), String> {
+    if tables.is_empty() {
+        return Err("no JPEG quantization tables".into());
+    }
+    out.put_u16(0xffd8); // SOI
+
+    // DQT
+    for (i, (precision, table)) in tables.iter().enumerate() {
+        out.put_u16(0xffdb);
+        out.put_u16(3 + table.len() as u16);
+        out.put_u8((precision << 4) | i as u8);
+        out.extend_from_slice(&table[..]);
+    }
+
+    if restart_interval != 0 {
+        out.put_u16(0xffdd); // DRI
+        out.put_u16(4);
+        out.put_u16(restart_interval);
+    }
+
+    // SOF0: baseline DCT with three components. Type 0 is 4:2:2 (2x1 luma
+    // sampling); type 1 is 4:2:0 (2x2).
+    let chroma_table = std::cmp::min(tables.len() - 1, 1) as u8;
+    out.put_u16(0xffc0);
+    out.put_u16(17);
+    out.put_u8(8); // sample precision
+    out.put_u16(hdr.height);
+    out.put_u16(hdr.width);
+    out.put_u8(3); // components
+    out.put_slice(&[1, if (hdr.type_ & 1) == 0 { 0x21 } else { 0x22 }, 0]);
+    out.put_slice(&[2, 0x11, chroma_table]);
+    out.put_slice(&[3, 0x11, chroma_table]);
+
+    // DHT
+    write_huffman_table(out, 0x00, &LUM_DC_CODELENS, &LUM_DC_SYMBOLS);
+    write_huffman_table(out, 0x10, &LUM_AC_CODELENS, &LUM_AC_SYMBOLS);
+    write_huffman_table(out, 0x01, &CHM_DC_CODELENS, &CHM_DC_SYMBOLS);
+    write_huffman_table(out, 0x11, &CHM_AC_CODELENS, &CHM_AC_SYMBOLS);
+
+    // SOS
+    out.put_u16(0xffda);
+    out.put_u16(12);
+    out.put_u8(3); // components
+    out.put_slice(&[1, 0x00]);
+    out.put_slice(&[2, 0x11]);
+    out.put_slice(&[3, 0x11]);
+    out.put_slice(&[0, 63, 0]); // Ss, Se, Ah/Al
+    Ok(())
+}
+
+/// Writes a DHT segment with a single table of the given class and id.
+fn write_huffman_table(out: &mut BytesMut, class_and_id: u8, codelens: &[u8; 16], symbols: &[u8]) {
+    out.put_u16(0xffc4);
+    out.put_u16(3 + 16 + symbols.len() as u16);
+    out.put_u8(class_and_id);
+    out.extend_from_slice(&codelens[..]);
+    out.extend_from_slice(symbols);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::codec::testutil::{packet, video_frame};
+
+    #[test]
+    fn huffman_tables() {
+        // The code lengths should count the symbols.
+        let sum = |l: &[u8; 16]| l.iter().map(|&l| usize::from(l)).sum::<usize>();
+        assert_eq!(sum(&super::LUM_DC_CODELENS), super::LUM_DC_SYMBOLS.len());
+        assert_eq!(sum(&super::LUM_AC_CODELENS), super::LUM_AC_SYMBOLS.len());
+        assert_eq!(sum(&super::CHM_DC_CODELENS), super::CHM_DC_SYMBOLS.len());
+        assert_eq!(sum(&super::CHM_AC_CODELENS), super::CHM_AC_SYMBOLS.len());
+    }
+
+    #[test]
+    fn make_tables() {
+        // Q 50 uses the example tables unscaled.
+        let t = super::make_tables(50);
+        assert_eq!(&t[0].1[..], &super::LUMA_QUANTIZER[..]);
+        assert_eq!(&t[1].1[..], &super::CHROMA_QUANTIZER[..]);
+
+        // Q 99 is the finest.
+        let t = super::make_tables(99);
+        assert_eq!(&t[0].1[..4], &[1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(90_000).unwrap();
+        assert!(d.parameters().is_none());
+
+        // A 4:2:0 frame in two fragments, with Q 50.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x00\x00\x00\x00\x01\x32\x50\x3cscan ",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(
+            1,
+            0,
+            0,
+            true,
+            b"\x00\x00\x00\x05\x01\x32\x50\x3cdata",
+        ))
+        .unwrap();
+        let frame = video_frame(d.pull());
+        let data = frame.data();
+        assert!(data.starts_with(b"\xff\xd8\xff\xdb\x00\x43\x00\x10\x0b\x0c"));
+        assert!(data.ends_with(b"\x00\x3f\x00scan data\xff\xd9"));
+        let sof = data.windows(2).position(|w| w == b"\xff\xc0").unwrap();
+        assert_eq!(
+            &data[sof..sof + 19],
+            b"\xff\xc0\x00\x11\x08\x01\xe0\x02\x80\x03\x01\x22\x00\x02\x11\x01\x03\x11\x01"
+        );
+        assert!(frame.is_random_access_point);
+        assert_eq!(frame.new_parameters.unwrap().pixel_dimensions(), (640, 480));
+        assert!(d.parameters().is_some());
+
+        // A frame with a lost first packet is discarded.
+        d.push(packet(
+            3,
+            1,
+            1,
+            true,
+            b"\x00\x00\x00\x05\x01\x32\x50\x3cdata",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+
+        // A 4:2:2 frame with a restart interval and in-band quantization tables.
+        let mut payload =
+            b"\x00\x00\x00\x00\x40\xff\x50\x3c\x00\x10\xff\xff\x00\x00\x00\x80".to_vec();
+        payload.extend_from_slice(&[1; 128]);
+        payload.extend_from_slice(b"scan data\xff\xd9");
+        let mut pkt = packet(4, 2, 0, true, b"");
+        pkt.payload = Bytes::from(payload);
+        d.push(pkt).unwrap();
+        let frame = video_frame(d.pull());
+        let data = frame.data();
+        assert!(data.starts_with(b"\xff\xd8\xff\xdb\x00\x43\x00\x01\x01"));
+        assert!(data.windows(6).any(|w| w == b"\xff\xdd\x00\x04\x00\x10"));
+        assert!(data.ends_with(b"scan data\xff\xd9"));
+        assert!(!data.ends_with(b"\xff\xd9\xff\xd9"));
+        assert!(frame.new_parameters.is_none());
+
+        // Unsupported types are rejected.
+        d.push(packet(
+            6,
+            3,
+            0,
+            true,
+            b"\x00\x00\x00\x00\x02\x32\x50\x3cdata",
+        ))
+        .unwrap_err();
+    }
+}
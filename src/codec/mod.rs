@@ -7,29 +7,114 @@
 //! codec, as needed for a client during `PLAY` and a server during `RECORD`.
 //! Packetization (needed for the reverse) may be added in the future.
 
+use std::convert::TryFrom;
 use std::num::{NonZeroU16, NonZeroU32};
 
 use crate::client::rtp;
 use crate::ConnectionContext;
 use crate::Error;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use pretty_hex::PrettyHex;
 
+/// Overwrites a buffer with a varint length, returning the length of the length.
+/// See ISO/IEC 14496-1 section 8.3.3.
+pub(crate) fn set_length(len: usize, data: &mut [u8]) -> Result<usize, String> {
+    if len < 1 << 7 {
+        data[0] = len as u8;
+        Ok(1)
+    } else if len < 1 << 14 {
+        data[0] = ((len & 0x7F) | 0x80) as u8;
+        data[1] = (len >> 7) as u8;
+        Ok(2)
+    } else if len < 1 << 21 {
+        data[0] = ((len & 0x7F) | 0x80) as u8;
+        data[1] = (((len >> 7) & 0x7F) | 0x80) as u8;
+        data[2] = (len >> 14) as u8;
+        Ok(3)
+    } else if len < 1 << 28 {
+        data[0] = ((len & 0x7F) | 0x80) as u8;
+        data[1] = (((len >> 7) & 0x7F) | 0x80) as u8;
+        data[2] = (((len >> 14) & 0x7F) | 0x80) as u8;
+        data[3] = (len >> 21) as u8;
+        Ok(4)
+    } else {
+        // BaseDescriptor sets a maximum length of 2**28 - 1.
+        return Err(format!("length {} too long", len));
+    }
+}
+
+/// Writes a box length and type (four-character code) for everything appended
+/// in the supplied scope.
+macro_rules! write_box {
+    ($buf:expr, $fourcc:expr, $b:block) => {{
+        let _: &mut bytes::BytesMut = $buf; // type-check.
+        let pos_start = $buf.len();
+        let fourcc: &[u8; 4] = $fourcc;
+        $buf.extend_from_slice(&[0, 0, 0, 0, fourcc[0], fourcc[1], fourcc[2], fourcc[3]]);
+        let r = {
+            $b;
+        };
+        let pos_end = $buf.len();
+        let len = pos_end.checked_sub(pos_start).unwrap();
+        $buf[pos_start..pos_start + 4].copy_from_slice(
+            &<u32 as std::convert::TryFrom<usize>>::try_from(len)
+                .map_err(|_| format!("box length {} exceeds u32::MAX", len))?
+                .to_be_bytes()[..],
+        );
+        r
+    }};
+}
+
+/// Writes a descriptor tag and length for everything appended in the supplied
+/// scope. See ISO/IEC 14496-1 Table 1 for the `tag`.
+macro_rules! write_descriptor {
+    ($buf:expr, $tag:expr, $b:block) => {{
+        let _: &mut bytes::BytesMut = $buf; // type-check.
+        let _: u8 = $tag;
+        let pos_start = $buf.len();
+
+        // Overallocate room for the varint length and append the body.
+        $buf.extend_from_slice(&[$tag, 0, 0, 0, 0]);
+        let r = {
+            $b;
+        };
+        let pos_end = $buf.len();
+
+        // Then fix it afterward: write the correct varint length and move
+        // the body backward. This approach seems better than requiring the
+        // caller to first prepare the body in a separate allocation (and
+        // awkward code ordering), or (as ffmpeg does) writing a "varint"
+        // which is padded with leading 0x80 bytes.
+        let len = pos_end.checked_sub(pos_start + 5).unwrap();
+        let len_len = $crate::codec::set_length(len, &mut $buf[pos_start + 1..pos_start + 4])?;
+        $buf.copy_within(pos_start + 5..pos_end, pos_start + 1 + len_len);
+        $buf.truncate(pos_end + len_len - 4);
+        r
+    }};
+}
+
 pub(crate) mod aac;
+pub(crate) mod av1;
 pub(crate) mod g723;
 
 #[doc(hidden)]
 pub mod h264;
 
+pub(crate) mod h265;
+pub(crate) mod jpeg;
+pub(crate) mod mp4v;
 pub(crate) mod onvif;
 pub(crate) mod simple_audio;
+pub(crate) mod vp8;
+pub(crate) mod vp9;
 
 #[derive(Debug)]
 pub enum CodecItem {
     VideoFrame(VideoFrame),
     AudioFrame(AudioFrame),
     MessageFrame(MessageFrame),
-    SenderReport(crate::client::rtp::SenderReport),
+    Rtcp(crate::client::rtp::ReceivedCompoundPacket),
+    Discontinuity(crate::client::rtp::Discontinuity),
 }
 
 #[derive(Clone, Debug)]
@@ -46,6 +131,54 @@ pub struct VideoParameters {
     pixel_aspect_ratio: Option<(u32, u32)>,
     frame_rate: Option<(u32, u32)>,
     extra_data: Bytes,
+    sample_entry: Bytes,
+    details: SequenceDetails,
+}
+
+/// Details of a [`VideoParameters`] which are only known for some codecs.
+#[derive(Clone, Debug, Default)]
+struct SequenceDetails {
+    profile_idc: Option<u8>,
+    level_idc: Option<u8>,
+    chroma_format: Option<ChromaFormat>,
+    bit_depth: Option<(u8, u8)>,
+    frame_mbs_only: Option<bool>,
+    cropping: Option<Cropping>,
+    full_range: Option<bool>,
+    color_description: Option<ColorDescription>,
+    fixed_frame_rate: Option<bool>,
+    max_num_reorder_frames: Option<u32>,
+}
+
+/// The arrangement of chroma samples, as in ISO/IEC 14496-10 Table 6-1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChromaFormat {
+    Monochrome,
+    Yuv420,
+    Yuv422,
+    Yuv444,
+}
+
+/// The number of luma samples cropped from each edge of the decoded picture.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cropping {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// Colour description code points, as defined in ITU-T H.273 (aka ISO/IEC 23091-2).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorDescription {
+    /// `colour_primaries`, eg 1 for BT.709.
+    pub primaries: u8,
+
+    /// `transfer_characteristics`, eg 1 for BT.709.
+    pub transfer_characteristics: u8,
+
+    /// `matrix_coefficients`, eg 1 for BT.709.
+    pub matrix_coefficients: u8,
 }
 
 impl VideoParameters {
@@ -74,25 +207,181 @@ impl VideoParameters {
         self.pixel_aspect_ratio
     }
 
-    /// Returns the maximum frame rate in seconds as `(numerator, denominator)`,
+    /// Returns the frame duration in seconds as `(numerator, denominator)`,
     /// if known.
     ///
     /// May not be minimized, and may not be in terms of the clock rate. Eg 15
     /// frames per second might be returned as `(1, 15)` or `(6000, 90000)`. The
     /// standard NTSC framerate (roughly 29.97 fps) might be returned as
-    /// `(1001, 30000)`.
+    /// `(1001, 30000)`. This is in seconds rather than clock rate units because
+    /// it comes from the bitstream, which may not agree with the RTP clock.
     ///
-    /// TODO: maybe return in clock rate units instead?
-    /// TODO: expose fixed vs max distinction (see H.264 fixed_frame_rate_flag).
+    /// This is the minimum duration (the maximum frame rate) unless
+    /// [`VideoParameters::fixed_frame_rate`] is `Some(true)`.
     pub fn frame_rate(&self) -> Option<(u32, u32)> {
         self.frame_rate
     }
 
+    /// Returns if the frame rate is constant, if known.
+    ///
+    /// For H.264, this is the VUI's `fixed_frame_rate_flag`.
+    pub fn fixed_frame_rate(&self) -> Option<bool> {
+        self.details.fixed_frame_rate
+    }
+
+    /// Returns the codec's profile, if known.
+    ///
+    /// For H.264, this is the SPS's `profile_idc` (eg 66 for Baseline, 77 for Main, 100 for High).
+    pub fn profile_idc(&self) -> Option<u8> {
+        self.details.profile_idc
+    }
+
+    /// Returns the codec's level, if known.
+    ///
+    /// For H.264, this is the SPS's `level_idc`: ten times the level number (eg 31 for level 3.1).
+    pub fn level_idc(&self) -> Option<u8> {
+        self.details.level_idc
+    }
+
+    /// Returns the chroma format, if known.
+    pub fn chroma_format(&self) -> Option<ChromaFormat> {
+        self.details.chroma_format
+    }
+
+    /// Returns the bit depth of luma samples, if known.
+    pub fn bit_depth_luma(&self) -> Option<u8> {
+        self.details.bit_depth.map(|(luma, _)| luma)
+    }
+
+    /// Returns the bit depth of chroma samples, if known.
+    pub fn bit_depth_chroma(&self) -> Option<u8> {
+        self.details.bit_depth.map(|(_, chroma)| chroma)
+    }
+
+    /// Returns if every picture is coded as a frame, if known.
+    ///
+    /// For H.264, this is the SPS's `frame_mbs_only_flag`. When `false`, pictures may be coded
+    /// as fields, so the video may be interlaced.
+    pub fn frame_mbs_only(&self) -> Option<bool> {
+        self.details.frame_mbs_only
+    }
+
+    /// Returns the cropping applied to the decoded picture, if known.
+    ///
+    /// [`VideoParameters::pixel_dimensions`] already accounts for this.
+    pub fn cropping(&self) -> Option<Cropping> {
+        self.details.cropping
+    }
+
+    /// Returns if samples use the full range of values (eg 0–255 for 8-bit video) rather than
+    /// the "studio" range, if known.
+    pub fn full_range(&self) -> Option<bool> {
+        self.details.full_range
+    }
+
+    /// Returns the colour primaries, transfer characteristics, and matrix coefficients, if known.
+    pub fn color_description(&self) -> Option<ColorDescription> {
+        self.details.color_description
+    }
+
+    /// Returns the maximum number of frames which precede any frame in decoding order and
+    /// follow it in output order, if known.
+    ///
+    /// For H.264, this is the VUI's `max_num_reorder_frames`. When zero, frames can be
+    /// output as soon as they're decoded.
+    pub fn max_num_reorder_frames(&self) -> Option<u32> {
+        self.details.max_num_reorder_frames
+    }
+
     /// The codec-specific "extra data" to feed to eg ffmpeg to decode the video frames.
+    /// *   AV1: an AV1CodecConfigurationRecord.
     /// *   H.264: an AvcDecoderConfig.
+    /// *   H.265: an HEVCDecoderConfigurationRecord.
+    /// *   MPEG-4 Part 2: the contents of an `esds` box (a version and ES_Descriptor).
+    /// *   VP8 and VP9: the contents of a `vpcC` box (a version, flags, and
+    ///     VPCodecConfigurationRecord).
     pub fn extra_data(&self) -> &Bytes {
         &self.extra_data
     }
+
+    /// An `.mp4` `VisualSampleEntry` box (as defined in ISO/IEC 14496-12 section 12.1.3), for
+    /// use within a `stsd` box.
+    ///
+    /// This has the codec's configuration box (eg `avcC` within an `avc1` entry), followed by
+    /// a `pasp` box if the pixel aspect ratio is known and a `btrt` box if the bitrate is known.
+    /// Motion JPEG uses a `mp4v` entry with an `esds` box, as in ISO/IEC 14496-14.
+    pub fn sample_entry(&self) -> &Bytes {
+        &self.sample_entry
+    }
+}
+
+/// The contents of a `btrt` box, as in ISO/IEC 14496-12 section 8.5.2.2.
+#[derive(Copy, Clone, Debug)]
+struct BitRate {
+    buffer_size_db: u32,
+    max_bitrate: u32,
+    avg_bitrate: u32,
+}
+
+/// Returns a `VisualSampleEntry` box of the given type, as in ISO/IEC 14496-12 section 12.1.3.
+/// `config_box` is the codec configuration box's type and contents.
+fn make_video_sample_entry(
+    sample_entry_type: &[u8; 4],
+    config_box: (&[u8; 4], &[u8]),
+    pixel_dimensions: (u32, u32),
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    bit_rate: Option<BitRate>,
+) -> Result<Bytes, String> {
+    let width = u16::try_from(pixel_dimensions.0)
+        .map_err(|_| format!("width {} doesn't fit in sample entry", pixel_dimensions.0))?;
+    let height = u16::try_from(pixel_dimensions.1)
+        .map_err(|_| format!("height {} doesn't fit in sample entry", pixel_dimensions.1))?;
+    let mut buf = BytesMut::new();
+    write_box!(&mut buf, sample_entry_type, {
+        buf.extend_from_slice(&[
+            0, 0, 0, 0, // SampleEntry.reserved
+            0, 0, 0, 1, // SampleEntry.reserved, SampleEntry.data_reference_index (1)
+            0, 0, 0, 0, // VisualSampleEntry.pre_defined, VisualSampleEntry.reserved
+            0, 0, 0, 0, // VisualSampleEntry.pre_defined
+            0, 0, 0, 0, //
+            0, 0, 0, 0, //
+        ]);
+        buf.put_u16(width);
+        buf.put_u16(height);
+        buf.extend_from_slice(&[
+            0x00, 0x48, 0x00, 0x00, // horizresolution
+            0x00, 0x48, 0x00, 0x00, // vertresolution
+            0x00, 0x00, 0x00, 0x00, // reserved
+            0x00, 0x01, // frame count
+            0x00, 0x00, 0x00, 0x00, // compressorname
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x18, 0xff, 0xff, // depth + pre_defined
+        ]);
+        write_box!(&mut buf, config_box.0, {
+            buf.extend_from_slice(config_box.1);
+        });
+        if let Some((h_spacing, v_spacing)) = pixel_aspect_ratio {
+            // PixelAspectRatioBox, ISO/IEC 14496-12 section 12.1.4.
+            write_box!(&mut buf, b"pasp", {
+                buf.put_u32(h_spacing);
+                buf.put_u32(v_spacing);
+            });
+        }
+        if let Some(b) = bit_rate {
+            write_box!(&mut buf, b"btrt", {
+                buf.put_u32(b.buffer_size_db);
+                buf.put_u32(b.max_bitrate);
+                buf.put_u32(b.avg_bitrate);
+            });
+        }
+    });
+    Ok(buf.freeze())
 }
 
 impl std::fmt::Debug for VideoParameters {
@@ -102,6 +391,7 @@ impl std::fmt::Debug for VideoParameters {
             .field("pixel_dimensions", &self.pixel_dimensions)
             .field("pixel_aspect_ratio", &self.pixel_aspect_ratio)
             .field("frame_rate", &self.frame_rate)
+            .field("details", &self.details)
             .field("extra_data", &self.extra_data.hex_dump())
             .finish()
     }
@@ -167,6 +457,21 @@ pub struct AudioFrame {
     /// of data may be skipped.
     pub loss: u16,
 
+    /// The estimated wall-clock time at which this frame was captured.
+    ///
+    /// This is derived from the stream's RTCP sender reports when
+    /// depacketizing via [`crate::client::Demuxed`]. It falls back to the
+    /// local receive time before the first usable sender report, or when the
+    /// sender's clock is implausible.
+    pub capture_time: std::time::SystemTime,
+
+    /// This frame's timestamp corrected for the sender's clock drift, if known.
+    /// See [`crate::client::Timeline::corrected`].
+    pub corrected_timestamp: Option<crate::Timestamp>,
+
+    /// The RTP header extension of this frame's first packet, if any.
+    pub extension: Option<crate::client::rtp::HeaderExtension>,
+
     // TODO: expose bytes or Buf (for zero-copy)?
     pub data: Bytes,
 }
@@ -179,6 +484,9 @@ impl std::fmt::Debug for AudioFrame {
             .field("loss", &self.loss)
             .field("timestamp", &self.timestamp)
             .field("frame_length", &self.frame_length)
+            .field("capture_time", &self.capture_time)
+            .field("corrected_timestamp", &self.corrected_timestamp)
+            .field("extension", &self.extension)
             .field("data", &self.data.hex_dump())
             .finish()
     }
@@ -210,6 +518,21 @@ pub struct MessageFrame {
     /// If this is non-zero, a prefix of the message may be missing.
     pub loss: u16,
 
+    /// The estimated wall-clock time at which this message was captured.
+    ///
+    /// This is derived from the stream's RTCP sender reports when
+    /// depacketizing via [`crate::client::Demuxed`]. It falls back to the
+    /// local receive time before the first usable sender report, or when the
+    /// sender's clock is implausible.
+    pub capture_time: std::time::SystemTime,
+
+    /// This message's timestamp corrected for the sender's clock drift, if known.
+    /// See [`crate::client::Timeline::corrected`].
+    pub corrected_timestamp: Option<crate::Timestamp>,
+
+    /// The RTP header extension of this message's first packet, if any.
+    pub extension: Option<crate::client::rtp::HeaderExtension>,
+
     // TODO: expose bytes or Buf (for zero-copy)?
     pub data: Bytes,
 }
@@ -221,6 +544,9 @@ impl std::fmt::Debug for MessageFrame {
             .field("stream_id", &self.stream_id)
             .field("loss", &self.loss)
             .field("timestamp", &self.timestamp)
+            .field("capture_time", &self.capture_time)
+            .field("corrected_timestamp", &self.corrected_timestamp)
+            .field("extension", &self.extension)
             .field("data", &self.data.hex_dump())
             .finish()
     }
@@ -258,8 +584,37 @@ pub struct VideoFrame {
 
     /// If no other pictures require this one to be decoded correctly.
     /// In H.264 terms, this is a frame with `nal_ref_idc == 0`.
+    ///
+    /// Together with [`VideoFrame::picture_type`], this distinguishes reference B-frames (as in
+    /// hierarchical or "pyramid" GOP structures) from non-reference B-frames.
     pub is_disposable: bool,
 
+    /// The estimated wall-clock time at which this picture was captured.
+    ///
+    /// This is derived from the stream's RTCP sender reports when
+    /// depacketizing via [`crate::client::Demuxed`]. It falls back to the
+    /// local receive time before the first usable sender report, or when the
+    /// sender's clock is implausible.
+    pub capture_time: std::time::SystemTime,
+
+    /// This picture's timestamp corrected for the sender's clock drift, if known.
+    /// See [`crate::client::Timeline::corrected`].
+    pub corrected_timestamp: Option<crate::Timestamp>,
+
+    /// The RTP header extension of this picture's first packet, if any.
+    pub extension: Option<crate::client::rtp::HeaderExtension>,
+
+    sei: Vec<SeiMessage>,
+
+    /// `timestamp` minus the decode timestamp, in clock rate units.
+    composition_offset: u32,
+
+    pic_order_cnt: Option<i32>,
+    picture_type: Option<PictureType>,
+
+    /// How `data` is divided into NAL units, for H.264 and H.265.
+    nal_layout: Option<NalLayout>,
+
     data: bytes::Bytes,
 }
 
@@ -283,6 +638,218 @@ impl VideoFrame {
     pub fn into_data(self) -> Bytes {
         self.data
     }
+
+    /// Returns the Supplemental Enhancement Information messages within this picture.
+    ///
+    /// Currently these are only extracted from H.264 streams; other codecs return an empty slice.
+    #[inline]
+    pub fn sei(&self) -> &[SeiMessage] {
+        &self.sei
+    }
+
+    /// Returns the time at which this picture should be decoded, in the same time base as
+    /// `timestamp` (its presentation time).
+    ///
+    /// This differs from `timestamp` when pictures are sent out of presentation order, as with
+    /// H.264 B-frames. It's never after `timestamp` and never decreases within a stream, except
+    /// possibly at a change of parameters or after packet loss. For the first few pictures of a
+    /// stream, it's extrapolated backward from the first picture's timestamp.
+    pub fn decode_timestamp(&self) -> crate::Timestamp {
+        crate::Timestamp::new(
+            self.timestamp.timestamp() - i64::from(self.composition_offset),
+            self.timestamp.clock_rate(),
+            self.timestamp.start(),
+        )
+        .unwrap_or(self.timestamp)
+    }
+
+    /// Returns `timestamp` minus [`VideoFrame::decode_timestamp`], in clock rate units.
+    ///
+    /// This is the composition time offset as written in a `.mp4` `ctts` box.
+    #[inline]
+    pub fn composition_offset(&self) -> u32 {
+        self.composition_offset
+    }
+
+    /// Returns the picture order count, if known.
+    ///
+    /// This is currently computed only for H.264, from the slice headers and the active SPS and
+    /// PPS as in ISO/IEC 14496-10 section 8.2.1. It restarts at each IDR picture. For frames, it's
+    /// the lesser of `TopFieldOrderCnt` and `BottomFieldOrderCnt`.
+    #[inline]
+    pub fn pic_order_cnt(&self) -> Option<i32> {
+        self.pic_order_cnt
+    }
+
+    /// Returns the picture's type as determined by its slice types, if known.
+    ///
+    /// This is currently only computed for H.264.
+    #[inline]
+    pub fn picture_type(&self) -> Option<PictureType> {
+        self.picture_type
+    }
+
+    /// Returns an iterator through the NAL units of a H.264 or H.265 picture.
+    ///
+    /// This works with either framing chosen via [`NalOutput`]. Other codecs yield no NAL units.
+    pub fn nal_units(&self) -> NalUnits<'_> {
+        NalUnits {
+            layout: self.nal_layout,
+            remaining: &self.data[..],
+        }
+    }
+}
+
+/// The type of a [`VideoFrame`], as returned by [`VideoFrame::picture_type`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PictureType {
+    /// All slices are intra-predicted (H.264 I or SI slices).
+    I,
+
+    /// At least one slice is predicted from earlier pictures (H.264 P or SP slices), and none are
+    /// bi-predicted.
+    P,
+
+    /// At least one slice is bi-predicted (H.264 B slices). See
+    /// [`VideoFrame::is_disposable`] to tell reference and non-reference B-frames apart.
+    B,
+}
+
+/// How H.264 and H.265 [`VideoFrame`] data is laid out, as set by
+/// [`crate::client::PlayOptions::nal_output`].
+///
+/// By default, each NAL unit is prefixed by its length as a 4-byte big-endian integer, as in
+/// ISO/IEC 14496-15 (matching the `extra_data` of [`VideoParameters`]), and parameter sets and
+/// access unit delimiters are included only as sent by the server.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NalOutput {
+    annex_b: bool,
+    insert_parameters: bool,
+    insert_aud: bool,
+}
+
+impl NalOutput {
+    /// Prefixes each NAL unit with a `00 00 00 01` start code, as in ISO/IEC 14496-10 Annex B,
+    /// rather than its length. This is the format expected by many decoders, MPEG-TS muxers,
+    /// and WebRTC pipelines.
+    pub fn annex_b(self, annex_b: bool) -> Self {
+        Self { annex_b, ..self }
+    }
+
+    /// Inserts the current parameter sets (SPS and PPS, plus VPS for H.265) before the first
+    /// VCL NAL unit of each random access point which lacks them.
+    pub fn insert_parameters(self, insert_parameters: bool) -> Self {
+        Self {
+            insert_parameters,
+            ..self
+        }
+    }
+
+    /// Inserts an access unit delimiter at the start of each picture which lacks one.
+    pub fn insert_aud(self, insert_aud: bool) -> Self {
+        Self { insert_aud, ..self }
+    }
+
+    /// Appends a start code or length prefix for a NAL unit of the given length.
+    fn put_prefix(self, data: &mut Vec<u8>, len: u32) {
+        if self.annex_b {
+            data.extend_from_slice(b"\x00\x00\x00\x01");
+        } else {
+            data.extend_from_slice(&len.to_be_bytes()[..]);
+        }
+    }
+
+    fn layout(self, h265: bool) -> NalLayout {
+        NalLayout {
+            h265,
+            annex_b: self.annex_b,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct NalLayout {
+    h265: bool,
+    annex_b: bool,
+}
+
+/// A NAL unit within a H.264 or H.265 [`VideoFrame`], as returned by [`VideoFrame::nal_units`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NalUnit<'a> {
+    h265: bool,
+    data: &'a [u8],
+}
+
+impl<'a> NalUnit<'a> {
+    /// Returns the `nal_unit_type`, as in H.264 or H.265 Table 7-1.
+    #[inline]
+    pub fn nal_unit_type(&self) -> u8 {
+        if self.h265 {
+            (self.data[0] >> 1) & 0b11_1111
+        } else {
+            self.data[0] & 0b1_1111
+        }
+    }
+
+    /// Returns the NAL unit's bytes, including its header but not its start code or length.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Iterator returned by [`VideoFrame::nal_units`].
+#[derive(Clone, Debug)]
+pub struct NalUnits<'a> {
+    layout: Option<NalLayout>,
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = NalUnit<'a>;
+
+    fn next(&mut self) -> Option<NalUnit<'a>> {
+        let layout = self.layout?;
+        loop {
+            let data = if layout.annex_b {
+                let start = find_start_code(self.remaining)? + 3;
+                let rest = &self.remaining[start..];
+                let end = find_start_code(rest).unwrap_or(rest.len());
+                self.remaining = &rest[end..];
+
+                // Trailing zeros belong to the following start code.
+                let mut data = &rest[..end];
+                while let [head @ .., 0] = data {
+                    data = head;
+                }
+                data
+            } else {
+                if self.remaining.len() < 4 {
+                    return None;
+                }
+                let (len, rest) = self.remaining.split_at(4);
+                let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+                let len = usize::try_from(len).expect("u32 fits in usize");
+                if rest.len() < len {
+                    return None;
+                }
+                let (data, rest) = rest.split_at(len);
+                self.remaining = rest;
+                data
+            };
+            if !data.is_empty() {
+                return Some(NalUnit {
+                    h265: layout.h265,
+                    data,
+                });
+            }
+        }
+    }
+}
+
+/// Returns the position of the first `00 00 01` start code prefix within `data`.
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|w| w == [0, 0, 1])
 }
 
 impl std::fmt::Debug for VideoFrame {
@@ -296,12 +863,121 @@ impl std::fmt::Debug for VideoFrame {
             .field("new_parameters", &self.new_parameters)
             .field("is_random_access_point", &self.is_random_access_point)
             .field("is_disposable", &self.is_disposable)
+            .field("capture_time", &self.capture_time)
+            .field("corrected_timestamp", &self.corrected_timestamp)
+            .field("extension", &self.extension)
+            .field("sei", &self.sei)
+            .field("composition_offset", &self.composition_offset)
+            .field("pic_order_cnt", &self.pic_order_cnt)
+            .field("picture_type", &self.picture_type)
             .field("data_len", &self.data.len())
             //.field("data", &self.data.hex_dump())
             .finish()
     }
 }
 
+/// A Supplemental Enhancement Information (SEI) message, as defined in ISO/IEC 14496-10
+/// section 7.3.2.3.1 and Annex D.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeiMessage {
+    /// The `payloadType`. See ISO/IEC 14496-10 section D.1.1 for the defined types.
+    pub payload_type: u32,
+
+    /// The `sei_payload`, with emulation prevention bytes removed.
+    /// Its length is the `payloadSize`.
+    pub payload: Bytes,
+
+    /// The payload's fields, for commonly used payload types.
+    pub decoded: SeiPayload,
+}
+
+/// The decoded contents of a [`SeiMessage`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SeiPayload {
+    /// Picture timing (`payloadType` 1), as in ISO/IEC 14496-10 section D.2.3.
+    PicTiming(PicTiming),
+
+    /// User data registered by ITU-T Recommendation T.35 (`payloadType` 4), as in ISO/IEC
+    /// 14496-10 section D.2.6.
+    ///
+    /// ATSC A/53 closed captions are carried this way, with `country_code` 0xB5 and `data`
+    /// starting with the provider code `00 31` and user identifier `GA94`.
+    UserDataRegisteredItuTT35 {
+        country_code: u8,
+
+        /// The `itu_t_t35_country_code_extension_byte`, present iff `country_code` is 0xFF.
+        country_code_extension: Option<u8>,
+
+        /// The remaining `itu_t_t35_payload_byte`s.
+        data: Bytes,
+    },
+
+    /// Unregistered user data (`payloadType` 5), as in ISO/IEC 14496-10 section D.2.7.
+    /// Cameras commonly use this for vendor-specific timestamps and on-screen display text.
+    UserDataUnregistered { uuid: [u8; 16], data: Bytes },
+
+    /// Recovery point (`payloadType` 6), as in ISO/IEC 14496-10 section D.2.8.
+    RecoveryPoint(RecoveryPoint),
+
+    /// A payload type which isn't decoded, or a payload which couldn't be decoded.
+    /// See [`SeiMessage::payload`] for the raw bytes.
+    Other,
+}
+
+/// A picture timing SEI message, as in ISO/IEC 14496-10 section D.2.3.
+///
+/// Which fields are present depends on the active SPS's VUI parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PicTiming {
+    /// The `cpb_removal_delay`, present iff the SPS has HRD parameters.
+    pub cpb_removal_delay: Option<u32>,
+
+    /// The `dpb_output_delay`, present iff the SPS has HRD parameters.
+    pub dpb_output_delay: Option<u32>,
+
+    /// The `pic_struct`, present iff the SPS's `pic_struct_present_flag` is set.
+    pub pic_struct: Option<u8>,
+
+    /// One entry per `NumClockTS` implied by `pic_struct`, which is `None` when
+    /// `clock_timestamp_flag` is unset. Empty when `pic_struct` is absent.
+    pub clock_timestamps: Vec<Option<ClockTimestamp>>,
+}
+
+/// A clock timestamp within a [`PicTiming`] SEI message.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockTimestamp {
+    pub ct_type: u8,
+    pub nuit_field_based_flag: bool,
+    pub counting_type: u8,
+    pub discontinuity_flag: bool,
+    pub cnt_dropped_flag: bool,
+    pub n_frames: u8,
+
+    /// The `seconds_value`, if present. When absent, it's inferred to be the same as in the
+    /// previous clock timestamp.
+    pub seconds: Option<u8>,
+
+    /// The `minutes_value`, if present, with the same inference rule as `seconds`.
+    pub minutes: Option<u8>,
+
+    /// The `hours_value`, if present, with the same inference rule as `seconds`.
+    pub hours: Option<u8>,
+
+    pub time_offset: i32,
+}
+
+/// A recovery point SEI message, as in ISO/IEC 14496-10 section D.2.8.
+///
+/// This marks a point from which decoding can start without an IDR picture, as many cameras
+/// which use periodic intra refresh send instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryPoint {
+    pub recovery_frame_cnt: u32,
+    pub exact_match_flag: bool,
+    pub broken_link_flag: bool,
+    pub changing_slice_group_idc: u8,
+}
+
 /// Turns RTP packets into [CodecItem]s.
 /// This interface unstable and for internal use; it's exposed for direct fuzzing and benchmarking.
 #[doc(hidden)]
@@ -311,9 +987,15 @@ pub struct Depacketizer(DepacketizerInner);
 #[derive(Debug)]
 enum DepacketizerInner {
     Aac(Box<aac::Depacketizer>),
+    Av1(Box<av1::Depacketizer>),
     SimpleAudio(Box<simple_audio::Depacketizer>),
+    Vp8(Box<vp8::Depacketizer>),
+    Vp9(Box<vp9::Depacketizer>),
     G723(Box<g723::Depacketizer>),
     H264(Box<h264::Depacketizer>),
+    H265(Box<h265::Depacketizer>),
+    Jpeg(Box<jpeg::Depacketizer>),
+    Mp4v(Box<mp4v::Depacketizer>),
     Onvif(Box<onvif::Depacketizer>),
 }
 
@@ -330,10 +1012,29 @@ impl Depacketizer {
         // RTP Payload Format Media Types
         // https://www.iana.org/assignments/rtp-parameters/rtp-parameters.xhtml#rtp-parameters-2
         Ok(Depacketizer(match (media, encoding_name) {
+            ("video", "av1") => {
+                DepacketizerInner::Av1(Box::new(av1::Depacketizer::new(clock_rate)?))
+            }
             ("video", "h264") => DepacketizerInner::H264(Box::new(h264::Depacketizer::new(
                 clock_rate,
                 format_specific_params,
             )?)),
+            ("video", "h265") => DepacketizerInner::H265(Box::new(h265::Depacketizer::new(
+                clock_rate,
+                format_specific_params,
+            )?)),
+            ("video", "jpeg") => {
+                DepacketizerInner::Jpeg(Box::new(jpeg::Depacketizer::new(clock_rate)?))
+            }
+            ("video", "mp4v-es") => {
+                DepacketizerInner::Mp4v(Box::new(mp4v::Depacketizer::new(format_specific_params)?))
+            }
+            ("video", "vp8") => {
+                DepacketizerInner::Vp8(Box::new(vp8::Depacketizer::new(clock_rate)?))
+            }
+            ("video", "vp9") => {
+                DepacketizerInner::Vp9(Box::new(vp9::Depacketizer::new(clock_rate)?))
+            }
             ("audio", "mpeg4-generic") => DepacketizerInner::Aac(Box::new(aac::Depacketizer::new(
                 clock_rate,
                 channels,
@@ -395,9 +1096,24 @@ impl Depacketizer {
         match &self.0 {
             DepacketizerInner::Aac(d) => d.parameters(),
             DepacketizerInner::G723(d) => d.parameters(),
+            DepacketizerInner::Av1(d) => d.parameters(),
             DepacketizerInner::H264(d) => d.parameters(),
+            DepacketizerInner::H265(d) => d.parameters(),
+            DepacketizerInner::Jpeg(d) => d.parameters(),
+            DepacketizerInner::Mp4v(d) => d.parameters(),
             DepacketizerInner::Onvif(d) => d.parameters(),
             DepacketizerInner::SimpleAudio(d) => d.parameters(),
+            DepacketizerInner::Vp8(d) => d.parameters(),
+            DepacketizerInner::Vp9(d) => d.parameters(),
+        }
+    }
+
+    /// Sets the layout of H.264 and H.265 frames. Other codecs ignore this.
+    pub fn set_nal_output(&mut self, output: NalOutput) {
+        match &mut self.0 {
+            DepacketizerInner::H264(d) => d.set_nal_output(output),
+            DepacketizerInner::H265(d) => d.set_nal_output(output),
+            _ => {}
         }
     }
 
@@ -405,9 +1121,53 @@ impl Depacketizer {
         match &mut self.0 {
             DepacketizerInner::Aac(d) => d.push(input),
             DepacketizerInner::G723(d) => d.push(input),
+            DepacketizerInner::Av1(d) => d.push(input),
             DepacketizerInner::H264(d) => d.push(input),
+            DepacketizerInner::H265(d) => d.push(input),
+            DepacketizerInner::Jpeg(d) => d.push(input),
+            DepacketizerInner::Mp4v(d) => d.push(input),
             DepacketizerInner::Onvif(d) => d.push(input),
             DepacketizerInner::SimpleAudio(d) => d.push(input),
+            DepacketizerInner::Vp8(d) => d.push(input),
+            DepacketizerInner::Vp9(d) => d.push(input),
+        }
+    }
+
+    /// Discards any partially depacketized frame, as after a change of RTP source.
+    ///
+    /// Parameters are retained until the new source supplies its own.
+    pub fn reset(&mut self) {
+        match &mut self.0 {
+            DepacketizerInner::Aac(d) => d.reset(),
+            DepacketizerInner::Av1(d) => d.reset(),
+            DepacketizerInner::H264(d) => d.reset(),
+            DepacketizerInner::H265(d) => d.reset(),
+            DepacketizerInner::Jpeg(d) => d.reset(),
+            DepacketizerInner::Mp4v(d) => d.reset(),
+            DepacketizerInner::Onvif(d) => d.reset(),
+            DepacketizerInner::Vp8(d) => d.reset(),
+            DepacketizerInner::Vp9(d) => d.reset(),
+
+            // These produce a frame from each packet, so they have nothing to discard.
+            DepacketizerInner::G723(_) | DepacketizerInner::SimpleAudio(_) => {}
+        }
+    }
+
+    /// Returns the number of frames discarded because of packet loss.
+    pub fn frames_discarded(&self) -> u64 {
+        match &self.0 {
+            DepacketizerInner::Aac(d) => d.discarded(),
+            DepacketizerInner::Av1(d) => d.discarded(),
+            DepacketizerInner::H264(d) => d.discarded(),
+            DepacketizerInner::H265(d) => d.discarded(),
+            DepacketizerInner::Jpeg(d) => d.discarded(),
+            DepacketizerInner::Mp4v(d) => d.discarded(),
+            DepacketizerInner::Onvif(d) => d.discarded(),
+            DepacketizerInner::Vp8(d) => d.discarded(),
+            DepacketizerInner::Vp9(d) => d.discarded(),
+
+            // These produce a frame from each packet, so they never discard.
+            DepacketizerInner::G723(_) | DepacketizerInner::SimpleAudio(_) => 0,
         }
     }
 
@@ -415,9 +1175,101 @@ impl Depacketizer {
         match &mut self.0 {
             DepacketizerInner::Aac(d) => d.pull(conn_ctx),
             DepacketizerInner::G723(d) => Ok(d.pull()),
+            DepacketizerInner::Av1(d) => Ok(d.pull()),
             DepacketizerInner::H264(d) => Ok(d.pull()),
+            DepacketizerInner::H265(d) => Ok(d.pull()),
+            DepacketizerInner::Jpeg(d) => Ok(d.pull()),
+            DepacketizerInner::Mp4v(d) => Ok(d.pull()),
             DepacketizerInner::Onvif(d) => Ok(d.pull()),
             DepacketizerInner::SimpleAudio(d) => Ok(d.pull()),
+            DepacketizerInner::Vp8(d) => Ok(d.pull()),
+            DepacketizerInner::Vp9(d) => Ok(d.pull()),
+        }
+    }
+}
+
+/// Reads the syntax elements of a RBSP, describing errors by element name.
+pub(crate) struct RbspReader<'a>(pub(crate) bitreader::BitReader<'a>);
+
+impl<'a> RbspReader<'a> {
+    pub(crate) fn bits(&mut self, bits: u8, name: &str) -> Result<u32, String> {
+        self.0
+            .read_u32(bits)
+            .map_err(|e| format!("unable to read {}: {}", name, e))
+    }
+
+    pub(crate) fn flag(&mut self, name: &str) -> Result<bool, String> {
+        self.0
+            .read_bool()
+            .map_err(|e| format!("unable to read {}: {}", name, e))
+    }
+
+    pub(crate) fn skip(&mut self, bits: u64, name: &str) -> Result<(), String> {
+        self.0
+            .skip(bits)
+            .map_err(|e| format!("unable to read {}: {}", name, e))
+    }
+
+    /// Reads an unsigned Exp-Golomb-coded element, as in H.264 section 9.1 and H.265 section 9.2.
+    pub(crate) fn ue(&mut self, name: &str) -> Result<u32, String> {
+        let mut leading_zeros = 0;
+        while !self.flag(name)? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(format!("{} is too large", name));
+            }
+        }
+        if leading_zeros == 0 {
+            return Ok(0);
+        }
+        Ok((1 << leading_zeros) - 1 + self.bits(leading_zeros, name)?)
+    }
+
+    /// Reads a signed Exp-Golomb-coded element, as in H.264 section 9.1.1 and H.265 section 9.2.2.
+    pub(crate) fn se(&mut self, name: &str) -> Result<i32, String> {
+        let k = self.ue(name)?;
+        let magnitude = ((k / 2) + (k % 2)) as i32;
+        Ok(if k % 2 == 1 { magnitude } else { -magnitude })
+    }
+}
+
+/// Helpers for depacketizer tests.
+#[cfg(test)]
+pub(crate) mod testutil {
+    use super::{CodecItem, VideoFrame};
+
+    /// Returns a 90 kHz RTP packet with payload type 96.
+    pub(crate) fn packet(
+        sequence_number: u16,
+        timestamp: i64,
+        loss: u16,
+        mark: bool,
+        payload: &'static [u8],
+    ) -> crate::client::rtp::Packet {
+        crate::client::rtp::Packet {
+            ctx: crate::RtspMessageContext::dummy(),
+            channel_id: 0,
+            stream_id: 0,
+            timestamp: crate::Timestamp {
+                timestamp,
+                clock_rate: std::num::NonZeroU32::new(90_000).unwrap(),
+                start: 0,
+            },
+            ssrc: 0,
+            payload_type: 96,
+            sequence_number,
+            loss,
+            mark,
+            extension: None,
+            payload: bytes::Bytes::from_static(payload),
+        }
+    }
+
+    /// Unwraps the result of a depacketizer's `pull` as a video frame.
+    pub(crate) fn video_frame(item: Option<CodecItem>) -> VideoFrame {
+        match item {
+            Some(CodecItem::VideoFrame(frame)) => frame,
+            o => panic!("unexpected pull result {:#?}", o),
         }
     }
 }
@@ -435,6 +1287,10 @@ mod tests {
                 "aac::Depacketizer",
                 std::mem::size_of::<aac::Depacketizer>(),
             ),
+            (
+                "av1::Depacketizer",
+                std::mem::size_of::<av1::Depacketizer>(),
+            ),
             (
                 "g723::Depacketizer",
                 std::mem::size_of::<g723::Depacketizer>(),
@@ -443,6 +1299,18 @@ mod tests {
                 "h264::Depacketizer",
                 std::mem::size_of::<h264::Depacketizer>(),
             ),
+            (
+                "h265::Depacketizer",
+                std::mem::size_of::<h265::Depacketizer>(),
+            ),
+            (
+                "jpeg::Depacketizer",
+                std::mem::size_of::<jpeg::Depacketizer>(),
+            ),
+            (
+                "mp4v::Depacketizer",
+                std::mem::size_of::<mp4v::Depacketizer>(),
+            ),
             (
                 "onvif::Depacketizer",
                 std::mem::size_of::<onvif::Depacketizer>(),
@@ -451,13 +1319,21 @@ mod tests {
                 "simple_audio::Depacketizer",
                 std::mem::size_of::<simple_audio::Depacketizer>(),
             ),
+            (
+                "vp8::Depacketizer",
+                std::mem::size_of::<vp8::Depacketizer>(),
+            ),
+            (
+                "vp9::Depacketizer",
+                std::mem::size_of::<vp9::Depacketizer>(),
+            ),
             ("CodecItem", std::mem::size_of::<CodecItem>()),
             ("VideoFrame", std::mem::size_of::<VideoFrame>()),
             ("AudioFrame", std::mem::size_of::<AudioFrame>()),
             ("MessageFrame", std::mem::size_of::<MessageFrame>()),
             (
-                "SenderReport",
-                std::mem::size_of::<crate::client::rtp::SenderReport>(),
+                "ReceivedCompoundPacket",
+                std::mem::size_of::<crate::client::rtp::ReceivedCompoundPacket>(),
             ),
             ("Parameters", std::mem::size_of::<Parameters>()),
             ("VideoParameters", std::mem::size_of::<VideoParameters>()),
@@ -470,4 +1346,43 @@ mod tests {
             println!("{:-40} {:4}", name, size);
         }
     }
+
+    #[test]
+    fn nal_units() {
+        // Annex B with mixed start code lengths and trailing zeros.
+        let nals = NalUnits {
+            layout: Some(NalLayout {
+                h265: false,
+                annex_b: true,
+            }),
+            remaining: b"\x00\x00\x00\x01\x09\xf0\x00\x00\x01\x67sps\x00\x00\x00\x00\x01\x65idr",
+        };
+        let nals: Vec<_> = nals.map(|n| (n.nal_unit_type(), n.data())).collect();
+        assert_eq!(
+            nals,
+            [
+                (9, &b"\x09\xf0"[..]),
+                (7, &b"\x67sps"[..]),
+                (5, &b"\x65idr"[..])
+            ]
+        );
+
+        // Length-prefixed H.265; a truncated NAL ends iteration.
+        let nals = NalUnits {
+            layout: Some(NalLayout {
+                h265: true,
+                annex_b: false,
+            }),
+            remaining: b"\x00\x00\x00\x03\x46\x01\x50\x00\x00\x00\x09\x26\x01",
+        };
+        let types: Vec<_> = nals.map(|n| n.nal_unit_type()).collect();
+        assert_eq!(types, [35]);
+
+        // Other codecs have no NAL units.
+        let mut nals = NalUnits {
+            layout: None,
+            remaining: b"\x00\x00\x00\x01\x09\xf0",
+        };
+        assert!(nals.next().is_none());
+    }
 }
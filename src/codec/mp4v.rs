@@ -0,0 +1,522 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! MPEG-4 Part 2 video (ISO/IEC 14496-2), as specified in
+//! [RFC 6416 section 5](https://datatracker.ietf.org/doc/html/rfc6416#section-5).
+//!
+//! Each RTP packet with the marker bit set ends a frame (VOP). The
+//! configuration (the Visual Object Sequence, Visual Object, and Video Object
+//! Layer headers) comes from the `config` `fmtp` parameter or in-band.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use log::debug;
+
+use super::{CodecItem, VideoFrame};
+
+const VISUAL_OBJECT_SEQUENCE_START_CODE: u8 = 0xb0;
+const GROUP_OF_VOP_START_CODE: u8 = 0xb3;
+const VOP_START_CODE: u8 = 0xb6;
+
+/// Returns true iff `code` is a `video_object_layer_start_code`.
+fn is_vol_start_code(code: u8) -> bool {
+    (0x20..=0x2f).contains(&code)
+}
+
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    state: State,
+
+    /// The `profile-level-id` from the `fmtp`, used when the configuration
+    /// lacks a Visual Object Sequence header.
+    profile_level_id: u8,
+
+    /// The current configuration and parameters, if known.
+    config: Option<(Bytes, super::VideoParameters)>,
+
+    high_water_size: usize,
+
+    /// The number of frames discarded because of packet loss.
+    discarded: u64,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Idle,
+
+    /// Ignoring the remainder of a frame because of packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+    },
+
+    InProgress(InProgress),
+    Ready(VideoFrame),
+}
+
+#[derive(Debug)]
+struct InProgress {
+    start_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
+    data: BytesMut,
+}
+
+impl Depacketizer {
+    pub(super) fn new(format_specific_params: Option<&str>) -> Result<Self, String> {
+        // RFC 6416 section 7.1: "If this parameter is not specified by the
+        // procedure, its default value of 1 (Simple Profile/Level 1) is used."
+        let mut profile_level_id = 1;
+        let mut config = None;
+        for p in format_specific_params.unwrap_or("").split(';') {
+            let (key, value) = match p.trim().split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match key {
+                "profile-level-id" => {
+                    profile_level_id = value
+                        .parse()
+                        .map_err(|_| format!("bad MP4V-ES profile-level-id {:?}", value))?;
+                }
+                "config" => {
+                    config = Some(Bytes::from(
+                        hex::decode(value)
+                            .map_err(|_| format!("bad MP4V-ES config {:?}", value))?,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let config = match config {
+            Some(c) => {
+                let parameters = parse_config(&c, profile_level_id)?;
+                Some((c, parameters))
+            }
+            None => None,
+        };
+        Ok(Depacketizer {
+            state: State::Idle,
+            profile_level_id,
+            config,
+            high_water_size: 0,
+            discarded: 0,
+        })
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.config
+            .as_ref()
+            .map(|(_, p)| super::Parameters::Video(p.clone()))
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
+        let mut discarded = false;
+        if pkt.loss > 0 {
+            if let State::InProgress(in_progress) = &self.state {
+                debug!(
+                    "Discarding {}-byte MP4V-ES frame prefix due to loss of {} RTP packets",
+                    in_progress.data.len(),
+                    pkt.loss
+                );
+                self.discarded += 1;
+                discarded = true;
+                self.state = State::Idle;
+            }
+        }
+        let mut in_progress = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(..) => panic!("push while in state ready"),
+            State::InProgress(in_progress) => {
+                if in_progress.timestamp.timestamp != pkt.timestamp.timestamp {
+                    return Err(format!(
+                        "Timestamp changed from {} to {} with frame in progress",
+                        &in_progress.timestamp, &pkt.timestamp,
+                    ));
+                }
+                in_progress
+            }
+            State::Loss { timestamp } if timestamp.timestamp == pkt.timestamp.timestamp => {
+                if !pkt.mark {
+                    self.state = State::Loss { timestamp };
+                }
+                return Ok(());
+            }
+            State::Idle | State::Loss { .. } => {
+                // After loss, a frame can only start at a start code.
+                if pkt.loss > 0 && !pkt.payload.starts_with(b"\x00\x00\x01") {
+                    if !discarded {
+                        self.discarded += 1;
+                    }
+                    if !pkt.mark {
+                        self.state = State::Loss {
+                            timestamp: pkt.timestamp,
+                        };
+                    }
+                    return Ok(());
+                }
+                InProgress {
+                    start_ctx: pkt.ctx,
+                    timestamp: pkt.timestamp,
+                    loss: pkt.loss,
+                    extension: pkt.extension,
+                    data: BytesMut::with_capacity(self.high_water_size),
+                }
+            }
+        };
+        in_progress.data.put(pkt.payload);
+        if !pkt.mark {
+            self.state = State::InProgress(in_progress);
+            return Ok(());
+        }
+        self.high_water_size = std::cmp::max(self.high_water_size, in_progress.data.len());
+        let data = in_progress.data.freeze();
+        let new_parameters = self.update_config(&data)?;
+        let mut is_random_access_point = false;
+        let mut is_disposable = true;
+        let mut vops = 0;
+        for (i, code) in start_codes(&data) {
+            if code != VOP_START_CODE {
+                continue;
+            }
+            let vop_coding_type = data.get(i + 4).map(|b| b >> 6);
+            if vops == 0 && vop_coding_type == Some(0) {
+                is_random_access_point = true;
+            }
+
+            // Only B-VOPs (type 2) are never used as references.
+            if vop_coding_type != Some(2) {
+                is_disposable = false;
+            }
+            vops += 1;
+        }
+        if vops == 0 {
+            return Err(format!(
+                "MP4V-ES frame at {} has no VOP",
+                in_progress.timestamp
+            ));
+        }
+        self.state = State::Ready(VideoFrame {
+            new_parameters,
+            loss: in_progress.loss,
+            start_ctx: in_progress.start_ctx,
+            end_ctx: pkt.ctx,
+            timestamp: in_progress.timestamp,
+            stream_id: pkt.stream_id,
+            is_random_access_point,
+            is_disposable,
+            capture_time: in_progress.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: in_progress.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: None,
+            data,
+        });
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<CodecItem> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(frame) => Some(CodecItem::VideoFrame(frame)),
+            s => {
+                self.state = s;
+                None
+            }
+        }
+    }
+
+    /// Updates the configuration from any in-band headers preceding the
+    /// frame's first group of VOPs or VOP, returning the new parameters if
+    /// they changed.
+    fn update_config(
+        &mut self,
+        data: &Bytes,
+    ) -> Result<Option<Box<super::VideoParameters>>, String> {
+        let mut has_vol = false;
+        let mut end = None;
+        for (i, code) in start_codes(data) {
+            if code == GROUP_OF_VOP_START_CODE || code == VOP_START_CODE {
+                end = Some(i);
+                break;
+            }
+            has_vol |= is_vol_start_code(code);
+        }
+        let end = match end {
+            Some(end) if has_vol => end,
+            _ => return Ok(None),
+        };
+        let config = data.slice(..end);
+        if matches!(&self.config, Some((c, _)) if c == &config) {
+            return Ok(None);
+        }
+        let parameters = parse_config(&config, self.profile_level_id)?;
+        self.config = Some((config, parameters.clone()));
+        Ok(Some(Box::new(parameters)))
+    }
+}
+
+/// Returns an iterator over the positions and values of start codes
+/// (`00 00 01 xx`) in `data`.
+fn start_codes(data: &[u8]) -> impl Iterator<Item = (usize, u8)> + '_ {
+    data.windows(4)
+        .enumerate()
+        .filter(|(_, w)| w[..3] == [0, 0, 1])
+        .map(|(i, w)| (i, w[3]))
+}
+
+/// Parses a configuration into parameters.
+///
+/// The configuration is the Visual Object Sequence header (optional),
+/// Visual Object header (optional), and Video Object Layer header, as in
+/// ISO/IEC 14496-2 section 6.2.
+fn parse_config(config: &[u8], profile_level_id: u8) -> Result<super::VideoParameters, String> {
+    let mut profile_and_level_indication = profile_level_id;
+    let mut vol = None;
+    for (i, code) in start_codes(config) {
+        if code == VISUAL_OBJECT_SEQUENCE_START_CODE {
+            profile_and_level_indication = *config
+                .get(i + 4)
+                .ok_or_else(|| "truncated visual object sequence header".to_string())?;
+        } else if is_vol_start_code(code) {
+            vol = Some(&config[i + 4..]);
+            break;
+        }
+    }
+    let vol = vol.ok_or_else(|| "MP4V-ES config has no video object layer".to_string())?;
+    let vol = VideoObjectLayer::parse(vol)?;
+    debug!("vol: {:#?}", &vol);
+    let esds = make_esds(0x20 /* Visual ISO/IEC 14496-2 */, Some(config))?;
+    Ok(super::VideoParameters {
+        pixel_dimensions: vol.pixel_dimensions,
+        rfc6381_codec: format!("mp4v.20.{}", profile_and_level_indication),
+        pixel_aspect_ratio: vol.pixel_aspect_ratio,
+        frame_rate: vol.frame_rate,
+        sample_entry: super::make_video_sample_entry(
+            b"mp4v",
+            (b"esds", &esds[..]),
+            vol.pixel_dimensions,
+            vol.pixel_aspect_ratio,
+            None,
+        )?,
+        details: Default::default(),
+        extra_data: esds,
+    })
+}
+
+/// Returns the contents of an `esds` box (a version and an ES_Descriptor),
+/// as in ISO/IEC 14496-14 section 5.6.1, for a visual stream of the given
+/// `objectTypeIndication` (ISO/IEC 14496-1 Table 5).
+pub(super) fn make_esds(
+    object_type_indication: u8,
+    config: Option<&[u8]>,
+) -> Result<Bytes, String> {
+    let mut buf = BytesMut::new();
+    buf.put_u32(0); // version
+    write_descriptor!(&mut buf, 0x03 /* ES_DescrTag */, {
+        buf.extend_from_slice(&[
+            0, 0,    // ES_ID=0
+            0x00, // streamDependenceFlag, URL_Flag, OCRStreamFlag, streamPriority.
+        ]);
+
+        // DecoderConfigDescriptor, defined in ISO/IEC 14496-1 section 7.2.6.6.
+        write_descriptor!(&mut buf, 0x04 /* DecoderConfigDescrTag */, {
+            buf.extend_from_slice(&[
+                object_type_indication,
+                0x11, // streamType = visual, upstream = false, reserved = 1
+            ]);
+
+            // bufferSizeDB, maxBitrate, and avgBitrate aren't known.
+            buf.extend_from_slice(&[0; 3 + 4 + 4]);
+
+            // The configuration, eg ISO/IEC 14496-2 section 6.2.
+            if let Some(config) = config {
+                write_descriptor!(&mut buf, 0x05 /* DecSpecificInfoTag */, {
+                    buf.extend_from_slice(config);
+                });
+            }
+        });
+
+        // SLConfigDescriptor, ISO/IEC 14496-1 section 7.3.2.3.1.
+        write_descriptor!(&mut buf, 0x06 /* SLConfigDescrTag */, {
+            buf.put_u8(2); // predefined = reserved for use in MP4 files
+        });
+    });
+    Ok(buf.freeze())
+}
+
+/// The fields of a Video Object Layer header needed for [super::VideoParameters].
+#[derive(Debug)]
+struct VideoObjectLayer {
+    pixel_dimensions: (u32, u32),
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    frame_rate: Option<(u32, u32)>,
+}
+
+impl VideoObjectLayer {
+    /// Parses from the bytes following the `video_object_layer_start_code`,
+    /// as in ISO/IEC 14496-2 section 6.2.3.
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let mut r = bitreader::BitReader::new(raw);
+        let mut read = |bits: u8, name: &str| {
+            r.read_u32(bits)
+                .map_err(|e| format!("unable to read {}: {}", name, e))
+        };
+        read(1, "random_accessible_vol")?;
+        read(8, "video_object_type_indication")?;
+        let mut verid = 1;
+        if read(1, "is_object_layer_identifier")? == 1 {
+            verid = read(4, "video_object_layer_verid")?;
+            read(3, "video_object_layer_priority")?;
+        }
+        let pixel_aspect_ratio = match read(4, "aspect_ratio_info")? {
+            1 => Some((1, 1)),
+            2 => Some((12, 11)),
+            3 => Some((10, 11)),
+            4 => Some((16, 11)),
+            5 => Some((40, 33)),
+            15 => Some((read(8, "par_width")?, read(8, "par_height")?)),
+            _ => None,
+        };
+        if read(1, "vol_control_parameters")? == 1 {
+            read(2, "chroma_format")?;
+            read(1, "low_delay")?;
+            if read(1, "vbv_parameters")? == 1 {
+                // bit rate, buffer size, and occupancy, with marker bits.
+                read(31, "bit_rate")?;
+                read(31, "vbv_buffer_size")?;
+                read(17, "vbv_occupancy")?;
+            }
+        }
+        let shape = read(2, "video_object_layer_shape")?;
+        if shape == 3 && verid != 1 {
+            read(4, "video_object_layer_shape_extension")?;
+        }
+        if shape != 0 {
+            return Err(format!("unimplemented video_object_layer_shape {}", shape));
+        }
+        read(1, "marker_bit")?;
+        let vop_time_increment_resolution = read(16, "vop_time_increment_resolution")?;
+        if vop_time_increment_resolution == 0 {
+            return Err("zero vop_time_increment_resolution".into());
+        }
+        read(1, "marker_bit")?;
+        let mut frame_rate = None;
+        if read(1, "fixed_vop_rate")? == 1 {
+            // The number of bits needed to represent values up to the resolution.
+            let bits = std::cmp::max(
+                1,
+                32 - (vop_time_increment_resolution - 1).leading_zeros() as u8,
+            );
+            let fixed_vop_time_increment = read(bits, "fixed_vop_time_increment")?;
+            if fixed_vop_time_increment != 0 {
+                frame_rate = Some((fixed_vop_time_increment, vop_time_increment_resolution));
+            }
+        }
+        read(1, "marker_bit")?;
+        let width = read(13, "video_object_layer_width")?;
+        read(1, "marker_bit")?;
+        let height = read(13, "video_object_layer_height")?;
+        if width == 0 || height == 0 {
+            return Err(format!("bad MP4V-ES dimensions {}x{}", width, height));
+        }
+        Ok(VideoObjectLayer {
+            pixel_dimensions: (width, height),
+            pixel_aspect_ratio,
+            frame_rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::testutil::{packet, video_frame};
+
+    /// A Simple Profile Level 1, 320x240 configuration.
+    const FMTP: &str =
+        "profile-level-id=1;config=000001B001000001B509000001000000012000844007A85020F0A21F";
+
+    #[test]
+    fn parse_config() {
+        let d = super::Depacketizer::new(Some(FMTP)).unwrap();
+        let p = match d.parameters() {
+            Some(crate::codec::Parameters::Video(p)) => p,
+            o => panic!("unexpected parameters {:#?}", o),
+        };
+        assert_eq!(p.rfc6381_codec(), "mp4v.20.1");
+        assert_eq!(p.pixel_dimensions(), (320, 240));
+        assert_eq!(p.pixel_aspect_ratio(), Some((1, 1)));
+        assert_eq!(p.frame_rate(), None);
+        assert_eq!(
+            &p.extra_data()[..],
+            b"\x00\x00\x00\x00\
+              \x03\x33\x00\x00\x00\
+              \x04\x2b\x20\x11\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+              \x05\x1c\x00\x00\x01\xb0\x01\x00\x00\x01\xb5\x09\x00\x00\x01\x00\x00\x00\x01\
+              \x20\x00\x84\x40\x07\xa8\x50\x20\xf0\xa2\x1f\
+              \x06\x01\x02"
+        );
+
+        // Without a config, parameters come in-band.
+        let d = super::Depacketizer::new(Some("profile-level-id=8")).unwrap();
+        assert!(d.parameters().is_none());
+    }
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(None).unwrap();
+
+        // An I-VOP with in-band configuration, in two packets.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x00\x00\x01\xb0\x03\x00\x00\x01\xb5\x09\x00\x00\x01\x00\x00\x00\x01\x20\x00\x84\
+              \x40\x07\xa8\x50\x20\xf0\xa2\x1f\x00\x00\x01\xb6\x10",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(0, 0, 0, true, b"i-vop")).unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.is_random_access_point);
+        assert!(!frame.is_disposable);
+        let p = frame.new_parameters.as_ref().unwrap();
+        assert_eq!(p.rfc6381_codec(), "mp4v.20.3");
+        assert_eq!(p.pixel_dimensions(), (320, 240));
+        assert!(frame.data().ends_with(b"\x00\x00\x01\xb6\x10i-vop"));
+
+        // A P-VOP.
+        d.push(packet(0, 3000, 0, true, b"\x00\x00\x01\xb6\x50p-vop"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(!frame.is_random_access_point);
+        assert!(!frame.is_disposable);
+        assert!(frame.new_parameters.is_none());
+
+        // A B-VOP.
+        d.push(packet(0, 6000, 0, true, b"\x00\x00\x01\xb6\x90b-vop"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(!frame.is_random_access_point);
+        assert!(frame.is_disposable);
+
+        // A frame whose start was lost is discarded.
+        d.push(packet(0, 9000, 1, false, b"middle")).unwrap();
+        d.push(packet(0, 9000, 0, true, b"end")).unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+        d.push(packet(0, 12000, 0, true, b"\x00\x00\x01\xb6\x50p-vop"))
+            .unwrap();
+        assert_eq!(video_frame(d.pull()).timestamp.timestamp, 12000);
+    }
+}
@@ -25,6 +25,9 @@ pub(crate) struct Depacketizer {
     compression_type: CompressionType,
     state: State,
     high_water_size: usize,
+
+    /// The number of messages discarded because of packet loss.
+    discarded: u64,
 }
 
 #[derive(Debug)]
@@ -40,6 +43,7 @@ struct InProgress {
     timestamp: crate::Timestamp,
     data: BytesMut,
     loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
 }
 
 impl Depacketizer {
@@ -48,6 +52,7 @@ impl Depacketizer {
             compression_type,
             state: State::Idle,
             high_water_size: 0,
+            discarded: 0,
         }
     }
 
@@ -57,6 +62,14 @@ impl Depacketizer {
         )))
     }
 
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
     pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
         if pkt.loss > 0 {
             if let State::InProgress(in_progress) = &self.state {
@@ -65,6 +78,7 @@ impl Depacketizer {
                     in_progress.data.len(),
                     pkt.loss
                 );
+                self.discarded += 1;
                 self.state = State::Idle;
             }
         }
@@ -87,6 +101,9 @@ impl Depacketizer {
                         loss: pkt.loss,
                         ctx: pkt.ctx,
                         timestamp: pkt.timestamp,
+                        capture_time: pkt.ctx.received_wall(),
+                        corrected_timestamp: None,
+                        extension: pkt.extension,
                         data: pkt.payload,
                     });
                     return Ok(());
@@ -95,6 +112,7 @@ impl Depacketizer {
                     loss: pkt.loss,
                     ctx: pkt.ctx,
                     timestamp: pkt.timestamp,
+                    extension: pkt.extension,
                     data: BytesMut::with_capacity(self.high_water_size),
                 }
             }
@@ -109,6 +127,9 @@ impl Depacketizer {
                 timestamp: in_progress.timestamp,
                 data: in_progress.data.freeze(),
                 loss: in_progress.loss,
+                capture_time: in_progress.ctx.received_wall(),
+                corrected_timestamp: None,
+                extension: in_progress.extension,
             });
         } else {
             self.state = State::InProgress(in_progress);
@@ -63,6 +63,9 @@ impl Depacketizer {
             stream_id: pkt.stream_id,
             timestamp: pkt.timestamp,
             frame_length,
+            capture_time: pkt.ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: pkt.extension,
             data: pkt.payload,
         });
         Ok(())
@@ -0,0 +1,416 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! [VP8](https://datatracker.ietf.org/doc/html/rfc6386)-encoded video, as
+//! specified in [RFC 7741](https://datatracker.ietf.org/doc/html/rfc7741).
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+
+use super::{CodecItem, VideoFrame};
+
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    state: State,
+
+    /// The configuration from the most recent key frame, if any.
+    config: Option<(VpxConfig, super::VideoParameters)>,
+
+    high_water_size: usize,
+
+    /// The number of frames discarded because of packet loss.
+    discarded: u64,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Idle,
+
+    /// Ignoring the remainder of a frame because of packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+    },
+
+    InProgress(InProgress),
+    Ready(VideoFrame),
+}
+
+#[derive(Debug)]
+struct InProgress {
+    start_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
+
+    /// True iff the `N` bit was set: this frame isn't used as a reference.
+    non_reference: bool,
+    data: BytesMut,
+}
+
+impl Depacketizer {
+    pub(super) fn new(clock_rate: u32) -> Result<Self, String> {
+        if clock_rate != 90_000 {
+            return Err(format!(
+                "invalid VP8 clock rate {}; must always be 90000",
+                clock_rate
+            ));
+        }
+        Ok(Depacketizer {
+            state: State::Idle,
+            config: None,
+            high_water_size: 0,
+            discarded: 0,
+        })
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.config
+            .as_ref()
+            .map(|(_, p)| super::Parameters::Video(p.clone()))
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
+        let mut payload = pkt.payload;
+        let descriptor = PayloadDescriptor::parse(&mut payload)?;
+        let starts_frame = descriptor.start_of_partition && descriptor.partition_index == 0;
+
+        let mut discarded = false;
+        if pkt.loss > 0 {
+            if let State::InProgress(in_progress) = &self.state {
+                debug!(
+                    "Discarding {}-byte VP8 frame prefix due to loss of {} RTP packets",
+                    in_progress.data.len(),
+                    pkt.loss
+                );
+                self.discarded += 1;
+                discarded = true;
+                self.state = State::Idle;
+            }
+        }
+        let mut in_progress = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(..) => panic!("push while in state ready"),
+            State::InProgress(in_progress) => {
+                if in_progress.timestamp.timestamp != pkt.timestamp.timestamp {
+                    return Err(format!(
+                        "Timestamp changed from {} to {} with frame in progress",
+                        &in_progress.timestamp, &pkt.timestamp,
+                    ));
+                }
+                if starts_frame {
+                    return Err("VP8 frame start while frame in progress".into());
+                }
+                in_progress
+            }
+            State::Loss { timestamp } if timestamp.timestamp == pkt.timestamp.timestamp => {
+                if !pkt.mark {
+                    self.state = State::Loss { timestamp };
+                }
+                return Ok(());
+            }
+            State::Idle | State::Loss { .. } => {
+                if !starts_frame {
+                    if pkt.loss == 0 {
+                        return Err("VP8 packet doesn't start a frame".into());
+                    }
+                    if !discarded {
+                        self.discarded += 1;
+                    }
+                    if !pkt.mark {
+                        self.state = State::Loss {
+                            timestamp: pkt.timestamp,
+                        };
+                    }
+                    return Ok(());
+                }
+                InProgress {
+                    start_ctx: pkt.ctx,
+                    timestamp: pkt.timestamp,
+                    loss: pkt.loss,
+                    extension: pkt.extension,
+                    non_reference: descriptor.non_reference,
+                    data: BytesMut::with_capacity(self.high_water_size),
+                }
+            }
+        };
+        in_progress.data.put(payload);
+        if !pkt.mark {
+            self.state = State::InProgress(in_progress);
+            return Ok(());
+        }
+        self.high_water_size = std::cmp::max(self.high_water_size, in_progress.data.len());
+        let data = in_progress.data.freeze();
+        let key_frame = parse_key_frame(&data)?;
+        let is_random_access_point = key_frame.is_some();
+        let mut new_parameters = None;
+        if let Some(config) = key_frame {
+            if !matches!(&self.config, Some((c, _)) if c == &config) {
+                let parameters = config.to_parameters()?;
+                new_parameters = Some(Box::new(parameters.clone()));
+                self.config = Some((config, parameters));
+            }
+        }
+        self.state = State::Ready(VideoFrame {
+            new_parameters,
+            loss: in_progress.loss,
+            start_ctx: in_progress.start_ctx,
+            end_ctx: pkt.ctx,
+            timestamp: in_progress.timestamp,
+            stream_id: pkt.stream_id,
+            is_random_access_point,
+            is_disposable: in_progress.non_reference,
+            capture_time: in_progress.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: in_progress.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: None,
+            data,
+        });
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<CodecItem> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(frame) => Some(CodecItem::VideoFrame(frame)),
+            s => {
+                self.state = s;
+                None
+            }
+        }
+    }
+}
+
+/// The fields of the VP8 payload descriptor used for depacketization, as in
+/// [RFC 7741 section 4.2](https://datatracker.ietf.org/doc/html/rfc7741#section-4.2).
+#[derive(Debug)]
+struct PayloadDescriptor {
+    non_reference: bool,
+    start_of_partition: bool,
+    partition_index: u8,
+}
+
+impl PayloadDescriptor {
+    /// Parses the descriptor, advancing `payload` to the VP8 data.
+    fn parse(payload: &mut Bytes) -> Result<Self, String> {
+        let too_short = || "VP8 payload descriptor too short".to_string();
+        if !payload.has_remaining() {
+            return Err(too_short());
+        }
+        let b = payload.get_u8();
+        let descriptor = PayloadDescriptor {
+            non_reference: (b & 0b0010_0000) != 0,
+            start_of_partition: (b & 0b0001_0000) != 0,
+            partition_index: b & 0b0000_0111,
+        };
+        if (b & 0b1000_0000) != 0 {
+            // X: the extension byte is present.
+            if !payload.has_remaining() {
+                return Err(too_short());
+            }
+            let x = payload.get_u8();
+            let mut len = 0;
+            if (x & 0b1000_0000) != 0 {
+                // I: PictureID, 7 or 15 bits depending on the M bit.
+                len += match payload.first() {
+                    Some(m) if (m & 0b1000_0000) != 0 => 2,
+                    Some(_) => 1,
+                    None => return Err(too_short()),
+                };
+            }
+            if (x & 0b0100_0000) != 0 {
+                len += 1; // L: TL0PICIDX.
+            }
+            if (x & 0b0011_0000) != 0 {
+                len += 1; // T or K: TID, Y, and KEYIDX.
+            }
+            if payload.remaining() < len {
+                return Err(too_short());
+            }
+            payload.advance(len);
+        }
+        if !payload.has_remaining() {
+            return Err("empty VP8 payload".into());
+        }
+        Ok(descriptor)
+    }
+}
+
+/// Parses the frame's header, returning its configuration iff it's a key frame.
+///
+/// See [RFC 6386 section 9.1](https://datatracker.ietf.org/doc/html/rfc6386#section-9.1).
+fn parse_key_frame(data: &[u8]) -> Result<Option<VpxConfig>, String> {
+    if data.len() < 3 {
+        return Err(format!("VP8 frame of {} bytes too short", data.len()));
+    }
+    if (data[0] & 1) != 0 {
+        return Ok(None); // inter frame.
+    }
+    let version = (data[0] >> 1) & 0b111;
+    if data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a] {
+        return Err("VP8 key frame lacks start code".into());
+    }
+
+    // The upper two bits of each are a scaling mode, which is ignored.
+    let width = u16::from_le_bytes([data[6], data[7]]) & 0x3fff;
+    let height = u16::from_le_bytes([data[8], data[9]]) & 0x3fff;
+    if width == 0 || height == 0 {
+        return Err(format!("bad VP8 dimensions {}x{}", width, height));
+    }
+    Ok(Some(VpxConfig {
+        codec: "vp08",
+        profile: version,
+        bit_depth: 8,
+        chroma_subsampling: 1,
+        full_range: false,
+        matrix_coefficients: 2, // unspecified
+        pixel_dimensions: (u32::from(width), u32::from(height)),
+    }))
+}
+
+/// The configuration of a VP8 or VP9 stream, as written to a `vpcC` box.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct VpxConfig {
+    /// The RFC 6381 codec prefix: `vp08` or `vp09`.
+    pub(super) codec: &'static str,
+    pub(super) profile: u8,
+    pub(super) bit_depth: u8,
+
+    /// The `chromaSubsampling` field: 1 for 4:2:0, 2 for 4:2:2, or 3 for 4:4:4.
+    pub(super) chroma_subsampling: u8,
+    pub(super) full_range: bool,
+
+    /// The `matrixCoefficients` field, as in ISO/IEC 23091-4.
+    pub(super) matrix_coefficients: u8,
+    pub(super) pixel_dimensions: (u32, u32),
+}
+
+impl VpxConfig {
+    /// Returns the VP9 level implied by the picture size, as in the [VP9
+    /// levels](https://www.webmproject.org/vp9/levels/) table.
+    ///
+    /// The level isn't signalled in the bitstream. VP8 has no levels of its
+    /// own, but its `vpcC` box and codec string have the same level field, and
+    /// codec string parsers expect one of the VP9 values there, so VP8
+    /// streams use this too.
+    fn level(&self) -> u8 {
+        const LEVELS: [(u64, u8); 8] = [
+            (36_864, 10),
+            (73_728, 11),
+            (122_880, 20),
+            (245_760, 21),
+            (552_960, 30),
+            (983_040, 31),
+            (2_228_224, 40),
+            (8_912_896, 50),
+        ];
+        let samples = u64::from(self.pixel_dimensions.0) * u64::from(self.pixel_dimensions.1);
+        LEVELS
+            .iter()
+            .find(|(max, _)| samples <= *max)
+            .map(|(_, level)| *level)
+            .unwrap_or(60)
+    }
+
+    pub(super) fn to_parameters(&self) -> Result<super::VideoParameters, String> {
+        let level = self.level();
+
+        // The contents of a vpcC box, as in the VP Codec ISO Media File
+        // Format Binding section 2.2.
+        let mut vpcc = BytesMut::with_capacity(12);
+        vpcc.put_u32(1 << 24); // version = 1, flags = 0
+        vpcc.put_u8(self.profile);
+        vpcc.put_u8(level);
+        vpcc.put_u8(
+            (self.bit_depth << 4) | (self.chroma_subsampling << 1) | u8::from(self.full_range),
+        );
+        vpcc.put_u8(2); // colourPrimaries: unspecified
+        vpcc.put_u8(2); // transferCharacteristics: unspecified
+        vpcc.put_u8(self.matrix_coefficients);
+        vpcc.put_u16(0); // codecInitializationDataSize
+        let sample_entry_type = if self.codec == "vp08" {
+            b"vp08"
+        } else {
+            b"vp09"
+        };
+        Ok(super::VideoParameters {
+            pixel_dimensions: self.pixel_dimensions,
+            rfc6381_codec: format!(
+                "{}.{:02}.{:02}.{:02}",
+                self.codec, self.profile, level, self.bit_depth
+            ),
+            pixel_aspect_ratio: None,
+            frame_rate: None,
+            sample_entry: super::make_video_sample_entry(
+                sample_entry_type,
+                (b"vpcC", &vpcc[..]),
+                self.pixel_dimensions,
+                None,
+                None,
+            )?,
+            details: Default::default(),
+            extra_data: vpcc.freeze(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::testutil::{packet, video_frame};
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(90_000).unwrap();
+        assert!(d.parameters().is_none());
+
+        // A 640x480 key frame in two packets, with a 15-bit PictureID.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x90\x80\x81\x23\x10\x02\x00\x9d\x01\x2a\x80\x02\xe0\x01key",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(0, 0, 0, true, b"\x80\x80\x81\x23 frame"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.is_random_access_point);
+        assert!(!frame.is_disposable);
+        assert_eq!(
+            &frame.data()[..],
+            b"\x10\x02\x00\x9d\x01\x2a\x80\x02\xe0\x01key frame"
+        );
+        let p = frame.new_parameters.as_ref().unwrap();
+        assert_eq!(p.rfc6381_codec(), "vp08.00.30.08");
+        assert_eq!(p.pixel_dimensions(), (640, 480));
+        assert_eq!(
+            &p.extra_data()[..],
+            b"\x01\x00\x00\x00\x00\x1e\x82\x02\x02\x02\x00\x00"
+        );
+
+        // A non-reference inter frame without extensions.
+        d.push(packet(0, 3000, 0, true, b"\x30\x01\x00\x00inter"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(!frame.is_random_access_point);
+        assert!(frame.is_disposable);
+        assert!(frame.new_parameters.is_none());
+
+        // A frame whose start was lost is discarded.
+        d.push(packet(0, 6000, 1, true, b"\x00rest")).unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+    }
+}
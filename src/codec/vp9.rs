@@ -0,0 +1,482 @@
+// Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! [VP9](https://www.webmproject.org/vp9/)-encoded video, as specified in
+//! [RFC 9628](https://datatracker.ietf.org/doc/html/rfc9628).
+//!
+//! Each frame is a picture: all the layer frames (one per spatial layer)
+//! sharing a timestamp. When there are several, they're combined into a
+//! superframe as in the VP9 bitstream specification Annex B.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+
+use super::vp8::VpxConfig;
+use super::{CodecItem, VideoFrame};
+
+#[derive(Debug)]
+pub(crate) struct Depacketizer {
+    state: State,
+
+    /// The configuration from the most recent key frame, if any.
+    config: Option<(VpxConfig, super::VideoParameters)>,
+
+    high_water_size: usize,
+
+    /// The number of pictures discarded because of packet loss.
+    discarded: u64,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Idle,
+
+    /// Ignoring the remainder of a picture because of packet loss.
+    Loss {
+        timestamp: crate::Timestamp,
+    },
+
+    InProgress(InProgress),
+    Ready(VideoFrame),
+}
+
+#[derive(Debug)]
+struct InProgress {
+    start_ctx: crate::RtspMessageContext,
+    timestamp: crate::Timestamp,
+    loss: u16,
+    extension: Option<crate::client::rtp::HeaderExtension>,
+
+    /// True iff the current layer frame has ended (or none has started).
+    layer_frame_ended: bool,
+
+    /// The sizes of each layer frame within `data`.
+    layer_frame_sizes: Vec<usize>,
+    data: BytesMut,
+}
+
+impl Depacketizer {
+    pub(super) fn new(clock_rate: u32) -> Result<Self, String> {
+        if clock_rate != 90_000 {
+            return Err(format!(
+                "invalid VP9 clock rate {}; must always be 90000",
+                clock_rate
+            ));
+        }
+        Ok(Depacketizer {
+            state: State::Idle,
+            config: None,
+            high_water_size: 0,
+            discarded: 0,
+        })
+    }
+
+    pub(super) fn parameters(&self) -> Option<super::Parameters> {
+        self.config
+            .as_ref()
+            .map(|(_, p)| super::Parameters::Video(p.clone()))
+    }
+
+    pub(super) fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub(super) fn push(&mut self, pkt: crate::client::rtp::Packet) -> Result<(), String> {
+        let mut payload = pkt.payload;
+        let descriptor = PayloadDescriptor::parse(&mut payload)?;
+
+        let mut discarded = false;
+        if pkt.loss > 0 {
+            if let State::InProgress(in_progress) = &self.state {
+                debug!(
+                    "Discarding {}-byte VP9 picture prefix due to loss of {} RTP packets",
+                    in_progress.data.len(),
+                    pkt.loss
+                );
+                self.discarded += 1;
+                discarded = true;
+                self.state = State::Idle;
+            }
+        }
+        let mut in_progress = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(..) => panic!("push while in state ready"),
+            State::InProgress(in_progress) => {
+                if in_progress.timestamp.timestamp != pkt.timestamp.timestamp {
+                    return Err(format!(
+                        "Timestamp changed from {} to {} with picture in progress",
+                        &in_progress.timestamp, &pkt.timestamp,
+                    ));
+                }
+                in_progress
+            }
+            State::Loss { timestamp } if timestamp.timestamp == pkt.timestamp.timestamp => {
+                if !pkt.mark {
+                    self.state = State::Loss { timestamp };
+                }
+                return Ok(());
+            }
+            State::Idle | State::Loss { .. } => {
+                if !descriptor.start_of_frame {
+                    if pkt.loss == 0 {
+                        return Err("VP9 packet doesn't start a frame".into());
+                    }
+                    if !discarded {
+                        self.discarded += 1;
+                    }
+                    if !pkt.mark {
+                        self.state = State::Loss {
+                            timestamp: pkt.timestamp,
+                        };
+                    }
+                    return Ok(());
+                }
+                InProgress {
+                    start_ctx: pkt.ctx,
+                    timestamp: pkt.timestamp,
+                    loss: pkt.loss,
+                    extension: pkt.extension,
+                    layer_frame_ended: true,
+                    layer_frame_sizes: Vec::new(),
+                    data: BytesMut::with_capacity(self.high_water_size),
+                }
+            }
+        };
+        if descriptor.start_of_frame != in_progress.layer_frame_ended {
+            return Err(format!(
+                "VP9 packet has B={} with layer frame {}",
+                descriptor.start_of_frame,
+                if in_progress.layer_frame_ended {
+                    "ended"
+                } else {
+                    "in progress"
+                }
+            ));
+        }
+        if descriptor.start_of_frame {
+            in_progress.layer_frame_sizes.push(0);
+        }
+        *in_progress
+            .layer_frame_sizes
+            .last_mut()
+            .expect("layer frame started") += payload.len();
+        in_progress.data.put(payload);
+        in_progress.layer_frame_ended = descriptor.end_of_frame;
+        if !pkt.mark {
+            self.state = State::InProgress(in_progress);
+            return Ok(());
+        }
+        if !in_progress.layer_frame_ended {
+            return Err("VP9 packet has mark bit set without E bit".into());
+        }
+        self.high_water_size = std::cmp::max(self.high_water_size, in_progress.data.len());
+
+        // Parse each layer frame's header. The picture is a random access
+        // point iff the first (base layer) frame is a key frame. Parameters
+        // come from the last (largest) key frame.
+        let mut pos = 0;
+        let mut is_random_access_point = false;
+        let mut key_frame = None;
+        for (i, &size) in in_progress.layer_frame_sizes.iter().enumerate() {
+            let header = parse_key_frame(&in_progress.data[pos..pos + size])?;
+            if i == 0 {
+                is_random_access_point = header.is_some();
+            }
+            key_frame = header.or(key_frame);
+            pos += size;
+        }
+        let mut new_parameters = None;
+        if let Some(config) = key_frame {
+            if !matches!(&self.config, Some((c, _)) if c == &config) {
+                let parameters = config.to_parameters()?;
+                new_parameters = Some(Box::new(parameters.clone()));
+                self.config = Some((config, parameters));
+            }
+        }
+        if in_progress.layer_frame_sizes.len() > 1 {
+            append_superframe_index(&mut in_progress.data, &in_progress.layer_frame_sizes)?;
+        }
+        self.state = State::Ready(VideoFrame {
+            new_parameters,
+            loss: in_progress.loss,
+            start_ctx: in_progress.start_ctx,
+            end_ctx: pkt.ctx,
+            timestamp: in_progress.timestamp,
+            stream_id: pkt.stream_id,
+            is_random_access_point,
+            is_disposable: false,
+            capture_time: in_progress.start_ctx.received_wall(),
+            corrected_timestamp: None,
+            extension: in_progress.extension,
+            sei: Vec::new(),
+            composition_offset: 0,
+            pic_order_cnt: None,
+            picture_type: None,
+            nal_layout: None,
+            data: in_progress.data.freeze(),
+        });
+        Ok(())
+    }
+
+    pub(super) fn pull(&mut self) -> Option<CodecItem> {
+        match std::mem::replace(&mut self.state, State::Idle) {
+            State::Ready(frame) => Some(CodecItem::VideoFrame(frame)),
+            s => {
+                self.state = s;
+                None
+            }
+        }
+    }
+}
+
+/// The fields of the VP9 payload descriptor used for depacketization, as in
+/// [RFC 9628 section 4.2](https://datatracker.ietf.org/doc/html/rfc9628#section-4.2).
+#[derive(Debug)]
+struct PayloadDescriptor {
+    start_of_frame: bool,
+    end_of_frame: bool,
+}
+
+impl PayloadDescriptor {
+    /// Parses the descriptor, advancing `payload` to the VP9 data.
+    fn parse(payload: &mut Bytes) -> Result<Self, String> {
+        let too_short = || "VP9 payload descriptor too short".to_string();
+        if !payload.has_remaining() {
+            return Err(too_short());
+        }
+        let b = payload.get_u8();
+        let picture_id_present = (b & 0b1000_0000) != 0;
+        let inter_picture_predicted = (b & 0b0100_0000) != 0;
+        let layer_indices_present = (b & 0b0010_0000) != 0;
+        let flexible_mode = (b & 0b0001_0000) != 0;
+        let descriptor = PayloadDescriptor {
+            start_of_frame: (b & 0b0000_1000) != 0,
+            end_of_frame: (b & 0b0000_0100) != 0,
+        };
+        let scalability_structure_present = (b & 0b0000_0010) != 0;
+        let skip = |payload: &mut Bytes, len: usize| {
+            if payload.remaining() < len {
+                return Err(too_short());
+            }
+            payload.advance(len);
+            Ok(())
+        };
+        if picture_id_present {
+            // 7 or 15 bits depending on the M bit.
+            let len = match payload.first() {
+                Some(m) if (m & 0b1000_0000) != 0 => 2,
+                Some(_) => 1,
+                None => return Err(too_short()),
+            };
+            skip(payload, len)?;
+        }
+        if layer_indices_present {
+            // TID, U, SID, and D, plus TL0PICIDX in non-flexible mode.
+            skip(payload, if flexible_mode { 1 } else { 2 })?;
+        }
+        if flexible_mode && inter_picture_predicted {
+            // Up to 3 reference indices, each with an N bit if another follows.
+            for _ in 0..3 {
+                let p_diff = *payload.first().ok_or_else(too_short)?;
+                payload.advance(1);
+                if (p_diff & 1) == 0 {
+                    break;
+                }
+            }
+        }
+        if scalability_structure_present {
+            // https://datatracker.ietf.org/doc/html/rfc9628#section-4.2.1
+            let ss = *payload.first().ok_or_else(too_short)?;
+            payload.advance(1);
+            let spatial_layers = usize::from(ss >> 5) + 1;
+            if (ss & 0b0001_0000) != 0 {
+                skip(payload, 4 * spatial_layers)?; // WIDTH and HEIGHT.
+            }
+            if (ss & 0b0000_1000) != 0 {
+                let pictures = *payload.first().ok_or_else(too_short)?;
+                payload.advance(1);
+                for _ in 0..pictures {
+                    let p = *payload.first().ok_or_else(too_short)?;
+                    payload.advance(1);
+                    skip(payload, usize::from((p >> 2) & 0b11))?; // P_DIFFs.
+                }
+            }
+        }
+        if !payload.has_remaining() {
+            return Err("empty VP9 payload".into());
+        }
+        Ok(descriptor)
+    }
+}
+
+/// Parses a frame's uncompressed header, returning its configuration iff it's
+/// a key frame, as in VP9 bitstream specification section 6.2.
+fn parse_key_frame(data: &[u8]) -> Result<Option<VpxConfig>, String> {
+    let mut r = bitreader::BitReader::new(data);
+    let mut read = |bits: u8, name: &str| {
+        r.read_u8(bits)
+            .map_err(|e| format!("unable to read VP9 {}: {}", name, e))
+    };
+    if read(2, "frame_marker")? != 2 {
+        return Err("bad VP9 frame_marker".into());
+    }
+    let profile_low_bit = read(1, "profile_low_bit")?;
+    let profile = (read(1, "profile_high_bit")? << 1) | profile_low_bit;
+    if profile == 3 {
+        read(1, "reserved_zero")?;
+    }
+    if read(1, "show_existing_frame")? == 1 || read(1, "frame_type")? != 0 {
+        return Ok(None);
+    }
+    read(1, "show_frame")?;
+    read(1, "error_resilient_mode")?;
+    if [
+        read(8, "frame_sync_code")?,
+        read(8, "frame_sync_code")?,
+        read(8, "frame_sync_code")?,
+    ] != [0x49, 0x83, 0x42]
+    {
+        return Err("bad VP9 frame_sync_code".into());
+    }
+
+    // color_config(), section 6.2.2.
+    let bit_depth = if profile >= 2 {
+        if read(1, "ten_or_twelve_bit")? == 1 {
+            12
+        } else {
+            10
+        }
+    } else {
+        8
+    };
+    let color_space = read(3, "color_space")?;
+    let full_range;
+    let (subsampling_x, subsampling_y);
+    if color_space != 7 {
+        full_range = read(1, "color_range")? == 1;
+        if profile == 1 || profile == 3 {
+            subsampling_x = read(1, "subsampling_x")? == 1;
+            subsampling_y = read(1, "subsampling_y")? == 1;
+            read(1, "reserved_zero")?;
+        } else {
+            subsampling_x = true;
+            subsampling_y = true;
+        }
+    } else {
+        // CS_RGB.
+        full_range = true;
+        subsampling_x = false;
+        subsampling_y = false;
+        if profile == 1 || profile == 3 {
+            read(1, "reserved_zero")?;
+        }
+    }
+
+    // frame_size(), section 6.2.5.
+    let mut read16 = |name: &str| -> Result<u32, String> {
+        Ok((u32::from(read(8, name)?) << 8) | u32::from(read(8, name)?))
+    };
+    let width = read16("frame_width_minus_1")? + 1;
+    let height = read16("frame_height_minus_1")? + 1;
+    Ok(Some(VpxConfig {
+        codec: "vp09",
+        profile,
+        bit_depth,
+        chroma_subsampling: match (subsampling_x, subsampling_y) {
+            (true, true) => 1,
+            (true, false) => 2,
+            _ => 3,
+        },
+        full_range,
+
+        // Map color_space to ISO/IEC 23091-4 matrix coefficients.
+        matrix_coefficients: match color_space {
+            1 | 3 => 6, // CS_BT_601, CS_SMPTE_170
+            2 => 1,     // CS_BT_709
+            4 => 7,     // CS_SMPTE_240
+            5 => 9,     // CS_BT_2020
+            7 => 0,     // CS_RGB
+            _ => 2,     // CS_UNKNOWN, CS_RESERVED
+        },
+        pixel_dimensions: (width, height),
+    }))
+}
+
+/// Appends a superframe index describing the given frame sizes, as in the VP9
+/// bitstream specification Annex B.
+fn append_superframe_index(data: &mut BytesMut, sizes: &[usize]) -> Result<(), String> {
+    if sizes.len() > 8 {
+        return Err(format!("VP9 picture has {} layer frames", sizes.len()));
+    }
+    let max = sizes.iter().copied().max().unwrap_or(0);
+    let bytes_per_size = match max {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => 4,
+    };
+    let marker = 0b1100_0000 | ((bytes_per_size - 1) << 3) | (sizes.len() as u8 - 1);
+    data.put_u8(marker);
+    for &size in sizes {
+        data.put_uint_le(size as u64, usize::from(bytes_per_size));
+    }
+    data.put_u8(marker);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::testutil::{packet, video_frame};
+
+    #[test]
+    fn depacketize() {
+        let mut d = super::Depacketizer::new(90_000).unwrap();
+        assert!(d.parameters().is_none());
+
+        // A 1280x720 profile 0 BT.709 key frame in two packets, with a 7-bit
+        // picture ID.
+        d.push(packet(
+            0,
+            0,
+            0,
+            false,
+            b"\x88\x01\x82\x49\x83\x42\x40\x4f\xf0\x2c\xf0key",
+        ))
+        .unwrap();
+        assert!(d.pull().is_none());
+        d.push(packet(0, 0, 0, true, b"\x84\x01 frame")).unwrap();
+        let frame = video_frame(d.pull());
+        assert!(frame.is_random_access_point);
+        assert_eq!(
+            &frame.data()[..],
+            b"\x82\x49\x83\x42\x40\x4f\xf0\x2c\xf0key frame"
+        );
+        let p = frame.new_parameters.as_ref().unwrap();
+        assert_eq!(p.rfc6381_codec(), "vp09.00.31.08");
+        assert_eq!(p.pixel_dimensions(), (1280, 720));
+        assert_eq!(
+            &p.extra_data()[..],
+            b"\x01\x00\x00\x00\x00\x1f\x82\x02\x02\x01\x00\x00"
+        );
+
+        // An inter picture with two spatial layers (L=1, non-flexible),
+        // combined into a superframe.
+        d.push(packet(0, 3000, 0, false, b"\x6c\x00\x00\x86inter0"))
+            .unwrap();
+        d.push(packet(0, 3000, 0, true, b"\x6c\x02\x00\x86inter1"))
+            .unwrap();
+        let frame = video_frame(d.pull());
+        assert!(!frame.is_random_access_point);
+        assert!(frame.new_parameters.is_none());
+        assert_eq!(&frame.data()[..], b"\x86inter0\x86inter1\xc1\x07\x07\xc1");
+
+        // A picture whose start was lost is discarded.
+        d.push(packet(0, 6000, 1, true, b"\x04rest")).unwrap();
+        assert!(d.pull().is_none());
+        assert_eq!(d.discarded(), 1);
+    }
+}
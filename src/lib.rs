@@ -9,7 +9,7 @@ use std::fmt::{Debug, Display};
 use std::num::NonZeroU32;
 
 mod error;
-mod rtcp;
+pub mod rtcp;
 
 #[cfg(test)]
 mod testutil;
@@ -272,6 +272,19 @@ impl RtspMessageContext {
         self.received
     }
 
+    /// Returns the wall-clock time when the message was received.
+    pub(crate) fn received_wall(&self) -> std::time::SystemTime {
+        use std::time::{Duration, SystemTime};
+        let t = self.received_wall.0;
+        let secs = Duration::from_secs(t.sec.unsigned_abs());
+        let since_epoch = if t.sec >= 0 {
+            SystemTime::UNIX_EPOCH + secs
+        } else {
+            SystemTime::UNIX_EPOCH - secs
+        };
+        since_epoch + Duration::from_nanos(t.nsec as u64)
+    }
+
     pub fn pos(&self) -> u64 {
         self.pos
     }
@@ -1,24 +1,93 @@
 // Copyright (C) 2021 Scott Lamb <slamb@slamb.org>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-/// Handles RTCP data as described in
-/// [RFC 3550 section 6](https://datatracker.ietf.org/doc/html/rfc3550#section-6).
+//! Handles RTCP data as described in
+//! [RFC 3550 section 6](https://datatracker.ietf.org/doc/html/rfc3550#section-6).
+//!
+//! Packets borrow from the buffer they were parsed from. Each packet type
+//! validates its lengths on parse, so the accessors don't fail.
+
+use bytes::{BufMut, BytesMut};
 use std::convert::TryInto;
 
+/// A single RTCP packet, as found within a compound packet.
+#[derive(Debug)]
 pub enum Packet<'a> {
     SenderReport(SenderReport<'a>),
+    ReceiverReport(ReceiverReport<'a>),
+    SourceDescription(SourceDescription<'a>),
+    Goodbye(Goodbye<'a>),
+    App(App<'a>),
+    ExtendedReport(ExtendedReport<'a>),
     Unknown(GenericPacket<'a>),
 }
 
 impl<'a> Packet<'a> {
+    /// Parses the first packet of `buf`, returning it and the remainder.
+    ///
+    /// Malformed sender reports are errors. Other malformed packets are
+    /// returned as [`Packet::Unknown`], as they were before this library
+    /// understood them.
     pub fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), String> {
         let (pkt, rest) = GenericPacket::parse(buf)?;
         let pkt = match pkt.payload_type() {
             200 => Packet::SenderReport(SenderReport::validate(pkt)?),
+            201 => or_unknown(pkt, ReceiverReport::validate, Packet::ReceiverReport),
+            202 => or_unknown(pkt, SourceDescription::validate, Packet::SourceDescription),
+            203 => or_unknown(pkt, Goodbye::validate, Packet::Goodbye),
+            204 => or_unknown(pkt, App::validate, Packet::App),
+            207 => or_unknown(pkt, ExtendedReport::validate, Packet::ExtendedReport),
             _ => Packet::Unknown(pkt),
         };
         Ok((pkt, rest))
     }
+
+    /// Returns the generic view of this packet.
+    pub fn generic(&self) -> &GenericPacket<'a> {
+        match self {
+            Packet::SenderReport(p) => &p.0,
+            Packet::ReceiverReport(p) => &p.0,
+            Packet::SourceDescription(p) => &p.0,
+            Packet::Goodbye(p) => &p.0,
+            Packet::App(p) => &p.0,
+            Packet::ExtendedReport(p) => &p.0,
+            Packet::Unknown(p) => p,
+        }
+    }
+}
+
+/// Validates a packet of a type which isn't essential to reception, logging and
+/// falling back to [`Packet::Unknown`] if it's malformed.
+fn or_unknown<'a, T>(
+    pkt: GenericPacket<'a>,
+    validate: fn(GenericPacket<'a>) -> Result<T, String>,
+    wrap: fn(T) -> Packet<'a>,
+) -> Packet<'a> {
+    match validate(pkt) {
+        Ok(p) => wrap(p),
+        Err(e) => {
+            log::debug!(
+                "Treating malformed RTCP packet with payload type {} as unknown: {}",
+                pkt.payload_type(),
+                e
+            );
+            Packet::Unknown(pkt)
+        }
+    }
+}
+
+const SENDER_INFO_LEN: usize = 20;
+
+/// Returns the report blocks starting at `start`, which have been validated to fit.
+fn report_blocks<'a>(
+    pkt: &GenericPacket<'a>,
+    start: usize,
+) -> impl Iterator<Item = ReportBlock> + 'a {
+    let buf = pkt.buf;
+    let end = start + usize::from(pkt.count()) * REPORT_BLOCK_LEN;
+    buf[start..end]
+        .chunks_exact(REPORT_BLOCK_LEN)
+        .map(ReportBlock::parse)
 }
 
 /// A RTCP sender report, as defined in
@@ -66,10 +135,7 @@ pub struct SenderReport<'a>(GenericPacket<'a>);
 impl<'a> SenderReport<'a> {
     fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
         let count = usize::from(pkt.count());
-        const HEADER_LEN: usize = 8;
-        const SENDER_INFO_LEN: usize = 20;
-        const REPORT_BLOCK_LEN: usize = 24;
-        let expected_len = HEADER_LEN + SENDER_INFO_LEN + (count * REPORT_BLOCK_LEN);
+        let expected_len = SSRC_HEADER_LEN + SENDER_INFO_LEN + (count * REPORT_BLOCK_LEN);
         if pkt.payload_end < expected_len {
             return Err(format!(
                 "RTCP SR has invalid count={} with unpadded_byte_len={}",
@@ -90,6 +156,679 @@ impl<'a> SenderReport<'a> {
     pub fn rtp_timestamp(&self) -> u32 {
         u32::from_be_bytes(self.0.buf[16..20].try_into().unwrap())
     }
+
+    /// Returns the total number of RTP packets sent since starting transmission.
+    pub fn sender_packet_count(&self) -> u32 {
+        u32::from_be_bytes(self.0.buf[20..24].try_into().unwrap())
+    }
+
+    /// Returns the total number of RTP payload octets sent since starting transmission.
+    pub fn sender_octet_count(&self) -> u32 {
+        u32::from_be_bytes(self.0.buf[24..28].try_into().unwrap())
+    }
+
+    /// Returns the sender's reception reports about other sources.
+    pub fn report_blocks(&self) -> impl Iterator<Item = ReportBlock> + 'a {
+        report_blocks(&self.0, SSRC_HEADER_LEN + SENDER_INFO_LEN)
+    }
+}
+
+impl<'a> std::fmt::Debug for SenderReport<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SenderReport")
+            .field("ssrc", &format_args!("{:08x}", self.ssrc()))
+            .field("ntp_timestamp", &self.ntp_timestamp())
+            .field("rtp_timestamp", &self.rtp_timestamp())
+            .field("sender_packet_count", &self.sender_packet_count())
+            .field("sender_octet_count", &self.sender_octet_count())
+            .field("report_blocks", &self.report_blocks().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A RTCP receiver report, as defined in
+/// [RFC 3550 section 6.4.2](https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.2).
+///
+/// This has the same layout as the [`SenderReport`] but without the sender
+/// information section.
+pub struct ReceiverReport<'a>(GenericPacket<'a>);
+
+impl<'a> ReceiverReport<'a> {
+    fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
+        let count = usize::from(pkt.count());
+        let expected_len = SSRC_HEADER_LEN + (count * REPORT_BLOCK_LEN);
+        if pkt.payload_end < expected_len {
+            return Err(format!(
+                "RTCP RR has invalid count={} with unpadded_byte_len={}",
+                count, pkt.payload_end
+            ));
+        }
+        Ok(ReceiverReport(pkt))
+    }
+
+    /// Returns the SSRC of the packet sender.
+    pub fn ssrc(&self) -> u32 {
+        u32::from_be_bytes(self.0.buf[4..8].try_into().unwrap())
+    }
+
+    pub fn report_blocks(&self) -> impl Iterator<Item = ReportBlock> + 'a {
+        report_blocks(&self.0, SSRC_HEADER_LEN)
+    }
+}
+
+impl<'a> std::fmt::Debug for ReceiverReport<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReceiverReport")
+            .field("ssrc", &format_args!("{:08x}", self.ssrc()))
+            .field("report_blocks", &self.report_blocks().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A RTCP source description, as defined in
+/// [RFC 3550 section 6.5](https://datatracker.ietf.org/doc/html/rfc3550#section-6.5).
+///
+/// ```text
+///         0                   1                   2                   3
+///         0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// header |V=2|P|    SC   |  PT=SDES=202  |             length            |
+///        +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+/// chunk  |                          SSRC/CSRC_1                          |
+///   1    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///        |                           SDES items                          |
+///        |                              ...                              |
+///        +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+/// chunk  |                          SSRC/CSRC_2                          |
+///   2    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///        |                           SDES items                          |
+///        |                              ...                              |
+///        +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+/// ```
+pub struct SourceDescription<'a>(GenericPacket<'a>);
+
+impl<'a> SourceDescription<'a> {
+    fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
+        let mut data = &pkt.buf[COMMON_HEADER_LEN..pkt.payload_end];
+        for i in 0..pkt.count() {
+            data = SdesChunk::parse(data)
+                .map_err(|e| format!("RTCP SDES chunk {}/{}: {}", i, pkt.count(), e))?
+                .1;
+        }
+        Ok(SourceDescription(pkt))
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = SdesChunk<'a>> {
+        let mut data = &self.0.buf[COMMON_HEADER_LEN..self.0.payload_end];
+        (0..self.0.count()).map(move |_| {
+            let (chunk, rest) = SdesChunk::parse(data).expect("SDES chunks were validated");
+            data = rest;
+            chunk
+        })
+    }
+}
+
+impl<'a> std::fmt::Debug for SourceDescription<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.chunks()).finish()
+    }
+}
+
+/// A chunk of a [`SourceDescription`], describing a single source.
+#[derive(Copy, Clone)]
+pub struct SdesChunk<'a> {
+    ssrc: u32,
+
+    /// The items, excluding the terminating null item and padding.
+    items: &'a [u8],
+}
+
+impl<'a> SdesChunk<'a> {
+    /// Parses a chunk, returning it and the remainder of `buf`.
+    fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), String> {
+        if buf.len() < 4 {
+            return Err(format!("only {} bytes remain for SSRC", buf.len()));
+        }
+        let mut i = 4;
+        loop {
+            match buf.get(i) {
+                None => return Err("missing terminating null item".to_owned()),
+                Some(0) => break,
+                Some(_) => {}
+            }
+            let len = match buf.get(i + 1) {
+                None => return Err("truncated item header".to_owned()),
+                Some(&l) => usize::from(l),
+            };
+            i += 2 + len;
+            if i > buf.len() {
+                return Err(format!("item of length {} overflows packet", len));
+            }
+        }
+
+        // The null item is followed by zero or more nulls to the next 32-bit
+        // boundary (chunks are aligned).
+        let chunk_end = (i + 4) & !3;
+        if chunk_end > buf.len() {
+            return Err("truncated padding".to_owned());
+        }
+        Ok((
+            SdesChunk {
+                ssrc: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
+                items: &buf[4..i],
+            },
+            &buf[chunk_end..],
+        ))
+    }
+
+    /// Returns the SSRC or CSRC described by this chunk.
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = SdesItem<'a>> {
+        let mut items = self.items;
+        std::iter::from_fn(move || {
+            if items.is_empty() {
+                return None;
+            }
+            let len = usize::from(items[1]);
+            let item = SdesItem {
+                item_type: SdesItemType::from(items[0]),
+                value: &items[2..2 + len],
+            };
+            items = &items[2 + len..];
+            Some(item)
+        })
+    }
+
+    /// Returns the value of the `CNAME` item, if present.
+    pub fn cname(&self) -> Option<&'a [u8]> {
+        self.items()
+            .find(|i| i.item_type == SdesItemType::Cname)
+            .map(|i| i.value)
+    }
+}
+
+impl<'a> std::fmt::Debug for SdesChunk<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SdesChunk")
+            .field("ssrc", &format_args!("{:08x}", self.ssrc))
+            .field("items", &self.items().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// An item within a [`SdesChunk`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct SdesItem<'a> {
+    pub item_type: SdesItemType,
+
+    /// The value, which is normally UTF-8 text but isn't validated as such.
+    pub value: &'a [u8],
+}
+
+impl<'a> std::fmt::Debug for SdesItem<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}={:?}",
+            self.item_type,
+            String::from_utf8_lossy(self.value)
+        )
+    }
+}
+
+/// The type of a [`SdesItem`], as listed in the
+/// [IANA RTP SDES Item Types registry](https://www.iana.org/assignments/rtp-parameters/rtp-parameters.xhtml#rtp-parameters-5).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SdesItemType {
+    Cname,
+    Name,
+    Email,
+    Phone,
+    Loc,
+    Tool,
+    Note,
+    Priv,
+    Other(u8),
+}
+
+impl From<u8> for SdesItemType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => SdesItemType::Cname,
+            2 => SdesItemType::Name,
+            3 => SdesItemType::Email,
+            4 => SdesItemType::Phone,
+            5 => SdesItemType::Loc,
+            6 => SdesItemType::Tool,
+            7 => SdesItemType::Note,
+            8 => SdesItemType::Priv,
+            o => SdesItemType::Other(o),
+        }
+    }
+}
+
+/// A RTCP goodbye, as defined in
+/// [RFC 3550 section 6.6](https://datatracker.ietf.org/doc/html/rfc3550#section-6.6).
+///
+/// ```text
+///        0                   1                   2                   3
+///        0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///       |V=2|P|    SC   |   PT=BYE=203  |             length            |
+///       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///       |                           SSRC/CSRC                           |
+///       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///       :                              ...                              :
+///       +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+/// (opt) |     length    |               reason for leaving            ...
+///       +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+pub struct Goodbye<'a>(GenericPacket<'a>);
+
+impl<'a> Goodbye<'a> {
+    fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
+        let ssrcs_end = COMMON_HEADER_LEN + 4 * usize::from(pkt.count());
+        if pkt.payload_end < ssrcs_end {
+            return Err(format!(
+                "RTCP BYE has invalid count={} with unpadded_byte_len={}",
+                pkt.count(),
+                pkt.payload_end
+            ));
+        }
+        if pkt.payload_end > ssrcs_end {
+            let reason_len = usize::from(pkt.buf[ssrcs_end]);
+            if ssrcs_end + 1 + reason_len > pkt.payload_end {
+                return Err(format!(
+                    "RTCP BYE reason of length {} overflows unpadded_byte_len={}",
+                    reason_len, pkt.payload_end
+                ));
+            }
+        }
+        Ok(Goodbye(pkt))
+    }
+
+    fn ssrcs_end(&self) -> usize {
+        COMMON_HEADER_LEN + 4 * usize::from(self.0.count())
+    }
+
+    /// Returns the sources which are leaving.
+    pub fn ssrcs(&self) -> impl Iterator<Item = u32> + 'a {
+        let buf = self.0.buf;
+        buf[COMMON_HEADER_LEN..self.ssrcs_end()]
+            .chunks_exact(4)
+            .map(|s| u32::from_be_bytes(s.try_into().unwrap()))
+    }
+
+    /// Returns the reason for leaving, if specified.
+    pub fn reason(&self) -> Option<&'a [u8]> {
+        let ssrcs_end = self.ssrcs_end();
+        if self.0.payload_end == ssrcs_end {
+            return None;
+        }
+        let buf = self.0.buf;
+        let reason_len = usize::from(buf[ssrcs_end]);
+        Some(&buf[ssrcs_end + 1..ssrcs_end + 1 + reason_len])
+    }
+}
+
+impl<'a> std::fmt::Debug for Goodbye<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Goodbye")
+            .field(
+                "ssrcs",
+                &self
+                    .ssrcs()
+                    .map(|s| format!("{:08x}", s))
+                    .collect::<Vec<_>>(),
+            )
+            .field("reason", &self.reason().map(String::from_utf8_lossy))
+            .finish()
+    }
+}
+
+/// An application-defined RTCP packet, as defined in
+/// [RFC 3550 section 6.7](https://datatracker.ietf.org/doc/html/rfc3550#section-6.7).
+///
+/// ```text
+///     0                   1                   2                   3
+///     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |V=2|P| subtype |   PT=APP=204  |             length            |
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |                           SSRC/CSRC                           |
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |                          name (ASCII)                         |
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |                   application-dependent data                ...
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+pub struct App<'a>(GenericPacket<'a>);
+
+impl<'a> App<'a> {
+    fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
+        if pkt.payload_end < 12 {
+            return Err(format!(
+                "RTCP APP has invalid unpadded_byte_len={}",
+                pkt.payload_end
+            ));
+        }
+        Ok(App(pkt))
+    }
+
+    pub fn subtype(&self) -> u8 {
+        self.0.count()
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        u32::from_be_bytes(self.0.buf[4..8].try_into().unwrap())
+    }
+
+    /// Returns the four-character name, which is normally ASCII.
+    pub fn name(&self) -> [u8; 4] {
+        self.0.buf[8..12].try_into().unwrap()
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        let buf = self.0.buf;
+        &buf[12..self.0.payload_end]
+    }
+}
+
+impl<'a> std::fmt::Debug for App<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("App")
+            .field("subtype", &self.subtype())
+            .field("ssrc", &format_args!("{:08x}", self.ssrc()))
+            .field("name", &String::from_utf8_lossy(&self.name()[..]))
+            .field("data_len", &self.data().len())
+            .finish()
+    }
+}
+
+/// A RTCP extended report, as defined in
+/// [RFC 3611 section 2](https://datatracker.ietf.org/doc/html/rfc3611#section-2).
+///
+/// ```text
+///     0                   1                   2                   3
+///     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |V=2|P|reserved |   PT=XR=207   |             length            |
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    |                              SSRC                             |
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///    :                         report blocks                         :
+///    +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+pub struct ExtendedReport<'a>(GenericPacket<'a>);
+
+impl<'a> ExtendedReport<'a> {
+    fn validate(pkt: GenericPacket<'a>) -> Result<Self, String> {
+        if pkt.payload_end < SSRC_HEADER_LEN {
+            return Err(format!(
+                "RTCP XR has invalid unpadded_byte_len={}",
+                pkt.payload_end
+            ));
+        }
+        let mut data = &pkt.buf[SSRC_HEADER_LEN..pkt.payload_end];
+        while !data.is_empty() {
+            data = XrBlock::parse(data)?.1;
+        }
+        Ok(ExtendedReport(pkt))
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        u32::from_be_bytes(self.0.buf[4..8].try_into().unwrap())
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = XrBlock<'a>> {
+        let mut data = &self.0.buf[SSRC_HEADER_LEN..self.0.payload_end];
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+            let (block, rest) = XrBlock::parse(data).expect("XR blocks were validated");
+            data = rest;
+            Some(block)
+        })
+    }
+}
+
+impl<'a> std::fmt::Debug for ExtendedReport<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtendedReport")
+            .field("ssrc", &format_args!("{:08x}", self.ssrc()))
+            .field("blocks", &self.blocks().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A block within an [`ExtendedReport`].
+///
+/// The block types are listed in the
+/// [IANA RTCP XR Block Type registry](https://www.iana.org/assignments/rtcp-xr-block-types/rtcp-xr-block-types.xhtml).
+#[derive(Copy, Clone, Debug)]
+pub struct XrBlock<'a> {
+    pub block_type: u8,
+    pub type_specific: u8,
+
+    /// The block contents, following the four-byte block header.
+    pub contents: &'a [u8],
+}
+
+impl<'a> XrBlock<'a> {
+    fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), String> {
+        if buf.len() < 4 {
+            return Err(format!(
+                "RTCP XR block header needs 4 bytes; have only {}",
+                buf.len()
+            ));
+        }
+
+        // RFC 3611 section 3: "The length of this report block in 32-bit words,
+        // minus one, including the header."
+        let len = 4 * (usize::from(u16::from_be_bytes([buf[2], buf[3]])) + 1);
+        if buf.len() < len {
+            return Err(format!(
+                "RTCP XR block has length {} bytes; have only {}",
+                len,
+                buf.len()
+            ));
+        }
+        Ok((
+            XrBlock {
+                block_type: buf[0],
+                type_specific: buf[1],
+                contents: &buf[4..len],
+            },
+            &buf[len..],
+        ))
+    }
+}
+
+/// A reception report block, as found in sender and receiver reports.
+///
+/// See [RFC 3550 section 6.4.1](https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.1)
+/// for the meaning of each field.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportBlock {
+    /// The SSRC of the source this block describes.
+    pub ssrc: u32,
+
+    /// The fraction of packets lost since the previous report, as a fixed
+    /// point number with the binary point at the left edge.
+    pub fraction_lost: u8,
+
+    /// The cumulative number of packets lost, a signed 24-bit value.
+    pub cumulative_lost: i32,
+
+    pub extended_highest_seq: u32,
+
+    /// The interarrival jitter, in RTP timestamp units.
+    pub jitter: u32,
+
+    /// The middle 32 bits of the NTP timestamp of the last SR from this source.
+    pub last_sr: u32,
+
+    /// Delay since receiving the last SR from this source, in units of 1/65536 seconds.
+    pub delay_since_last_sr: u32,
+}
+
+const REPORT_BLOCK_LEN: usize = 24;
+
+impl ReportBlock {
+    fn parse(buf: &[u8]) -> Self {
+        ReportBlock {
+            ssrc: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
+            fraction_lost: buf[4],
+
+            // Sign-extend the 24-bit value.
+            cumulative_lost: i32::from_be_bytes([buf[5], buf[6], buf[7], 0]) >> 8,
+            extended_highest_seq: u32::from_be_bytes(buf[8..12].try_into().unwrap()),
+            jitter: u32::from_be_bytes(buf[12..16].try_into().unwrap()),
+            last_sr: u32::from_be_bytes(buf[16..20].try_into().unwrap()),
+            delay_since_last_sr: u32::from_be_bytes(buf[20..24].try_into().unwrap()),
+        }
+    }
+
+    fn write(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.ssrc);
+        buf.put_u8(self.fraction_lost);
+        let cumulative_lost = self.cumulative_lost.clamp(-0x80_0000, 0x7F_FFFF) as u32;
+        buf.put_slice(&cumulative_lost.to_be_bytes()[1..]);
+        buf.put_u32(self.extended_highest_seq);
+        buf.put_u32(self.jitter);
+        buf.put_u32(self.last_sr);
+        buf.put_u32(self.delay_since_last_sr);
+    }
+}
+
+/// Appends a receiver report, as defined in
+/// [RFC 3550 section 6.4.2](https://datatracker.ietf.org/doc/html/rfc3550#section-6.4.2).
+///
+/// ```text
+///         0                   1                   2                   3
+///         0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+///        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// header |V=2|P|    RC   |   PT=RR=201   |             length            |
+///        +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+///        |                     SSRC of packet sender                     |
+///        +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+/// report |                 SSRC_1 (SSRC of first source)                 |
+/// block  :                               ...                             :
+/// ```
+///
+/// Panics if there are more than 31 report blocks.
+pub(crate) fn write_receiver_report(ssrc: u32, blocks: &[ReportBlock], buf: &mut BytesMut) {
+    assert!(
+        blocks.len() < 32,
+        "{} report blocks won't fit in RC",
+        blocks.len()
+    );
+    let len = COMMON_HEADER_LEN + 4 + blocks.len() * REPORT_BLOCK_LEN;
+    buf.reserve(len);
+    buf.put_u8(0b1000_0000 | (blocks.len() as u8));
+    buf.put_u8(201);
+    buf.put_u16((len / 4 - 1) as u16);
+    buf.put_u32(ssrc);
+    for b in blocks {
+        b.write(buf);
+    }
+}
+
+/// Appends a source description with a single chunk holding only a `CNAME`
+/// item, as defined in
+/// [RFC 3550 section 6.5](https://datatracker.ietf.org/doc/html/rfc3550#section-6.5).
+///
+/// `cname` is truncated to 255 bytes if necessary.
+pub(crate) fn write_sdes_cname(ssrc: u32, cname: &str, buf: &mut BytesMut) {
+    let cname = &cname.as_bytes()[..std::cmp::min(cname.len(), 255)];
+
+    // The chunk is the SSRC, the CNAME item, and a terminating null item, padded
+    // with more nulls to a 32-bit boundary.
+    let chunk_len = (4 + 2 + cname.len() + 1 + 3) & !3;
+    let len = COMMON_HEADER_LEN + chunk_len;
+    buf.reserve(len);
+    buf.put_u8(0b1000_0001);
+    buf.put_u8(202);
+    buf.put_u16((len / 4 - 1) as u16);
+    buf.put_u32(ssrc);
+    buf.put_u8(1); // CNAME
+    buf.put_u8(cname.len() as u8);
+    buf.put_slice(cname);
+    for _ in 4 + 2 + cname.len()..chunk_len {
+        buf.put_u8(0);
+    }
+}
+
+/// Appends a Picture Loss Indication, as defined in
+/// [RFC 4585 section 6.3.1](https://datatracker.ietf.org/doc/html/rfc4585#section-6.3.1).
+///
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |V=2|P| FMT=1   |   PT=PSFB=206 |          length=2             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                  SSRC of packet sender                        |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                  SSRC of media source                         |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+pub(crate) fn write_pli(ssrc: u32, media_ssrc: u32, buf: &mut BytesMut) {
+    buf.reserve(SSRC_HEADER_LEN + 4);
+    buf.put_u8(0b1000_0001);
+    buf.put_u8(206);
+    buf.put_u16(2);
+    buf.put_u32(ssrc);
+    buf.put_u32(media_ssrc);
+}
+
+/// Appends a Full Intra Request, as defined in
+/// [RFC 5104 section 4.3.1](https://datatracker.ietf.org/doc/html/rfc5104#section-4.3.1).
+///
+/// The media source field is unused; the request's single FCI entry holds
+/// `media_ssrc` and the command sequence number `seq_nr`, which should be
+/// incremented for each new request.
+pub(crate) fn write_fir(ssrc: u32, media_ssrc: u32, seq_nr: u8, buf: &mut BytesMut) {
+    buf.reserve(SSRC_HEADER_LEN + 12);
+    buf.put_u8(0b1000_0100);
+    buf.put_u8(206);
+    buf.put_u16(4);
+    buf.put_u32(ssrc);
+    buf.put_u32(0);
+    buf.put_u32(media_ssrc);
+    buf.put_u32(u32::from(seq_nr) << 24);
+}
+
+/// Appends a Generic NACK, as defined in
+/// [RFC 4585 section 6.2.1](https://datatracker.ietf.org/doc/html/rfc4585#section-6.2.1),
+/// requesting the lost sequence numbers `lost` (in order).
+///
+/// Each FCI entry holds a packet ID and a bitmask of lost packets among the
+/// following 16.
+pub(crate) fn write_nack(ssrc: u32, media_ssrc: u32, lost: &[u16], buf: &mut BytesMut) {
+    let mut entries: Vec<(u16, u16)> = Vec::new();
+    for &seq in lost {
+        match entries.last_mut() {
+            Some((pid, blp)) if (1..=16).contains(&seq.wrapping_sub(*pid)) => {
+                *blp |= 1 << (seq.wrapping_sub(*pid) - 1);
+            }
+            _ => entries.push((seq, 0)),
+        }
+    }
+    buf.reserve(SSRC_HEADER_LEN + 4 + 4 * entries.len());
+    buf.put_u8(0b1000_0001);
+    buf.put_u8(205);
+    buf.put_u16(2 + entries.len() as u16);
+    buf.put_u32(ssrc);
+    buf.put_u32(media_ssrc);
+    for (pid, blp) in entries {
+        buf.put_u16(pid);
+        buf.put_u16(blp);
+    }
 }
 
 /// A generic packet, not parsed as any particular payload type.
@@ -103,6 +842,7 @@ impl<'a> SenderReport<'a> {
 /// |V=2|P|         |   PT          |             length            |
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 /// ```
+#[derive(Copy, Clone)]
 pub struct GenericPacket<'a> {
     buf: &'a [u8],
     payload_end: usize,
@@ -110,6 +850,9 @@ pub struct GenericPacket<'a> {
 
 const COMMON_HEADER_LEN: usize = 4;
 
+/// The length of the common header plus the SSRC which follows it in most packet types.
+const SSRC_HEADER_LEN: usize = 8;
+
 impl<'a> GenericPacket<'a> {
     /// Parses a buffer into this packet and rest, doing only basic validation
     /// of the version, padding, and length.
@@ -178,6 +921,21 @@ impl<'a> GenericPacket<'a> {
     pub fn count(&self) -> u8 {
         self.buf[0] & 0b0001_1111
     }
+
+    /// Returns the full packet, including header and any padding.
+    pub fn raw(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+impl<'a> std::fmt::Debug for GenericPacket<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GenericPacket")
+            .field("payload_type", &self.payload_type())
+            .field("count", &self.count())
+            .field("len", &self.buf.len())
+            .finish()
+    }
 }
 
 #[cfg(test)]
@@ -204,9 +962,196 @@ mod tests {
         }
         let (sdes, buf) = Packet::parse(buf).unwrap();
         match sdes {
-            Packet::Unknown(p) => assert_eq!(p.payload_type(), 202),
+            Packet::SourceDescription(p) => {
+                let chunks: Vec<_> = p.chunks().collect();
+                assert_eq!(chunks.len(), 1);
+                assert_eq!(chunks[0].ssrc(), 0x66426ae1);
+                assert_eq!(chunks[0].cname(), Some(&b"(none)"[..]));
+            }
             _ => panic!(),
         }
         assert_eq!(buf.len(), 0);
     }
+
+    #[test]
+    fn sr_with_report_block() {
+        let buf = b"\x81\xc8\x00\x0c\x66\x42\x6a\xe1\
+                    \xe4\x36\x2f\x99\xcc\xcc\xcc\xcc\
+                    \x85\x2e\xf8\x07\x00\x00\x01\x00\
+                    \x00\x01\x00\x00\
+                    \x01\x02\x03\x04\x10\xff\xff\xfe\
+                    \x00\x01\x41\xd4\x00\x00\x00\x11\
+                    \x2f\x99\xcc\xcc\x00\x01\x80\x00";
+        let (sr, rest) = Packet::parse(buf).unwrap();
+        assert_eq!(rest.len(), 0);
+        let sr = match sr {
+            Packet::SenderReport(p) => p,
+            _ => panic!(),
+        };
+        assert_eq!(sr.sender_packet_count(), 256);
+        assert_eq!(sr.sender_octet_count(), 65536);
+        let blocks: Vec<_> = sr.report_blocks().collect();
+        assert_eq!(
+            blocks,
+            &[ReportBlock {
+                ssrc: 0x01020304,
+                fraction_lost: 0x10,
+                cumulative_lost: -2,
+                extended_highest_seq: 0x141d4,
+                jitter: 17,
+                last_sr: 0x2f99cccc,
+                delay_since_last_sr: 0x18000,
+            }]
+        );
+
+        // Truncating the report block is an error.
+        let mut truncated = buf[..buf.len() - 4].to_vec();
+        truncated[3] = 0x0b;
+        Packet::parse(&truncated).unwrap_err();
+    }
+
+    #[test]
+    fn bye() {
+        // BYE with two SSRCs and a reason.
+        let buf = b"\x82\xcb\x00\x04\x66\x42\x6a\xe1\
+                    \x01\x02\x03\x04\x07restart";
+        let bye = match Packet::parse(buf).unwrap() {
+            (Packet::Goodbye(p), b"") => p,
+            _ => panic!(),
+        };
+        assert_eq!(bye.ssrcs().collect::<Vec<_>>(), &[0x66426ae1, 0x01020304]);
+        assert_eq!(bye.reason(), Some(&b"restart"[..]));
+
+        // BYE without a reason.
+        let buf = b"\x81\xcb\x00\x01\x66\x42\x6a\xe1";
+        let bye = match Packet::parse(buf).unwrap() {
+            (Packet::Goodbye(p), b"") => p,
+            _ => panic!(),
+        };
+        assert_eq!(bye.ssrcs().collect::<Vec<_>>(), &[0x66426ae1]);
+        assert_eq!(bye.reason(), None);
+
+        // Reason overflowing the packet; treated as unknown.
+        assert!(matches!(
+            Packet::parse(b"\x81\xcb\x00\x02\x66\x42\x6a\xe1\x07res").unwrap(),
+            (Packet::Unknown(_), b"")
+        ));
+    }
+
+    #[test]
+    fn app_and_xr() {
+        let buf = b"\x83\xcc\x00\x03\x66\x42\x6a\xe1ONVF\x01\x02\x03\x04\
+                    \x80\xcf\x00\x04\x66\x42\x6a\xe1\
+                    \x04\x00\x00\x02\xe4\x36\x2f\x99\xcc\xcc\xcc\xcc";
+        let (app, rest) = Packet::parse(buf).unwrap();
+        match app {
+            Packet::App(p) => {
+                assert_eq!(p.subtype(), 3);
+                assert_eq!(p.ssrc(), 0x66426ae1);
+                assert_eq!(&p.name(), b"ONVF");
+                assert_eq!(p.data(), b"\x01\x02\x03\x04");
+            }
+            _ => panic!(),
+        }
+        let xr = match Packet::parse(rest).unwrap() {
+            (Packet::ExtendedReport(p), b"") => p,
+            _ => panic!(),
+        };
+        let blocks: Vec<_> = xr.blocks().collect();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].block_type, 4); // receiver reference time
+        assert_eq!(blocks[0].contents, b"\xe4\x36\x2f\x99\xcc\xcc\xcc\xcc");
+
+        // A block which overflows the packet; treated as unknown.
+        assert!(matches!(
+            Packet::parse(b"\x80\xcf\x00\x02\x66\x42\x6a\xe1\x04\x00\x00\x02").unwrap(),
+            (Packet::Unknown(_), b"")
+        ));
+    }
+
+    #[test]
+    fn bad_sdes() {
+        // Missing the terminating null item. It's treated as an unknown packet.
+        let (pkt, rest) = Packet::parse(b"\x81\xca\x00\x02\x66\x42\x6a\xe1\x01\x02ab").unwrap();
+        assert!(matches!(pkt, Packet::Unknown(_)));
+        assert_eq!(pkt.generic().payload_type(), 202);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn bad_rr() {
+        // Claims a report block but has none. It's treated as an unknown packet.
+        let (pkt, rest) = Packet::parse(b"\x81\xc9\x00\x01\x66\x42\x6a\xe1").unwrap();
+        assert!(matches!(pkt, Packet::Unknown(_)));
+        assert_eq!(pkt.generic().payload_type(), 201);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn write_rr_and_sdes() {
+        let mut buf = BytesMut::new();
+        write_receiver_report(
+            0x0102_0304,
+            &[ReportBlock {
+                ssrc: 0x6642_6ae1,
+                fraction_lost: 0x40,
+                cumulative_lost: -2,
+                extended_highest_seq: 0x0001_41d4,
+                jitter: 17,
+                last_sr: 0x2f99_cccc,
+                delay_since_last_sr: 0x0001_8000,
+            }],
+            &mut buf,
+        );
+        write_sdes_cname(0x0102_0304, "retina@192.168.5.3", &mut buf);
+        assert_eq!(
+            &buf[..],
+            &b"\x81\xc9\x00\x07\x01\x02\x03\x04\
+               \x66\x42\x6a\xe1\x40\xff\xff\xfe\
+               \x00\x01\x41\xd4\x00\x00\x00\x11\
+               \x2f\x99\xcc\xcc\x00\x01\x80\x00\
+               \x81\xca\x00\x07\x01\x02\x03\x04\
+               \x01\x12retina@192.168.5.3\
+               \x00\x00\x00\x00"[..]
+        );
+        let (rr, rest) = GenericPacket::parse(&buf[..]).unwrap();
+        assert_eq!(rr.payload_type(), 201);
+        assert_eq!(rr.count(), 1);
+        let (sdes, rest) = GenericPacket::parse(rest).unwrap();
+        assert_eq!(sdes.payload_type(), 202);
+        assert_eq!(sdes.count(), 1);
+        assert_eq!(rest.len(), 0);
+    }
+
+    #[test]
+    fn write_feedback() {
+        let mut buf = BytesMut::new();
+        write_pli(0x0102_0304, 0x6642_6ae1, &mut buf);
+        write_fir(0x0102_0304, 0x6642_6ae1, 7, &mut buf);
+        assert_eq!(
+            &buf[..],
+            &b"\x81\xce\x00\x02\x01\x02\x03\x04\x66\x42\x6a\xe1\
+               \x84\xce\x00\x04\x01\x02\x03\x04\x00\x00\x00\x00\
+               \x66\x42\x6a\xe1\x07\x00\x00\x00"[..]
+        );
+        let (pli, rest) = GenericPacket::parse(&buf[..]).unwrap();
+        assert_eq!(pli.payload_type(), 206);
+        assert_eq!(pli.count(), 1);
+        let (fir, rest) = GenericPacket::parse(rest).unwrap();
+        assert_eq!(fir.count(), 4);
+        assert_eq!(rest.len(), 0);
+
+        let mut buf = BytesMut::new();
+        write_nack(
+            0x0102_0304,
+            0x6642_6ae1,
+            &[0xfffe, 0xffff, 0x0001, 0x0010],
+            &mut buf,
+        );
+        assert_eq!(
+            &buf[..],
+            &b"\x81\xcd\x00\x04\x01\x02\x03\x04\x66\x42\x6a\xe1\
+               \xff\xfe\x00\x05\x00\x10\x00\x00"[..]
+        );
+    }
 }